@@ -49,9 +49,11 @@ use std::pin::Pin;
 
 mod worker;
 mod store;
+mod subsystem;
 
 pub use worker::AvailabilityBlockImport;
 pub use store::AwaitedFrontierEntry;
+pub use subsystem::AvailabilityStoreSubsystem;
 
 use worker::{
 	Worker, WorkerHandle, IncludedParachainBlocks, WorkerMsg, MakeAvailable, Chunks
@@ -22,6 +22,7 @@ use polkadot_erasure_coding as erasure;
 use polkadot_primitives::v0::{
 	Hash, ErasureChunk, AvailableData, AbridgedCandidateReceipt,
 };
+use polkadot_primitives::v1;
 use parking_lot::Mutex;
 
 use log::{trace, warn};
@@ -146,6 +147,24 @@ impl Store {
 		self.inner.write(tx)
 	}
 
+	/// Make some v1 data available.
+	///
+	/// The v1 `AvailableData` is encoded the same way as the `ExecutionData` stored by
+	/// `make_available`, so both can be read back with either of the accessors.
+	pub(crate) fn make_available_v1(&self, candidate_hash: Hash, available_data: &v1::AvailableData)
+		-> io::Result<()>
+	{
+		let mut tx = DBTransaction::new();
+
+		tx.put_vec(
+			columns::DATA,
+			execution_data_key(&candidate_hash).as_slice(),
+			available_data.encode(),
+		);
+
+		self.inner.write(tx)
+	}
+
 	/// Get a set of all chunks we are waiting for.
 	pub fn awaited_chunks(&self) -> Option<HashSet<AwaitedFrontierEntry>> {
 		self.query_inner(columns::META, &AWAITED_CHUNKS_KEY).map(|vec: Vec<AwaitedFrontierEntry>| {
@@ -289,6 +308,43 @@ impl Store {
 		}
 	}
 
+	/// Add a set of chunks of a candidate whose receipt is not known to the store.
+	///
+	/// Unlike `add_erasure_chunks` this neither updates the awaited frontier nor attempts to
+	/// reconstruct the available data, and chunks which are already stored are ignored.
+	pub(crate) fn add_erasure_chunks_v1<I>(
+		&self,
+		candidate_hash: &Hash,
+		chunks: I,
+	) -> io::Result<()>
+		where I: IntoIterator<Item = ErasureChunk>
+	{
+		let mut tx = DBTransaction::new();
+		let dbkey = erasure_chunks_key(candidate_hash);
+
+		let mut v: Vec<ErasureChunk> = self.query_inner(columns::DATA, &dbkey).unwrap_or(Vec::new());
+
+		let av_chunks_key = available_chunks_key(candidate_hash);
+		let mut have_chunks = self.available_chunks(candidate_hash);
+
+		for chunk in chunks.into_iter() {
+			if !have_chunks.contains(&chunk.index) {
+				have_chunks.push(chunk.index);
+				v.push(chunk);
+			}
+		}
+
+		tx.put_vec(columns::DATA, &dbkey, v.encode());
+		tx.put_vec(columns::META, &av_chunks_key, have_chunks.encode());
+
+		self.inner.write(tx)
+	}
+
+	/// Queries the indices of the erasure chunks of a candidate which are stored.
+	pub(crate) fn available_chunks(&self, candidate_hash: &Hash) -> Vec<u32> {
+		self.query_inner(columns::META, &available_chunks_key(candidate_hash)).unwrap_or(Vec::new())
+	}
+
 	/// Queries an erasure chunk by its block's relay-parent, the candidate hash, and index.
 	pub fn get_erasure_chunk(
 		&self,
@@ -355,6 +411,21 @@ impl Store {
 		self.inner.write(tx)
 	}
 
+	/// Pin a candidate to the relay parent it was built on, unless it already is, so that its
+	/// data is pruned along with the other candidates of that relay parent.
+	pub(crate) fn pin_candidate(&self, relay_parent: &Hash, candidate_hash: Hash) -> io::Result<()> {
+		if self.get_candidates_with_relay_parent(relay_parent).contains(&candidate_hash) {
+			return Ok(());
+		}
+
+		self.note_candidates_with_relay_parent(relay_parent, &[candidate_hash])
+	}
+
+	/// Query v1 available data by candidate hash.
+	pub(crate) fn available_data_v1(&self, candidate_hash: &Hash) -> Option<v1::AvailableData> {
+		self.query_inner(columns::DATA, &execution_data_key(candidate_hash))
+	}
+
 	/// Query execution data by relay parent and candidate hash.
 	pub(crate) fn execution_data(&self, candidate_hash: &Hash) -> Option<ExecutionData> {
 		self.query_inner(columns::DATA, &execution_data_key(candidate_hash))
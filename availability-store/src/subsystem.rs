@@ -0,0 +1,352 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The availability store as an overseer subsystem.
+//!
+//! This serves `AvailabilityStoreMessage`s from the same database as the [`Store`], and prunes
+//! the data of candidates which were not included in the finalized chain.
+//!
+//! [`Store`]: ../struct.Store.html
+
+use futures::prelude::*;
+use futures::channel::oneshot;
+use polkadot_primitives::v1::{CoreOccupied, Hash, Id as ParaId};
+use polkadot_subsystem::{
+	FromOverseer, OverseerSignal, SpawnedSubsystem, Subsystem, SubsystemContext, SubsystemResult,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, AvailabilityStoreMessage, RuntimeApiMessage, RuntimeApiRequest, SchedulerRoster,
+};
+
+use log::warn;
+
+use std::collections::HashSet;
+use std::io;
+
+use crate::{LOG_TARGET, Config};
+use crate::store::Store as InnerStore;
+
+/// The availability store subsystem.
+pub struct AvailabilityStoreSubsystem {
+	inner: InnerStore,
+}
+
+impl AvailabilityStoreSubsystem {
+	/// Create a new `AvailabilityStoreSubsystem` with given config on disk.
+	#[cfg(not(target_os = "unknown"))]
+	pub fn new(config: Config) -> io::Result<Self> {
+		Ok(AvailabilityStoreSubsystem {
+			inner: InnerStore::new(config)?,
+		})
+	}
+
+	/// Create a new in-memory `AvailabilityStoreSubsystem`. Useful for tests.
+	pub fn new_in_memory() -> Self {
+		AvailabilityStoreSubsystem {
+			inner: InnerStore::new_in_memory(),
+		}
+	}
+}
+
+impl<C> Subsystem<C> for AvailabilityStoreSubsystem
+	where C: SubsystemContext<Message = AvailabilityStoreMessage>
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		SpawnedSubsystem(run(self.inner, ctx).map(|_| ()).boxed())
+	}
+}
+
+/// The para occupying the given availability core, if any.
+///
+/// Parachains are assigned to cores in the order in which they are upcoming.
+fn occupying_para(roster: &SchedulerRoster, core: usize) -> Option<ParaId> {
+	match roster.availability_cores.get(core)? {
+		Some(CoreOccupied::Parachain) => roster.upcoming.get(core).cloned(),
+		Some(CoreOccupied::Parathread(entry)) => Some(entry.claim.0),
+		None => None,
+	}
+}
+
+/// The hashes of the candidates built on `parent` which were backed in `block`.
+///
+/// These are the candidates pending availability in the state of `block` whose relay-parent is
+/// `parent`, as candidates are always backed in a child of their relay-parent.
+async fn candidates_backed_in(
+	ctx: &mut impl SubsystemContext<Message = AvailabilityStoreMessage>,
+	block: Hash,
+	parent: Hash,
+) -> Result<HashSet<Hash>, oneshot::Canceled> {
+	let (tx, rx) = oneshot::channel();
+	let _ = ctx.send_message(AllMessages::RuntimeApi(
+		RuntimeApiMessage::Request(block, RuntimeApiRequest::ValidatorGroups(tx)),
+	)).await;
+	let roster = rx.await?;
+
+	let (messages, receivers): (Vec<_>, Vec<_>) = (0..roster.availability_cores.len())
+		.filter_map(|core| occupying_para(&roster, core))
+		.map(|para| {
+			let (tx, rx) = oneshot::channel();
+			let message = AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+				block,
+				RuntimeApiRequest::CandidatePendingAvailability(para, tx),
+			));
+
+			(message, rx)
+		})
+		.unzip();
+
+	let _ = ctx.send_messages(messages).await;
+
+	Ok(future::try_join_all(receivers).await?
+		.into_iter()
+		.flatten()
+		.filter(|candidate| candidate.descriptor.relay_parent == parent)
+		.map(|candidate| candidate.hash())
+		.collect())
+}
+
+async fn run(
+	store: InnerStore,
+	mut ctx: impl SubsystemContext<Message = AvailabilityStoreMessage>,
+) -> SubsystemResult<()> {
+	// Finality notifications are issued for every finalized block, so the previously finalized
+	// block is the parent of the one being finalized.
+	let mut last_finalized: Option<Hash> = None;
+
+	loop {
+		match ctx.recv().await? {
+			FromOverseer::Signal(OverseerSignal::Conclude) => return Ok(()),
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(hash)) => {
+				let parent = match last_finalized.replace(hash) {
+					Some(parent) => parent,
+					None => continue,
+				};
+
+				match candidates_backed_in(&mut ctx, hash, parent).await {
+					Ok(backed) => if let Err(e) = store.candidates_finalized(parent, backed) {
+						warn!(target: LOG_TARGET, "Failed to prune the availability store: {:?}", e);
+					},
+					Err(_) => warn!(
+						target: LOG_TARGET,
+						"Failed to fetch the candidates backed in finalized block {}",
+						hash,
+					),
+				}
+			}
+			FromOverseer::Signal(_) => {}
+			FromOverseer::Communication { msg } => {
+				if let Err(e) = process_message(&store, msg) {
+					warn!(target: LOG_TARGET, "Error writing to the availability store: {:?}", e);
+				}
+			}
+		}
+	}
+}
+
+fn process_message(store: &InnerStore, msg: AvailabilityStoreMessage) -> io::Result<()> {
+	match msg {
+		AvailabilityStoreMessage::QueryPoV(hash, tx) => {
+			let _ = tx.send(store.available_data_v1(&hash).map(|data| data.pov));
+		}
+		AvailabilityStoreMessage::QueryChunk(hash, index, tx) => {
+			let _ = tx.send(store.get_erasure_chunk(&hash, index as usize));
+		}
+		AvailabilityStoreMessage::QueryAvailableChunks(hash, tx) => {
+			let _ = tx.send(store.available_chunks(&hash));
+		}
+		AvailabilityStoreMessage::StoreChunk(hash, relay_parent, _index, chunk) => {
+			store.pin_candidate(&relay_parent, hash)?;
+			store.add_erasure_chunks_v1(&hash, std::iter::once(chunk))?;
+		}
+		AvailabilityStoreMessage::StoreAvailableData(hash, relay_parent, available_data) => {
+			store.pin_candidate(&relay_parent, hash)?;
+			store.make_available_v1(hash, &available_data)?;
+		}
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::{self, ThreadPool};
+	use polkadot_primitives::v1::{
+		AvailableData, BlockData, CandidateDescriptor, CommittedCandidateReceipt, ErasureChunk,
+		OmittedValidationData, PoV,
+	};
+	use assert_matches::assert_matches;
+
+	fn candidate(para_id: u32, relay_parent: Hash) -> CommittedCandidateReceipt {
+		CommittedCandidateReceipt {
+			descriptor: CandidateDescriptor {
+				para_id: para_id.into(),
+				relay_parent,
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	fn chunk(index: u32) -> ErasureChunk {
+		ErasureChunk {
+			chunk: vec![index as u8; 3],
+			index,
+			proof: vec![],
+		}
+	}
+
+	fn available_data(block_data: &[u8]) -> AvailableData {
+		AvailableData {
+			pov: PoV {
+				block_data: BlockData(block_data.to_vec()),
+			},
+			omitted_validation: OmittedValidationData {
+				global_validation: Default::default(),
+				local_validation: Default::default(),
+			},
+		}
+	}
+
+	#[test]
+	fn stores_and_serves_chunks_and_pov() {
+		let pool = ThreadPool::new().unwrap();
+		let (ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+		let subsystem = run(InnerStore::new_in_memory(), ctx);
+
+		let relay_parent = Hash::repeat_byte(1);
+		let candidate_hash = candidate(1, relay_parent).hash();
+		let data = available_data(&[1, 2, 3]);
+
+		let test_fut = async move {
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::StoreChunk(candidate_hash, relay_parent, 2, chunk(2)),
+			}).await;
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::StoreChunk(candidate_hash, relay_parent, 0, chunk(0)),
+			}).await;
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::StoreChunk(candidate_hash, relay_parent, 2, chunk(2)),
+			}).await;
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::StoreAvailableData(
+					candidate_hash,
+					relay_parent,
+					data.clone(),
+				),
+			}).await;
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::QueryAvailableChunks(candidate_hash, tx),
+			}).await;
+			assert_eq!(rx.await.unwrap(), vec![2, 0]);
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::QueryChunk(candidate_hash, 2, tx),
+			}).await;
+			assert_eq!(rx.await.unwrap(), Some(chunk(2)));
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::QueryChunk(candidate_hash, 1, tx),
+			}).await;
+			assert_eq!(rx.await.unwrap(), None);
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::QueryPoV(candidate_hash, tx),
+			}).await;
+			assert_eq!(rx.await.unwrap(), Some(data.pov));
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(subsystem, test_fut)).0.unwrap();
+	}
+
+	#[test]
+	fn finality_prunes_candidates_which_were_not_backed() {
+		let pool = ThreadPool::new().unwrap();
+		let (ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+		let subsystem = run(InnerStore::new_in_memory(), ctx);
+
+		let parent = Hash::repeat_byte(1);
+		let block = Hash::repeat_byte(2);
+
+		let backed = candidate(1, parent);
+		let orphaned = candidate(2, parent);
+		let backed_hash = backed.hash();
+		let orphaned_hash = orphaned.hash();
+
+		let test_fut = async move {
+			for hash in vec![backed_hash, orphaned_hash] {
+				handle.send(FromOverseer::Communication {
+					msg: AvailabilityStoreMessage::StoreChunk(hash, parent, 0, chunk(0)),
+				}).await;
+			}
+
+			// The first finalized block is only noted.
+			handle.send(FromOverseer::Signal(OverseerSignal::BlockFinalized(parent))).await;
+			handle.send(FromOverseer::Signal(OverseerSignal::BlockFinalized(block))).await;
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::ValidatorGroups(tx),
+				)) => {
+					assert_eq!(relay_parent, block);
+					tx.send(SchedulerRoster {
+						validator_groups: vec![vec![0], vec![1]],
+						scheduled: vec![],
+						upcoming: vec![1.into(), 2.into()],
+						availability_cores: vec![Some(CoreOccupied::Parachain), None],
+					}).unwrap();
+				}
+			);
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::CandidatePendingAvailability(para, tx),
+				)) => {
+					assert_eq!(relay_parent, block);
+					assert_eq!(para, 1.into());
+					tx.send(Some(backed)).unwrap();
+				}
+			);
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::QueryChunk(backed_hash, 0, tx),
+			}).await;
+			assert_eq!(rx.await.unwrap(), Some(chunk(0)));
+
+			let (tx, rx) = oneshot::channel();
+			handle.send(FromOverseer::Communication {
+				msg: AvailabilityStoreMessage::QueryChunk(orphaned_hash, 0, tx),
+			}).await;
+			assert_eq!(rx.await.unwrap(), None);
+
+			handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		};
+
+		executor::block_on(future::join(subsystem, test_fut)).0.unwrap();
+	}
+}
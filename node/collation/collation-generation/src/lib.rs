@@ -0,0 +1,446 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Collation Generation subsystem.
+//!
+//! This is the interface between a collator and the rest of the node. Once initialized with a
+//! [`CollationGenerationConfig`], it calls the collation function for every core scheduled to
+//! the collator's para on each new relay-parent, turns the output into a signed
+//! `CandidateReceipt` and hands it over to the Collator Protocol subsystem for distribution.
+//!
+//! [`CollationGenerationConfig`]: ../polkadot_node_primitives/struct.CollationGenerationConfig.html
+
+use std::sync::Arc;
+
+use futures::{
+	channel::{mpsc, oneshot},
+	pin_mut, select, FutureExt, SinkExt, StreamExt,
+};
+use sp_core::Pair;
+
+use polkadot_subsystem::{
+	Subsystem, SubsystemContext, SpawnedSubsystem, SubsystemResult,
+	FromOverseer, OverseerSignal,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, CollationGenerationMessage, CollatorProtocolMessage, RuntimeApiMessage,
+	RuntimeApiRequest,
+};
+use polkadot_node_primitives::{Collation, CollationGenerationConfig};
+use polkadot_primitives::v1::{
+	Hash, CandidateCommitments, CandidateDescriptor, CandidateReceipt, PoV, AvailableData,
+	OmittedValidationData, GlobalValidationSchedule, LocalValidationData,
+	collator_signature_payload,
+};
+
+const LOG_TARGET: &str = "collation_generation";
+
+/// The Collation Generation subsystem.
+pub struct CollationGenerationSubsystem;
+
+impl<C> Subsystem<C> for CollationGenerationSubsystem
+	where C: SubsystemContext<Message = CollationGenerationMessage>
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		SpawnedSubsystem(Box::pin(async move {
+			if let Err(e) = run(ctx).await {
+				log::error!(target: LOG_TARGET, "Collation generation subsystem exited with an error: {:?}", e);
+			}
+		}))
+	}
+}
+
+/// Either something coming from the overseer or a collation built by a spawned task.
+enum Incoming {
+	Overseer(FromOverseer<CollationGenerationMessage>),
+	Collation(AllMessages),
+}
+
+async fn run(
+	mut ctx: impl SubsystemContext<Message = CollationGenerationMessage>,
+) -> SubsystemResult<()> {
+	let mut config: Option<Arc<CollationGenerationConfig>> = None;
+
+	// Collation functions run in their own tasks and send the resulting messages back here,
+	// as only the main loop has access to the context.
+	let (collations_tx, mut collations_rx) = mpsc::channel(0);
+
+	loop {
+		let incoming = {
+			let from_overseer = ctx.recv().fuse();
+			let from_collators = collations_rx.next().fuse();
+			pin_mut!(from_overseer, from_collators);
+
+			select! {
+				msg = from_overseer => Incoming::Overseer(msg?),
+				msg = from_collators => match msg {
+					Some(msg) => Incoming::Collation(msg),
+					// we hold a sender ourselves, so this cannot happen.
+					None => continue,
+				},
+			}
+		};
+
+		match incoming {
+			Incoming::Overseer(FromOverseer::Signal(OverseerSignal::StartWork(relay_parent))) => {
+				if let Some(ref config) = config {
+					handle_new_activation(&mut ctx, config.clone(), relay_parent, collations_tx.clone()).await?;
+				}
+			}
+			Incoming::Overseer(FromOverseer::Signal(OverseerSignal::StopWork(_))) => {}
+			Incoming::Overseer(FromOverseer::Signal(OverseerSignal::BlockFinalized(_))) => {}
+			Incoming::Overseer(FromOverseer::Signal(OverseerSignal::Conclude)) => return Ok(()),
+			Incoming::Overseer(FromOverseer::Communication { msg }) => match msg {
+				CollationGenerationMessage::Initialize(new_config) => {
+					if config.is_some() {
+						log::warn!(target: LOG_TARGET, "Collation generation subsystem initialized more than once");
+					} else {
+						config = Some(Arc::new(new_config));
+					}
+				}
+			},
+			Incoming::Collation(msg) => ctx.send_message(msg).await?,
+		}
+	}
+}
+
+async fn runtime_api_request<T>(
+	ctx: &mut impl SubsystemContext<Message = CollationGenerationMessage>,
+	relay_parent: Hash,
+	request: RuntimeApiRequest,
+	receiver: oneshot::Receiver<T>,
+) -> SubsystemResult<T> {
+	ctx.send_message(
+		AllMessages::RuntimeApi(RuntimeApiMessage::Request(relay_parent, request))
+	).await?;
+
+	receiver.await.map_err(Into::into)
+}
+
+/// Spawn a collation task for every core scheduled to our para in a child of the
+/// given relay-parent, validated under the data predicted for that core.
+async fn handle_new_activation(
+	ctx: &mut impl SubsystemContext<Message = CollationGenerationMessage>,
+	config: Arc<CollationGenerationConfig>,
+	relay_parent: Hash,
+	sender: mpsc::Sender<AllMessages>,
+) -> SubsystemResult<()> {
+	let availability_cores = {
+		let (tx, rx) = oneshot::channel();
+		runtime_api_request(ctx, relay_parent, RuntimeApiRequest::AvailabilityCores(tx), rx).await?
+	};
+
+	let scheduled: Vec<OmittedValidationData> = availability_cores.iter()
+		.filter_map(|core| core.scheduled())
+		.filter(|scheduled| scheduled.assignment.para_id == config.para_id)
+		.map(|scheduled| scheduled.omitted_validation.clone())
+		.collect();
+
+	if scheduled.is_empty() {
+		return Ok(());
+	}
+
+	let n_validators = {
+		let (tx, rx) = oneshot::channel();
+		runtime_api_request(ctx, relay_parent, RuntimeApiRequest::Validators(tx), rx).await?.len()
+	};
+
+	for omitted_validation in scheduled {
+		let task = collate(
+			config.clone(),
+			relay_parent,
+			omitted_validation.global_validation,
+			omitted_validation.local_validation,
+			n_validators,
+			sender.clone(),
+		);
+
+		ctx.spawn(Box::pin(task)).await?;
+	}
+
+	Ok(())
+}
+
+/// Run the collation function and send the resulting candidate off to the collator protocol.
+async fn collate(
+	config: Arc<CollationGenerationConfig>,
+	relay_parent: Hash,
+	global_validation: GlobalValidationSchedule,
+	local_validation: LocalValidationData,
+	n_validators: usize,
+	mut sender: mpsc::Sender<AllMessages>,
+) {
+	let collation = match (config.collator)(&global_validation, &local_validation).await {
+		Some(collation) => collation,
+		None => {
+			log::debug!(target: LOG_TARGET, "Collator produced no collation at {}", relay_parent);
+			return;
+		}
+	};
+
+	let (receipt, pov) = match make_candidate(
+		&config,
+		relay_parent,
+		collation,
+		global_validation,
+		local_validation,
+		n_validators,
+	) {
+		Ok(candidate) => candidate,
+		Err(e) => {
+			log::warn!(target: LOG_TARGET, "Failed to build candidate at {}: {:?}", relay_parent, e);
+			return;
+		}
+	};
+
+	let msg = AllMessages::CollatorProtocol(
+		CollatorProtocolMessage::DistributeCollation(receipt, pov)
+	);
+
+	if let Err(e) = sender.send(msg).await {
+		log::warn!(target: LOG_TARGET, "Failed to send collation for {}: {:?}", relay_parent, e);
+	}
+}
+
+/// Compute the commitments of a collation and sign the candidate descriptor.
+fn make_candidate(
+	config: &CollationGenerationConfig,
+	relay_parent: Hash,
+	collation: Collation,
+	global_validation: GlobalValidationSchedule,
+	local_validation: LocalValidationData,
+	n_validators: usize,
+) -> Result<(CandidateReceipt, PoV), erasure_coding::Error> {
+	let pov = collation.proof_of_validity;
+	let pov_hash = pov.hash();
+
+	let erasure_root = {
+		let available_data = AvailableData {
+			pov: pov.clone(),
+			omitted_validation: OmittedValidationData {
+				global_validation,
+				local_validation,
+			},
+		};
+
+		let chunks = erasure_coding::obtain_chunks_v1(n_validators, &available_data)?;
+		erasure_coding::branches(&chunks).root()
+	};
+
+	let commitments = CandidateCommitments {
+		fees: collation.fees,
+		upward_messages: collation.upward_messages,
+		erasure_root,
+		new_validation_code: collation.new_validation_code,
+		head_data: collation.head_data,
+		processed_downward_messages: collation.processed_downward_messages,
+		horizontal_messages: collation.horizontal_messages,
+		hrmp_watermark: collation.hrmp_watermark,
+	};
+
+	let signature = config.key.sign(&collator_signature_payload(
+		&relay_parent,
+		&config.para_id,
+		&pov_hash,
+	));
+
+	let receipt = CandidateReceipt {
+		descriptor: CandidateDescriptor {
+			para_id: config.para_id,
+			relay_parent,
+			collator: config.key.public(),
+			signature,
+			pov_hash,
+		},
+		commitments_hash: commitments.hash(),
+	};
+
+	Ok((receipt, pov))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{executor, future};
+	use assert_matches::assert_matches;
+	use polkadot_primitives::v1::{
+		BlockData, HeadData, Id as ParaId, CollatorPair, CoreAssignment, CoreIndex, GroupIndex,
+		AssignmentKind, CoreState, ScheduledCore, ValidatorId,
+	};
+
+	fn test_collation() -> Collation {
+		Collation {
+			fees: 0,
+			upward_messages: Vec::new(),
+			new_validation_code: None,
+			head_data: HeadData(vec![1, 2, 3]),
+			processed_downward_messages: 0,
+			horizontal_messages: Vec::new(),
+			hrmp_watermark: 0,
+			proof_of_validity: PoV { block_data: BlockData(vec![4, 5, 6]) },
+		}
+	}
+
+	fn test_config(para_id: ParaId) -> Arc<CollationGenerationConfig> {
+		Arc::new(CollationGenerationConfig {
+			key: Arc::new(CollatorPair::generate().0),
+			collator: Box::new(|_, _| Box::new(future::ready(Some(test_collation())))),
+			para_id,
+		})
+	}
+
+	fn cores_for(para_ids: &[ParaId]) -> Vec<CoreState> {
+		para_ids.iter().enumerate().map(|(i, para_id)| CoreState::Free(Some(ScheduledCore {
+			assignment: CoreAssignment {
+				core: CoreIndex(i as _),
+				para_id: *para_id,
+				kind: AssignmentKind::Parachain,
+				group_idx: GroupIndex(i as _),
+			},
+			omitted_validation: OmittedValidationData {
+				global_validation: global_validation(),
+				local_validation: local_validation(),
+			},
+		}))).collect()
+	}
+
+	fn global_validation() -> GlobalValidationSchedule {
+		GlobalValidationSchedule {
+			max_code_size: 1024,
+			max_head_data_size: 1024,
+			block_number: 5,
+		}
+	}
+
+	fn local_validation() -> LocalValidationData {
+		LocalValidationData {
+			parent_head: HeadData(vec![7, 8, 9]),
+			balance: 0,
+			validation_code_hash: Default::default(),
+			code_upgrade_allowed: None,
+			downward_messages_hash: Default::default(),
+			inbound_hrmp_messages: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn collates_for_scheduled_core() {
+		let pool = executor::ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context::<CollationGenerationMessage, _>(pool);
+
+		let para_id = ParaId::from(100);
+		let relay_parent = Hash::repeat_byte(1);
+		let config = test_config(para_id);
+		let n_validators = 5;
+		let (tx, mut rx) = mpsc::channel(0);
+
+		let activation = handle_new_activation(&mut ctx, config.clone(), relay_parent, tx);
+
+		let responder = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					r,
+					RuntimeApiRequest::AvailabilityCores(tx),
+				)) if r == relay_parent => {
+					tx.send(cores_for(&[ParaId::from(1), para_id])).unwrap();
+				}
+			);
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					r,
+					RuntimeApiRequest::Validators(tx),
+				)) if r == relay_parent => {
+					tx.send(vec![ValidatorId::default(); n_validators]).unwrap();
+				}
+			);
+		};
+
+		let (res, _) = executor::block_on(future::join(activation, responder));
+		res.unwrap();
+
+		let msg = executor::block_on(rx.next()).unwrap();
+		let expected_pov = test_collation().proof_of_validity;
+
+		assert_matches!(
+			msg,
+			AllMessages::CollatorProtocol(
+				CollatorProtocolMessage::DistributeCollation(receipt, pov)
+			) => {
+				assert_eq!(pov, expected_pov);
+				assert_eq!(receipt.descriptor.para_id, para_id);
+				assert_eq!(receipt.descriptor.relay_parent, relay_parent);
+				assert_eq!(receipt.descriptor.pov_hash, expected_pov.hash());
+				assert_eq!(receipt.descriptor.collator, config.key.public());
+				assert!(receipt.descriptor.check_collator_signature().is_ok());
+
+				let available_data = AvailableData {
+					pov: expected_pov,
+					omitted_validation: OmittedValidationData {
+						global_validation: global_validation(),
+						local_validation: local_validation(),
+					},
+				};
+				let chunks = erasure_coding::obtain_chunks_v1(n_validators, &available_data).unwrap();
+				let collation = test_collation();
+				let commitments = CandidateCommitments {
+					fees: collation.fees,
+					upward_messages: collation.upward_messages,
+					erasure_root: erasure_coding::branches(&chunks).root(),
+					new_validation_code: collation.new_validation_code,
+					head_data: collation.head_data,
+					processed_downward_messages: collation.processed_downward_messages,
+					horizontal_messages: collation.horizontal_messages,
+					hrmp_watermark: collation.hrmp_watermark,
+				};
+
+				assert_eq!(receipt.commitments_hash, commitments.hash());
+			}
+		);
+	}
+
+	#[test]
+	fn does_not_collate_when_not_scheduled() {
+		let pool = executor::ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context::<CollationGenerationMessage, _>(pool);
+
+		let relay_parent = Hash::repeat_byte(1);
+		let config = test_config(ParaId::from(100));
+		let (tx, mut rx) = mpsc::channel(0);
+
+		let activation = handle_new_activation(&mut ctx, config, relay_parent, tx);
+
+		let responder = async move {
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					r,
+					RuntimeApiRequest::AvailabilityCores(tx),
+				)) if r == relay_parent => {
+					tx.send(cores_for(&[ParaId::from(1), ParaId::from(2)])).unwrap();
+				}
+			);
+		};
+
+		let (res, _) = executor::block_on(future::join(activation, responder));
+		res.unwrap();
+
+		// all senders are gone, so the channel is closed without any collations.
+		assert!(executor::block_on(rx.next()).is_none());
+	}
+}
@@ -53,7 +53,7 @@ use polkadot_subsystem::{
 use polkadot_subsystem::messages::{
 	AllMessages, CandidateBackingMessage, CandidateSelectionMessage, SchedulerRoster,
 	RuntimeApiMessage, RuntimeApiRequest, CandidateValidationMessage, ValidationFailed,
-	StatementDistributionMessage, NewBackedCandidate, ProvisionerMessage, ProvisionableData,
+	StatementDistributionMessage, NewBackedCandidate, MisbehaviorArbitrationMessage,
 	PoVDistributionMessage, AvailabilityStoreMessage,
 };
 use statement_table::{
@@ -174,7 +174,7 @@ enum FromJob {
 	RuntimeApiMessage(RuntimeApiMessage),
 	CandidateValidation(CandidateValidationMessage),
 	CandidateSelection(CandidateSelectionMessage),
-	Provisioner(ProvisionerMessage),
+	MisbehaviorArbitration(MisbehaviorArbitrationMessage),
 	PoVDistribution(PoVDistributionMessage),
 	StatementDistribution(StatementDistributionMessage),
 }
@@ -188,7 +188,7 @@ impl From<FromJob> for AllMessages {
 			FromJob::CandidateSelection(msg) => AllMessages::CandidateSelection(msg),
 			FromJob::StatementDistribution(msg) => AllMessages::StatementDistribution(msg),
 			FromJob::PoVDistribution(msg) => AllMessages::PoVDistribution(msg),
-			FromJob::Provisioner(msg) => AllMessages::Provisioner(msg),
+			FromJob::MisbehaviorArbitration(msg) => AllMessages::MisbehaviorArbitration(msg),
 		}
 	}
 }
@@ -269,6 +269,7 @@ impl CandidateBackingJob {
 				// If the commitments hash produced by validation is not the same as given by
 				// the collator, do not make available and report the collator.
 				let commitments_check = self.make_pov_available(
+					candidate_hash,
 					pov,
 					outputs,
 					|commitments| if commitments.hash() == candidate.commitments_hash {
@@ -370,9 +371,7 @@ impl CandidateBackingJob {
 				};
 
 				if let Ok(report) = MisbehaviorReport::try_from(f) {
-					let message = ProvisionerMessage::ProvisionableData(
-						ProvisionableData::MisbehaviorReport(self.parent, report)
-					);
+					let message = MisbehaviorArbitrationMessage::Report(self.parent, report);
 
 					reports.push(message);
 				}
@@ -380,7 +379,7 @@ impl CandidateBackingJob {
 		}
 
 		for report in reports.drain(..) {
-			self.send_to_provisioner(report).await?
+			self.send_to_misbehavior_arbitration(report).await?
 		}
 
 		Ok(())
@@ -468,6 +467,7 @@ impl CandidateBackingJob {
 			ValidationResult::Valid(outputs) => {
 				// If validation produces a new set of commitments, we vote the candidate as invalid.
 				let commitments_check = self.make_pov_available(
+					candidate_hash,
 					(&*pov).clone(),
 					outputs,
 					|commitments| if commitments == expected_commitments {
@@ -542,8 +542,11 @@ impl CandidateBackingJob {
 		Ok(())
 	}
 
-	async fn send_to_provisioner(&mut self, msg: ProvisionerMessage) -> Result<(), Error> {
-		self.tx_from.send(FromJob::Provisioner(msg)).await?;
+	async fn send_to_misbehavior_arbitration(
+		&mut self,
+		msg: MisbehaviorArbitrationMessage,
+	) -> Result<(), Error> {
+		self.tx_from.send(FromJob::MisbehaviorArbitration(msg)).await?;
 
 		Ok(())
 	}
@@ -580,13 +583,27 @@ impl CandidateBackingJob {
 		Ok(rx.await??)
 	}
 
+	async fn store_available_data(
+		&mut self,
+		candidate_hash: Hash,
+		available_data: AvailableData,
+	) -> Result<(), Error> {
+		self.tx_from.send(FromJob::AvailabilityStore(
+				AvailabilityStoreMessage::StoreAvailableData(candidate_hash, self.parent, available_data)
+			)
+		).await?;
+
+		Ok(())
+	}
+
 	async fn store_chunk(
 		&mut self,
+		candidate_hash: Hash,
 		id: ValidatorIndex,
 		chunk: ErasureChunk,
 	) -> Result<(), Error> {
 		self.tx_from.send(FromJob::AvailabilityStore(
-				AvailabilityStoreMessage::StoreChunk(self.parent, id, chunk)
+				AvailabilityStoreMessage::StoreChunk(candidate_hash, self.parent, id, chunk)
 			)
 		).await?;
 
@@ -600,6 +617,7 @@ impl CandidateBackingJob {
 	// early without making the PoV available.
 	async fn make_pov_available<T, E>(
 		&mut self,
+		candidate_hash: Hash,
 		pov: PoV,
 		outputs: ValidationOutputs,
 		with_commitments: impl FnOnce(CandidateCommitments) -> Result<T, E>,
@@ -628,6 +646,9 @@ impl CandidateBackingJob {
 			erasure_root,
 			new_validation_code: outputs.new_validation_code,
 			head_data: outputs.head_data,
+			processed_downward_messages: outputs.processed_downward_messages,
+			horizontal_messages: outputs.horizontal_messages,
+			hrmp_watermark: outputs.hrmp_watermark,
 		};
 
 		let res = match with_commitments(commitments) {
@@ -635,6 +656,8 @@ impl CandidateBackingJob {
 			Err(e) => return Ok(Err(e)),
 		};
 
+		self.store_available_data(candidate_hash, available_data).await?;
+
 		for (index, (proof, chunk)) in branches.enumerate() {
 			let chunk = ErasureChunk {
 				chunk: chunk.to_vec(),
@@ -642,7 +665,7 @@ impl CandidateBackingJob {
 				proof,
 			};
 
-			self.store_chunk(index as ValidatorIndex, chunk).await?;
+			self.store_chunk(candidate_hash, index as ValidatorIndex, chunk).await?;
 		}
 
 		Ok(Ok(res))
@@ -1073,6 +1096,8 @@ mod tests {
 				balance: Default::default(),
 				code_upgrade_allowed: None,
 				validation_code_hash: Default::default(),
+				downward_messages_hash: Default::default(),
+				inbound_hrmp_messages: Vec::new(),
 			};
 
 			let global_validation_schedule = GlobalValidationSchedule {
@@ -1252,16 +1277,26 @@ mod tests {
 							upward_messages: Vec::new(),
 							fees: Default::default(),
 							new_validation_code: None,
+							processed_downward_messages: 0,
+							horizontal_messages: Vec::new(),
+							hrmp_watermark: 0,
 						}),
 					)).unwrap();
 				}
 			);
 
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::AvailabilityStore(
+					AvailabilityStoreMessage::StoreAvailableData(_, parent_hash, _)
+				) if parent_hash == test_state.relay_parent
+			);
+
 			for _ in 0..test_state.validators.len() {
 				assert_matches!(
 					virtual_overseer.recv().await,
 					AllMessages::AvailabilityStore(
-						AvailabilityStoreMessage::StoreChunk(parent_hash, _, _)
+						AvailabilityStoreMessage::StoreChunk(_, parent_hash, _, _)
 					) if parent_hash == test_state.relay_parent
 				);
 			}
@@ -1364,6 +1399,9 @@ mod tests {
 							upward_messages: Vec::new(),
 							fees: Default::default(),
 							new_validation_code: None,
+							processed_downward_messages: 0,
+							horizontal_messages: Vec::new(),
+							hrmp_watermark: 0,
 						}),
 					)).unwrap();
 				}
@@ -1485,16 +1523,26 @@ mod tests {
 							upward_messages: Vec::new(),
 							fees: Default::default(),
 							new_validation_code: None,
+							processed_downward_messages: 0,
+							horizontal_messages: Vec::new(),
+							hrmp_watermark: 0,
 						}),
 					)).unwrap();
 				}
 			);
 
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::AvailabilityStore(
+					AvailabilityStoreMessage::StoreAvailableData(_, parent_hash, _)
+				) if parent_hash == test_state.relay_parent
+			);
+
 			for _ in 0..test_state.validators.len() {
 				assert_matches!(
 					virtual_overseer.recv().await,
 					AllMessages::AvailabilityStore(
-						AvailabilityStoreMessage::StoreChunk(parent_hash, _, _)
+						AvailabilityStoreMessage::StoreChunk(_, parent_hash, _, _)
 					) if parent_hash == test_state.relay_parent
 				);
 			}
@@ -1526,12 +1574,10 @@ mod tests {
 
 			assert_matches!(
 				virtual_overseer.recv().await,
-				AllMessages::Provisioner(
-					ProvisionerMessage::ProvisionableData(
-						ProvisionableData::MisbehaviorReport(
-							relay_parent,
-							MisbehaviorReport::SelfContradiction(_, s1, s2),
-						)
+				AllMessages::MisbehaviorArbitration(
+					MisbehaviorArbitrationMessage::Report(
+						relay_parent,
+						MisbehaviorReport::SelfContradiction(_, s1, s2),
 					)
 				) if relay_parent == test_state.relay_parent => {
 					s1.check_signature(
@@ -1645,16 +1691,26 @@ mod tests {
 							upward_messages: Vec::new(),
 							fees: Default::default(),
 							new_validation_code: None,
+							processed_downward_messages: 0,
+							horizontal_messages: Vec::new(),
+							hrmp_watermark: 0,
 						}),
 					)).unwrap();
 				}
 			);
 
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::AvailabilityStore(
+					AvailabilityStoreMessage::StoreAvailableData(_, parent_hash, _)
+				) if parent_hash == test_state.relay_parent
+			);
+
 			for _ in 0..test_state.validators.len() {
 				assert_matches!(
 					virtual_overseer.recv().await,
 					AllMessages::AvailabilityStore(
-						AvailabilityStoreMessage::StoreChunk(parent_hash, _, _)
+						AvailabilityStoreMessage::StoreChunk(_, parent_hash, _, _)
 					) if parent_hash == test_state.relay_parent
 				);
 			}
@@ -0,0 +1,659 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The bitfield signing subsystem produces `SignedAvailabilityBitfield`s once per block.
+//!
+//! For every relay-parent it waits for a while, to give availability distribution a chance to
+//! fetch our chunks, and then attests to the candidates pending availability on top of that
+//! relay-parent for which our chunk is in the availability store.
+
+#![recursion_limit="256"]
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::time::Duration;
+
+use bitvec::vec::BitVec;
+use log;
+use futures::{
+	select, FutureExt, SinkExt, StreamExt,
+	channel::{oneshot, mpsc},
+	future::{self, Either},
+	task::{Spawn, SpawnError, SpawnExt},
+};
+use futures_timer::Delay;
+use streamunordered::{StreamUnordered, StreamYield};
+
+use keystore::KeyStorePtr;
+use polkadot_primitives::v1::{
+	AvailabilityBitfield, CommittedCandidateReceipt, CoreOccupied, Hash, Id as ParaId,
+	SignedAvailabilityBitfield, SigningContext, ValidatorId, ValidatorIndex, ValidatorPair,
+};
+use polkadot_subsystem::{
+	FromOverseer, OverseerSignal, Subsystem, SubsystemContext, SpawnedSubsystem,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, AvailabilityStoreMessage, BitfieldDistributionMessage, BitfieldSigningMessage,
+	ProvisionableData, ProvisionerMessage, RuntimeApiMessage, RuntimeApiRequest, SchedulerRoster,
+};
+
+/// The default time a job waits after its relay-parent is activated before signing a bitfield.
+pub const JOB_DELAY: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, derive_more::From)]
+enum Error {
+	JobNotFound(Hash),
+	#[from]
+	Oneshot(oneshot::Canceled),
+	#[from]
+	Mpsc(mpsc::SendError),
+	#[from]
+	Spawn(SpawnError),
+}
+
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A message type that is sent from `BitfieldSigningSubsystem` to a bitfield signing job.
+enum ToJob {
+	/// Stop working.
+	Stop,
+}
+
+/// A message type that is sent from a bitfield signing job to `BitfieldSigningSubsystem`.
+enum FromJob {
+	AvailabilityStore(AvailabilityStoreMessage),
+	BitfieldDistribution(BitfieldDistributionMessage),
+	Provisioner(ProvisionerMessage),
+	RuntimeApiMessage(RuntimeApiMessage),
+}
+
+impl From<FromJob> for AllMessages {
+	fn from(f: FromJob) -> Self {
+		match f {
+			FromJob::AvailabilityStore(msg) => AllMessages::AvailabilityStore(msg),
+			FromJob::BitfieldDistribution(msg) => AllMessages::BitfieldDistribution(msg),
+			FromJob::Provisioner(msg) => AllMessages::Provisioner(msg),
+			FromJob::RuntimeApiMessage(msg) => AllMessages::RuntimeApi(msg),
+		}
+	}
+}
+
+/// Find the key of the local validator within the validator set, along with its index.
+fn signing_key(
+	validators: &[ValidatorId],
+	keystore: &KeyStorePtr,
+) -> Option<(ValidatorPair, ValidatorIndex)> {
+	let keystore = keystore.read();
+	validators.iter()
+		.enumerate()
+		.find_map(|(i, v)| {
+			keystore.key_pair::<ValidatorPair>(&v).ok().map(|pair| (pair, i as ValidatorIndex))
+		})
+}
+
+/// The para occupying the given availability core, if any.
+///
+/// Parachains are assigned to cores in the order in which they are upcoming.
+fn occupying_para(roster: &SchedulerRoster, core: usize) -> Option<ParaId> {
+	match roster.availability_cores.get(core)? {
+		Some(CoreOccupied::Parachain) => roster.upcoming.get(core).cloned(),
+		Some(CoreOccupied::Parathread(entry)) => Some(entry.claim.0),
+		None => None,
+	}
+}
+
+/// Whether the local validator's chunk of the candidate pending availability on the given
+/// core is in the availability store.
+///
+/// Unoccupied cores, and cores whose candidate could not be determined, are never available.
+async fn chunk_available(
+	parent: Hash,
+	roster: &SchedulerRoster,
+	core: usize,
+	validator_index: ValidatorIndex,
+	mut sender: mpsc::Sender<FromJob>,
+) -> Result<bool, Error> {
+	let para = match occupying_para(roster, core) {
+		Some(para) => para,
+		None => return Ok(false),
+	};
+
+	let candidate = match request_candidate_pending_availability(parent, para, &mut sender)
+		.await?
+		.await
+	{
+		Ok(Some(candidate)) => candidate,
+		_ => return Ok(false),
+	};
+
+	let (tx, rx) = oneshot::channel();
+	sender.send(FromJob::AvailabilityStore(
+		AvailabilityStoreMessage::QueryChunk(candidate.hash(), validator_index, tx)
+	)).await?;
+
+	Ok(rx.await.ok().flatten().is_some())
+}
+
+async fn run_job(
+	parent: Hash,
+	keystore: KeyStorePtr,
+	delay: Duration,
+	mut rx_to: mpsc::Receiver<ToJob>,
+	mut tx_from: mpsc::Sender<FromJob>,
+) -> Result<(), Error> {
+	// Give availability distribution some time to fetch our chunks, unless we're
+	// told to stop before that.
+	if let Either::Right(_) = future::select(Delay::new(delay), rx_to.next()).await {
+		return Ok(());
+	}
+
+	let (validators, roster, signing_context) = futures::try_join!(
+		request_validators(parent, &mut tx_from).await?,
+		request_validator_groups(parent, &mut tx_from).await?,
+		request_signing_context(parent, &mut tx_from).await?,
+	)?;
+
+	let (key, validator_index) = match signing_key(&validators[..], &keystore) {
+		Some(key) => key,
+		// Not a validator at this relay-parent: nothing to attest to.
+		None => return Ok(()),
+	};
+
+	let availability = future::try_join_all(
+		(0..roster.availability_cores.len())
+			.map(|core| chunk_available(parent, &roster, core, validator_index, tx_from.clone()))
+	).await?;
+
+	let bitfield: BitVec<_, _> = availability.into_iter().collect();
+	let signed_bitfield = SignedAvailabilityBitfield::sign(
+		AvailabilityBitfield(bitfield),
+		&signing_context,
+		validator_index,
+		&key,
+	);
+
+	tx_from.send(FromJob::BitfieldDistribution(
+		BitfieldDistributionMessage::DistributeBitfield(parent, signed_bitfield.clone())
+	)).await?;
+
+	tx_from.send(FromJob::Provisioner(ProvisionerMessage::ProvisionableData(
+		ProvisionableData::Bitfield(parent, signed_bitfield)
+	))).await?;
+
+	Ok(())
+}
+
+/// Request a validator set from the `RuntimeApi`.
+async fn request_validators(
+	parent: Hash,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<Vec<ValidatorId>>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::Validators(tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+/// Request the scheduler roster from `RuntimeApi`.
+async fn request_validator_groups(
+	parent: Hash,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<SchedulerRoster>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::ValidatorGroups(tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+/// Request a `SigningContext` from the `RuntimeApi`.
+async fn request_signing_context(
+	parent: Hash,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<SigningContext>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::SigningContext(tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+/// Request the candidate pending availability for a para from the `RuntimeApi`.
+async fn request_candidate_pending_availability(
+	parent: Hash,
+	para: ParaId,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<Option<CommittedCandidateReceipt>>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::CandidatePendingAvailability(para, tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+struct JobHandle {
+	abort_handle: future::AbortHandle,
+	to_job: mpsc::Sender<ToJob>,
+	finished: oneshot::Receiver<()>,
+	su_handle: usize,
+}
+
+impl JobHandle {
+	async fn stop(mut self) {
+		let _ = self.to_job.send(ToJob::Stop).await;
+		let stop_timer = Delay::new(Duration::from_secs(1));
+
+		match future::select(stop_timer, self.finished).await {
+			Either::Left((_, _)) => {
+			},
+			Either::Right((_, _)) => {
+				self.abort_handle.abort();
+			},
+		}
+	}
+}
+
+struct Jobs<S> {
+	spawner: S,
+	running: HashMap<Hash, JobHandle>,
+	outgoing_msgs: StreamUnordered<mpsc::Receiver<FromJob>>,
+}
+
+impl<S: Spawn> Jobs<S> {
+	fn new(spawner: S) -> Self {
+		Self {
+			spawner,
+			running: HashMap::default(),
+			outgoing_msgs: StreamUnordered::new(),
+		}
+	}
+
+	fn spawn_job(
+		&mut self,
+		parent_hash: Hash,
+		keystore: KeyStorePtr,
+		delay: Duration,
+	) -> Result<(), Error> {
+		let (to_job_tx, to_job_rx) = mpsc::channel(CHANNEL_CAPACITY);
+		let (from_job_tx, from_job_rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+		let (future, abort_handle) = future::abortable(async move {
+			if let Err(e) = run_job(parent_hash, keystore, delay, to_job_rx, from_job_tx).await {
+				log::error!(
+					"BitfieldSigningJob({}) finished with an error {:?}",
+					parent_hash,
+					e,
+				);
+			}
+		});
+
+		let (finished_tx, finished) = oneshot::channel();
+
+		let future = async move {
+			let _ = future.await;
+			let _ = finished_tx.send(());
+		};
+		self.spawner.spawn(future)?;
+
+		let su_handle = self.outgoing_msgs.push(from_job_rx);
+
+		let handle = JobHandle {
+			abort_handle,
+			to_job: to_job_tx,
+			finished,
+			su_handle,
+		};
+
+		self.running.insert(parent_hash, handle);
+
+		Ok(())
+	}
+
+	async fn stop_job(&mut self, parent_hash: Hash) -> Result<(), Error> {
+		match self.running.remove(&parent_hash) {
+			Some(handle) => {
+				Pin::new(&mut self.outgoing_msgs).remove(handle.su_handle);
+				handle.stop().await;
+				Ok(())
+			}
+			None => Err(Error::JobNotFound(parent_hash))
+		}
+	}
+
+	async fn next(&mut self) -> Option<FromJob> {
+		self.outgoing_msgs.next().await.and_then(|(e, _)| match e {
+			StreamYield::Item(e) => Some(e),
+			_ => None,
+		})
+	}
+}
+
+/// An implementation of the Bitfield Signing subsystem.
+pub struct BitfieldSigningSubsystem<S, Context> {
+	spawner: S,
+	keystore: KeyStorePtr,
+	delay: Duration,
+	_context: std::marker::PhantomData<Context>,
+}
+
+impl<S, Context> BitfieldSigningSubsystem<S, Context>
+	where
+		S: Spawn + Clone,
+		Context: SubsystemContext<Message=BitfieldSigningMessage>,
+{
+	/// Creates a new `BitfieldSigningSubsystem`, which signs a bitfield `delay` after
+	/// each relay-parent is activated.
+	pub fn new(keystore: KeyStorePtr, spawner: S, delay: Duration) -> Self {
+		Self {
+			spawner,
+			keystore,
+			delay,
+			_context: std::marker::PhantomData,
+		}
+	}
+
+	async fn run(
+		mut ctx: Context,
+		keystore: KeyStorePtr,
+		spawner: S,
+		delay: Duration,
+	) {
+		let mut jobs = Jobs::new(spawner.clone());
+
+		loop {
+			select! {
+				incoming = ctx.recv().fuse() => {
+					match incoming {
+						Ok(msg) => match msg {
+							FromOverseer::Signal(OverseerSignal::StartWork(hash)) => {
+								if let Err(e) = jobs.spawn_job(hash, keystore.clone(), delay) {
+									log::error!("Failed to spawn a job: {:?}", e);
+									break;
+								}
+							}
+							FromOverseer::Signal(OverseerSignal::StopWork(hash)) => {
+								if let Err(e) = jobs.stop_job(hash).await {
+									log::error!("Failed to stop a job: {:?}", e);
+									break;
+								}
+							}
+							FromOverseer::Signal(OverseerSignal::BlockFinalized(_)) => {}
+							FromOverseer::Signal(OverseerSignal::Conclude) => break,
+							FromOverseer::Communication { msg } => match msg {},
+						},
+						Err(_) => break,
+					}
+				}
+				outgoing = jobs.next().fuse() => {
+					match outgoing {
+						Some(msg) => {
+							let _ = ctx.send_message(msg.into()).await;
+						}
+						None => break,
+					}
+				}
+				complete => break,
+			}
+		}
+	}
+}
+
+impl<S, Context> Subsystem<Context> for BitfieldSigningSubsystem<S, Context>
+	where
+		S: Spawn + Send + Clone + 'static,
+		Context: SubsystemContext<Message=BitfieldSigningMessage>,
+{
+	fn start(self, ctx: Context) -> SpawnedSubsystem {
+		let keystore = self.keystore.clone();
+		let spawner = self.spawner.clone();
+		let delay = self.delay;
+
+		SpawnedSubsystem(Box::pin(async move {
+			Self::run(ctx, keystore, spawner, delay).await;
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{Future, executor::{self, ThreadPool}};
+	use bitvec::{bitvec, order::Lsb0};
+	use sp_keyring::Sr25519Keyring;
+	use polkadot_primitives::v1::{
+		CandidateDescriptor, CollatorId, ErasureChunk, ParathreadClaim, ParathreadEntry,
+	};
+	use assert_matches::assert_matches;
+
+	fn validator_pubkeys(val_ids: &[Sr25519Keyring]) -> Vec<ValidatorId> {
+		val_ids.iter().map(|v| v.public().into()).collect()
+	}
+
+	struct TestState {
+		keystore: KeyStorePtr,
+		validator_public: Vec<ValidatorId>,
+		roster: SchedulerRoster,
+		candidates: HashMap<ParaId, CommittedCandidateReceipt>,
+		signing_context: SigningContext,
+		relay_parent: Hash,
+	}
+
+	impl Default for TestState {
+		fn default() -> Self {
+			let chain_a = ParaId::from(1);
+			let chain_b = ParaId::from(2);
+			let thread_a = ParaId::from(3);
+
+			let validators = vec![
+				Sr25519Keyring::Alice,
+				Sr25519Keyring::Bob,
+				Sr25519Keyring::Charlie,
+			];
+
+			let keystore = keystore::Store::new_in_memory();
+			// Make sure `Bob` key is in the keystore, so this mocked node will be a parachain validator.
+			keystore.write().insert_ephemeral_from_seed::<ValidatorPair>(&validators[1].to_seed())
+				.expect("Insert key into keystore");
+
+			let validator_public = validator_pubkeys(&validators);
+
+			let thread_collator: CollatorId = Sr25519Keyring::Two.public().into();
+
+			// `chain_a` is occupying core 0, `thread_a` is occupying core 2 and core 1
+			// (the one of `chain_b`) is free.
+			let roster = SchedulerRoster {
+				validator_groups: vec![vec![0], vec![1], vec![2]],
+				scheduled: vec![],
+				upcoming: vec![chain_a, chain_b, thread_a],
+				availability_cores: vec![
+					Some(CoreOccupied::Parachain),
+					None,
+					Some(CoreOccupied::Parathread(ParathreadEntry {
+						claim: ParathreadClaim(thread_a, thread_collator),
+						retries: 0,
+					})),
+				],
+			};
+
+			let relay_parent = Hash::from([5; 32]);
+
+			let candidates = vec![chain_a, thread_a].into_iter()
+				.map(|para_id| (para_id, CommittedCandidateReceipt {
+					descriptor: CandidateDescriptor {
+						para_id,
+						relay_parent: Hash::from([4; 32]),
+						..Default::default()
+					},
+					..Default::default()
+				}))
+				.collect();
+
+			let signing_context = SigningContext {
+				session_index: 1,
+				parent_hash: relay_parent,
+			};
+
+			Self {
+				keystore,
+				validator_public,
+				roster,
+				candidates,
+				signing_context,
+				relay_parent,
+			}
+		}
+	}
+
+	struct TestHarness {
+		virtual_overseer: subsystem_test::TestSubsystemContextHandle<BitfieldSigningMessage>,
+	}
+
+	fn test_harness<T: Future<Output=()>>(keystore: KeyStorePtr, test: impl FnOnce(TestHarness) -> T) {
+		let pool = ThreadPool::new().unwrap();
+
+		let (context, virtual_overseer) = subsystem_test::make_subsystem_context(pool.clone());
+
+		let subsystem = BitfieldSigningSubsystem::run(
+			context,
+			keystore,
+			pool.clone(),
+			Duration::from_millis(0),
+		);
+
+		let test_fut = test(TestHarness {
+			virtual_overseer,
+		});
+
+		futures::pin_mut!(test_fut);
+		futures::pin_mut!(subsystem);
+
+		executor::block_on(future::select(test_fut, subsystem));
+	}
+
+	// The bitfield has a bit set for each occupied core whose candidate has our chunk available.
+	#[test]
+	fn signs_bitfield_of_available_chunks() {
+		let test_state = TestState::default();
+		test_harness(test_state.keystore.clone(), |test_harness| async move {
+			let TestHarness { mut virtual_overseer } = test_harness;
+
+			virtual_overseer.send(FromOverseer::Signal(
+				OverseerSignal::StartWork(test_state.relay_parent))
+			).await;
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::RuntimeApi(
+					RuntimeApiMessage::Request(parent, RuntimeApiRequest::Validators(tx))
+				) if parent == test_state.relay_parent => {
+					tx.send(test_state.validator_public.clone()).unwrap();
+				}
+			);
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::RuntimeApi(
+					RuntimeApiMessage::Request(parent, RuntimeApiRequest::ValidatorGroups(tx))
+				) if parent == test_state.relay_parent => {
+					tx.send(test_state.roster.clone()).unwrap();
+				}
+			);
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::RuntimeApi(
+					RuntimeApiMessage::Request(parent, RuntimeApiRequest::SigningContext(tx))
+				) if parent == test_state.relay_parent => {
+					tx.send(test_state.signing_context.clone()).unwrap();
+				}
+			);
+
+			let available_hash = test_state.candidates[&ParaId::from(1)].hash();
+
+			// Candidates are looked up and queried concurrently, so the order of the messages
+			// is not fixed.
+			let signed_bitfield = loop {
+				match virtual_overseer.recv().await {
+					AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+						parent,
+						RuntimeApiRequest::CandidatePendingAvailability(para, tx),
+					)) => {
+						assert_eq!(parent, test_state.relay_parent);
+						tx.send(test_state.candidates.get(&para).cloned()).unwrap();
+					}
+					AllMessages::AvailabilityStore(
+						AvailabilityStoreMessage::QueryChunk(hash, validator_index, tx)
+					) => {
+						assert_eq!(validator_index, 1);
+
+						let chunk = if hash == available_hash {
+							Some(ErasureChunk { chunk: vec![1, 2, 3], index: 1, proof: Vec::new() })
+						} else {
+							None
+						};
+
+						tx.send(chunk).unwrap();
+					}
+					AllMessages::BitfieldDistribution(
+						BitfieldDistributionMessage::DistributeBitfield(parent, signed_bitfield)
+					) => {
+						assert_eq!(parent, test_state.relay_parent);
+						break signed_bitfield;
+					}
+					msg => panic!("Unexpected message: {:?}", msg),
+				}
+			};
+
+			assert_eq!(signed_bitfield.validator_index(), 1);
+			assert_eq!(signed_bitfield.payload().0, bitvec![Lsb0, u8; 1, 0, 0]);
+			assert!(signed_bitfield.check_signature(
+				&test_state.signing_context,
+				&test_state.validator_public[1],
+			).is_ok());
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(parent, provisioned)
+				)) => {
+					assert_eq!(parent, test_state.relay_parent);
+					assert_eq!(provisioned, signed_bitfield);
+				}
+			);
+
+			virtual_overseer.send(FromOverseer::Signal(
+				OverseerSignal::StopWork(test_state.relay_parent))
+			).await;
+		});
+	}
+}
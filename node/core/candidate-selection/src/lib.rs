@@ -0,0 +1,712 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Candidate Selection subsystem picks up to one collation per relay-parent for the
+//! Candidate Backing subsystem to second.
+//!
+//! Collations are advertised to it by the Collator Protocol. The first one for the para assigned
+//! to the local validator is fetched and handed over to be seconded. If it later turns out to be
+//! invalid, the collator which provided it is reported.
+
+#![recursion_limit="256"]
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::time::Duration;
+
+use log;
+use futures::{
+	select, FutureExt, SinkExt, StreamExt,
+	channel::{oneshot, mpsc},
+	future::{self, Either},
+	task::{Spawn, SpawnError, SpawnExt},
+};
+use futures_timer::Delay;
+use streamunordered::{StreamUnordered, StreamYield};
+
+use keystore::KeyStorePtr;
+use polkadot_primitives::v1::{
+	CandidateReceipt, CollatorId, CoreAssignment, Hash, Id as ParaId, PoV, ValidatorId,
+	ValidatorIndex, ValidatorPair,
+};
+use polkadot_subsystem::{
+	FromOverseer, OverseerSignal, Subsystem, SubsystemContext, SpawnedSubsystem,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, CandidateBackingMessage, CandidateSelectionMessage, CollatorProtocolMessage,
+	RuntimeApiMessage, RuntimeApiRequest, SchedulerRoster,
+};
+
+#[derive(Debug, derive_more::From)]
+enum Error {
+	JobNotFound(Hash),
+	#[from]
+	Oneshot(oneshot::Canceled),
+	#[from]
+	Mpsc(mpsc::SendError),
+	#[from]
+	Spawn(SpawnError),
+}
+
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A message type that is sent from `CandidateSelectionSubsystem` to a candidate selection job.
+enum ToJob {
+	/// A `CandidateSelectionMessage`.
+	CandidateSelection(CandidateSelectionMessage),
+	/// Stop working.
+	Stop,
+}
+
+/// A message type that is sent from a candidate selection job to `CandidateSelectionSubsystem`.
+enum FromJob {
+	CandidateBacking(CandidateBackingMessage),
+	CollatorProtocol(CollatorProtocolMessage),
+	RuntimeApiMessage(RuntimeApiMessage),
+}
+
+impl From<FromJob> for AllMessages {
+	fn from(f: FromJob) -> Self {
+		match f {
+			FromJob::CandidateBacking(msg) => AllMessages::CandidateBacking(msg),
+			FromJob::CollatorProtocol(msg) => AllMessages::CollatorProtocol(msg),
+			FromJob::RuntimeApiMessage(msg) => AllMessages::RuntimeApi(msg),
+		}
+	}
+}
+
+/// Find the index of the local validator within the validator set.
+fn local_validator_index(
+	validators: &[ValidatorId],
+	keystore: &KeyStorePtr,
+) -> Option<ValidatorIndex> {
+	let keystore = keystore.read();
+	validators.iter().position(|v| keystore.key_pair::<ValidatorPair>(&v).is_ok())
+		.map(|i| i as ValidatorIndex)
+}
+
+/// The scheduled assignment of the group the given validator is a member of, if any.
+fn our_assignment(roster: &SchedulerRoster, validator_index: ValidatorIndex) -> Option<CoreAssignment> {
+	let group = roster.validator_groups.iter().position(|g| g.contains(&validator_index))?;
+
+	roster.scheduled.iter().find(|a| a.group_idx.0 as usize == group).cloned()
+}
+
+/// The state of a single candidate selection job.
+struct JobState {
+	parent: Hash,
+	assignment: CoreAssignment,
+	/// The candidate we have asked Candidate Backing to second, along with the collator
+	/// it was fetched from.
+	seconded: Option<(Hash, CollatorId)>,
+	tx_from: mpsc::Sender<FromJob>,
+}
+
+impl JobState {
+	/// Fetch and second the collation of the given collator, if it is for our para and we
+	/// haven't seconded anything yet.
+	async fn handle_collation(&mut self, para_id: ParaId, collator: CollatorId) -> Result<(), Error> {
+		if self.seconded.is_some() || para_id != self.assignment.para_id {
+			return Ok(());
+		}
+
+		if let Some(required) = self.assignment.required_collator() {
+			if required != &collator {
+				return Ok(());
+			}
+		}
+
+		let (tx, rx) = oneshot::channel();
+		self.tx_from.send(FromJob::CollatorProtocol(
+			CollatorProtocolMessage::FetchCollation(self.parent, collator.clone(), para_id, tx)
+		)).await?;
+
+		let (receipt, pov) = match rx.await {
+			Ok(collation) => collation,
+			// The collation could not be fetched. Wait for another one.
+			Err(oneshot::Canceled) => return Ok(()),
+		};
+
+		self.second(receipt, pov, collator).await
+	}
+
+	async fn second(
+		&mut self,
+		receipt: CandidateReceipt,
+		pov: PoV,
+		collator: CollatorId,
+	) -> Result<(), Error> {
+		let candidate_hash = receipt.hash();
+
+		self.tx_from.send(FromJob::CandidateBacking(
+			CandidateBackingMessage::Second(self.parent, receipt, pov)
+		)).await?;
+
+		self.seconded = Some((candidate_hash, collator));
+
+		Ok(())
+	}
+
+	/// Report the collator of a candidate we seconded which turned out to be invalid.
+	async fn handle_invalid(&mut self, receipt: CandidateReceipt) -> Result<(), Error> {
+		let collator = match self.seconded {
+			Some((ref hash, ref collator)) if *hash == receipt.hash() => collator.clone(),
+			_ => {
+				log::warn!(
+					"CandidateSelectionJob({}) was told about an invalid candidate it didn't second: {}",
+					self.parent,
+					receipt.hash(),
+				);
+				return Ok(());
+			}
+		};
+
+		self.tx_from.send(FromJob::CollatorProtocol(
+			CollatorProtocolMessage::ReportCollator(collator)
+		)).await?;
+
+		Ok(())
+	}
+}
+
+async fn run_job(
+	parent: Hash,
+	keystore: KeyStorePtr,
+	mut rx_to: mpsc::Receiver<ToJob>,
+	mut tx_from: mpsc::Sender<FromJob>,
+) -> Result<(), Error> {
+	let (validators, roster) = futures::try_join!(
+		request_validators(parent, &mut tx_from).await?,
+		request_validator_groups(parent, &mut tx_from).await?,
+	)?;
+
+	let assignment = match local_validator_index(&validators[..], &keystore)
+		.and_then(|index| our_assignment(&roster, index))
+	{
+		Some(assignment) => assignment,
+		// Not a validator, or not assigned to a core at this relay-parent: nothing to select.
+		None => return Ok(()),
+	};
+
+	let mut state = JobState {
+		parent,
+		assignment,
+		seconded: None,
+		tx_from,
+	};
+
+	while let Some(msg) = rx_to.next().await {
+		match msg {
+			ToJob::CandidateSelection(CandidateSelectionMessage::Collation(_, para_id, collator)) => {
+				state.handle_collation(para_id, collator).await?;
+			}
+			ToJob::CandidateSelection(CandidateSelectionMessage::Invalid(_, receipt)) => {
+				state.handle_invalid(receipt).await?;
+			}
+			ToJob::Stop => break,
+		}
+	}
+
+	Ok(())
+}
+
+/// Request a validator set from the `RuntimeApi`.
+async fn request_validators(
+	parent: Hash,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<Vec<ValidatorId>>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::Validators(tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+/// Request the scheduler roster from `RuntimeApi`.
+async fn request_validator_groups(
+	parent: Hash,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<SchedulerRoster>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::ValidatorGroups(tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+struct JobHandle {
+	abort_handle: future::AbortHandle,
+	to_job: mpsc::Sender<ToJob>,
+	finished: oneshot::Receiver<()>,
+	su_handle: usize,
+}
+
+impl JobHandle {
+	async fn stop(mut self) {
+		let _ = self.to_job.send(ToJob::Stop).await;
+		let stop_timer = Delay::new(Duration::from_secs(1));
+
+		match future::select(stop_timer, self.finished).await {
+			Either::Left((_, _)) => {
+			},
+			Either::Right((_, _)) => {
+				self.abort_handle.abort();
+			},
+		}
+	}
+
+	async fn send_msg(&mut self, msg: ToJob) -> Result<(), Error> {
+		Ok(self.to_job.send(msg).await?)
+	}
+}
+
+struct Jobs<S> {
+	spawner: S,
+	running: HashMap<Hash, JobHandle>,
+	outgoing_msgs: StreamUnordered<mpsc::Receiver<FromJob>>,
+}
+
+impl<S: Spawn> Jobs<S> {
+	fn new(spawner: S) -> Self {
+		Self {
+			spawner,
+			running: HashMap::default(),
+			outgoing_msgs: StreamUnordered::new(),
+		}
+	}
+
+	fn spawn_job(&mut self, parent_hash: Hash, keystore: KeyStorePtr) -> Result<(), Error> {
+		let (to_job_tx, to_job_rx) = mpsc::channel(CHANNEL_CAPACITY);
+		let (from_job_tx, from_job_rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+		let (future, abort_handle) = future::abortable(async move {
+			if let Err(e) = run_job(parent_hash, keystore, to_job_rx, from_job_tx).await {
+				log::error!(
+					"CandidateSelectionJob({}) finished with an error {:?}",
+					parent_hash,
+					e,
+				);
+			}
+		});
+
+		let (finished_tx, finished) = oneshot::channel();
+
+		let future = async move {
+			let _ = future.await;
+			let _ = finished_tx.send(());
+		};
+		self.spawner.spawn(future)?;
+
+		let su_handle = self.outgoing_msgs.push(from_job_rx);
+
+		let handle = JobHandle {
+			abort_handle,
+			to_job: to_job_tx,
+			finished,
+			su_handle,
+		};
+
+		self.running.insert(parent_hash, handle);
+
+		Ok(())
+	}
+
+	async fn stop_job(&mut self, parent_hash: Hash) -> Result<(), Error> {
+		match self.running.remove(&parent_hash) {
+			Some(handle) => {
+				Pin::new(&mut self.outgoing_msgs).remove(handle.su_handle);
+				handle.stop().await;
+				Ok(())
+			}
+			None => Err(Error::JobNotFound(parent_hash))
+		}
+	}
+
+	async fn send_msg(&mut self, parent_hash: Hash, msg: ToJob) -> Result<(), Error> {
+		if let Some(job) = self.running.get_mut(&parent_hash) {
+			job.send_msg(msg).await?;
+		}
+		Ok(())
+	}
+
+	async fn next(&mut self) -> Option<FromJob> {
+		self.outgoing_msgs.next().await.and_then(|(e, _)| match e {
+			StreamYield::Item(e) => Some(e),
+			_ => None,
+		})
+	}
+}
+
+/// An implementation of the Candidate Selection subsystem.
+pub struct CandidateSelectionSubsystem<S, Context> {
+	spawner: S,
+	keystore: KeyStorePtr,
+	_context: std::marker::PhantomData<Context>,
+}
+
+impl<S, Context> CandidateSelectionSubsystem<S, Context>
+	where
+		S: Spawn + Clone,
+		Context: SubsystemContext<Message=CandidateSelectionMessage>,
+{
+	/// Creates a new `CandidateSelectionSubsystem`.
+	pub fn new(keystore: KeyStorePtr, spawner: S) -> Self {
+		Self {
+			spawner,
+			keystore,
+			_context: std::marker::PhantomData,
+		}
+	}
+
+	async fn run(
+		mut ctx: Context,
+		keystore: KeyStorePtr,
+		spawner: S,
+	) {
+		let mut jobs = Jobs::new(spawner.clone());
+
+		loop {
+			select! {
+				incoming = ctx.recv().fuse() => {
+					match incoming {
+						Ok(msg) => match msg {
+							FromOverseer::Signal(OverseerSignal::StartWork(hash)) => {
+								if let Err(e) = jobs.spawn_job(hash, keystore.clone()) {
+									log::error!("Failed to spawn a job: {:?}", e);
+									break;
+								}
+							}
+							FromOverseer::Signal(OverseerSignal::StopWork(hash)) => {
+								if let Err(e) = jobs.stop_job(hash).await {
+									log::error!("Failed to stop a job: {:?}", e);
+									break;
+								}
+							}
+							FromOverseer::Signal(OverseerSignal::BlockFinalized(_)) => {}
+							FromOverseer::Signal(OverseerSignal::Conclude) => break,
+							FromOverseer::Communication { msg } => {
+								let hash = match msg {
+									CandidateSelectionMessage::Collation(hash, _, _) |
+									CandidateSelectionMessage::Invalid(hash, _) => hash,
+								};
+
+								if let Err(e) = jobs.send_msg(hash, ToJob::CandidateSelection(msg)).await {
+									log::error!("Failed to send a message to a job: {:?}", e);
+									break;
+								}
+							}
+						},
+						Err(_) => break,
+					}
+				}
+				outgoing = jobs.next().fuse() => {
+					match outgoing {
+						Some(msg) => {
+							let _ = ctx.send_message(msg.into()).await;
+						}
+						None => break,
+					}
+				}
+				complete => break,
+			}
+		}
+	}
+}
+
+impl<S, Context> Subsystem<Context> for CandidateSelectionSubsystem<S, Context>
+	where
+		S: Spawn + Send + Clone + 'static,
+		Context: SubsystemContext<Message=CandidateSelectionMessage>,
+{
+	fn start(self, ctx: Context) -> SpawnedSubsystem {
+		let keystore = self.keystore.clone();
+		let spawner = self.spawner.clone();
+
+		SpawnedSubsystem(Box::pin(async move {
+			Self::run(ctx, keystore, spawner).await;
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{Future, executor::{self, ThreadPool}};
+	use sp_keyring::Sr25519Keyring;
+	use polkadot_primitives::v1::{
+		AssignmentKind, BlockData, CandidateDescriptor, CoreIndex, GroupIndex,
+	};
+	use assert_matches::assert_matches;
+
+	fn validator_pubkeys(val_ids: &[Sr25519Keyring]) -> Vec<ValidatorId> {
+		val_ids.iter().map(|v| v.public().into()).collect()
+	}
+
+	struct TestState {
+		keystore: KeyStorePtr,
+		validator_public: Vec<ValidatorId>,
+		roster: SchedulerRoster,
+		our_para: ParaId,
+		other_para: ParaId,
+		relay_parent: Hash,
+	}
+
+	impl Default for TestState {
+		fn default() -> Self {
+			let chain_a = ParaId::from(1);
+			let chain_b = ParaId::from(2);
+
+			let validators = vec![
+				Sr25519Keyring::Alice,
+				Sr25519Keyring::Bob,
+			];
+
+			let keystore = keystore::Store::new_in_memory();
+			// Make sure `Bob` key is in the keystore, so this mocked node will be assigned to `chain_b`.
+			keystore.write().insert_ephemeral_from_seed::<ValidatorPair>(&validators[1].to_seed())
+				.expect("Insert key into keystore");
+
+			let validator_public = validator_pubkeys(&validators);
+
+			let roster = SchedulerRoster {
+				validator_groups: vec![vec![0], vec![1]],
+				scheduled: vec![
+					CoreAssignment {
+						core: CoreIndex::from(0),
+						para_id: chain_a,
+						kind: AssignmentKind::Parachain,
+						group_idx: GroupIndex::from(0),
+					},
+					CoreAssignment {
+						core: CoreIndex::from(1),
+						para_id: chain_b,
+						kind: AssignmentKind::Parachain,
+						group_idx: GroupIndex::from(1),
+					},
+				],
+				upcoming: vec![chain_a, chain_b],
+				availability_cores: vec![None, None],
+			};
+
+			Self {
+				keystore,
+				validator_public,
+				roster,
+				our_para: chain_b,
+				other_para: chain_a,
+				relay_parent: Hash::from([5; 32]),
+			}
+		}
+	}
+
+	impl TestState {
+		fn collation(&self) -> (CandidateReceipt, PoV) {
+			let pov = PoV { block_data: BlockData(vec![1, 2, 3]) };
+			let receipt = CandidateReceipt {
+				descriptor: CandidateDescriptor {
+					para_id: self.our_para,
+					relay_parent: self.relay_parent,
+					pov_hash: pov.hash(),
+					..Default::default()
+				},
+				..Default::default()
+			};
+
+			(receipt, pov)
+		}
+	}
+
+	struct TestHarness {
+		virtual_overseer: subsystem_test::TestSubsystemContextHandle<CandidateSelectionMessage>,
+	}
+
+	fn test_harness<T: Future<Output=()>>(keystore: KeyStorePtr, test: impl FnOnce(TestHarness) -> T) {
+		let pool = ThreadPool::new().unwrap();
+
+		let (context, virtual_overseer) = subsystem_test::make_subsystem_context(pool.clone());
+
+		let subsystem = CandidateSelectionSubsystem::run(context, keystore, pool.clone());
+
+		let test_fut = test(TestHarness {
+			virtual_overseer,
+		});
+
+		futures::pin_mut!(test_fut);
+		futures::pin_mut!(subsystem);
+
+		executor::block_on(future::select(test_fut, subsystem));
+	}
+
+	async fn activate_leaf(
+		virtual_overseer: &mut subsystem_test::TestSubsystemContextHandle<CandidateSelectionMessage>,
+		test_state: &TestState,
+	) {
+		virtual_overseer.send(FromOverseer::Signal(
+			OverseerSignal::StartWork(test_state.relay_parent))
+		).await;
+
+		assert_matches!(
+			virtual_overseer.recv().await,
+			AllMessages::RuntimeApi(
+				RuntimeApiMessage::Request(parent, RuntimeApiRequest::Validators(tx))
+			) if parent == test_state.relay_parent => {
+				tx.send(test_state.validator_public.clone()).unwrap();
+			}
+		);
+
+		assert_matches!(
+			virtual_overseer.recv().await,
+			AllMessages::RuntimeApi(
+				RuntimeApiMessage::Request(parent, RuntimeApiRequest::ValidatorGroups(tx))
+			) if parent == test_state.relay_parent => {
+				tx.send(test_state.roster.clone()).unwrap();
+			}
+		);
+	}
+
+	// Only the first collation for our own para is fetched and seconded.
+	#[test]
+	fn seconds_first_collation_for_assigned_para() {
+		let test_state = TestState::default();
+		test_harness(test_state.keystore.clone(), |test_harness| async move {
+			let TestHarness { mut virtual_overseer } = test_harness;
+
+			activate_leaf(&mut virtual_overseer, &test_state).await;
+
+			let collator_a: CollatorId = Sr25519Keyring::One.public().into();
+			let collator_b: CollatorId = Sr25519Keyring::Two.public().into();
+
+			// A collation for a para we are not assigned to is ignored.
+			virtual_overseer.send(FromOverseer::Communication {
+				msg: CandidateSelectionMessage::Collation(
+					test_state.relay_parent,
+					test_state.other_para,
+					collator_a.clone(),
+				),
+			}).await;
+
+			virtual_overseer.send(FromOverseer::Communication {
+				msg: CandidateSelectionMessage::Collation(
+					test_state.relay_parent,
+					test_state.our_para,
+					collator_b.clone(),
+				),
+			}).await;
+
+			let (receipt, pov) = test_state.collation();
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::CollatorProtocol(
+					CollatorProtocolMessage::FetchCollation(parent, collator, para_id, tx)
+				) => {
+					assert_eq!(parent, test_state.relay_parent);
+					assert_eq!(collator, collator_b);
+					assert_eq!(para_id, test_state.our_para);
+					tx.send((receipt.clone(), pov.clone())).unwrap();
+				}
+			);
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::CandidateBacking(
+					CandidateBackingMessage::Second(parent, seconded_receipt, seconded_pov)
+				) => {
+					assert_eq!(parent, test_state.relay_parent);
+					assert_eq!(seconded_receipt, receipt);
+					assert_eq!(seconded_pov, pov);
+				}
+			);
+
+			// We already seconded a candidate, so further collations are not fetched.
+			virtual_overseer.send(FromOverseer::Communication {
+				msg: CandidateSelectionMessage::Collation(
+					test_state.relay_parent,
+					test_state.our_para,
+					collator_a,
+				),
+			}).await;
+
+			virtual_overseer.send(FromOverseer::Signal(
+				OverseerSignal::StopWork(test_state.relay_parent))
+			).await;
+
+			assert!(virtual_overseer.recv().now_or_never().is_none());
+		});
+	}
+
+	// The collator of a seconded candidate which turns out to be invalid gets reported.
+	#[test]
+	fn reports_collator_of_invalid_candidate() {
+		let test_state = TestState::default();
+		test_harness(test_state.keystore.clone(), |test_harness| async move {
+			let TestHarness { mut virtual_overseer } = test_harness;
+
+			activate_leaf(&mut virtual_overseer, &test_state).await;
+
+			let collator: CollatorId = Sr25519Keyring::One.public().into();
+
+			virtual_overseer.send(FromOverseer::Communication {
+				msg: CandidateSelectionMessage::Collation(
+					test_state.relay_parent,
+					test_state.our_para,
+					collator.clone(),
+				),
+			}).await;
+
+			let (receipt, pov) = test_state.collation();
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::CollatorProtocol(
+					CollatorProtocolMessage::FetchCollation(_, _, _, tx)
+				) => {
+					tx.send((receipt.clone(), pov)).unwrap();
+				}
+			);
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::CandidateBacking(CandidateBackingMessage::Second(..))
+			);
+
+			virtual_overseer.send(FromOverseer::Communication {
+				msg: CandidateSelectionMessage::Invalid(test_state.relay_parent, receipt),
+			}).await;
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::CollatorProtocol(
+					CollatorProtocolMessage::ReportCollator(reported)
+				) => {
+					assert_eq!(reported, collator);
+				}
+			);
+
+			virtual_overseer.send(FromOverseer::Signal(
+				OverseerSignal::StopWork(test_state.relay_parent))
+			).await;
+		});
+	}
+}
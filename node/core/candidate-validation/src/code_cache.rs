@@ -0,0 +1,125 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A cache of validation code, addressed by the hash of the code.
+//!
+//! Validation code can be megabytes in size and is shared by all candidates of a para between
+//! code upgrades, so it is only fetched from the runtime API once it is not found here.
+
+use std::collections::{HashMap, VecDeque};
+
+use polkadot_primitives::v1::{Hash, ValidationCode};
+
+/// A cache of validation code, bounded by the total size of the code it holds.
+///
+/// Once the bound is exceeded, the least recently used code is evicted. The most recently
+/// inserted code is always kept, even if it exceeds the bound on its own.
+pub(crate) struct CodeCache {
+	max_size: usize,
+	size: usize,
+	code: HashMap<Hash, ValidationCode>,
+	// hashes of the cached code, from least to most recently used.
+	usage: VecDeque<Hash>,
+}
+
+impl CodeCache {
+	/// Create a new, empty cache holding at most `max_size` bytes of code.
+	pub(crate) fn new(max_size: usize) -> Self {
+		CodeCache {
+			max_size,
+			size: 0,
+			code: HashMap::new(),
+			usage: VecDeque::new(),
+		}
+	}
+
+	/// Get the code with the given hash, if it is cached.
+	pub(crate) fn get(&mut self, hash: &Hash) -> Option<&ValidationCode> {
+		if let Some(pos) = self.usage.iter().position(|h| h == hash) {
+			let _ = self.usage.remove(pos);
+			self.usage.push_back(*hash);
+		}
+
+		self.code.get(hash)
+	}
+
+	/// Insert code with the given hash, evicting the least recently used code as necessary.
+	pub(crate) fn insert(&mut self, hash: Hash, code: ValidationCode) {
+		if self.get(&hash).is_some() {
+			return;
+		}
+
+		self.size += code.0.len();
+		self.code.insert(hash, code);
+		self.usage.push_back(hash);
+
+		while self.size > self.max_size && self.usage.len() > 1 {
+			let evicted = match self.usage.pop_front() {
+				Some(evicted) => evicted,
+				None => break,
+			};
+
+			if let Some(code) = self.code.remove(&evicted) {
+				self.size -= code.0.len();
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn code(byte: u8, len: usize) -> ValidationCode {
+		ValidationCode(vec![byte; len])
+	}
+
+	#[test]
+	fn evicts_least_recently_used() {
+		let mut cache = CodeCache::new(10);
+		let (a, b, c) = (Hash::repeat_byte(1), Hash::repeat_byte(2), Hash::repeat_byte(3));
+
+		cache.insert(a, code(1, 4));
+		cache.insert(b, code(2, 4));
+
+		// `a` is now more recently used than `b`.
+		assert_eq!(cache.get(&a), Some(&code(1, 4)));
+
+		cache.insert(c, code(3, 4));
+
+		assert_eq!(cache.get(&a), Some(&code(1, 4)));
+		assert!(cache.get(&b).is_none());
+		assert_eq!(cache.get(&c), Some(&code(3, 4)));
+		assert_eq!(cache.size, 8);
+	}
+
+	#[test]
+	fn keeps_most_recent_code_beyond_bound() {
+		let mut cache = CodeCache::new(10);
+		let (a, b) = (Hash::repeat_byte(1), Hash::repeat_byte(2));
+
+		cache.insert(a, code(1, 4));
+		cache.insert(b, code(2, 20));
+
+		assert!(cache.get(&a).is_none());
+		assert_eq!(cache.get(&b), Some(&code(2, 20)));
+		assert_eq!(cache.size, 20);
+
+		// inserting known code again does not count twice.
+		cache.insert(b, code(2, 20));
+		assert_eq!(cache.size, 20);
+	}
+}
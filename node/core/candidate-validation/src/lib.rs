@@ -0,0 +1,833 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Candidate Validation subsystem.
+//!
+//! This handles incoming requests from other subsystems to validate candidates
+//! according to a validation function. This delegates validation to an underlying
+//! pool of processes used for execution of the Wasm.
+
+use std::sync::Arc;
+
+use futures::channel::oneshot;
+
+use polkadot_subsystem::{
+	Subsystem, SubsystemContext, SpawnedSubsystem, SubsystemResult,
+	FromOverseer, OverseerSignal,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, CandidateValidationMessage, RuntimeApiMessage, RuntimeApiRequest,
+	ValidationFailed,
+};
+use polkadot_node_primitives::{ValidationResult, ValidationOutputs};
+use polkadot_primitives::v1::{
+	ValidationCode, PoV, CandidateDescriptor, OmittedValidationData, Hash,
+	GlobalValidationSchedule, LocalValidationData, DownwardMessage, downward_messages_hash,
+};
+use polkadot_parachain::wasm_executor::{self, ValidationPool, ExecutionMode, Error as WasmError};
+use polkadot_parachain::primitives::{
+	ValidationResult as WasmValidationResult, ValidationParams,
+};
+
+mod code_cache;
+
+use code_cache::CodeCache;
+
+const LOG_TARGET: &str = "candidate_validation";
+
+/// The maximum total size of the validation code kept in the code cache, in bytes.
+const CODE_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The candidate validation subsystem.
+pub struct CandidateValidationSubsystem;
+
+impl<C> Subsystem<C> for CandidateValidationSubsystem
+	where C: SubsystemContext<Message = CandidateValidationMessage>
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		SpawnedSubsystem(Box::pin(async move {
+			if let Err(e) = run(ctx).await {
+				log::error!(target: LOG_TARGET, "Candidate validation subsystem exited with an error: {:?}", e);
+			}
+		}))
+	}
+}
+
+async fn run(
+	mut ctx: impl SubsystemContext<Message = CandidateValidationMessage>,
+) -> SubsystemResult<()> {
+	let pool = ValidationPool::new();
+	let mut code_cache = CodeCache::new(CODE_CACHE_SIZE);
+
+	loop {
+		match ctx.recv().await? {
+			FromOverseer::Signal(OverseerSignal::StartWork(_)) => {}
+			FromOverseer::Signal(OverseerSignal::StopWork(_)) => {}
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(_)) => {}
+			FromOverseer::Signal(OverseerSignal::Conclude) => return Ok(()),
+			FromOverseer::Communication { msg } => match msg {
+				CandidateValidationMessage::ValidateFromChainState(
+					descriptor,
+					pov,
+					response_sender,
+				) => {
+					spawn_validate_from_chain_state(
+						&mut ctx,
+						&mut code_cache,
+						Some(pool.clone()),
+						descriptor,
+						pov,
+						response_sender,
+					).await?;
+				}
+				CandidateValidationMessage::ValidateFromExhaustive(
+					omitted_validation,
+					validation_code,
+					downward_messages,
+					descriptor,
+					pov,
+					response_sender,
+				) => {
+					spawn_validate_exhaustive(
+						&mut ctx,
+						Some(pool.clone()),
+						omitted_validation,
+						validation_code,
+						downward_messages,
+						descriptor,
+						pov,
+						response_sender,
+					).await?;
+				}
+			}
+		}
+	}
+}
+
+async fn runtime_api_request<T>(
+	ctx: &mut impl SubsystemContext<Message = CandidateValidationMessage>,
+	relay_parent: Hash,
+	request: RuntimeApiRequest,
+	receiver: oneshot::Receiver<T>,
+) -> SubsystemResult<Result<T, oneshot::Canceled>> {
+	ctx.send_message(
+		AllMessages::RuntimeApi(RuntimeApiMessage::Request(relay_parent, request))
+	).await?;
+
+	Ok(receiver.await)
+}
+
+/// Gather the `OmittedValidationData`, `ValidationCode` and downward messages for the candidate
+/// from the state of its relay-parent and then validate it in a spawned task, which answers the
+/// request.
+///
+/// The validation code is only fetched if it is not found in the code cache by its hash.
+async fn spawn_validate_from_chain_state(
+	ctx: &mut impl SubsystemContext<Message = CandidateValidationMessage>,
+	code_cache: &mut CodeCache,
+	validation_pool: Option<ValidationPool>,
+	descriptor: CandidateDescriptor,
+	pov: Arc<PoV>,
+	response_sender: oneshot::Sender<Result<ValidationResult, ValidationFailed>>,
+) -> SubsystemResult<()> {
+	let relay_parent = descriptor.relay_parent;
+	let para_id = descriptor.para_id;
+
+	let global_validation = {
+		let (tx, rx) = oneshot::channel();
+		match runtime_api_request(
+			ctx,
+			relay_parent,
+			RuntimeApiRequest::GlobalValidationSchedule(tx),
+			rx,
+		).await? {
+			Ok(g) => g,
+			Err(_) => {
+				let _ = response_sender.send(Err(ValidationFailed));
+				return Ok(());
+			}
+		}
+	};
+
+	let local_validation = {
+		let (tx, rx) = oneshot::channel();
+		match runtime_api_request(
+			ctx,
+			relay_parent,
+			RuntimeApiRequest::LocalValidationData(para_id, tx),
+			rx,
+		).await? {
+			Ok(Some(l)) => l,
+			Ok(None) => {
+				// The para is not registered in the state of the relay-parent.
+				log::debug!(target: LOG_TARGET, "No local validation data for para {:?}", para_id);
+				let _ = response_sender.send(Ok(ValidationResult::Invalid));
+				return Ok(());
+			}
+			Err(_) => {
+				let _ = response_sender.send(Err(ValidationFailed));
+				return Ok(());
+			}
+		}
+	};
+
+	let validation_code_hash = {
+		let (tx, rx) = oneshot::channel();
+		match runtime_api_request(
+			ctx,
+			relay_parent,
+			RuntimeApiRequest::ValidationCodeHash(
+				para_id,
+				global_validation.block_number,
+				None,
+				tx,
+			),
+			rx,
+		).await? {
+			Ok(Some(h)) => h,
+			Ok(None) | Err(_) => {
+				let _ = response_sender.send(Err(ValidationFailed));
+				return Ok(());
+			}
+		}
+	};
+
+	let validation_code = match code_cache.get(&validation_code_hash).cloned() {
+		Some(c) => c,
+		None => {
+			let (tx, rx) = oneshot::channel();
+			let validation_code = match runtime_api_request(
+				ctx,
+				relay_parent,
+				RuntimeApiRequest::ValidationCodeByHash(validation_code_hash, tx),
+				rx,
+			).await? {
+				Ok(Some(c)) => c,
+				Ok(None) | Err(_) => {
+					let _ = response_sender.send(Err(ValidationFailed));
+					return Ok(());
+				}
+			};
+
+			code_cache.insert(validation_code_hash, validation_code.clone());
+			validation_code
+		}
+	};
+
+	let downward_messages = {
+		let (tx, rx) = oneshot::channel();
+		match runtime_api_request(
+			ctx,
+			relay_parent,
+			RuntimeApiRequest::DownwardMessages(para_id, tx),
+			rx,
+		).await? {
+			Ok(m) => m,
+			Err(_) => {
+				let _ = response_sender.send(Err(ValidationFailed));
+				return Ok(());
+			}
+		}
+	};
+
+	let omitted_validation = OmittedValidationData {
+		global_validation,
+		local_validation,
+	};
+
+	spawn_validate_exhaustive(
+		ctx,
+		validation_pool,
+		omitted_validation,
+		validation_code,
+		downward_messages,
+		descriptor,
+		pov,
+		response_sender,
+	).await
+}
+
+async fn spawn_validate_exhaustive(
+	ctx: &mut impl SubsystemContext<Message = CandidateValidationMessage>,
+	validation_pool: Option<ValidationPool>,
+	omitted_validation: OmittedValidationData,
+	validation_code: ValidationCode,
+	downward_messages: Vec<DownwardMessage>,
+	descriptor: CandidateDescriptor,
+	pov: Arc<PoV>,
+	response_sender: oneshot::Sender<Result<ValidationResult, ValidationFailed>>,
+) -> SubsystemResult<()> {
+	let fut = async move {
+		let res = validate_candidate_exhaustive::<RealValidationBackend>(
+			validation_pool,
+			omitted_validation,
+			validation_code,
+			downward_messages,
+			descriptor,
+			pov,
+		);
+
+		let _ = response_sender.send(res);
+	};
+
+	ctx.spawn(Box::pin(fut)).await
+}
+
+/// Does basic checks of a candidate against its descriptor and the validation data.
+fn passes_basic_checks(
+	descriptor: &CandidateDescriptor,
+	pov: &PoV,
+	local_validation: &LocalValidationData,
+	downward_messages: &[DownwardMessage],
+) -> Result<(), &'static str> {
+	if pov.hash() != descriptor.pov_hash {
+		return Err("PoV hash does not match the descriptor");
+	}
+
+	if descriptor.check_collator_signature().is_err() {
+		return Err("Invalid collator signature");
+	}
+
+	if downward_messages_hash(downward_messages) != local_validation.downward_messages_hash {
+		return Err("Downward messages do not match the local validation data");
+	}
+
+	Ok(())
+}
+
+/// Check the result of Wasm execution against the constraints given by the relay-chain.
+fn check_wasm_result_against_constraints(
+	global_validation: &GlobalValidationSchedule,
+	local_validation: &LocalValidationData,
+	result: &WasmValidationResult,
+) -> Result<(), &'static str> {
+	if result.head_data.0.len() > global_validation.max_head_data_size as _ {
+		return Err("Head data exceeds the maximum size");
+	}
+
+	if result.hrmp_watermark > global_validation.block_number {
+		return Err("HRMP watermark is ahead of the relay-parent");
+	}
+
+	if let Some(ref code) = result.new_validation_code {
+		if local_validation.code_upgrade_allowed.is_none() {
+			return Err("Code upgrade signaled when not allowed");
+		}
+
+		if code.0.len() > global_validation.max_code_size as _ {
+			return Err("New validation code exceeds the maximum size");
+		}
+	}
+
+	Ok(())
+}
+
+/// Whether an error from the Wasm executor is the fault of the local node rather than
+/// the candidate.
+fn is_internal_error(e: &WasmError) -> bool {
+	match e {
+		WasmError::Io(_) | WasmError::System(_) => true,
+		#[cfg(not(any(target_os = "android", target_os = "unknown")))]
+		WasmError::SharedMem(_) => true,
+		_ => false,
+	}
+}
+
+trait ValidationBackend {
+	type Arg;
+
+	fn validate(
+		arg: Self::Arg,
+		validation_code: &ValidationCode,
+		params: ValidationParams,
+	) -> Result<WasmValidationResult, WasmError>;
+}
+
+struct RealValidationBackend;
+
+impl ValidationBackend for RealValidationBackend {
+	type Arg = Option<ValidationPool>;
+
+	fn validate(
+		pool: Option<ValidationPool>,
+		validation_code: &ValidationCode,
+		params: ValidationParams,
+	) -> Result<WasmValidationResult, WasmError> {
+		let execution_mode = pool.as_ref()
+			.map(ExecutionMode::Remote)
+			.unwrap_or(ExecutionMode::Local);
+
+		wasm_executor::validate_candidate(&validation_code.0, params, execution_mode)
+	}
+}
+
+/// Validates the candidate from exhaustive parameters.
+///
+/// Returns an error only if validation could not be performed due to an internal error.
+fn validate_candidate_exhaustive<B: ValidationBackend>(
+	backend_arg: B::Arg,
+	omitted_validation: OmittedValidationData,
+	validation_code: ValidationCode,
+	downward_messages: Vec<DownwardMessage>,
+	descriptor: CandidateDescriptor,
+	pov: Arc<PoV>,
+) -> Result<ValidationResult, ValidationFailed> {
+	let OmittedValidationData { global_validation, local_validation } = omitted_validation;
+
+	if let Err(reason) = passes_basic_checks(
+		&descriptor,
+		&*pov,
+		&local_validation,
+		&downward_messages,
+	) {
+		log::debug!(target: LOG_TARGET, "Candidate failed basic checks: {}", reason);
+		return Ok(ValidationResult::Invalid);
+	}
+
+	let params = ValidationParams {
+		parent_head: local_validation.parent_head.clone(),
+		block_data: pov.block_data.clone(),
+		max_code_size: global_validation.max_code_size,
+		max_head_data_size: global_validation.max_head_data_size,
+		relay_chain_height: global_validation.block_number,
+		code_upgrade_allowed: local_validation.code_upgrade_allowed,
+		downward_messages,
+		inbound_hrmp_messages: local_validation.inbound_hrmp_messages.clone(),
+	};
+
+	match B::validate(backend_arg, &validation_code, params) {
+		Err(ref e) if is_internal_error(e) => {
+			log::warn!(target: LOG_TARGET, "Internal error during candidate validation: {:?}", e);
+			Err(ValidationFailed)
+		}
+		Err(e) => {
+			log::debug!(target: LOG_TARGET, "Candidate execution failed: {:?}", e);
+			Ok(ValidationResult::Invalid)
+		}
+		Ok(res) => {
+			if let Err(reason) = check_wasm_result_against_constraints(
+				&global_validation,
+				&local_validation,
+				&res,
+			) {
+				log::debug!(target: LOG_TARGET, "Candidate outputs violate constraints: {}", reason);
+				return Ok(ValidationResult::Invalid);
+			}
+
+			Ok(ValidationResult::Valid(ValidationOutputs {
+				head_data: res.head_data,
+				global_validation_schedule: global_validation,
+				local_validation_data: local_validation,
+				upward_messages: res.upward_messages,
+				fees: 0,
+				new_validation_code: res.new_validation_code,
+				processed_downward_messages: res.processed_downward_messages,
+				horizontal_messages: res.horizontal_messages,
+				hrmp_watermark: res.hrmp_watermark,
+			}))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::{self, ThreadPool};
+	use futures::future;
+	use polkadot_primitives::v1::{HeadData, BlockData, Id as ParaId, collator_signature_payload};
+	use sp_keyring::Sr25519Keyring;
+	use assert_matches::assert_matches;
+
+	struct MockValidationBackend;
+
+	impl ValidationBackend for MockValidationBackend {
+		type Arg = Result<WasmValidationResult, WasmError>;
+
+		fn validate(
+			arg: Self::Arg,
+			_validation_code: &ValidationCode,
+			_params: ValidationParams,
+		) -> Result<WasmValidationResult, WasmError> {
+			arg
+		}
+	}
+
+	fn collator_sign(descriptor: &mut CandidateDescriptor, collator: Sr25519Keyring) {
+		descriptor.collator = collator.public().into();
+		let payload = collator_signature_payload(
+			&descriptor.relay_parent,
+			&descriptor.para_id,
+			&descriptor.pov_hash,
+		);
+
+		descriptor.signature = collator.sign(&payload[..]).into();
+		assert!(descriptor.check_collator_signature().is_ok());
+	}
+
+	fn omitted_validation() -> OmittedValidationData {
+		OmittedValidationData {
+			global_validation: GlobalValidationSchedule {
+				max_code_size: 1024,
+				max_head_data_size: 1024,
+				block_number: 5,
+			},
+			local_validation: LocalValidationData {
+				parent_head: HeadData(vec![1, 2, 3]),
+				balance: 0,
+				validation_code_hash: Default::default(),
+				code_upgrade_allowed: None,
+				downward_messages_hash: downward_messages_hash(&[]),
+				inbound_hrmp_messages: Vec::new(),
+			},
+		}
+	}
+
+	fn make_candidate(pov: &PoV) -> CandidateDescriptor {
+		let mut descriptor = CandidateDescriptor::default();
+		descriptor.para_id = ParaId::from(1);
+		descriptor.relay_parent = [2; 32].into();
+		descriptor.pov_hash = pov.hash();
+		collator_sign(&mut descriptor, Sr25519Keyring::Alice);
+
+		descriptor
+	}
+
+	#[test]
+	fn basic_checks_reject_mismatched_descriptor() {
+		let pov = PoV { block_data: BlockData(vec![1; 32]) };
+		let mut descriptor = make_candidate(&pov);
+
+		let local_validation = omitted_validation().local_validation;
+
+		assert!(passes_basic_checks(&descriptor, &pov, &local_validation, &[]).is_ok());
+
+		// the downward messages must match the hash of the local validation data.
+		assert!(passes_basic_checks(
+			&descriptor,
+			&pov,
+			&local_validation,
+			&[DownwardMessage::Opaque(vec![1])],
+		).is_err());
+
+		descriptor.pov_hash = [5; 32].into();
+		assert!(passes_basic_checks(&descriptor, &pov, &local_validation, &[]).is_err());
+
+		let mut descriptor = make_candidate(&pov);
+		descriptor.para_id = ParaId::from(2);
+		assert!(passes_basic_checks(&descriptor, &pov, &local_validation, &[]).is_err());
+	}
+
+	#[test]
+	fn candidate_validation_ok_is_ok() {
+		let pov = PoV { block_data: BlockData(vec![1; 32]) };
+		let descriptor = make_candidate(&pov);
+		let omitted_validation = omitted_validation();
+
+		let validation_result = WasmValidationResult {
+			head_data: HeadData(vec![1, 1, 1]),
+			new_validation_code: None,
+			upward_messages: Vec::new(),
+			processed_downward_messages: 0,
+			horizontal_messages: Vec::new(),
+			hrmp_watermark: 0,
+		};
+
+		let v = validate_candidate_exhaustive::<MockValidationBackend>(
+			Ok(validation_result),
+			omitted_validation.clone(),
+			vec![1, 2, 3].into(),
+			Vec::new(),
+			descriptor,
+			Arc::new(pov),
+		).unwrap();
+
+		assert_matches!(v, ValidationResult::Valid(outputs) => {
+			assert_eq!(outputs.head_data, HeadData(vec![1, 1, 1]));
+			assert_eq!(outputs.global_validation_schedule, omitted_validation.global_validation);
+			assert_eq!(outputs.local_validation_data, omitted_validation.local_validation);
+			assert!(outputs.upward_messages.is_empty());
+			assert!(outputs.new_validation_code.is_none());
+		});
+	}
+
+	#[test]
+	fn candidate_validation_bad_return_is_invalid() {
+		let pov = PoV { block_data: BlockData(vec![1; 32]) };
+		let descriptor = make_candidate(&pov);
+
+		let v = validate_candidate_exhaustive::<MockValidationBackend>(
+			Err(WasmError::BadReturn),
+			omitted_validation(),
+			vec![1, 2, 3].into(),
+			Vec::new(),
+			descriptor,
+			Arc::new(pov),
+		).unwrap();
+
+		assert_matches!(v, ValidationResult::Invalid);
+	}
+
+	#[test]
+	fn candidate_validation_internal_error_is_error() {
+		let pov = PoV { block_data: BlockData(vec![1; 32]) };
+		let descriptor = make_candidate(&pov);
+
+		let v = validate_candidate_exhaustive::<MockValidationBackend>(
+			Err(WasmError::Io(std::io::Error::new(std::io::ErrorKind::Other, "dummy"))),
+			omitted_validation(),
+			vec![1, 2, 3].into(),
+			Vec::new(),
+			descriptor,
+			Arc::new(pov),
+		);
+
+		assert!(v.is_err());
+	}
+
+	#[test]
+	fn candidate_validation_checks_outputs() {
+		let pov = PoV { block_data: BlockData(vec![1; 32]) };
+		let descriptor = make_candidate(&pov);
+
+		let oversized_head = WasmValidationResult {
+			head_data: HeadData(vec![1; 2048]),
+			new_validation_code: None,
+			upward_messages: Vec::new(),
+			processed_downward_messages: 0,
+			horizontal_messages: Vec::new(),
+			hrmp_watermark: 0,
+		};
+
+		let v = validate_candidate_exhaustive::<MockValidationBackend>(
+			Ok(oversized_head),
+			omitted_validation(),
+			vec![1, 2, 3].into(),
+			Vec::new(),
+			descriptor.clone(),
+			Arc::new(pov.clone()),
+		).unwrap();
+
+		assert_matches!(v, ValidationResult::Invalid);
+
+		let disallowed_upgrade = WasmValidationResult {
+			head_data: HeadData(vec![1, 1, 1]),
+			new_validation_code: Some(vec![2, 2, 2].into()),
+			upward_messages: Vec::new(),
+			processed_downward_messages: 0,
+			horizontal_messages: Vec::new(),
+			hrmp_watermark: 0,
+		};
+
+		let v = validate_candidate_exhaustive::<MockValidationBackend>(
+			Ok(disallowed_upgrade),
+			omitted_validation(),
+			vec![1, 2, 3].into(),
+			Vec::new(),
+			descriptor.clone(),
+			Arc::new(pov.clone()),
+		).unwrap();
+
+		assert_matches!(v, ValidationResult::Invalid);
+
+		let future_watermark = WasmValidationResult {
+			head_data: HeadData(vec![1, 1, 1]),
+			new_validation_code: None,
+			upward_messages: Vec::new(),
+			processed_downward_messages: 0,
+			horizontal_messages: Vec::new(),
+			hrmp_watermark: 6,
+		};
+
+		let v = validate_candidate_exhaustive::<MockValidationBackend>(
+			Ok(future_watermark),
+			omitted_validation(),
+			vec![1, 2, 3].into(),
+			Vec::new(),
+			descriptor,
+			Arc::new(pov),
+		).unwrap();
+
+		assert_matches!(v, ValidationResult::Invalid);
+	}
+
+	#[test]
+	fn unknown_para_is_invalid() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut ctx_handle) = subsystem_test::make_subsystem_context(pool);
+
+		let pov = PoV { block_data: BlockData(vec![1; 32]) };
+		let descriptor = make_candidate(&pov);
+		let relay_parent = descriptor.relay_parent;
+		let (tx, rx) = oneshot::channel();
+
+		let mut code_cache = CodeCache::new(CODE_CACHE_SIZE);
+		let validate = spawn_validate_from_chain_state(
+			&mut ctx,
+			&mut code_cache,
+			None,
+			descriptor,
+			Arc::new(pov),
+			tx,
+		);
+
+		let test_fut = async move {
+			assert_matches!(
+				ctx_handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					r,
+					RuntimeApiRequest::GlobalValidationSchedule(tx),
+				)) => {
+					assert_eq!(r, relay_parent);
+					tx.send(omitted_validation().global_validation).unwrap();
+				}
+			);
+
+			assert_matches!(
+				ctx_handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					r,
+					RuntimeApiRequest::LocalValidationData(p, tx),
+				)) => {
+					assert_eq!(r, relay_parent);
+					assert_eq!(p, ParaId::from(1));
+					tx.send(None).unwrap();
+				}
+			);
+
+			assert_matches!(rx.await, Ok(Ok(ValidationResult::Invalid)));
+		};
+
+		executor::block_on(future::join(validate, test_fut)).0.unwrap();
+	}
+
+	#[test]
+	fn validation_code_is_fetched_once() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut ctx_handle) = subsystem_test::make_subsystem_context(pool);
+
+		let pov = PoV { block_data: BlockData(vec![1; 32]) };
+		let descriptor = make_candidate(&pov);
+		let relay_parent = descriptor.relay_parent;
+		let code_hash = Hash::repeat_byte(42);
+
+		let validate = async move {
+			let mut code_cache = CodeCache::new(CODE_CACHE_SIZE);
+			let pov = Arc::new(pov);
+
+			for _ in 0..2 {
+				let (tx, _rx) = oneshot::channel();
+				spawn_validate_from_chain_state(
+					&mut ctx,
+					&mut code_cache,
+					None,
+					descriptor.clone(),
+					pov.clone(),
+					tx,
+				).await?;
+			}
+
+			// a candidate of an unknown para, marking the end of the test.
+			let (tx, _rx) = oneshot::channel();
+			spawn_validate_from_chain_state(
+				&mut ctx,
+				&mut code_cache,
+				None,
+				descriptor,
+				pov,
+				tx,
+			).await
+		};
+
+		let test_fut = async move {
+			for fetched in vec![true, false] {
+				assert_matches!(
+					ctx_handle.recv().await,
+					AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+						r,
+						RuntimeApiRequest::GlobalValidationSchedule(tx),
+					)) if r == relay_parent => {
+						tx.send(omitted_validation().global_validation).unwrap();
+					}
+				);
+
+				assert_matches!(
+					ctx_handle.recv().await,
+					AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+						r,
+						RuntimeApiRequest::LocalValidationData(_, tx),
+					)) if r == relay_parent => {
+						tx.send(Some(omitted_validation().local_validation)).unwrap();
+					}
+				);
+
+				assert_matches!(
+					ctx_handle.recv().await,
+					AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+						r,
+						RuntimeApiRequest::ValidationCodeHash(_, _, None, tx),
+					)) if r == relay_parent => {
+						tx.send(Some(code_hash)).unwrap();
+					}
+				);
+
+				if fetched {
+					assert_matches!(
+						ctx_handle.recv().await,
+						AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+							r,
+							RuntimeApiRequest::ValidationCodeByHash(h, tx),
+						)) if r == relay_parent && h == code_hash => {
+							tx.send(Some(ValidationCode(vec![1, 2, 3]))).unwrap();
+						}
+					);
+				}
+
+				assert_matches!(
+					ctx_handle.recv().await,
+					AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+						r,
+						RuntimeApiRequest::DownwardMessages(_, tx),
+					)) if r == relay_parent => {
+						tx.send(Vec::new()).unwrap();
+					}
+				);
+			}
+
+			// the cached code was used for the second candidate.
+			assert_matches!(
+				ctx_handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					_,
+					RuntimeApiRequest::GlobalValidationSchedule(tx),
+				)) => {
+					tx.send(omitted_validation().global_validation).unwrap();
+				}
+			);
+
+			assert_matches!(
+				ctx_handle.recv().await,
+				AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					_,
+					RuntimeApiRequest::LocalValidationData(_, tx),
+				)) => {
+					tx.send(None).unwrap();
+				}
+			);
+		};
+
+		executor::block_on(future::join(validate, test_fut)).0.unwrap();
+	}
+}
@@ -0,0 +1,535 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Misbehavior Arbitration subsystem.
+//!
+//! This gathers reports of validator misbehavior from other subsystems, verifies them against
+//! the state of the relay-parent they were witnessed under and turns the ones which hold up
+//! into proofs which can be included in a relay-chain block by the provisioner.
+//!
+//! Every offender is only reported once per session, and every disputed candidate is only
+//! escalated once.
+
+#![deny(missing_docs)]
+
+use std::collections::{HashMap, HashSet};
+
+use futures::channel::oneshot;
+
+use polkadot_subsystem::{
+	Subsystem, SubsystemContext, SpawnedSubsystem, SubsystemResult,
+	FromOverseer, OverseerSignal,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, MisbehaviorArbitrationMessage, ProvisionerMessage, ProvisionableData,
+	RuntimeApiMessage, RuntimeApiRequest,
+};
+use polkadot_node_primitives::{MisbehaviorReport, SignedFullStatement};
+use polkadot_primitives::v1::{
+	CompactStatement, DoubleVoteReport, Hash, SessionIndex, SignedStatement, SigningContext,
+	ValidatorId,
+};
+
+const LOG_TARGET: &str = "misbehavior_arbitration";
+
+/// The number of sessions, including the latest one, for which reported offenders are
+/// remembered. Reports from older sessions are ignored.
+const SESSIONS_TO_KEEP: SessionIndex = 2;
+
+/// The Misbehavior Arbitration subsystem.
+pub struct MisbehaviorArbitrationSubsystem;
+
+impl<C> Subsystem<C> for MisbehaviorArbitrationSubsystem
+	where C: SubsystemContext<Message = MisbehaviorArbitrationMessage>
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		SpawnedSubsystem(Box::pin(async move {
+			if let Err(e) = run(ctx).await {
+				log::error!(target: LOG_TARGET, "Misbehavior arbitration subsystem exited with an error: {:?}", e);
+			}
+		}))
+	}
+}
+
+/// What has already been reported, by session.
+#[derive(Default)]
+struct State {
+	/// The latest session a report has been accepted for.
+	latest_session: SessionIndex,
+	/// Validators already reported for issuing conflicting statements.
+	reported_offenders: HashMap<SessionIndex, HashSet<ValidatorId>>,
+	/// Candidates whose validity has already been disputed.
+	disputed_candidates: HashMap<SessionIndex, HashSet<Hash>>,
+}
+
+impl State {
+	/// Whether reports from the given session should still be considered. Notes the session
+	/// as the latest one and prunes older ones, if it is newer than any seen before.
+	fn note_session(&mut self, session: SessionIndex) -> bool {
+		if session > self.latest_session {
+			self.latest_session = session;
+
+			let earliest = self.earliest_session();
+			self.reported_offenders.retain(|s, _| *s >= earliest);
+			self.disputed_candidates.retain(|s, _| *s >= earliest);
+		}
+
+		session >= self.earliest_session()
+	}
+
+	fn earliest_session(&self) -> SessionIndex {
+		(self.latest_session + 1).saturating_sub(SESSIONS_TO_KEEP)
+	}
+
+	/// Note an offender. Returns `false` if it has already been reported for the session.
+	fn note_offender(&mut self, session: SessionIndex, offender: ValidatorId) -> bool {
+		self.reported_offenders.entry(session).or_default().insert(offender)
+	}
+
+	/// Note a disputed candidate. Returns `false` if it has already been disputed in the session.
+	fn note_dispute(&mut self, session: SessionIndex, candidate_hash: Hash) -> bool {
+		self.disputed_candidates.entry(session).or_default().insert(candidate_hash)
+	}
+}
+
+async fn run(
+	mut ctx: impl SubsystemContext<Message = MisbehaviorArbitrationMessage>,
+) -> SubsystemResult<()> {
+	let mut state = State::default();
+
+	loop {
+		match ctx.recv().await? {
+			FromOverseer::Signal(OverseerSignal::StartWork(_)) => {}
+			FromOverseer::Signal(OverseerSignal::StopWork(_)) => {}
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(_)) => {}
+			FromOverseer::Signal(OverseerSignal::Conclude) => return Ok(()),
+			FromOverseer::Communication { msg } => match msg {
+				MisbehaviorArbitrationMessage::Report(relay_parent, report) => {
+					handle_report(&mut ctx, &mut state, relay_parent, report).await?;
+				}
+			}
+		}
+	}
+}
+
+async fn runtime_api_request<T>(
+	ctx: &mut impl SubsystemContext<Message = MisbehaviorArbitrationMessage>,
+	relay_parent: Hash,
+	request: RuntimeApiRequest,
+	receiver: oneshot::Receiver<T>,
+) -> SubsystemResult<Result<T, oneshot::Canceled>> {
+	ctx.send_message(
+		AllMessages::RuntimeApi(RuntimeApiMessage::Request(relay_parent, request))
+	).await?;
+
+	Ok(receiver.await)
+}
+
+/// Convert a full statement into a compact one, checking its signature against the validator set.
+fn to_compact(
+	statement: &SignedFullStatement,
+	signing_context: &SigningContext,
+	validators: &[ValidatorId],
+) -> Option<SignedStatement> {
+	let key = validators.get(statement.validator_index() as usize)?;
+
+	SignedStatement::new(
+		statement.payload().to_compact(),
+		statement.validator_index(),
+		statement.signature().clone(),
+		signing_context,
+		key,
+	)
+}
+
+/// Verify a report against the state of the relay-parent it was witnessed under and forward
+/// the resulting proof to the provisioner, unless it has been dealt with already.
+async fn handle_report(
+	ctx: &mut impl SubsystemContext<Message = MisbehaviorArbitrationMessage>,
+	state: &mut State,
+	relay_parent: Hash,
+	report: MisbehaviorReport,
+) -> SubsystemResult<()> {
+	let validators = {
+		let (tx, rx) = oneshot::channel();
+		match runtime_api_request(ctx, relay_parent, RuntimeApiRequest::Validators(tx), rx).await? {
+			Ok(v) => v,
+			Err(_) => return Ok(()),
+		}
+	};
+
+	let signing_context = {
+		let (tx, rx) = oneshot::channel();
+		match runtime_api_request(ctx, relay_parent, RuntimeApiRequest::SigningContext(tx), rx).await? {
+			Ok(c) => c,
+			Err(_) => return Ok(()),
+		}
+	};
+
+	let session = signing_context.session_index;
+	if !state.note_session(session) {
+		log::debug!(target: LOG_TARGET, "Ignoring report from old session {}", session);
+		return Ok(());
+	}
+
+	let data = match report {
+		MisbehaviorReport::SelfContradiction(_, first, second)
+			| MisbehaviorReport::DoubleVote(first, second) =>
+		{
+			let (first, second) = match (
+				to_compact(&first, &signing_context, &validators),
+				to_compact(&second, &signing_context, &validators),
+			) {
+				(Some(first), Some(second)) => (first, second),
+				_ => {
+					log::debug!(target: LOG_TARGET, "Discarding report with bad signatures");
+					return Ok(());
+				}
+			};
+
+			let identity = validators[first.validator_index() as usize].clone();
+			let report = DoubleVoteReport { signing_context, identity, first, second };
+
+			if report.check().is_err() {
+				log::debug!(target: LOG_TARGET, "Discarding report of non-conflicting statements");
+				return Ok(());
+			}
+
+			if !state.note_offender(session, report.identity.clone()) {
+				return Ok(());
+			}
+
+			ProvisionableData::MisbehaviorReport(relay_parent, report)
+		}
+		MisbehaviorReport::CandidateValidityDisagreement(receipt, statements) => {
+			let candidate_hash = receipt.hash();
+
+			let statements: Vec<_> = statements.iter()
+				.filter_map(|s| to_compact(s, &signing_context, &validators))
+				.filter(|s| s.payload().candidate_hash() == &candidate_hash)
+				.collect();
+
+			let is_invalid = |s: &SignedStatement| match s.payload() {
+				CompactStatement::Invalid(_) => true,
+				CompactStatement::Candidate(_) | CompactStatement::Valid(_) => false,
+			};
+
+			if !statements.iter().any(is_invalid) || statements.iter().all(is_invalid) {
+				log::debug!(target: LOG_TARGET, "Discarding disagreement without conflicting statements");
+				return Ok(());
+			}
+
+			if !state.note_dispute(session, candidate_hash) {
+				return Ok(());
+			}
+
+			ProvisionableData::Dispute(relay_parent, candidate_hash, statements)
+		}
+	};
+
+	ctx.send_message(
+		AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(data))
+	).await
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{executor, future, pin_mut};
+	use futures::executor::ThreadPool;
+	use polkadot_node_primitives::Statement;
+	use polkadot_primitives::v1::CommittedCandidateReceipt;
+	use sp_keyring::Sr25519Keyring;
+	use assert_matches::assert_matches;
+	use subsystem_test::TestSubsystemContextHandle;
+
+	const VALIDATORS: [Sr25519Keyring; 3] = [
+		Sr25519Keyring::Alice,
+		Sr25519Keyring::Bob,
+		Sr25519Keyring::Charlie,
+	];
+
+	fn validator_ids() -> Vec<ValidatorId> {
+		VALIDATORS.iter().map(|v| v.public().into()).collect()
+	}
+
+	fn signing_context(session_index: SessionIndex) -> SigningContext {
+		SigningContext { session_index, parent_hash: [1; 32].into() }
+	}
+
+	fn candidate(para_id: u32) -> CommittedCandidateReceipt {
+		let mut c = CommittedCandidateReceipt::default();
+		c.descriptor.para_id = para_id.into();
+		c
+	}
+
+	fn sign(statement: Statement, context: &SigningContext, index: usize) -> SignedFullStatement {
+		SignedFullStatement::sign(
+			statement,
+			context,
+			index as _,
+			&VALIDATORS[index].pair().into(),
+		)
+	}
+
+	/// Answer the runtime API requests made for a single report.
+	async fn answer_runtime_requests(
+		handle: &mut TestSubsystemContextHandle<MisbehaviorArbitrationMessage>,
+		relay_parent: Hash,
+		context: SigningContext,
+	) {
+		assert_matches!(
+			handle.recv().await,
+			AllMessages::RuntimeApi(
+				RuntimeApiMessage::Request(r, RuntimeApiRequest::Validators(tx))
+			) if r == relay_parent => {
+				tx.send(validator_ids()).unwrap();
+			}
+		);
+
+		assert_matches!(
+			handle.recv().await,
+			AllMessages::RuntimeApi(
+				RuntimeApiMessage::Request(r, RuntimeApiRequest::SigningContext(tx))
+			) if r == relay_parent => {
+				tx.send(context).unwrap();
+			}
+		);
+	}
+
+	fn test_harness<T: future::Future<Output=()>>(
+		test: impl FnOnce(TestSubsystemContextHandle<MisbehaviorArbitrationMessage>) -> T,
+	) {
+		let pool = ThreadPool::new().unwrap();
+		let (ctx, handle) = subsystem_test::make_subsystem_context(pool);
+
+		let subsystem = run(ctx);
+		let test = test(handle);
+
+		pin_mut!(subsystem);
+		pin_mut!(test);
+
+		executor::block_on(future::select(test, subsystem));
+	}
+
+	#[test]
+	fn double_vote_is_reported_once_per_session() {
+		let relay_parent: Hash = [1; 32].into();
+		let context = signing_context(1);
+
+		let first = sign(Statement::Seconded(candidate(1)), &context, 0);
+		let second = sign(Statement::Seconded(candidate(2)), &context, 0);
+
+		let other_first = sign(Statement::Seconded(candidate(1)), &context, 1);
+		let other_second = sign(Statement::Seconded(candidate(2)), &context, 1);
+
+		test_harness(|mut handle| async move {
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::DoubleVote(first.clone(), second.clone()),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::MisbehaviorReport(r, report)
+				)) => {
+					assert_eq!(r, relay_parent);
+					assert_eq!(report.identity, validator_ids()[0]);
+					assert_eq!(report.signing_context, context);
+					assert!(report.check().is_ok());
+				}
+			);
+
+			// the duplicate is not forwarded, so the next report is that of another validator.
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::DoubleVote(second, first),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::DoubleVote(other_first, other_second),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::MisbehaviorReport(_, report)
+				)) => {
+					assert_eq!(report.identity, validator_ids()[1]);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn self_contradiction_is_reported() {
+		let relay_parent: Hash = [1; 32].into();
+		let context = signing_context(1);
+		let candidate = candidate(1);
+
+		let first = sign(Statement::Valid(candidate.hash()), &context, 1);
+		let second = sign(Statement::Invalid(candidate.hash()), &context, 1);
+
+		test_harness(|mut handle| async move {
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::SelfContradiction(candidate.to_plain(), first, second),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::MisbehaviorReport(_, report)
+				)) => {
+					assert_eq!(report.identity, validator_ids()[1]);
+					assert_eq!(report.first.payload(), &CompactStatement::Valid(candidate.hash()));
+					assert_eq!(report.second.payload(), &CompactStatement::Invalid(candidate.hash()));
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn reports_with_bad_signatures_or_without_conflicts_are_discarded() {
+		let relay_parent: Hash = [1; 32].into();
+		let context = signing_context(1);
+		let candidate_a = candidate(1);
+		let candidate_b = candidate(2);
+
+		// signed in the wrong session.
+		let bad_signature = sign(Statement::Seconded(candidate_b.clone()), &signing_context(0), 0);
+		let first = sign(Statement::Seconded(candidate_a.clone()), &context, 0);
+		let agreeing = sign(Statement::Valid(candidate_a.hash()), &context, 0);
+		let second = sign(Statement::Seconded(candidate_b), &context, 0);
+
+		test_harness(|mut handle| async move {
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::DoubleVote(first.clone(), bad_signature),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::SelfContradiction(
+						candidate_a.to_plain(),
+						first.clone(),
+						agreeing,
+					),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			// neither report has been forwarded, so the next message is for a valid one.
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::DoubleVote(first, second),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::MisbehaviorReport(_, report)
+				)) => {
+					assert_eq!(report.identity, validator_ids()[0]);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn disagreement_is_escalated_as_dispute() {
+		let relay_parent: Hash = [1; 32].into();
+		let context = signing_context(1);
+		let candidate = candidate(1);
+
+		let statements = vec![
+			sign(Statement::Seconded(candidate.clone()), &context, 0),
+			sign(Statement::Valid(candidate.hash()), &context, 1),
+			sign(Statement::Invalid(candidate.hash()), &context, 2),
+		];
+
+		test_harness(|mut handle| async move {
+			handle.send(FromOverseer::Communication {
+				msg: MisbehaviorArbitrationMessage::Report(
+					relay_parent,
+					MisbehaviorReport::CandidateValidityDisagreement(
+						candidate.to_plain(),
+						statements,
+					),
+				),
+			}).await;
+
+			answer_runtime_requests(&mut handle, relay_parent, context.clone()).await;
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Dispute(r, candidate_hash, statements)
+				)) => {
+					assert_eq!(r, relay_parent);
+					assert_eq!(candidate_hash, candidate.hash());
+					assert_eq!(statements.len(), 3);
+					assert_eq!(statements[0].payload(), &CompactStatement::Candidate(candidate.hash()));
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn old_sessions_are_pruned() {
+		let mut state = State::default();
+
+		assert!(state.note_session(1));
+		assert!(state.note_offender(1, validator_ids()[0].clone()));
+		assert!(!state.note_offender(1, validator_ids()[0].clone()));
+
+		assert!(state.note_session(2));
+		assert!(state.reported_offenders.contains_key(&1));
+
+		assert!(state.note_session(3));
+		assert!(!state.reported_offenders.contains_key(&1));
+		assert!(!state.note_session(1));
+		assert!(state.note_session(2));
+	}
+}
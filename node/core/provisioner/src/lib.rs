@@ -0,0 +1,941 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The provisioner is responsible for assembling a relay chain block
+//! from a set of available parachain candidates of its choice.
+//!
+//! It keeps one job per relay-parent, which collects the bitfields and backed candidates
+//! provisioned on top of that relay-parent and selects the data for the `InclusionInherent`
+//! when the proposer asks for it.
+
+#![recursion_limit="256"]
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::pin::Pin;
+use std::time::Duration;
+
+use log;
+use futures::{
+	select, FutureExt, SinkExt, StreamExt,
+	channel::{oneshot, mpsc},
+	future::{self, Either},
+	task::{Spawn, SpawnError, SpawnExt},
+};
+use futures_timer::Delay;
+use streamunordered::{StreamUnordered, StreamYield};
+
+use polkadot_primitives::v1::{
+	BackedCandidate, CoreState, Hash, Id as ParaId, SignedAvailabilityBitfield, ValidatorId,
+	ValidatorIndex,
+};
+use polkadot_subsystem::{
+	FromOverseer, OverseerSignal, Subsystem, SubsystemContext, SpawnedSubsystem,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, ProvisionableData, ProvisionerInherentData, ProvisionerMessage,
+	RuntimeApiMessage, RuntimeApiRequest,
+};
+
+const LOG_TARGET: &str = "provisioner";
+
+#[derive(Debug, derive_more::From)]
+enum Error {
+	JobNotFound(Hash),
+	#[from]
+	Oneshot(oneshot::Canceled),
+	#[from]
+	Mpsc(mpsc::SendError),
+	#[from]
+	Spawn(SpawnError),
+}
+
+/// Holds all data needed for a provisioning job operation.
+struct ProvisioningJob {
+	/// The hash of the relay parent on top of which this job is doing it's work.
+	parent: Hash,
+	/// Inbound message channel receiving part.
+	rx_to: mpsc::Receiver<ToJob>,
+	/// Outbound message channel sending part.
+	tx_from: mpsc::Sender<FromJob>,
+
+	/// Potential block authors which want to be kept updated with new provisionable data.
+	provisionable_data_channels: Vec<mpsc::Sender<ProvisionableData>>,
+	/// The first bitfield we have received from each validator.
+	signed_bitfields: BTreeMap<ValidatorIndex, SignedAvailabilityBitfield>,
+	/// The first backed candidate we have received for each para.
+	backed_candidates: BTreeMap<ParaId, BackedCandidate>,
+}
+
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A message type that is sent from `ProvisioningSubsystem` to `ProvisioningJob`.
+enum ToJob {
+	/// A `ProvisionerMessage`.
+	Provisioner(ProvisionerMessage),
+	/// Stop working.
+	Stop,
+}
+
+/// A message type that is sent from `ProvisioningJob` to `ProvisioningSubsystem`.
+enum FromJob {
+	RuntimeApiMessage(RuntimeApiMessage),
+}
+
+impl From<FromJob> for AllMessages {
+	fn from(f: FromJob) -> Self {
+		match f {
+			FromJob::RuntimeApiMessage(msg) => AllMessages::RuntimeApi(msg),
+		}
+	}
+}
+
+/// The number of validators which must attest to the availability of a candidate
+/// for the runtime to consider it available.
+///
+/// This mirrors the threshold used by the inclusion module of the runtime.
+const fn availability_threshold(n_validators: usize) -> usize {
+	let mut threshold = (n_validators * 2) / 3;
+	threshold += (n_validators * 2) % 3;
+	threshold
+}
+
+/// The reason the runtime would reject the given bitfield, if any.
+fn bitfield_rejection(
+	n_validators: usize,
+	availability_cores: &[CoreState],
+	bitfield: &SignedAvailabilityBitfield,
+) -> Option<&'static str> {
+	if (bitfield.validator_index() as usize) >= n_validators {
+		return Some("unknown validator");
+	}
+
+	if bitfield.payload().0.len() != availability_cores.len() {
+		return Some("wrong number of bits");
+	}
+
+	let attests_unoccupied = bitfield.payload().0.iter()
+		.zip(availability_cores.iter())
+		.any(|(bit, core)| *bit && core.occupied().is_none());
+
+	if attests_unoccupied {
+		return Some("bit set for an unoccupied core");
+	}
+
+	None
+}
+
+/// Select the bitfields and backed candidates to be put into the `InclusionInherent`.
+///
+/// Bitfields are returned in ascending order of the validator index, skipping those which
+/// the runtime would reject: bitfields of unknown validators, bitfields of the wrong length and
+/// bitfields attesting to the availability of unoccupied cores.
+///
+/// A backed candidate is only selected if its para is scheduled on a core which is free in the
+/// next block, or on a core whose candidate the selected bitfields, together with the
+/// availability votes already on chain, make available. Candidates are returned in the order of
+/// the cores they are assigned to.
+///
+/// Every skipped bitfield and candidate is logged. The runtime drops whatever else fails its
+/// checks on its own.
+fn select_inherent_data(
+	n_validators: usize,
+	availability_cores: &[CoreState],
+	signed_bitfields: &BTreeMap<ValidatorIndex, SignedAvailabilityBitfield>,
+	backed_candidates: &BTreeMap<ParaId, BackedCandidate>,
+) -> ProvisionerInherentData {
+	let bitfields: Vec<_> = signed_bitfields.values()
+		.filter(|bitfield| match bitfield_rejection(n_validators, availability_cores, bitfield) {
+			None => true,
+			Some(reason) => {
+				log::warn!(
+					target: LOG_TARGET,
+					"Skipping the bitfield of validator {}: {}",
+					bitfield.validator_index(),
+					reason,
+				);
+				false
+			}
+		})
+		.cloned()
+		.collect();
+
+	// The validators attesting to the availability of the candidate on each core, both
+	// on chain and in the selected bitfields.
+	let mut availability_votes: Vec<HashSet<usize>> = availability_cores.iter()
+		.map(|core| core.occupied().map_or_else(HashSet::new, |occupied| occupied.availability.iter()
+			.enumerate()
+			.filter(|(_, bit)| **bit)
+			.map(|(validator, _)| validator)
+			.collect()
+		))
+		.collect();
+
+	for bitfield in &bitfields {
+		for (core, _) in bitfield.payload().0.iter().enumerate().filter(|(_, bit)| **bit) {
+			availability_votes[core].insert(bitfield.validator_index() as usize);
+		}
+	}
+
+	let threshold = availability_threshold(n_validators);
+
+	let candidates: Vec<_> = availability_cores.iter()
+		.enumerate()
+		.filter_map(|(core, state)| match state {
+			CoreState::Free(scheduled) | CoreState::Freeing(_, scheduled) =>
+				scheduled.as_ref().map(|scheduled| &scheduled.assignment),
+			CoreState::Occupied(occupied) => if availability_votes[core].len() >= threshold {
+				occupied.next_up_on_available.as_ref()
+			} else {
+				None
+			},
+		})
+		.filter_map(|assignment| backed_candidates.get(&assignment.para_id).cloned())
+		.collect();
+
+	for para_id in backed_candidates.keys()
+		.filter(|para_id| candidates.iter().all(|c| c.descriptor().para_id != **para_id))
+	{
+		log::debug!(
+			target: LOG_TARGET,
+			"Skipping the backed candidate of para {:?}: not scheduled on a core free in the next block",
+			para_id,
+		);
+	}
+
+	(bitfields, candidates)
+}
+
+impl ProvisioningJob {
+	/// Run asynchronously.
+	async fn run(mut self) -> Result<(), Error> {
+		while let Some(msg) = self.rx_to.next().await {
+			match msg {
+				ToJob::Provisioner(msg) => {
+					self.process_msg(msg).await?;
+				}
+				_ => break,
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn process_msg(&mut self, msg: ProvisionerMessage) -> Result<(), Error> {
+		match msg {
+			ProvisionerMessage::RequestBlockAuthorshipData(_, sender) => {
+				self.provisionable_data_channels.push(sender);
+			}
+			ProvisionerMessage::RequestInherentData(_, sender) => {
+				match self.inherent_data().await {
+					Ok(data) => {
+						let _ = sender.send(data);
+					}
+					Err(e) => {
+						log::warn!(
+							"ProvisioningJob({}) failed to select inherent data: {:?}",
+							self.parent,
+							e,
+						);
+					}
+				}
+			}
+			ProvisionerMessage::ProvisionableData(data) => {
+				self.distribute_provisionable_data(&data).await;
+				self.note_provisionable_data(data);
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Send the provisionable data to all potential block authors, dropping the ones which
+	/// are no longer interested.
+	async fn distribute_provisionable_data(&mut self, data: &ProvisionableData) {
+		let channels = std::mem::take(&mut self.provisionable_data_channels);
+
+		for mut channel in channels {
+			if channel.send(data.clone()).await.is_ok() {
+				self.provisionable_data_channels.push(channel);
+			}
+		}
+	}
+
+	fn note_provisionable_data(&mut self, data: ProvisionableData) {
+		match data {
+			ProvisionableData::Bitfield(_, signed_bitfield) => {
+				self.signed_bitfields
+					.entry(signed_bitfield.validator_index())
+					.or_insert(signed_bitfield);
+			}
+			ProvisionableData::BackedCandidate(backed_candidate) => {
+				self.backed_candidates
+					.entry(backed_candidate.descriptor().para_id)
+					.or_insert(backed_candidate);
+			}
+			// Misbehavior reports and disputes only need to reach the block authors.
+			_ => (),
+		}
+	}
+
+	async fn inherent_data(&mut self) -> Result<ProvisionerInherentData, Error> {
+		let (validators, availability_cores) = futures::try_join!(
+			request_validators(self.parent, &mut self.tx_from).await?,
+			request_availability_cores(self.parent, &mut self.tx_from).await?,
+		)?;
+
+		Ok(select_inherent_data(
+			validators.len(),
+			&availability_cores,
+			&self.signed_bitfields,
+			&self.backed_candidates,
+		))
+	}
+}
+
+struct JobHandle {
+	abort_handle: future::AbortHandle,
+	to_job: mpsc::Sender<ToJob>,
+	finished: oneshot::Receiver<()>,
+	su_handle: usize,
+}
+
+impl JobHandle {
+	async fn stop(mut self) {
+		let _ = self.to_job.send(ToJob::Stop).await;
+		let stop_timer = Delay::new(Duration::from_secs(1));
+
+		match future::select(stop_timer, self.finished).await {
+			Either::Left((_, _)) => {
+			},
+			Either::Right((_, _)) => {
+				self.abort_handle.abort();
+			},
+		}
+	}
+
+	async fn send_msg(&mut self, msg: ToJob) -> Result<(), Error> {
+		Ok(self.to_job.send(msg).await?)
+	}
+}
+
+struct Jobs<S> {
+	spawner: S,
+	running: HashMap<Hash, JobHandle>,
+	outgoing_msgs: StreamUnordered<mpsc::Receiver<FromJob>>,
+}
+
+async fn run_job(
+	parent: Hash,
+	rx_to: mpsc::Receiver<ToJob>,
+	tx_from: mpsc::Sender<FromJob>,
+) -> Result<(), Error> {
+	let job = ProvisioningJob {
+		parent,
+		rx_to,
+		tx_from,
+		provisionable_data_channels: Vec::new(),
+		signed_bitfields: BTreeMap::new(),
+		backed_candidates: BTreeMap::new(),
+	};
+
+	job.run().await
+}
+
+/// Request a validator set from the `RuntimeApi`.
+async fn request_validators(
+	parent: Hash,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<Vec<ValidatorId>>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::Validators(tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+/// Request the states of the availability cores from `RuntimeApi`.
+async fn request_availability_cores(
+	parent: Hash,
+	s: &mut mpsc::Sender<FromJob>,
+) -> Result<oneshot::Receiver<Vec<CoreState>>, Error> {
+	let (tx, rx) = oneshot::channel();
+
+	s.send(FromJob::RuntimeApiMessage(RuntimeApiMessage::Request(
+			parent,
+			RuntimeApiRequest::AvailabilityCores(tx),
+		)
+	)).await?;
+
+	Ok(rx)
+}
+
+impl<S: Spawn> Jobs<S> {
+	fn new(spawner: S) -> Self {
+		Self {
+			spawner,
+			running: HashMap::default(),
+			outgoing_msgs: StreamUnordered::new(),
+		}
+	}
+
+	fn spawn_job(&mut self, parent_hash: Hash) -> Result<(), Error> {
+		let (to_job_tx, to_job_rx) = mpsc::channel(CHANNEL_CAPACITY);
+		let (from_job_tx, from_job_rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+		let (future, abort_handle) = future::abortable(async move {
+			if let Err(e) = run_job(parent_hash, to_job_rx, from_job_tx).await {
+				log::error!(
+					"ProvisioningJob({}) finished with an error {:?}",
+					parent_hash,
+					e,
+				);
+			}
+		});
+
+		let (finished_tx, finished) = oneshot::channel();
+
+		let future = async move {
+			let _ = future.await;
+			let _ = finished_tx.send(());
+		};
+		self.spawner.spawn(future)?;
+
+		let su_handle = self.outgoing_msgs.push(from_job_rx);
+
+		let handle = JobHandle {
+			abort_handle,
+			to_job: to_job_tx,
+			finished,
+			su_handle,
+		};
+
+		self.running.insert(parent_hash, handle);
+
+		Ok(())
+	}
+
+	async fn stop_job(&mut self, parent_hash: Hash) -> Result<(), Error> {
+		match self.running.remove(&parent_hash) {
+			Some(handle) => {
+				Pin::new(&mut self.outgoing_msgs).remove(handle.su_handle);
+				handle.stop().await;
+				Ok(())
+			}
+			None => Err(Error::JobNotFound(parent_hash))
+		}
+	}
+
+	async fn send_msg(&mut self, parent_hash: Hash, msg: ToJob) -> Result<(), Error> {
+		if let Some(job) = self.running.get_mut(&parent_hash) {
+			job.send_msg(msg).await?;
+		}
+		Ok(())
+	}
+
+	async fn next(&mut self) -> Option<FromJob> {
+		self.outgoing_msgs.next().await.and_then(|(e, _)| match e {
+			StreamYield::Item(e) => Some(e),
+			_ => None,
+		})
+	}
+}
+
+/// The relay-parent a `ProvisionerMessage` should be handled in the context of.
+fn relay_parent(msg: &ProvisionerMessage) -> Hash {
+	match msg {
+		ProvisionerMessage::RequestBlockAuthorshipData(hash, _) |
+		ProvisionerMessage::RequestInherentData(hash, _) => *hash,
+		ProvisionerMessage::ProvisionableData(data) => match data {
+			ProvisionableData::Bitfield(hash, _) |
+			ProvisionableData::MisbehaviorReport(hash, _) |
+			ProvisionableData::Dispute(hash, _, _) => *hash,
+			ProvisionableData::BackedCandidate(candidate) => candidate.descriptor().relay_parent,
+		},
+	}
+}
+
+/// An implementation of the Provisioner subsystem.
+pub struct ProvisioningSubsystem<S, Context> {
+	spawner: S,
+	_context: std::marker::PhantomData<Context>,
+}
+
+impl<S, Context> ProvisioningSubsystem<S, Context>
+	where
+		S: Spawn + Clone,
+		Context: SubsystemContext<Message=ProvisionerMessage>,
+{
+	/// Creates a new `ProvisioningSubsystem`.
+	pub fn new(spawner: S) -> Self {
+		Self {
+			spawner,
+			_context: std::marker::PhantomData,
+		}
+	}
+
+	async fn run(
+		mut ctx: Context,
+		spawner: S,
+	) {
+		let mut jobs = Jobs::new(spawner.clone());
+
+		loop {
+			select! {
+				incoming = ctx.recv().fuse() => {
+					match incoming {
+						Ok(msg) => match msg {
+							FromOverseer::Signal(OverseerSignal::StartWork(hash)) => {
+								if let Err(e) = jobs.spawn_job(hash) {
+									log::error!("Failed to spawn a job: {:?}", e);
+									break;
+								}
+							}
+							FromOverseer::Signal(OverseerSignal::StopWork(hash)) => {
+								if let Err(e) = jobs.stop_job(hash).await {
+									log::error!("Failed to stop a job: {:?}", e);
+									break;
+								}
+							}
+							FromOverseer::Communication { msg } => {
+								let res = jobs.send_msg(
+									relay_parent(&msg),
+									ToJob::Provisioner(msg),
+								).await;
+
+								if let Err(e) = res {
+									log::error!(
+										"Failed to send a message to a job: {:?}",
+										e,
+									);
+
+									break;
+								}
+							}
+							_ => (),
+						},
+						Err(_) => break,
+					}
+				}
+				outgoing = jobs.next().fuse() => {
+					match outgoing {
+						Some(msg) => {
+							let _ = ctx.send_message(msg.into()).await;
+						}
+						None => break,
+					}
+				}
+				complete => break,
+			}
+		}
+	}
+}
+
+impl<S, Context> Subsystem<Context> for ProvisioningSubsystem<S, Context>
+	where
+		S: Spawn + Send + Clone + 'static,
+		Context: SubsystemContext<Message=ProvisionerMessage>,
+{
+	fn start(self, ctx: Context) -> SpawnedSubsystem {
+		let spawner = self.spawner.clone();
+
+		SpawnedSubsystem(Box::pin(async move {
+			Self::run(ctx, spawner).await;
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::{Future, executor::{self, ThreadPool}};
+	use bitvec::{bitvec, order::Lsb0};
+	use sp_keyring::Sr25519Keyring;
+	use polkadot_primitives::v1::{
+		AvailabilityBitfield, CandidateDescriptor, CommittedCandidateReceipt,
+		SigningContext, CoreAssignment, CoreIndex, GroupIndex, AssignmentKind, ScheduledCore,
+		OccupiedCore, OmittedValidationData,
+	};
+	use assert_matches::assert_matches;
+
+	fn validator_pubkeys(val_ids: &[Sr25519Keyring]) -> Vec<ValidatorId> {
+		val_ids.iter().map(|v| v.public().into()).collect()
+	}
+
+	struct TestState {
+		chain_ids: Vec<ParaId>,
+		validators: Vec<Sr25519Keyring>,
+		validator_public: Vec<ValidatorId>,
+		availability_cores: Vec<CoreState>,
+		signing_context: SigningContext,
+		relay_parent: Hash,
+	}
+
+	impl Default for TestState {
+		fn default() -> Self {
+			let chain_a = ParaId::from(1);
+			let chain_b = ParaId::from(2);
+			let thread_a = ParaId::from(3);
+
+			let chain_ids = vec![chain_a, chain_b, thread_a];
+
+			let validators = vec![
+				Sr25519Keyring::Alice,
+				Sr25519Keyring::Bob,
+				Sr25519Keyring::Charlie,
+			];
+
+			let validator_public = validator_pubkeys(&validators);
+
+			let assignment = |core: u32, para_id: ParaId, kind: AssignmentKind| CoreAssignment {
+				core: CoreIndex(core),
+				para_id,
+				kind,
+				group_idx: GroupIndex(core),
+			};
+
+			let occupied = |core: u32, para_id: ParaId, next_up_on_available: Option<CoreAssignment>| OccupiedCore {
+				candidate_hash: Hash::repeat_byte(core as u8),
+				candidate_descriptor: CandidateDescriptor { para_id, ..Default::default() },
+				availability: bitvec![Lsb0, u8; 0; 3],
+				occupied_since: 1,
+				time_out_at: None,
+				next_up_on_available,
+			};
+
+			// `chain_a` is occupying core 0, `thread_a` is occupying core 2 without further claims
+			// and `chain_b` is scheduled on core 1, which is free.
+			let availability_cores = vec![
+				CoreState::Occupied(occupied(
+					0,
+					chain_a,
+					Some(assignment(0, chain_a, AssignmentKind::Parachain)),
+				)),
+				CoreState::Free(Some(ScheduledCore {
+					assignment: assignment(1, chain_b, AssignmentKind::Parachain),
+					omitted_validation: OmittedValidationData {
+						global_validation: Default::default(),
+						local_validation: Default::default(),
+					},
+				})),
+				CoreState::Occupied(occupied(2, thread_a, None)),
+			];
+
+			let relay_parent = Hash::from([5; 32]);
+
+			let signing_context = SigningContext {
+				session_index: 1,
+				parent_hash: relay_parent,
+			};
+
+			Self {
+				chain_ids,
+				validators,
+				validator_public,
+				availability_cores,
+				signing_context,
+				relay_parent,
+			}
+		}
+	}
+
+	impl TestState {
+		fn signed_bitfield(
+			&self,
+			validator_index: ValidatorIndex,
+			bitfield: AvailabilityBitfield,
+		) -> SignedAvailabilityBitfield {
+			SignedAvailabilityBitfield::sign(
+				bitfield,
+				&self.signing_context,
+				validator_index,
+				&self.validators[validator_index as usize].pair().into(),
+			)
+		}
+
+		fn backed_candidate(&self, para_id: ParaId) -> BackedCandidate {
+			BackedCandidate {
+				candidate: CommittedCandidateReceipt {
+					descriptor: CandidateDescriptor {
+						para_id,
+						relay_parent: self.relay_parent,
+						..Default::default()
+					},
+					..Default::default()
+				},
+				validity_votes: Vec::new(),
+				validator_indices: Default::default(),
+			}
+		}
+	}
+
+	fn bitfields_map(
+		bitfields: Vec<SignedAvailabilityBitfield>,
+	) -> BTreeMap<ValidatorIndex, SignedAvailabilityBitfield> {
+		bitfields.into_iter().map(|b| (b.validator_index(), b)).collect()
+	}
+
+	fn candidates_map(candidates: Vec<BackedCandidate>) -> BTreeMap<ParaId, BackedCandidate> {
+		candidates.into_iter().map(|c| (c.descriptor().para_id, c)).collect()
+	}
+
+	#[test]
+	fn availability_threshold_matches_runtime() {
+		assert_eq!(availability_threshold(1), 2);
+		assert_eq!(availability_threshold(2), 2);
+		assert_eq!(availability_threshold(3), 2);
+		assert_eq!(availability_threshold(4), 4);
+		assert_eq!(availability_threshold(10), 8);
+	}
+
+	#[test]
+	fn selects_only_candidates_for_free_or_freed_cores() {
+		let test_state = TestState::default();
+
+		let candidates = candidates_map(
+			test_state.chain_ids.iter().map(|id| test_state.backed_candidate(*id)).collect()
+		);
+
+		// Nothing is available: only the candidate of the free core may be included.
+		let (bitfields, selected) = select_inherent_data(
+			test_state.validator_public.len(),
+			&test_state.availability_cores,
+			&BTreeMap::new(),
+			&candidates,
+		);
+
+		assert!(bitfields.is_empty());
+		assert_eq!(selected, vec![test_state.backed_candidate(test_state.chain_ids[1])]);
+
+		// Two out of three validators attest to the availability of the candidate of `chain_a`,
+		// freeing its core, while the candidate of `thread_a` is only available to one of them.
+		let bitfields = bitfields_map(vec![
+			test_state.signed_bitfield(0, bitvec![Lsb0, u8; 1, 0, 1].into()),
+			test_state.signed_bitfield(1, bitvec![Lsb0, u8; 1, 0, 0].into()),
+		]);
+
+		let (selected_bitfields, selected) = select_inherent_data(
+			test_state.validator_public.len(),
+			&test_state.availability_cores,
+			&bitfields,
+			&candidates,
+		);
+
+		assert_eq!(selected_bitfields, bitfields.values().cloned().collect::<Vec<_>>());
+		assert_eq!(
+			selected,
+			vec![
+				test_state.backed_candidate(test_state.chain_ids[0]),
+				test_state.backed_candidate(test_state.chain_ids[1]),
+			],
+		);
+	}
+
+	#[test]
+	fn counts_availability_votes_already_on_chain() {
+		let test_state = TestState::default();
+
+		let candidates = candidates_map(vec![test_state.backed_candidate(test_state.chain_ids[0])]);
+		let bitfields = |validator_index| bitfields_map(vec![
+			test_state.signed_bitfield(validator_index, bitvec![Lsb0, u8; 1, 0, 0].into()),
+		]);
+
+		// A single bitfield is not enough to make the candidate of `chain_a` available.
+		let (_, selected) = select_inherent_data(
+			test_state.validator_public.len(),
+			&test_state.availability_cores,
+			&bitfields(2),
+			&candidates,
+		);
+
+		assert!(selected.is_empty());
+
+		// But together with the vote of Bob on chain, it is.
+		let mut availability_cores = test_state.availability_cores.clone();
+		if let CoreState::Occupied(ref mut occupied) = availability_cores[0] {
+			occupied.availability = bitvec![Lsb0, u8; 0, 1, 0];
+		}
+
+		let (_, selected) = select_inherent_data(
+			test_state.validator_public.len(),
+			&availability_cores,
+			&bitfields(2),
+			&candidates,
+		);
+
+		assert_eq!(selected, vec![test_state.backed_candidate(test_state.chain_ids[0])]);
+
+		// A bitfield of Bob does not count twice.
+		let (_, selected) = select_inherent_data(
+			test_state.validator_public.len(),
+			&availability_cores,
+			&bitfields(1),
+			&candidates,
+		);
+
+		assert!(selected.is_empty());
+	}
+
+	#[test]
+	fn skips_bitfields_the_runtime_would_reject() {
+		let test_state = TestState::default();
+
+		let valid = test_state.signed_bitfield(0, bitvec![Lsb0, u8; 1, 0, 1].into());
+
+		let bitfields = bitfields_map(vec![
+			valid.clone(),
+			// Attests to the availability of the free core.
+			test_state.signed_bitfield(1, bitvec![Lsb0, u8; 0, 1, 0].into()),
+			// Has the wrong number of bits.
+			test_state.signed_bitfield(2, bitvec![Lsb0, u8; 1, 0].into()),
+		]);
+
+		let (selected_bitfields, _) = select_inherent_data(
+			test_state.validator_public.len(),
+			&test_state.availability_cores,
+			&bitfields,
+			&BTreeMap::new(),
+		);
+
+		assert_eq!(selected_bitfields, vec![valid]);
+
+		// Out of range validator index.
+		let (selected_bitfields, _) = select_inherent_data(
+			0,
+			&test_state.availability_cores,
+			&bitfields,
+			&BTreeMap::new(),
+		);
+
+		assert!(selected_bitfields.is_empty());
+	}
+
+	#[test]
+	fn reports_why_bitfields_are_rejected() {
+		let test_state = TestState::default();
+		let n_validators = test_state.validator_public.len();
+		let cores = &test_state.availability_cores;
+
+		let valid = test_state.signed_bitfield(0, bitvec![Lsb0, u8; 1, 0, 1].into());
+		assert_eq!(bitfield_rejection(n_validators, cores, &valid), None);
+		assert_eq!(bitfield_rejection(0, cores, &valid), Some("unknown validator"));
+
+		let wrong_length = test_state.signed_bitfield(1, bitvec![Lsb0, u8; 1, 0].into());
+		assert_eq!(
+			bitfield_rejection(n_validators, cores, &wrong_length),
+			Some("wrong number of bits"),
+		);
+
+		let unoccupied = test_state.signed_bitfield(2, bitvec![Lsb0, u8; 0, 1, 0].into());
+		assert_eq!(
+			bitfield_rejection(n_validators, cores, &unoccupied),
+			Some("bit set for an unoccupied core"),
+		);
+	}
+
+	struct TestHarness {
+		virtual_overseer: subsystem_test::TestSubsystemContextHandle<ProvisionerMessage>,
+	}
+
+	fn test_harness<T: Future<Output=()>>(test: impl FnOnce(TestHarness) -> T) {
+		let pool = ThreadPool::new().unwrap();
+
+		let (context, virtual_overseer) = subsystem_test::make_subsystem_context(pool.clone());
+
+		let subsystem = ProvisioningSubsystem::run(context, pool.clone());
+
+		let test_fut = test(TestHarness {
+			virtual_overseer,
+		});
+
+		futures::pin_mut!(test_fut);
+		futures::pin_mut!(subsystem);
+
+		executor::block_on(future::select(test_fut, subsystem));
+	}
+
+	// Provisionable data is collected per relay-parent and handed out on request,
+	// keeping only the first bitfield of each validator.
+	#[test]
+	fn provides_collected_inherent_data() {
+		let test_state = TestState::default();
+		test_harness(|test_harness| async move {
+			let TestHarness { mut virtual_overseer } = test_harness;
+
+			virtual_overseer.send(FromOverseer::Signal(
+				OverseerSignal::StartWork(test_state.relay_parent))
+			).await;
+
+			let first = test_state.signed_bitfield(0, bitvec![Lsb0, u8; 1, 0, 0].into());
+			let second = test_state.signed_bitfield(0, bitvec![Lsb0, u8; 0, 0, 1].into());
+			let candidate = test_state.backed_candidate(test_state.chain_ids[1]);
+
+			let (authorship_tx, mut authorship_rx) = mpsc::channel(CHANNEL_CAPACITY);
+			virtual_overseer.send(FromOverseer::Communication {
+				msg: ProvisionerMessage::RequestBlockAuthorshipData(
+					test_state.relay_parent,
+					authorship_tx,
+				),
+			}).await;
+
+			for data in vec![
+				ProvisionableData::Bitfield(test_state.relay_parent, first.clone()),
+				ProvisionableData::Bitfield(test_state.relay_parent, second),
+				ProvisionableData::BackedCandidate(candidate.clone()),
+			] {
+				virtual_overseer.send(FromOverseer::Communication {
+					msg: ProvisionerMessage::ProvisionableData(data),
+				}).await;
+			}
+
+			for _ in 0..3 {
+				assert!(authorship_rx.next().await.is_some());
+			}
+
+			let (tx, rx) = oneshot::channel();
+			virtual_overseer.send(FromOverseer::Communication {
+				msg: ProvisionerMessage::RequestInherentData(test_state.relay_parent, tx),
+			}).await;
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::RuntimeApi(
+					RuntimeApiMessage::Request(parent, RuntimeApiRequest::Validators(tx))
+				) if parent == test_state.relay_parent => {
+					tx.send(test_state.validator_public.clone()).unwrap();
+				}
+			);
+
+			assert_matches!(
+				virtual_overseer.recv().await,
+				AllMessages::RuntimeApi(
+					RuntimeApiMessage::Request(parent, RuntimeApiRequest::AvailabilityCores(tx))
+				) if parent == test_state.relay_parent => {
+					tx.send(test_state.availability_cores.clone()).unwrap();
+				}
+			);
+
+			assert_eq!(rx.await.unwrap(), (vec![first], vec![candidate]));
+
+			virtual_overseer.send(FromOverseer::Signal(
+				OverseerSignal::StopWork(test_state.relay_parent))
+			).await;
+		});
+	}
+}
@@ -0,0 +1,600 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Implements the Runtime API Subsystem
+//!
+//! This provides a clean, ownerless wrapper around the parachain-related runtime APIs. This crate
+//! can also be used to cache responses from heavy runtime APIs.
+
+use std::sync::Arc;
+
+use polkadot_subsystem::{
+	Subsystem, SpawnedSubsystem, SubsystemResult, SubsystemContext,
+	FromOverseer, OverseerSignal,
+};
+use polkadot_subsystem::messages::{RuntimeApiMessage, RuntimeApiRequest};
+use polkadot_primitives::v1::{Block, BlockId, Hash, ParachainHost, SigningContext};
+
+use sp_api::ProvideRuntimeApi;
+
+const LOG_TARGET: &str = "runtime_api";
+
+/// The `RuntimeApiSubsystem`. See module docs for more details.
+pub struct RuntimeApiSubsystem<Client>(Arc<Client>);
+
+impl<Client> RuntimeApiSubsystem<Client> {
+	/// Create a new Runtime API subsystem wrapping the given client.
+	pub fn new(client: Arc<Client>) -> Self {
+		RuntimeApiSubsystem(client)
+	}
+}
+
+impl<Client, Context> Subsystem<Context> for RuntimeApiSubsystem<Client> where
+	Client: ProvideRuntimeApi<Block> + Send + Sync + 'static,
+	Client::Api: ParachainHost<Block>,
+	Context: SubsystemContext<Message = RuntimeApiMessage>
+{
+	fn start(self, ctx: Context) -> SpawnedSubsystem {
+		SpawnedSubsystem(Box::pin(async move {
+			if let Err(e) = run(ctx, self.0).await {
+				log::error!(target: LOG_TARGET, "Runtime API subsystem exited with an error: {:?}", e);
+			}
+		}))
+	}
+}
+
+async fn run<Client>(
+	mut ctx: impl SubsystemContext<Message = RuntimeApiMessage>,
+	client: Arc<Client>,
+) -> SubsystemResult<()> where
+	Client: ProvideRuntimeApi<Block>,
+	Client::Api: ParachainHost<Block>,
+{
+	loop {
+		match ctx.recv().await? {
+			FromOverseer::Signal(OverseerSignal::Conclude) => return Ok(()),
+			FromOverseer::Signal(_) => {},
+			FromOverseer::Communication { msg } => match msg {
+				RuntimeApiMessage::Request(relay_parent, request) => make_runtime_api_request(
+					&*client,
+					relay_parent,
+					request,
+				),
+			}
+		}
+	}
+}
+
+/// Answer a request using the runtime API at the given relay-parent.
+///
+/// If the runtime API call fails, the response sender is dropped, which the requester
+/// observes as a cancellation.
+fn make_runtime_api_request<Client>(
+	client: &Client,
+	relay_parent: Hash,
+	request: RuntimeApiRequest,
+) where
+	Client: ProvideRuntimeApi<Block>,
+	Client::Api: ParachainHost<Block>,
+{
+	macro_rules! query {
+		($api_name:ident ($($param:expr),*), $sender:expr) => {{
+			let sender = $sender;
+			let api = client.runtime_api();
+			match api.$api_name(&BlockId::Hash(relay_parent), $($param),*) {
+				Ok(res) => {
+					let _ = sender.send(res);
+				}
+				Err(_) => log::debug!(
+					target: LOG_TARGET,
+					"Runtime API call `{}` failed at {}",
+					stringify!($api_name),
+					relay_parent,
+				),
+			}
+		}}
+	}
+
+	match request {
+		RuntimeApiRequest::Validators(sender) => query!(validators(), sender),
+		RuntimeApiRequest::ValidatorGroups(sender) => query!(validator_groups(), sender),
+		RuntimeApiRequest::SigningContext(sender) => {
+			let api = client.runtime_api();
+			match api.session_index_for_child(&BlockId::Hash(relay_parent)) {
+				Ok(session_index) => {
+					let _ = sender.send(SigningContext {
+						session_index,
+						parent_hash: relay_parent,
+					});
+				}
+				Err(_) => log::debug!(
+					target: LOG_TARGET,
+					"Runtime API call `session_index_for_child` failed at {}",
+					relay_parent,
+				),
+			}
+		}
+		RuntimeApiRequest::GlobalValidationSchedule(sender) =>
+			query!(global_validation_schedule(), sender),
+		RuntimeApiRequest::LocalValidationData(para, sender) =>
+			query!(local_validation_data(para), sender),
+		RuntimeApiRequest::ValidationCode(para, at, assume_intermediate, sender) =>
+			query!(validation_code(para, at, assume_intermediate), sender),
+		RuntimeApiRequest::ValidationCodeHash(para, at, assume_intermediate, sender) =>
+			query!(validation_code_hash(para, at, assume_intermediate), sender),
+		RuntimeApiRequest::ValidationCodeByHash(hash, sender) =>
+			query!(validation_code_by_hash(hash), sender),
+		RuntimeApiRequest::HeadData(para, sender) => query!(head_data(para), sender),
+		RuntimeApiRequest::CandidatePendingAvailability(para, sender) =>
+			query!(candidate_pending_availability(para), sender),
+		RuntimeApiRequest::DownwardMessages(para, sender) =>
+			query!(downward_messages(para), sender),
+		RuntimeApiRequest::AvailabilityCores(sender) => query!(availability_cores(), sender),
+		RuntimeApiRequest::SessionInfo(index, sender) => query!(session_info(index), sender),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use polkadot_primitives::v1::{
+		ValidatorId, SchedulerRoster, GlobalValidationSchedule, LocalValidationData, Id as ParaId,
+		ValidationCode, HeadData, BlockNumber, ValidatorIndex, CommittedCandidateReceipt,
+		DownwardMessage, CoreState, OccupiedCore, SessionIndex, SessionInfo,
+	};
+	use futures::{future, channel::oneshot, executor::ThreadPool, FutureExt};
+	use sp_keyring::Sr25519Keyring;
+	use std::collections::HashMap;
+
+	#[derive(Default, Clone)]
+	struct MockRuntimeApi {
+		validators: Vec<ValidatorId>,
+		roster: SchedulerRoster,
+		session_index_for_child: u32,
+		global_validation_schedule: GlobalValidationSchedule,
+		local_validation_data: HashMap<ParaId, LocalValidationData>,
+		validation_code: HashMap<ParaId, ValidationCode>,
+		validation_code_hash: HashMap<ParaId, Hash>,
+		validation_code_by_hash: HashMap<Hash, ValidationCode>,
+		head_data: HashMap<ParaId, HeadData>,
+		candidate_pending_availability: HashMap<ParaId, CommittedCandidateReceipt>,
+		downward_messages: HashMap<ParaId, Vec<DownwardMessage>>,
+		availability_cores: Vec<CoreState>,
+		session_info: HashMap<SessionIndex, SessionInfo>,
+	}
+
+	impl ProvideRuntimeApi<Block> for MockRuntimeApi {
+		type Api = Self;
+
+		fn runtime_api<'a>(&'a self) -> sp_api::ApiRef<'a, Self::Api> {
+			self.clone().into()
+		}
+	}
+
+	sp_api::mock_impl_runtime_apis! {
+		impl ParachainHost<Block> for MockRuntimeApi {
+			type Error = String;
+
+			fn validators(&self) -> Vec<ValidatorId> {
+				self.validators.clone()
+			}
+
+			fn validator_groups(&self) -> SchedulerRoster {
+				self.roster.clone()
+			}
+
+			fn session_index_for_child(&self) -> u32 {
+				self.session_index_for_child
+			}
+
+			fn global_validation_schedule(&self) -> GlobalValidationSchedule {
+				self.global_validation_schedule.clone()
+			}
+
+			fn local_validation_data(&self, para: ParaId) -> Option<LocalValidationData> {
+				self.local_validation_data.get(&para).map(|l| l.clone())
+			}
+
+			fn validation_code(
+				&self,
+				para: ParaId,
+				_at: BlockNumber,
+				_assume_intermediate: Option<BlockNumber>,
+			) -> Option<ValidationCode> {
+				self.validation_code.get(&para).map(|c| c.clone())
+			}
+
+			fn validation_code_hash(
+				&self,
+				para: ParaId,
+				_at: BlockNumber,
+				_assume_intermediate: Option<BlockNumber>,
+			) -> Option<Hash> {
+				self.validation_code_hash.get(&para).map(|h| h.clone())
+			}
+
+			fn validation_code_by_hash(&self, hash: Hash) -> Option<ValidationCode> {
+				self.validation_code_by_hash.get(&hash).map(|c| c.clone())
+			}
+
+			fn head_data(&self, para: ParaId) -> Option<HeadData> {
+				self.head_data.get(&para).map(|h| h.clone())
+			}
+
+			fn candidate_pending_availability(&self, para: ParaId) -> Option<CommittedCandidateReceipt> {
+				self.candidate_pending_availability.get(&para).map(|c| c.clone())
+			}
+
+			fn downward_messages(&self, para: ParaId) -> Vec<DownwardMessage> {
+				self.downward_messages.get(&para).cloned().unwrap_or_default()
+			}
+
+			fn availability_cores(&self) -> Vec<CoreState> {
+				self.availability_cores.clone()
+			}
+
+			fn session_info(&self, index: SessionIndex) -> Option<SessionInfo> {
+				self.session_info.get(&index).cloned()
+			}
+		}
+	}
+
+	fn test_harness(
+		runtime_api: MockRuntimeApi,
+		test: impl FnOnce(subsystem_test::TestSubsystemContextHandle<RuntimeApiMessage>) -> future::BoxFuture<'static, ()>,
+	) {
+		let pool = ThreadPool::new().unwrap();
+		let (ctx, ctx_handle) = subsystem_test::make_subsystem_context(pool);
+
+		let subsystem_task = run(ctx, Arc::new(runtime_api)).map(|x| x.unwrap());
+		let test_task = test(ctx_handle);
+
+		futures::executor::block_on(future::join(subsystem_task, test_task));
+	}
+
+	#[test]
+	fn requests_validators() {
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.validators = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+
+		let expected = runtime_api.validators.clone();
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::Validators(tx)),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), expected);
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_validator_groups() {
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.roster.validator_groups = vec![vec![0, 1], vec![2] as Vec<ValidatorIndex>];
+		runtime_api.roster.upcoming = vec![ParaId::from(5)];
+
+		let expected = runtime_api.roster.clone();
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::ValidatorGroups(tx)),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), expected);
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_signing_context() {
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.session_index_for_child = 7;
+
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::SigningContext(tx)),
+			}).await;
+
+			assert_eq!(
+				rx.await.unwrap(),
+				SigningContext { session_index: 7, parent_hash: relay_parent },
+			);
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_validation_data() {
+		let para_a = ParaId::from(5);
+		let para_b = ParaId::from(6);
+
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.global_validation_schedule.block_number = 10;
+		runtime_api.local_validation_data.insert(para_a, LocalValidationData::default());
+
+		let expected_global = runtime_api.global_validation_schedule.clone();
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::GlobalValidationSchedule(tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), expected_global);
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::LocalValidationData(para_a, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), Some(Default::default()));
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::LocalValidationData(para_b, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), None);
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_validation_code_and_head_data() {
+		let para_a = ParaId::from(5);
+		let para_b = ParaId::from(6);
+
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.validation_code.insert(para_a, vec![1, 2, 3].into());
+		runtime_api.validation_code_hash.insert(para_a, [7; 32].into());
+		runtime_api.validation_code_by_hash.insert([7; 32].into(), vec![1, 2, 3].into());
+		runtime_api.head_data.insert(para_a, HeadData(vec![4, 5, 6]));
+
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::ValidationCode(para_a, 10, None, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), Some(vec![1, 2, 3].into()));
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::ValidationCode(para_b, 10, None, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), None);
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::ValidationCodeHash(para_a, 10, None, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), Some([7; 32].into()));
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::ValidationCodeByHash([7; 32].into(), tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), Some(vec![1, 2, 3].into()));
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::HeadData(para_a, tx)),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), Some(HeadData(vec![4, 5, 6])));
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_candidate_pending_availability() {
+		let para_a = ParaId::from(5);
+		let para_b = ParaId::from(6);
+
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.candidate_pending_availability.insert(para_a, Default::default());
+
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::CandidatePendingAvailability(para_a, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), Some(Default::default()));
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::CandidatePendingAvailability(para_b, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), None);
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_downward_messages() {
+		let para_a = ParaId::from(5);
+		let para_b = ParaId::from(6);
+
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.downward_messages.insert(para_a, vec![DownwardMessage::Opaque(vec![1, 2, 3])]);
+
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::DownwardMessages(para_a, tx),
+				),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), vec![DownwardMessage::Opaque(vec![1, 2, 3])]);
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::DownwardMessages(para_b, tx),
+				),
+			}).await;
+
+			assert!(rx.await.unwrap().is_empty());
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_availability_cores() {
+		let mut runtime_api = MockRuntimeApi::default();
+		runtime_api.availability_cores = vec![
+			CoreState::Free(None),
+			CoreState::Occupied(OccupiedCore {
+				candidate_hash: [2; 32].into(),
+				candidate_descriptor: Default::default(),
+				availability: Default::default(),
+				occupied_since: 5,
+				time_out_at: None,
+				next_up_on_available: None,
+			}),
+		];
+
+		let expected = runtime_api.availability_cores.clone();
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::AvailabilityCores(tx)),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), expected);
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+
+	#[test]
+	fn requests_session_info() {
+		let mut runtime_api = MockRuntimeApi::default();
+		let session_info = SessionInfo {
+			validators: vec![
+				Sr25519Keyring::Alice.public().into(),
+				Sr25519Keyring::Bob.public().into(),
+			],
+			validator_groups: vec![vec![0], vec![1]],
+			n_cores: 2,
+			needed_approvals: 1,
+			..Default::default()
+		};
+		runtime_api.session_info.insert(3, session_info.clone());
+
+		let relay_parent = [1; 32].into();
+
+		test_harness(runtime_api, |mut ctx_handle| async move {
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::SessionInfo(3, tx)),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), Some(session_info));
+
+			let (tx, rx) = oneshot::channel();
+
+			ctx_handle.send(FromOverseer::Communication {
+				msg: RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::SessionInfo(2, tx)),
+			}).await;
+
+			assert_eq!(rx.await.unwrap(), None);
+
+			ctx_handle.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}.boxed());
+	}
+}
@@ -0,0 +1,1064 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Availability Distribution Subsystem.
+//!
+//! This is responsible for distributing the erasure chunks of candidates pending availability
+//! to the validators owning them, and for serving chunks to peers asking for them.
+
+use polkadot_subsystem::{
+	Subsystem, SubsystemResult, SubsystemContext, SpawnedSubsystem,
+	FromOverseer, OverseerSignal,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, NetworkBridgeMessage, NetworkBridgeEvent, AvailabilityDistributionMessage,
+	AvailabilityStoreMessage, PeerId, ReputationChange as Rep, RuntimeApiMessage,
+	RuntimeApiRequest, SchedulerRoster,
+};
+use node_primitives::{ProtocolId, View};
+use polkadot_primitives::v1::{
+	CommittedCandidateReceipt, CoreOccupied, ErasureChunk, Hash, Id as ParaId, ValidatorId,
+	ValidatorIndex, ValidatorPair,
+};
+use keystore::KeyStorePtr;
+use sp_runtime::traits::{BlakeTwo256, Hash as HashT};
+use parity_scale_codec::{Encode, Decode};
+
+use futures::prelude::*;
+use futures::channel::oneshot;
+
+use std::collections::{HashMap, HashSet};
+
+const PROTOCOL_V1: ProtocolId = *b"avad";
+
+const COST_UNEXPECTED_MESSAGE: Rep = Rep::new(-100, "Unexpected message");
+const COST_INVALID_MESSAGE: Rep = Rep::new(-500, "Invalid message");
+const COST_CHUNK_INDEX_INVALID: Rep = Rep::new(-100, "Chunk of unknown validator");
+const COST_INVALID_MERKLE_PROOF: Rep = Rep::new(-500, "Chunk does not match erasure root");
+const COST_DUPLICATE_CHUNK: Rep = Rep::new(-250, "Chunk sent more than once by peer");
+
+const BENEFIT_VALID_CHUNK: Rep = Rep::new(5, "Peer provided a valid chunk");
+const BENEFIT_VALID_CHUNK_FIRST: Rep = Rep::new(
+	25,
+	"Peer was the first to provide a valid chunk",
+);
+
+/// The availability distribution subsystem.
+pub struct AvailabilityDistribution {
+	keystore: KeyStorePtr,
+}
+
+impl AvailabilityDistribution {
+	/// Create a new instance of the `AvailabilityDistribution`.
+	pub fn new(keystore: KeyStorePtr) -> Self {
+		AvailabilityDistribution { keystore }
+	}
+}
+
+impl<C> Subsystem<C> for AvailabilityDistribution
+	where C: SubsystemContext<Message=AvailabilityDistributionMessage>
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		// Swallow error because failure is fatal to the node and we log with more precision
+		// within `run`.
+		SpawnedSubsystem(run(ctx, self.keystore).map(|_| ()).boxed())
+	}
+}
+
+fn network_update_message(n: NetworkBridgeEvent) -> AllMessages {
+	AllMessages::AvailabilityDistribution(AvailabilityDistributionMessage::NetworkBridgeUpdate(n))
+}
+
+/// A chunk of a candidate, identified by the candidate hash and the chunk index.
+type ChunkId = (Hash, u32);
+
+/// knowledge that a peer has about the chunks of candidates pending availability at a
+/// relay parent.
+#[derive(Default)]
+struct PeerRelayParentKnowledge {
+	/// chunks sent to the peer by us.
+	sent_chunks: HashSet<ChunkId>,
+	/// chunks sent to us by the peer.
+	received_chunks: HashSet<ChunkId>,
+}
+
+impl PeerRelayParentKnowledge {
+	/// Attempt to update our view of the peer's knowledge with this chunk based on something
+	/// that we would like to send to the peer.
+	///
+	/// Returns `false` if the peer is already aware of the chunk, without altering internal
+	/// state.
+	fn send(&mut self, chunk_id: ChunkId) -> bool {
+		if self.received_chunks.contains(&chunk_id) {
+			return false;
+		}
+
+		self.sent_chunks.insert(chunk_id)
+	}
+
+	/// Attempt to update our view of the peer's knowledge with this chunk based on a message
+	/// we are receiving from the peer.
+	///
+	/// This returns an error if the peer has already sent us the chunk.
+	fn receive(&mut self, chunk_id: ChunkId) -> Result<(), Rep> {
+		if !self.received_chunks.insert(chunk_id) {
+			return Err(COST_DUPLICATE_CHUNK);
+		}
+
+		Ok(())
+	}
+}
+
+struct PeerData {
+	view: View,
+	view_knowledge: HashMap<Hash, PeerRelayParentKnowledge>,
+}
+
+impl PeerData {
+	fn send(&mut self, relay_parent: &Hash, chunk_id: ChunkId) -> bool {
+		self.view_knowledge.get_mut(relay_parent).map_or(false, |k| k.send(chunk_id))
+	}
+
+	fn receive(&mut self, relay_parent: &Hash, chunk_id: ChunkId) -> Result<(), Rep> {
+		self.view_knowledge.get_mut(relay_parent).ok_or(COST_UNEXPECTED_MESSAGE)?
+			.receive(chunk_id)
+	}
+}
+
+struct ActiveHeadData {
+	/// The number of validators, which is also the number of chunks of each candidate.
+	n_validators: usize,
+	/// Our index in the validator set, if we are a validator.
+	validator_index: Option<ValidatorIndex>,
+	/// The candidates pending availability at this relay parent, by hash.
+	live_candidates: HashMap<Hash, CommittedCandidateReceipt>,
+	/// The chunks of live candidates we know of.
+	chunks: HashMap<ChunkId, ErasureChunk>,
+	/// Chunks we have been asked to fetch and are waiting for.
+	awaited: HashSet<ChunkId>,
+}
+
+impl ActiveHeadData {
+	fn new(
+		n_validators: usize,
+		validator_index: Option<ValidatorIndex>,
+		live_candidates: HashMap<Hash, CommittedCandidateReceipt>,
+	) -> Self {
+		ActiveHeadData {
+			n_validators,
+			validator_index,
+			live_candidates,
+			chunks: Default::default(),
+			awaited: Default::default(),
+		}
+	}
+
+	/// Note a chunk, returning `true` if it was not known before.
+	fn note_chunk(&mut self, candidate_hash: Hash, chunk: ErasureChunk) -> bool {
+		let mut fresh = false;
+		self.chunks.entry((candidate_hash, chunk.index)).or_insert_with(|| {
+			fresh = true;
+			chunk
+		});
+
+		fresh
+	}
+
+	/// Whether a chunk should be kept in the availability store: either because it is ours
+	/// or because it was explicitly asked for.
+	fn should_store(&mut self, candidate_hash: Hash, index: u32) -> bool {
+		let awaited = self.awaited.remove(&(candidate_hash, index));
+		awaited || self.validator_index == Some(index)
+	}
+
+	/// Check that the chunk belongs to a live candidate and is committed to by its erasure
+	/// root.
+	fn check_chunk(&self, candidate_hash: &Hash, chunk: &ErasureChunk) -> Result<(), Rep> {
+		let candidate = self.live_candidates.get(candidate_hash).ok_or(COST_UNEXPECTED_MESSAGE)?;
+
+		if chunk.index as usize >= self.n_validators {
+			return Err(COST_CHUNK_INDEX_INVALID);
+		}
+
+		let expected = erasure_coding::branch_hash(
+			&candidate.commitments.erasure_root,
+			&chunk.proof,
+			chunk.index as usize,
+		);
+
+		if expected != Ok(BlakeTwo256::hash(&chunk.chunk)) {
+			return Err(COST_INVALID_MERKLE_PROOF);
+		}
+
+		Ok(())
+	}
+}
+
+#[derive(Encode, Decode)]
+enum WireMessage {
+	/// A chunk of a candidate pending availability at the relay parent.
+	#[codec(index = "0")]
+	Chunk(Hash, Hash, ErasureChunk),
+	/// A request for a chunk of a candidate pending availability at the relay parent.
+	#[codec(index = "1")]
+	RequestChunk(Hash, Hash, u32),
+}
+
+fn signing_key_index(validators: &[ValidatorId], keystore: &KeyStorePtr) -> Option<ValidatorIndex> {
+	let keystore = keystore.read();
+	validators.iter()
+		.position(|v| keystore.key_pair::<ValidatorPair>(&v).is_ok())
+		.map(|i| i as ValidatorIndex)
+}
+
+/// The para occupying the given availability core, if any.
+///
+/// Parachains are assigned to cores in the order in which they are upcoming.
+fn occupying_para(roster: &SchedulerRoster, core: usize) -> Option<ParaId> {
+	match roster.availability_cores.get(core)? {
+		Some(CoreOccupied::Parachain) => roster.upcoming.get(core).cloned(),
+		Some(CoreOccupied::Parathread(entry)) => Some(entry.claim.0),
+		None => None,
+	}
+}
+
+/// Fetch the validators and candidates pending availability at a relay parent, along with
+/// the chunks of those candidates which are in our availability store.
+async fn fetch_active_head_data(
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	relay_parent: Hash,
+	keystore: &KeyStorePtr,
+) -> SubsystemResult<ActiveHeadData> {
+	let (validators, roster) = {
+		let (val_tx, val_rx) = oneshot::channel();
+		let (roster_tx, roster_rx) = oneshot::channel();
+
+		let val_message = AllMessages::RuntimeApi(
+			RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::Validators(val_tx)),
+		);
+		let roster_message = AllMessages::RuntimeApi(
+			RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::ValidatorGroups(roster_tx)),
+		);
+
+		ctx.send_messages(
+			std::iter::once(val_message).chain(std::iter::once(roster_message))
+		).await?;
+
+		(val_rx.await?, roster_rx.await?)
+	};
+
+	let (candidate_messages, candidate_rxs): (Vec<_>, Vec<_>) =
+		(0..roster.availability_cores.len())
+			.filter_map(|core| occupying_para(&roster, core))
+			.map(|para| {
+				let (tx, rx) = oneshot::channel();
+				let message = AllMessages::RuntimeApi(RuntimeApiMessage::Request(
+					relay_parent,
+					RuntimeApiRequest::CandidatePendingAvailability(para, tx),
+				));
+
+				(message, rx)
+			})
+			.unzip();
+
+	ctx.send_messages(candidate_messages).await?;
+
+	let live_candidates: HashMap<_, _> = future::try_join_all(candidate_rxs).await?
+		.into_iter()
+		.flatten()
+		.map(|c| (c.hash(), c))
+		.collect();
+
+	let n_validators = validators.len();
+	let mut head_data = ActiveHeadData::new(
+		n_validators,
+		signing_key_index(&validators, keystore),
+		live_candidates,
+	);
+
+	let (chunk_messages, chunk_rxs): (Vec<_>, Vec<_>) = head_data.live_candidates.keys()
+		.flat_map(|candidate_hash| (0..n_validators).map(move |i| (*candidate_hash, i)))
+		.map(|(candidate_hash, i)| {
+			let (tx, rx) = oneshot::channel();
+			let message = AllMessages::AvailabilityStore(AvailabilityStoreMessage::QueryChunk(
+				candidate_hash,
+				i as ValidatorIndex,
+				tx,
+			));
+
+			(message, rx.map_ok(move |chunk| chunk.map(|c| (candidate_hash, c))))
+		})
+		.unzip();
+
+	ctx.send_messages(chunk_messages).await?;
+
+	for (candidate_hash, chunk) in future::try_join_all(chunk_rxs).await?.into_iter().flatten() {
+		head_data.note_chunk(candidate_hash, chunk);
+	}
+
+	Ok(head_data)
+}
+
+/// Send a chunk to all peers interested in the relay parent which are not aware of it yet.
+async fn circulate_chunk(
+	peers: &mut HashMap<PeerId, PeerData>,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	relay_parent: Hash,
+	candidate_hash: Hash,
+	chunk: &ErasureChunk,
+) -> SubsystemResult<()> {
+	let peers_to_send: Vec<PeerId> = peers.iter_mut()
+		.filter_map(|(peer, data)| if data.send(&relay_parent, (candidate_hash, chunk.index)) {
+			Some(peer.clone())
+		} else {
+			None
+		})
+		.collect();
+
+	if !peers_to_send.is_empty() {
+		let payload = WireMessage::Chunk(relay_parent, candidate_hash, chunk.clone()).encode();
+		ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+			peers_to_send,
+			PROTOCOL_V1,
+			payload,
+		))).await?;
+	}
+
+	Ok(())
+}
+
+async fn send_chunk(
+	peer: PeerId,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	relay_parent: Hash,
+	candidate_hash: Hash,
+	chunk: ErasureChunk,
+) -> SubsystemResult<()> {
+	let payload = WireMessage::Chunk(relay_parent, candidate_hash, chunk).encode();
+	ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+		vec![peer],
+		PROTOCOL_V1,
+		payload,
+	))).await
+}
+
+async fn send_chunks(
+	peer: PeerId,
+	peer_data: &mut PeerData,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	relay_parent: Hash,
+	active_head: &ActiveHeadData,
+) -> SubsystemResult<()> {
+	for (chunk_id, chunk) in active_head.chunks.iter() {
+		if peer_data.send(&relay_parent, *chunk_id) {
+			send_chunk(peer.clone(), ctx, relay_parent, chunk_id.0, chunk.clone()).await?;
+		}
+	}
+
+	Ok(())
+}
+
+async fn store_chunk(
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	candidate: &CommittedCandidateReceipt,
+	chunk: ErasureChunk,
+) -> SubsystemResult<()> {
+	ctx.send_message(AllMessages::AvailabilityStore(AvailabilityStoreMessage::StoreChunk(
+		candidate.hash(),
+		candidate.descriptor.relay_parent,
+		chunk.index,
+		chunk,
+	))).await
+}
+
+async fn report_peer(
+	ctx: &mut impl SubsystemContext,
+	peer: PeerId,
+	rep: Rep,
+) -> SubsystemResult<()> {
+	ctx.send_message(AllMessages::NetworkBridge(
+		NetworkBridgeMessage::ReportPeer(peer, rep)
+	)).await
+}
+
+/// Handle a chunk we have produced or obtained ourselves.
+async fn handle_local_chunk(
+	peers: &mut HashMap<PeerId, PeerData>,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	candidate_hash: Hash,
+	chunk: ErasureChunk,
+) -> SubsystemResult<()> {
+	for (relay_parent, active_head) in active_heads.iter_mut() {
+		if !active_head.live_candidates.contains_key(&candidate_hash) {
+			continue;
+		}
+
+		if active_head.note_chunk(candidate_hash, chunk.clone()) {
+			circulate_chunk(peers, ctx, *relay_parent, candidate_hash, &chunk).await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Ask all peers interested in a relay parent the candidate is live at for one of its chunks.
+async fn handle_fetch_chunk(
+	peers: &HashMap<PeerId, PeerData>,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	candidate_hash: Hash,
+	index: u32,
+) -> SubsystemResult<()> {
+	let (relay_parent, active_head) = match active_heads.iter_mut()
+		.find(|(_, head)| head.live_candidates.contains_key(&candidate_hash))
+	{
+		Some((relay_parent, head)) => (*relay_parent, head),
+		None => {
+			log::debug!(
+				target: "availability_distribution",
+				"Asked to fetch chunk {} of {} which is not pending availability",
+				index,
+				candidate_hash,
+			);
+			return Ok(());
+		}
+	};
+
+	if let Some(chunk) = active_head.chunks.get(&(candidate_hash, index)).cloned() {
+		return store_chunk(ctx, &active_head.live_candidates[&candidate_hash], chunk).await;
+	}
+
+	active_head.awaited.insert((candidate_hash, index));
+
+	let peers_to_ask: Vec<PeerId> = peers.iter()
+		.filter(|(_, data)| data.view.contains(&relay_parent))
+		.map(|(peer, _)| peer.clone())
+		.collect();
+
+	if !peers_to_ask.is_empty() {
+		let payload = WireMessage::RequestChunk(relay_parent, candidate_hash, index).encode();
+		ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+			peers_to_ask,
+			PROTOCOL_V1,
+			payload,
+		))).await?;
+	}
+
+	Ok(())
+}
+
+/// Handle a chunk sent by a peer, returning the chunk if it was fresh.
+async fn handle_incoming_chunk(
+	peer: PeerId,
+	peer_data: &mut PeerData,
+	our_view: &View,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	relay_parent: Hash,
+	candidate_hash: Hash,
+	chunk: ErasureChunk,
+) -> SubsystemResult<Option<ErasureChunk>> {
+	if !our_view.contains(&relay_parent) {
+		return report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await.map(|_| None);
+	}
+
+	let active_head = match active_heads.get_mut(&relay_parent) {
+		Some(h) => h,
+		None => {
+			log::warn!("Our view out-of-sync with active heads. Head {} not found", relay_parent);
+			return Ok(None);
+		}
+	};
+
+	if let Err(rep) = active_head.check_chunk(&candidate_hash, &chunk) {
+		return report_peer(ctx, peer, rep).await.map(|_| None);
+	}
+
+	if let Err(rep) = peer_data.receive(&relay_parent, (candidate_hash, chunk.index)) {
+		return report_peer(ctx, peer, rep).await.map(|_| None);
+	}
+
+	if !active_head.note_chunk(candidate_hash, chunk.clone()) {
+		report_peer(ctx, peer, BENEFIT_VALID_CHUNK).await?;
+		return Ok(None);
+	}
+
+	report_peer(ctx, peer, BENEFIT_VALID_CHUNK_FIRST).await?;
+
+	if active_head.should_store(candidate_hash, chunk.index) {
+		store_chunk(ctx, &active_head.live_candidates[&candidate_hash], chunk.clone()).await?;
+	}
+
+	Ok(Some(chunk))
+}
+
+/// Serve a chunk to a peer which has the relay parent in its view.
+async fn handle_chunk_request(
+	peer: PeerId,
+	peer_data: &mut PeerData,
+	active_heads: &HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	relay_parent: Hash,
+	candidate_hash: Hash,
+	index: u32,
+) -> SubsystemResult<()> {
+	let active_head = match (peer_data.view.contains(&relay_parent), active_heads.get(&relay_parent)) {
+		(true, Some(head)) if head.live_candidates.contains_key(&candidate_hash) => head,
+		_ => return report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await,
+	};
+
+	let chunk = match active_head.chunks.get(&(candidate_hash, index)) {
+		Some(chunk) => Some(chunk.clone()),
+		None => {
+			let (tx, rx) = oneshot::channel();
+			ctx.send_message(AllMessages::AvailabilityStore(
+				AvailabilityStoreMessage::QueryChunk(candidate_hash, index, tx)
+			)).await?;
+
+			rx.await?
+		}
+	};
+
+	// Requests are answered even if we believe the peer to know the chunk already, as it
+	// might have been dropped by the peer since.
+	if let Some(chunk) = chunk {
+		peer_data.send(&relay_parent, (candidate_hash, index));
+		send_chunk(peer, ctx, relay_parent, candidate_hash, chunk).await?;
+	}
+
+	Ok(())
+}
+
+async fn update_peer_view_and_send_unlocked(
+	peer: PeerId,
+	peer_data: &mut PeerData,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	active_heads: &HashMap<Hash, ActiveHeadData>,
+	new_view: View,
+) -> SubsystemResult<()> {
+	let old_view = std::mem::replace(&mut peer_data.view, new_view);
+
+	for removed in old_view.difference(&peer_data.view) {
+		let _ = peer_data.view_knowledge.remove(removed);
+	}
+
+	let new_view = peer_data.view.difference(&old_view).copied().collect::<Vec<_>>();
+	for new in new_view.iter().copied() {
+		peer_data.view_knowledge.insert(new, Default::default());
+
+		if let Some(active_head) = active_heads.get(&new) {
+			send_chunks(
+				peer.clone(),
+				peer_data,
+				ctx,
+				new,
+				active_head,
+			).await?;
+		}
+	}
+
+	Ok(())
+}
+
+async fn handle_network_update(
+	peers: &mut HashMap<PeerId, PeerData>,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	our_view: &mut View,
+	update: NetworkBridgeEvent,
+) -> SubsystemResult<()> {
+	match update {
+		NetworkBridgeEvent::PeerConnected(peer, _role) => {
+			peers.insert(peer, PeerData {
+				view: Default::default(),
+				view_knowledge: Default::default(),
+			});
+
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerDisconnected(peer) => {
+			peers.remove(&peer);
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerMessage(peer, message) => {
+			let message = match WireMessage::decode(&mut &message[..]) {
+				Err(_) => return report_peer(ctx, peer, COST_INVALID_MESSAGE).await,
+				Ok(m) => m,
+			};
+
+			let peer_data = match peers.get_mut(&peer) {
+				Some(data) => data,
+				None => return Ok(()),
+			};
+
+			match message {
+				WireMessage::Chunk(relay_parent, candidate_hash, chunk) => {
+					let new_chunk = handle_incoming_chunk(
+						peer,
+						peer_data,
+						&*our_view,
+						active_heads,
+						ctx,
+						relay_parent,
+						candidate_hash,
+						chunk,
+					).await?;
+
+					if let Some(chunk) = new_chunk {
+						circulate_chunk(peers, ctx, relay_parent, candidate_hash, &chunk).await?;
+					}
+
+					Ok(())
+				}
+				WireMessage::RequestChunk(relay_parent, candidate_hash, index) => {
+					handle_chunk_request(
+						peer,
+						peer_data,
+						&*active_heads,
+						ctx,
+						relay_parent,
+						candidate_hash,
+						index,
+					).await
+				}
+			}
+		}
+		NetworkBridgeEvent::PeerViewChange(peer, view) => {
+			match peers.get_mut(&peer) {
+				Some(data) => {
+					update_peer_view_and_send_unlocked(
+						peer,
+						data,
+						ctx,
+						&*active_heads,
+						view,
+					).await
+				}
+				None => Ok(()),
+			}
+		}
+		NetworkBridgeEvent::OurViewChange(view) => {
+			let old_view = std::mem::replace(our_view, view);
+			active_heads.retain(|head, _| our_view.contains(head));
+
+			for new in our_view.difference(&old_view) {
+				if !active_heads.contains_key(&new) {
+					log::warn!(target: "availability_distribution", "Our network bridge view update \
+						inconsistent with `StartWork` messages we have received from overseer. \
+						Contains unknown hash {}", new);
+				}
+			}
+
+			Ok(())
+		}
+	}
+}
+
+async fn run(
+	mut ctx: impl SubsystemContext<Message = AvailabilityDistributionMessage>,
+	keystore: KeyStorePtr,
+) -> SubsystemResult<()> {
+	ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(
+		PROTOCOL_V1,
+		network_update_message,
+	))).await?;
+
+	let mut peers: HashMap<PeerId, PeerData> = HashMap::new();
+	let mut our_view = View::default();
+	let mut active_heads: HashMap<Hash, ActiveHeadData> = HashMap::new();
+
+	loop {
+		let message = ctx.recv().await?;
+		match message {
+			FromOverseer::Signal(OverseerSignal::StartWork(relay_parent)) => {
+				let head_data = fetch_active_head_data(&mut ctx, relay_parent, &keystore).await?;
+
+				for (peer, peer_data) in peers.iter_mut() {
+					send_chunks(peer.clone(), peer_data, &mut ctx, relay_parent, &head_data).await?;
+				}
+
+				active_heads.insert(relay_parent, head_data);
+			}
+			FromOverseer::Signal(OverseerSignal::StopWork(_relay_parent)) => {
+				// do nothing - we will handle this when our view changes.
+			}
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(_block_hash)) => {}
+			FromOverseer::Signal(OverseerSignal::Conclude) => break,
+			FromOverseer::Communication { msg } => match msg {
+				AvailabilityDistributionMessage::DistributeChunk(candidate_hash, chunk) =>
+					handle_local_chunk(
+						&mut peers,
+						&mut active_heads,
+						&mut ctx,
+						candidate_hash,
+						chunk,
+					).await?,
+				AvailabilityDistributionMessage::FetchChunk(candidate_hash, index) =>
+					handle_fetch_chunk(
+						&peers,
+						&mut active_heads,
+						&mut ctx,
+						candidate_hash,
+						index,
+					).await?,
+				AvailabilityDistributionMessage::NetworkBridgeUpdate(event) => handle_network_update(
+					&mut peers,
+					&mut active_heads,
+					&mut ctx,
+					&mut our_view,
+					event,
+				).await?,
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use polkadot_primitives::v1::{CandidateCommitments, CandidateDescriptor};
+	use assert_matches::assert_matches;
+	use futures::executor::{self, ThreadPool};
+
+	const N_VALIDATORS: usize = 3;
+
+	/// A candidate along with its erasure chunks.
+	fn candidate_and_chunks() -> (CommittedCandidateReceipt, Vec<ErasureChunk>) {
+		let data = vec![vec![1u8; 16], vec![2u8; 16], vec![3u8; 16]];
+		let branches = erasure_coding::branches(&data);
+
+		let candidate = CommittedCandidateReceipt {
+			descriptor: CandidateDescriptor {
+				para_id: 1.into(),
+				..Default::default()
+			},
+			commitments: CandidateCommitments {
+				erasure_root: branches.root(),
+				..Default::default()
+			},
+		};
+
+		let chunks = branches.enumerate()
+			.map(|(index, (proof, chunk))| ErasureChunk {
+				chunk: chunk.to_vec(),
+				index: index as u32,
+				proof,
+			})
+			.collect();
+
+		(candidate, chunks)
+	}
+
+	fn head_data(
+		validator_index: Option<ValidatorIndex>,
+		candidate: &CommittedCandidateReceipt,
+	) -> ActiveHeadData {
+		ActiveHeadData::new(
+			N_VALIDATORS,
+			validator_index,
+			vec![(candidate.hash(), candidate.clone())].into_iter().collect(),
+		)
+	}
+
+	fn peer_data_from_view(view: View) -> PeerData {
+		PeerData {
+			view: view.clone(),
+			view_knowledge: view.0.iter().map(|v| (v.clone(), Default::default())).collect(),
+		}
+	}
+
+	#[test]
+	fn active_head_checks_merkle_proofs() {
+		let (candidate, chunks) = candidate_and_chunks();
+		let head_data = head_data(None, &candidate);
+		let candidate_hash = candidate.hash();
+
+		for chunk in chunks.iter() {
+			assert!(head_data.check_chunk(&candidate_hash, chunk).is_ok());
+		}
+
+		let mut wrong_data = chunks[0].clone();
+		wrong_data.chunk[0] = 5;
+		assert_eq!(
+			head_data.check_chunk(&candidate_hash, &wrong_data),
+			Err(COST_INVALID_MERKLE_PROOF),
+		);
+
+		let mut wrong_index = chunks[0].clone();
+		wrong_index.index = 1;
+		assert_eq!(
+			head_data.check_chunk(&candidate_hash, &wrong_index),
+			Err(COST_INVALID_MERKLE_PROOF),
+		);
+
+		let mut unknown_index = chunks[0].clone();
+		unknown_index.index = N_VALIDATORS as u32;
+		assert_eq!(
+			head_data.check_chunk(&candidate_hash, &unknown_index),
+			Err(COST_CHUNK_INDEX_INVALID),
+		);
+
+		assert_eq!(
+			head_data.check_chunk(&Hash::repeat_byte(9), &chunks[0]),
+			Err(COST_UNEXPECTED_MESSAGE),
+		);
+	}
+
+	#[test]
+	fn per_peer_relay_parent_knowledge_send_and_receive() {
+		let candidate_hash = Hash::repeat_byte(1);
+		let mut knowledge = PeerRelayParentKnowledge::default();
+
+		assert!(knowledge.send((candidate_hash, 0)));
+		assert!(!knowledge.send((candidate_hash, 0)));
+
+		assert!(knowledge.receive((candidate_hash, 1)).is_ok());
+		assert_eq!(knowledge.receive((candidate_hash, 1)), Err(COST_DUPLICATE_CHUNK));
+
+		// No need to send back what the peer sent us.
+		assert!(!knowledge.send((candidate_hash, 1)));
+	}
+
+	#[test]
+	fn valid_chunk_is_stored_by_its_owner_and_circulated() {
+		let hash_a: Hash = [1; 32].into();
+
+		let (candidate, chunks) = candidate_and_chunks();
+		let candidate_hash = candidate.hash();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let mut peers: HashMap<_, _> = vec![
+			(peer_a.clone(), peer_data_from_view(View(vec![hash_a]))),
+			(peer_b.clone(), peer_data_from_view(View(vec![hash_a]))),
+		].into_iter().collect();
+
+		// We are the validator with index 1.
+		let mut active_heads: HashMap<_, _> = vec![
+			(hash_a, head_data(Some(1), &candidate)),
+		].into_iter().collect();
+
+		let mut our_view = View(vec![hash_a]);
+
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		executor::block_on(async move {
+			// Our own chunk is stored and passed on.
+			let payload = WireMessage::Chunk(hash_a, candidate_hash, chunks[1].clone()).encode();
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), payload.clone()),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, BENEFIT_VALID_CHUNK_FIRST);
+				}
+			);
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::AvailabilityStore(
+					AvailabilityStoreMessage::StoreChunk(hash, relay_parent, index, chunk)
+				) => {
+					assert_eq!(hash, candidate_hash);
+					assert_eq!(relay_parent, candidate.descriptor.relay_parent);
+					assert_eq!(index, 1);
+					assert_eq!(chunk, chunks[1]);
+				}
+			);
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+					to,
+					protocol,
+					sent_payload,
+				)) => {
+					assert_eq!(to, vec![peer_b.clone()]);
+					assert_eq!(protocol, PROTOCOL_V1);
+					assert_eq!(sent_payload, payload);
+				}
+			);
+
+			// Somebody else's chunk is only passed on.
+			let payload = WireMessage::Chunk(hash_a, candidate_hash, chunks[2].clone()).encode();
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), payload.clone()),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, BENEFIT_VALID_CHUNK_FIRST);
+				}
+			);
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(to, _, sent_payload)) => {
+					assert_eq!(to, vec![peer_a.clone()]);
+					assert_eq!(sent_payload, payload);
+				}
+			);
+
+			// Sending the same chunk twice is punished.
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), payload),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, COST_DUPLICATE_CHUNK);
+				}
+			);
+
+			// As is a chunk not matching the erasure root.
+			let mut bad_chunk = chunks[0].clone();
+			bad_chunk.chunk[0] = 5;
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(
+					peer_a.clone(),
+					WireMessage::Chunk(hash_a, candidate_hash, bad_chunk).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_INVALID_MERKLE_PROOF);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn chunk_requests_are_served_only_to_peers_with_relay_parent_in_view() {
+		let hash_a: Hash = [1; 32].into();
+		let hash_b: Hash = [2; 32].into();
+
+		let (candidate, chunks) = candidate_and_chunks();
+		let candidate_hash = candidate.hash();
+
+		let active_heads: HashMap<_, _> = vec![
+			(hash_a, head_data(Some(0), &candidate)),
+		].into_iter().collect();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let mut peer_data_a = peer_data_from_view(View(vec![hash_a]));
+		let mut peer_data_b = peer_data_from_view(View(vec![hash_b]));
+
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		executor::block_on(async move {
+			let request = handle_chunk_request(
+				peer_a.clone(),
+				&mut peer_data_a,
+				&active_heads,
+				&mut ctx,
+				hash_a,
+				candidate_hash,
+				0,
+			);
+
+			let respond = async {
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::AvailabilityStore(
+						AvailabilityStoreMessage::QueryChunk(hash, 0, tx)
+					) => {
+						assert_eq!(hash, candidate_hash);
+						tx.send(Some(chunks[0].clone())).unwrap();
+					}
+				);
+
+				assert_matches!(
+					handle.recv().await,
+					AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+						to,
+						protocol,
+						payload,
+					)) => {
+						assert_eq!(to, vec![peer_a.clone()]);
+						assert_eq!(protocol, PROTOCOL_V1);
+						assert_eq!(
+							payload,
+							WireMessage::Chunk(hash_a, candidate_hash, chunks[0].clone()).encode(),
+						);
+					}
+				);
+			};
+
+			let (res, _) = future::join(request, respond).await;
+			res.unwrap();
+
+			handle_chunk_request(
+				peer_b.clone(),
+				&mut peer_data_b,
+				&active_heads,
+				&mut ctx,
+				hash_a,
+				candidate_hash,
+				0,
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, COST_UNEXPECTED_MESSAGE);
+				}
+			);
+		});
+	}
+}
@@ -0,0 +1,769 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Bitfield Distribution Subsystem.
+//!
+//! This is responsible for gossiping signed availability bitfields amongst validators
+//! and handing them to the provisioner.
+
+use polkadot_subsystem::{
+	Subsystem, SubsystemResult, SubsystemContext, SpawnedSubsystem,
+	FromOverseer, OverseerSignal,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, NetworkBridgeMessage, NetworkBridgeEvent, BitfieldDistributionMessage,
+	PeerId, ReputationChange as Rep, ProvisionerMessage, ProvisionableData, RuntimeApiMessage,
+	RuntimeApiRequest,
+};
+use node_primitives::{ProtocolId, View};
+use polkadot_primitives::v1::{
+	Hash, SignedAvailabilityBitfield, SigningContext, ValidatorId, ValidatorIndex,
+};
+use parity_scale_codec::{Encode, Decode};
+
+use futures::prelude::*;
+use futures::channel::oneshot;
+
+use std::collections::{HashMap, HashSet};
+
+const PROTOCOL_V1: ProtocolId = *b"bitd";
+
+const COST_UNEXPECTED_BITFIELD: Rep = Rep::new(-100, "Unexpected Bitfield");
+const COST_INVALID_SIGNATURE: Rep = Rep::new(-500, "Invalid Bitfield Signature");
+const COST_INVALID_MESSAGE: Rep = Rep::new(-500, "Invalid message");
+const COST_VALIDATOR_INDEX_INVALID: Rep = Rep::new(-100, "Bitfield of unknown validator");
+const COST_DUPLICATE_BITFIELD: Rep = Rep::new(-250, "Bitfield sent more than once by peer");
+
+const BENEFIT_VALID_BITFIELD: Rep = Rep::new(5, "Peer provided a valid bitfield");
+const BENEFIT_VALID_BITFIELD_FIRST: Rep = Rep::new(
+	25,
+	"Peer was the first to provide a valid bitfield",
+);
+
+/// The bitfield distribution subsystem.
+pub struct BitfieldDistribution;
+
+impl<C> Subsystem<C> for BitfieldDistribution
+	where C: SubsystemContext<Message=BitfieldDistributionMessage>
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		// Swallow error because failure is fatal to the node and we log with more precision
+		// within `run`.
+		SpawnedSubsystem(run(ctx).map(|_| ()).boxed())
+	}
+}
+
+fn network_update_message(n: NetworkBridgeEvent) -> AllMessages {
+	AllMessages::BitfieldDistribution(BitfieldDistributionMessage::NetworkBridgeUpdate(n))
+}
+
+/// knowledge that a peer has about the bitfields of a relay parent.
+#[derive(Default)]
+struct PeerRelayParentKnowledge {
+	/// validators whose bitfield was sent to the peer by us.
+	sent_bitfields: HashSet<ValidatorIndex>,
+	/// validators whose bitfield was sent to us by the peer.
+	received_bitfields: HashSet<ValidatorIndex>,
+}
+
+impl PeerRelayParentKnowledge {
+	/// Attempt to update our view of the peer's knowledge with the bitfield of this validator
+	/// based on something that we would like to send to the peer.
+	///
+	/// Returns `false` if the peer is already aware of a bitfield of the validator, without
+	/// altering internal state.
+	fn send(&mut self, validator_index: ValidatorIndex) -> bool {
+		if self.received_bitfields.contains(&validator_index) {
+			return false;
+		}
+
+		self.sent_bitfields.insert(validator_index)
+	}
+
+	/// Attempt to update our view of the peer's knowledge with the bitfield of this validator
+	/// based on a message we are receiving from the peer.
+	///
+	/// This returns an error if the peer has already sent us a bitfield of the validator.
+	fn receive(&mut self, validator_index: ValidatorIndex) -> Result<(), Rep> {
+		if !self.received_bitfields.insert(validator_index) {
+			return Err(COST_DUPLICATE_BITFIELD);
+		}
+
+		Ok(())
+	}
+}
+
+struct PeerData {
+	view: View,
+	view_knowledge: HashMap<Hash, PeerRelayParentKnowledge>,
+}
+
+impl PeerData {
+	fn send(&mut self, relay_parent: &Hash, validator_index: ValidatorIndex) -> bool {
+		self.view_knowledge.get_mut(relay_parent).map_or(false, |k| k.send(validator_index))
+	}
+
+	fn receive(&mut self, relay_parent: &Hash, validator_index: ValidatorIndex) -> Result<(), Rep> {
+		self.view_knowledge.get_mut(relay_parent).ok_or(COST_UNEXPECTED_BITFIELD)?
+			.receive(validator_index)
+	}
+}
+
+struct ActiveHeadData {
+	/// The first bitfield we have seen of each validator.
+	bitfields: HashMap<ValidatorIndex, SignedAvailabilityBitfield>,
+	validators: Vec<ValidatorId>,
+	signing_context: SigningContext,
+}
+
+impl ActiveHeadData {
+	fn new(validators: Vec<ValidatorId>, signing_context: SigningContext) -> Self {
+		ActiveHeadData {
+			bitfields: Default::default(),
+			validators,
+			signing_context,
+		}
+	}
+
+	/// Note a bitfield, returning `true` if it is the first one of its validator.
+	fn note_bitfield(&mut self, bitfield: SignedAvailabilityBitfield) -> bool {
+		let mut fresh = false;
+		self.bitfields.entry(bitfield.validator_index()).or_insert_with(|| {
+			fresh = true;
+			bitfield
+		});
+
+		fresh
+	}
+
+	fn check_bitfield_signature(&self, bitfield: &SignedAvailabilityBitfield) -> Result<(), Rep> {
+		let validator = self.validators.get(bitfield.validator_index() as usize)
+			.ok_or(COST_VALIDATOR_INDEX_INVALID)?;
+
+		bitfield.check_signature(&self.signing_context, validator)
+			.map_err(|()| COST_INVALID_SIGNATURE)
+	}
+}
+
+#[derive(Encode, Decode)]
+enum WireMessage {
+	#[codec(index = "0")]
+	Bitfield(Hash, SignedAvailabilityBitfield),
+}
+
+/// Send a fresh bitfield to all peers interested in the relay parent which are not aware of
+/// a bitfield of its validator yet.
+async fn circulate_bitfield(
+	peers: &mut HashMap<PeerId, PeerData>,
+	ctx: &mut impl SubsystemContext<Message = BitfieldDistributionMessage>,
+	relay_parent: Hash,
+	bitfield: &SignedAvailabilityBitfield,
+) -> SubsystemResult<()> {
+	let peers_to_send: Vec<PeerId> = peers.iter_mut()
+		.filter_map(|(peer, data)| if data.send(&relay_parent, bitfield.validator_index()) {
+			Some(peer.clone())
+		} else {
+			None
+		})
+		.collect();
+
+	if !peers_to_send.is_empty() {
+		let payload = WireMessage::Bitfield(relay_parent, bitfield.clone()).encode();
+		ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+			peers_to_send,
+			PROTOCOL_V1,
+			payload,
+		))).await?;
+	}
+
+	Ok(())
+}
+
+async fn send_bitfields(
+	peer: PeerId,
+	peer_data: &mut PeerData,
+	ctx: &mut impl SubsystemContext<Message = BitfieldDistributionMessage>,
+	relay_parent: Hash,
+	active_head: &ActiveHeadData,
+) -> SubsystemResult<()> {
+	for (validator_index, bitfield) in active_head.bitfields.iter() {
+		if peer_data.send(&relay_parent, *validator_index) {
+			let payload = WireMessage::Bitfield(relay_parent, bitfield.clone()).encode();
+
+			ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+				vec![peer.clone()],
+				PROTOCOL_V1,
+				payload,
+			))).await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Hand a fresh bitfield to the provisioner for potential inclusion in a block.
+async fn provision_bitfield(
+	ctx: &mut impl SubsystemContext<Message = BitfieldDistributionMessage>,
+	relay_parent: Hash,
+	bitfield: SignedAvailabilityBitfield,
+) -> SubsystemResult<()> {
+	ctx.send_message(AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+		ProvisionableData::Bitfield(relay_parent, bitfield)
+	))).await
+}
+
+async fn report_peer(
+	ctx: &mut impl SubsystemContext,
+	peer: PeerId,
+	rep: Rep,
+) -> SubsystemResult<()> {
+	ctx.send_message(AllMessages::NetworkBridge(
+		NetworkBridgeMessage::ReportPeer(peer, rep)
+	)).await
+}
+
+/// Handle a bitfield we have signed ourselves.
+async fn handle_local_bitfield(
+	peers: &mut HashMap<PeerId, PeerData>,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = BitfieldDistributionMessage>,
+	relay_parent: Hash,
+	bitfield: SignedAvailabilityBitfield,
+) -> SubsystemResult<()> {
+	let active_head = match active_heads.get_mut(&relay_parent) {
+		Some(h) => h,
+		None => return Ok(()),
+	};
+
+	if active_head.note_bitfield(bitfield.clone()) {
+		circulate_bitfield(peers, ctx, relay_parent, &bitfield).await?;
+		provision_bitfield(ctx, relay_parent, bitfield).await?;
+	}
+
+	Ok(())
+}
+
+/// Handle a message from a peer, returning the bitfield it contained if it was fresh.
+async fn handle_incoming_message(
+	peer: PeerId,
+	peer_data: &mut PeerData,
+	our_view: &View,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = BitfieldDistributionMessage>,
+	message: Vec<u8>,
+) -> SubsystemResult<Option<(Hash, SignedAvailabilityBitfield)>> {
+	let (relay_parent, bitfield) = match WireMessage::decode(&mut &message[..]) {
+		Err(_) => return report_peer(ctx, peer, COST_INVALID_MESSAGE).await.map(|_| None),
+		Ok(WireMessage::Bitfield(r, b)) => (r, b),
+	};
+
+	if !our_view.contains(&relay_parent) {
+		return report_peer(ctx, peer, COST_UNEXPECTED_BITFIELD).await.map(|_| None);
+	}
+
+	let active_head = match active_heads.get_mut(&relay_parent) {
+		Some(h) => h,
+		None => {
+			log::warn!("Our view out-of-sync with active heads. Head {} not found", relay_parent);
+			return Ok(None);
+		}
+	};
+
+	if let Err(rep) = active_head.check_bitfield_signature(&bitfield) {
+		return report_peer(ctx, peer, rep).await.map(|_| None);
+	}
+
+	if let Err(rep) = peer_data.receive(&relay_parent, bitfield.validator_index()) {
+		return report_peer(ctx, peer, rep).await.map(|_| None);
+	}
+
+	if active_head.note_bitfield(bitfield.clone()) {
+		report_peer(ctx, peer, BENEFIT_VALID_BITFIELD_FIRST).await?;
+		Ok(Some((relay_parent, bitfield)))
+	} else {
+		report_peer(ctx, peer, BENEFIT_VALID_BITFIELD).await?;
+		Ok(None)
+	}
+}
+
+async fn update_peer_view_and_send_unlocked(
+	peer: PeerId,
+	peer_data: &mut PeerData,
+	ctx: &mut impl SubsystemContext<Message = BitfieldDistributionMessage>,
+	active_heads: &HashMap<Hash, ActiveHeadData>,
+	new_view: View,
+) -> SubsystemResult<()> {
+	let old_view = std::mem::replace(&mut peer_data.view, new_view);
+
+	for removed in old_view.difference(&peer_data.view) {
+		let _ = peer_data.view_knowledge.remove(removed);
+	}
+
+	let new_view = peer_data.view.difference(&old_view).copied().collect::<Vec<_>>();
+	for new in new_view.iter().copied() {
+		peer_data.view_knowledge.insert(new, Default::default());
+
+		if let Some(active_head) = active_heads.get(&new) {
+			send_bitfields(
+				peer.clone(),
+				peer_data,
+				ctx,
+				new,
+				active_head,
+			).await?;
+		}
+	}
+
+	Ok(())
+}
+
+async fn handle_network_update(
+	peers: &mut HashMap<PeerId, PeerData>,
+	active_heads: &mut HashMap<Hash, ActiveHeadData>,
+	ctx: &mut impl SubsystemContext<Message = BitfieldDistributionMessage>,
+	our_view: &mut View,
+	update: NetworkBridgeEvent,
+) -> SubsystemResult<()> {
+	match update {
+		NetworkBridgeEvent::PeerConnected(peer, _role) => {
+			peers.insert(peer, PeerData {
+				view: Default::default(),
+				view_knowledge: Default::default(),
+			});
+
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerDisconnected(peer) => {
+			peers.remove(&peer);
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerMessage(peer, message) => {
+			let new_bitfield = match peers.get_mut(&peer) {
+				Some(data) => handle_incoming_message(
+					peer,
+					data,
+					&*our_view,
+					active_heads,
+					ctx,
+					message,
+				).await?,
+				None => None,
+			};
+
+			if let Some((relay_parent, bitfield)) = new_bitfield {
+				circulate_bitfield(peers, ctx, relay_parent, &bitfield).await?;
+				provision_bitfield(ctx, relay_parent, bitfield).await?;
+			}
+
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerViewChange(peer, view) => {
+			match peers.get_mut(&peer) {
+				Some(data) => {
+					update_peer_view_and_send_unlocked(
+						peer,
+						data,
+						ctx,
+						&*active_heads,
+						view,
+					).await
+				}
+				None => Ok(()),
+			}
+		}
+		NetworkBridgeEvent::OurViewChange(view) => {
+			let old_view = std::mem::replace(our_view, view);
+			active_heads.retain(|head, _| our_view.contains(head));
+
+			for new in our_view.difference(&old_view) {
+				if !active_heads.contains_key(&new) {
+					log::warn!(target: "bitfield_distribution", "Our network bridge view update \
+						inconsistent with `StartWork` messages we have received from overseer. \
+						Contains unknown hash {}", new);
+				}
+			}
+
+			Ok(())
+		}
+	}
+}
+
+async fn run(
+	mut ctx: impl SubsystemContext<Message = BitfieldDistributionMessage>,
+) -> SubsystemResult<()> {
+	ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(
+		PROTOCOL_V1,
+		network_update_message,
+	))).await?;
+
+	let mut peers: HashMap<PeerId, PeerData> = HashMap::new();
+	let mut our_view = View::default();
+	let mut active_heads: HashMap<Hash, ActiveHeadData> = HashMap::new();
+
+	loop {
+		let message = ctx.recv().await?;
+		match message {
+			FromOverseer::Signal(OverseerSignal::StartWork(relay_parent)) => {
+				let (validators, signing_context) = {
+					let (val_tx, val_rx) = oneshot::channel();
+					let (signing_tx, signing_rx) = oneshot::channel();
+
+					let val_message = AllMessages::RuntimeApi(
+						RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::Validators(val_tx)),
+					);
+					let signing_message = AllMessages::RuntimeApi(
+						RuntimeApiMessage::Request(relay_parent, RuntimeApiRequest::SigningContext(signing_tx)),
+					);
+
+					ctx.send_messages(
+						std::iter::once(val_message).chain(std::iter::once(signing_message))
+					).await?;
+
+					(val_rx.await?, signing_rx.await?)
+				};
+
+				active_heads.entry(relay_parent)
+					.or_insert(ActiveHeadData::new(validators, signing_context));
+			}
+			FromOverseer::Signal(OverseerSignal::StopWork(_relay_parent)) => {
+				// do nothing - we will handle this when our view changes.
+			}
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(_block_hash)) => {}
+			FromOverseer::Signal(OverseerSignal::Conclude) => break,
+			FromOverseer::Communication { msg } => match msg {
+				BitfieldDistributionMessage::DistributeBitfield(relay_parent, bitfield) =>
+					handle_local_bitfield(
+						&mut peers,
+						&mut active_heads,
+						&mut ctx,
+						relay_parent,
+						bitfield,
+					).await?,
+				BitfieldDistributionMessage::NetworkBridgeUpdate(event) => handle_network_update(
+					&mut peers,
+					&mut active_heads,
+					&mut ctx,
+					&mut our_view,
+					event,
+				).await?,
+			}
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sp_keyring::Sr25519Keyring;
+	use polkadot_primitives::v1::AvailabilityBitfield;
+	use bitvec::{bitvec, order::Lsb0};
+	use assert_matches::assert_matches;
+	use futures::executor::{self, ThreadPool};
+
+	fn validators() -> Vec<ValidatorId> {
+		vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+			Sr25519Keyring::Charlie.public().into(),
+		]
+	}
+
+	fn signed_bitfield(
+		signing_context: &SigningContext,
+		validator_index: ValidatorIndex,
+		key: Sr25519Keyring,
+	) -> SignedAvailabilityBitfield {
+		SignedAvailabilityBitfield::sign(
+			AvailabilityBitfield(bitvec![Lsb0, u8; 1, 0, 1]),
+			signing_context,
+			validator_index,
+			&key.pair().into(),
+		)
+	}
+
+	fn peer_data_from_view(view: View) -> PeerData {
+		PeerData {
+			view: view.clone(),
+			view_knowledge: view.0.iter().map(|v| (v.clone(), Default::default())).collect(),
+		}
+	}
+
+	#[test]
+	fn active_head_accepts_only_first_bitfield_per_validator() {
+		let parent_hash: Hash = [1; 32].into();
+		let signing_context = SigningContext {
+			parent_hash,
+			session_index: 1,
+		};
+
+		let mut head_data = ActiveHeadData::new(validators(), signing_context.clone());
+
+		let bitfield = signed_bitfield(&signing_context, 0, Sr25519Keyring::Alice);
+		assert!(head_data.check_bitfield_signature(&bitfield).is_ok());
+		assert!(head_data.note_bitfield(bitfield.clone()));
+		assert!(!head_data.note_bitfield(bitfield));
+
+		let other = SignedAvailabilityBitfield::sign(
+			AvailabilityBitfield(bitvec![Lsb0, u8; 0, 0, 0]),
+			&signing_context,
+			0,
+			&Sr25519Keyring::Alice.pair().into(),
+		);
+		assert!(!head_data.note_bitfield(other));
+
+		assert!(head_data.note_bitfield(signed_bitfield(&signing_context, 1, Sr25519Keyring::Bob)));
+	}
+
+	#[test]
+	fn active_head_checks_signatures() {
+		let parent_hash: Hash = [1; 32].into();
+		let signing_context = SigningContext {
+			parent_hash,
+			session_index: 1,
+		};
+
+		let head_data = ActiveHeadData::new(validators(), signing_context.clone());
+
+		let wrong_key = signed_bitfield(&signing_context, 0, Sr25519Keyring::Bob);
+		assert_eq!(
+			head_data.check_bitfield_signature(&wrong_key),
+			Err(COST_INVALID_SIGNATURE),
+		);
+
+		let wrong_context = signed_bitfield(
+			&SigningContext { parent_hash, session_index: 2 },
+			0,
+			Sr25519Keyring::Alice,
+		);
+		assert_eq!(
+			head_data.check_bitfield_signature(&wrong_context),
+			Err(COST_INVALID_SIGNATURE),
+		);
+
+		let unknown_validator = signed_bitfield(&signing_context, 3, Sr25519Keyring::Dave);
+		assert_eq!(
+			head_data.check_bitfield_signature(&unknown_validator),
+			Err(COST_VALIDATOR_INDEX_INVALID),
+		);
+	}
+
+	#[test]
+	fn per_peer_relay_parent_knowledge_send_and_receive() {
+		let mut knowledge = PeerRelayParentKnowledge::default();
+
+		assert!(knowledge.send(0));
+		assert!(!knowledge.send(0));
+
+		assert!(knowledge.receive(1).is_ok());
+		assert_eq!(knowledge.receive(1), Err(COST_DUPLICATE_BITFIELD));
+
+		// No need to send back what the peer sent us.
+		assert!(!knowledge.send(1));
+
+		// But the peer may send us what we sent it.
+		assert!(knowledge.receive(0).is_ok());
+	}
+
+	#[test]
+	fn fresh_bitfield_from_peer_is_provisioned_and_circulated() {
+		let hash_a: Hash = [1; 32].into();
+		let hash_b: Hash = [2; 32].into();
+
+		let signing_context = SigningContext {
+			parent_hash: hash_a,
+			session_index: 1,
+		};
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+		let peer_c = PeerId::random();
+
+		let mut peers: HashMap<_, _> = vec![
+			(peer_a.clone(), peer_data_from_view(View(vec![hash_a]))),
+			(peer_b.clone(), peer_data_from_view(View(vec![hash_a, hash_b]))),
+			(peer_c.clone(), peer_data_from_view(View(vec![hash_b]))),
+		].into_iter().collect();
+
+		let mut active_heads: HashMap<_, _> = vec![
+			(hash_a, ActiveHeadData::new(validators(), signing_context.clone())),
+		].into_iter().collect();
+
+		let mut our_view = View(vec![hash_a]);
+
+		let bitfield = signed_bitfield(&signing_context, 0, Sr25519Keyring::Alice);
+		let payload = WireMessage::Bitfield(hash_a, bitfield.clone()).encode();
+
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		executor::block_on(async move {
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), payload.clone()),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, BENEFIT_VALID_BITFIELD_FIRST);
+				}
+			);
+
+			// Only `peer_b` has `hash_a` in its view and doesn't know of the bitfield yet.
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+					to,
+					protocol,
+					sent_payload,
+				)) => {
+					assert_eq!(to, vec![peer_b.clone()]);
+					assert_eq!(protocol, PROTOCOL_V1);
+					assert_eq!(sent_payload, payload);
+				}
+			);
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::Provisioner(ProvisionerMessage::ProvisionableData(
+					ProvisionableData::Bitfield(relay_parent, provisioned)
+				)) => {
+					assert_eq!(relay_parent, hash_a);
+					assert_eq!(provisioned, bitfield);
+				}
+			);
+
+			// The same message again is a duplicate.
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(peer_a.clone(), payload.clone()),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_a);
+					assert_eq!(rep, COST_DUPLICATE_BITFIELD);
+				}
+			);
+
+			// `peer_b` echoing the bitfield back is fine, but not useful.
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(peer_b.clone(), payload.clone()),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_b);
+					assert_eq!(rep, BENEFIT_VALID_BITFIELD);
+				}
+			);
+
+			// A bitfield outside of our view is unexpected.
+			let unexpected = WireMessage::Bitfield(hash_b, bitfield.clone()).encode();
+			handle_network_update(
+				&mut peers,
+				&mut active_heads,
+				&mut ctx,
+				&mut our_view,
+				NetworkBridgeEvent::PeerMessage(peer_c.clone(), unexpected),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(peer, rep)
+				) => {
+					assert_eq!(peer, peer_c);
+					assert_eq!(rep, COST_UNEXPECTED_BITFIELD);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn peer_view_update_sends_bitfields() {
+		let hash_a: Hash = [1; 32].into();
+		let hash_b: Hash = [2; 32].into();
+
+		let signing_context = SigningContext {
+			parent_hash: hash_b,
+			session_index: 1,
+		};
+
+		let bitfield = signed_bitfield(&signing_context, 1, Sr25519Keyring::Bob);
+
+		let mut active_heads = HashMap::new();
+		active_heads.insert(hash_b, {
+			let mut data = ActiveHeadData::new(validators(), signing_context.clone());
+			assert!(data.note_bitfield(bitfield.clone()));
+			data
+		});
+
+		let mut peer_data = peer_data_from_view(View(vec![hash_a]));
+		let new_view = View(vec![hash_b]);
+
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+		let peer = PeerId::random();
+
+		executor::block_on(async move {
+			update_peer_view_and_send_unlocked(
+				peer.clone(),
+				&mut peer_data,
+				&mut ctx,
+				&active_heads,
+				new_view.clone(),
+			).await.unwrap();
+
+			assert_eq!(peer_data.view, new_view);
+			assert!(!peer_data.view_knowledge.contains_key(&hash_a));
+			assert!(peer_data.view_knowledge.get(&hash_b).unwrap().sent_bitfields.contains(&1));
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+					to,
+					protocol,
+					payload,
+				)) => {
+					assert_eq!(to, vec![peer.clone()]);
+					assert_eq!(protocol, PROTOCOL_V1);
+					assert_eq!(payload, WireMessage::Bitfield(hash_b, bitfield).encode());
+				}
+			);
+		});
+	}
+}
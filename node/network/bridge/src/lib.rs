@@ -208,6 +208,7 @@ enum Action {
 	PeerMessages(PeerId, Vec<WireMessage>),
 
 	Abort,
+	Nop,
 }
 
 fn action_from_overseer_message(
@@ -218,6 +219,7 @@ fn action_from_overseer_message(
 			=> Action::StartWork(relay_parent),
 		Ok(FromOverseer::Signal(OverseerSignal::StopWork(relay_parent)))
 			=> Action::StopWork(relay_parent),
+		Ok(FromOverseer::Signal(OverseerSignal::BlockFinalized(_))) => Action::Nop,
 		Ok(FromOverseer::Signal(OverseerSignal::Conclude)) => Action::Abort,
 		Ok(FromOverseer::Communication { msg }) => match msg {
 			NetworkBridgeMessage::RegisterEventProducer(protocol_id, message_producer)
@@ -513,6 +515,7 @@ async fn run_network<N: Network>(
 			},
 
 			Action::Abort => return Ok(()),
+			Action::Nop => {}
 		}
 	}
 }
@@ -0,0 +1,397 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The collator side of the collator protocol.
+//!
+//! Declares our para to every peer, advertises our collations to the peers which have their
+//! relay-parent in view and serves them upon request.
+
+use std::collections::{HashMap, HashSet};
+
+use polkadot_primitives::v1::{Hash, CandidateReceipt, PoV, CollatorId, Id as ParaId};
+use polkadot_subsystem::{
+	OverseerSignal, SubsystemContext, SubsystemResult, FromOverseer,
+};
+use polkadot_subsystem::messages::{CollatorProtocolMessage, NetworkBridgeEvent, PeerId};
+use node_primitives::View;
+
+use parity_scale_codec::Decode;
+
+use super::{
+	WireMessage, RequestId, report_peer, send_message,
+	COST_MALFORMED_MESSAGE, COST_UNEXPECTED_MESSAGE,
+};
+
+#[derive(Default)]
+struct PeerData {
+	view: View,
+	/// The relay-parents we have advertised a collation for to this peer.
+	advertised: HashSet<Hash>,
+}
+
+struct State {
+	our_id: CollatorId,
+	our_para: ParaId,
+	our_view: View,
+	peers: HashMap<PeerId, PeerData>,
+	/// Our collations, by relay-parent.
+	collations: HashMap<Hash, (CandidateReceipt, PoV)>,
+}
+
+impl State {
+	fn new(our_id: CollatorId, our_para: ParaId) -> Self {
+		State {
+			our_id,
+			our_para,
+			our_view: View(Vec::new()),
+			peers: HashMap::new(),
+			collations: HashMap::new(),
+		}
+	}
+}
+
+/// Advertise the collation at the given relay-parent to all peers which have it in their view
+/// and haven't been told about it yet.
+async fn advertise_collation(
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	peers: &mut HashMap<PeerId, PeerData>,
+	relay_parent: Hash,
+) -> SubsystemResult<()> {
+	let peers_to_send: Vec<_> = peers.iter_mut()
+		.filter(|(_, data)| data.view.contains(&relay_parent))
+		.filter_map(|(peer, data)| if data.advertised.insert(relay_parent) {
+			Some(peer.clone())
+		} else {
+			None
+		})
+		.collect();
+
+	send_message(ctx, peers_to_send, WireMessage::AdvertiseCollation(relay_parent)).await
+}
+
+/// Handles a `DistributeCollation` message from the collation generation subsystem.
+async fn handle_distribute_collation(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	receipt: CandidateReceipt,
+	pov: PoV,
+) -> SubsystemResult<()> {
+	let relay_parent = receipt.descriptor.relay_parent;
+
+	if receipt.descriptor.para_id != state.our_para {
+		log::warn!(
+			"Asked to distribute a collation for para {:?}, but we collate for {:?}",
+			receipt.descriptor.para_id,
+			state.our_para,
+		);
+		return Ok(());
+	}
+
+	if !state.our_view.contains(&relay_parent) {
+		log::debug!("Dropping collation for relay-parent {} which is out of our view", relay_parent);
+		return Ok(());
+	}
+
+	if state.collations.contains_key(&relay_parent) {
+		log::debug!("Already distributing a collation for relay-parent {}", relay_parent);
+		return Ok(());
+	}
+
+	state.collations.insert(relay_parent, (receipt, pov));
+
+	advertise_collation(ctx, &mut state.peers, relay_parent).await
+}
+
+/// Handles a request for a collation from a validator.
+async fn handle_collation_request(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	peer: PeerId,
+	request_id: RequestId,
+	relay_parent: Hash,
+	para_id: ParaId,
+) -> SubsystemResult<()> {
+	let collation = if para_id == state.our_para {
+		state.collations.get(&relay_parent)
+	} else {
+		None
+	};
+
+	match collation {
+		Some((receipt, pov)) => send_message(
+			ctx,
+			vec![peer],
+			WireMessage::Collation(request_id, receipt.clone(), pov.clone()),
+		).await,
+		None => report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await,
+	}
+}
+
+/// Handles a network bridge update.
+async fn handle_network_update(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	update: NetworkBridgeEvent,
+) -> SubsystemResult<()> {
+	match update {
+		NetworkBridgeEvent::PeerConnected(peer, _observed_role) => {
+			state.peers.insert(peer.clone(), PeerData::default());
+			send_message(
+				ctx,
+				vec![peer],
+				WireMessage::Declare(state.our_id.clone(), state.our_para),
+			).await
+		}
+		NetworkBridgeEvent::PeerDisconnected(peer) => {
+			state.peers.remove(&peer);
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerViewChange(peer, view) => {
+			let new_heads: Vec<_> = match state.peers.get_mut(&peer) {
+				Some(data) => {
+					data.advertised.retain(|relay_parent| view.contains(relay_parent));
+					let new_heads = view.difference(&data.view).cloned().collect();
+					data.view = view;
+					new_heads
+				}
+				None => return Ok(()),
+			};
+
+			for relay_parent in new_heads {
+				if state.collations.contains_key(&relay_parent) {
+					advertise_collation(ctx, &mut state.peers, relay_parent).await?;
+				}
+			}
+
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerMessage(peer, bytes) => {
+			match WireMessage::decode(&mut &bytes[..]) {
+				Ok(WireMessage::RequestCollation(request_id, relay_parent, para_id)) =>
+					handle_collation_request(state, ctx, peer, request_id, relay_parent, para_id).await,
+				// validators do not declare themselves or send collations.
+				Ok(_) => report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await,
+				Err(_) => report_peer(ctx, peer, COST_MALFORMED_MESSAGE).await,
+			}
+		}
+		NetworkBridgeEvent::OurViewChange(view) => {
+			state.collations.retain(|relay_parent, _| view.contains(relay_parent));
+			state.our_view = view;
+			Ok(())
+		}
+	}
+}
+
+/// Run the collator side of the protocol.
+pub(crate) async fn run(
+	mut ctx: impl SubsystemContext<Message = CollatorProtocolMessage>,
+	our_id: CollatorId,
+	our_para: ParaId,
+) -> SubsystemResult<()> {
+	let mut state = State::new(our_id, our_para);
+
+	loop {
+		match ctx.recv().await? {
+			FromOverseer::Signal(OverseerSignal::StartWork(_)) => {}
+			FromOverseer::Signal(OverseerSignal::StopWork(_)) => {
+				// do nothing - we will handle this when our view changes.
+			}
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(_)) => {}
+			FromOverseer::Signal(OverseerSignal::Conclude) => return Ok(()),
+			FromOverseer::Communication { msg } => match msg {
+				CollatorProtocolMessage::DistributeCollation(receipt, pov) =>
+					handle_distribute_collation(&mut state, &mut ctx, receipt, pov).await?,
+				CollatorProtocolMessage::FetchCollation(..) |
+				CollatorProtocolMessage::ReportCollator(..) =>
+					log::warn!("Received a validator-side message on the collator side of the protocol"),
+				CollatorProtocolMessage::NetworkBridgeUpdate(event) =>
+					handle_network_update(&mut state, &mut ctx, event).await?,
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::{self, ThreadPool};
+	use polkadot_primitives::v1::{BlockData, CandidateDescriptor};
+	use polkadot_subsystem::messages::{AllMessages, NetworkBridgeMessage, ObservedRole};
+	use parity_scale_codec::Encode;
+	use sp_keyring::Sr25519Keyring;
+	use assert_matches::assert_matches;
+
+	use crate::PROTOCOL_V1;
+
+	fn make_collation(para_id: ParaId, relay_parent: Hash) -> (CandidateReceipt, PoV) {
+		let pov = PoV { block_data: BlockData(vec![1, 2, 3]) };
+		let receipt = CandidateReceipt {
+			descriptor: CandidateDescriptor {
+				para_id,
+				relay_parent,
+				pov_hash: pov.hash(),
+				..Default::default()
+			},
+			commitments_hash: Default::default(),
+		};
+
+		(receipt, pov)
+	}
+
+	fn make_state(our_view: Vec<Hash>) -> State {
+		let mut state = State::new(Sr25519Keyring::Alice.public().into(), 1.into());
+		state.our_view = View(our_view);
+		state
+	}
+
+	fn peer_data(view: Vec<Hash>) -> PeerData {
+		PeerData { view: View(view), advertised: HashSet::new() }
+	}
+
+	#[test]
+	fn declares_to_new_peers() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		let mut state = make_state(vec![]);
+		let peer = PeerId::random();
+
+		executor::block_on(async move {
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerConnected(peer.clone(), ObservedRole::Full),
+			).await.unwrap();
+
+			assert!(state.peers.contains_key(&peer));
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, protocol, msg)
+				) => {
+					assert_eq!(peers, vec![peer]);
+					assert_eq!(protocol, PROTOCOL_V1);
+					assert_eq!(msg, WireMessage::Declare(state.our_id.clone(), state.our_para).encode());
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn advertises_to_peers_with_relay_parent_in_view() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let mut state = make_state(vec![hash_a, hash_b]);
+		state.peers.insert(peer_a.clone(), peer_data(vec![hash_a]));
+		state.peers.insert(peer_b.clone(), peer_data(vec![hash_b]));
+
+		let (receipt, pov) = make_collation(state.our_para, hash_a);
+
+		executor::block_on(async move {
+			handle_distribute_collation(&mut state, &mut ctx, receipt.clone(), pov.clone())
+				.await
+				.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, _, msg)
+				) => {
+					assert_eq!(peers, vec![peer_a.clone()]);
+					assert_eq!(msg, WireMessage::AdvertiseCollation(hash_a).encode());
+				}
+			);
+
+			// peer B now has hash A in its view as well and is told about the collation.
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerViewChange(peer_b.clone(), View(vec![hash_a, hash_b])),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, _, msg)
+				) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+					assert_eq!(msg, WireMessage::AdvertiseCollation(hash_a).encode());
+				}
+			);
+
+			// and serve it upon request.
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					peer_b.clone(),
+					WireMessage::RequestCollation(5, hash_a, state.our_para).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, _, msg)
+				) => {
+					assert_eq!(peers, vec![peer_b.clone()]);
+					assert_eq!(msg, WireMessage::Collation(5, receipt, pov).encode());
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn requests_for_unknown_collations_cost_reputation() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		let hash_a: Hash = [0; 32].into();
+		let peer = PeerId::random();
+
+		let mut state = make_state(vec![hash_a]);
+		state.peers.insert(peer.clone(), peer_data(vec![hash_a]));
+
+		executor::block_on(async move {
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					peer.clone(),
+					WireMessage::RequestCollation(1, hash_a, state.our_para).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(p, rep)
+				) => {
+					assert_eq!(p, peer);
+					assert_eq!(rep, COST_UNEXPECTED_MESSAGE);
+				}
+			);
+		});
+	}
+}
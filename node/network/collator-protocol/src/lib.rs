@@ -0,0 +1,139 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The Collator Protocol allows collators and validators to talk to each other.
+//!
+//! Collators declare the para they are collating for and advertise their collations to
+//! validators by relay-parent. Validators forward advertisements to Candidate Selection and
+//! fetch the collations it picks. This subsystem implements both sides of the protocol; which
+//! one is run is decided by the [`ProtocolSide`] it is created with.
+//!
+//! [`ProtocolSide`]: enum.ProtocolSide.html
+
+use polkadot_primitives::v1::{Hash, CandidateReceipt, PoV, CollatorId, Id as ParaId};
+use polkadot_subsystem::{Subsystem, SubsystemContext, SubsystemResult, SpawnedSubsystem};
+use polkadot_subsystem::messages::{
+	AllMessages, CollatorProtocolMessage, NetworkBridgeEvent, NetworkBridgeMessage,
+	ReputationChange as Rep, PeerId,
+};
+use node_primitives::ProtocolId;
+
+use futures::prelude::*;
+use parity_scale_codec::{Encode, Decode};
+
+mod collator_side;
+mod validator_side;
+
+const COST_MALFORMED_MESSAGE: Rep = Rep::new(-500, "Peer sent us a malformed message");
+const COST_UNEXPECTED_MESSAGE: Rep = Rep::new(-100, "Peer sent us an unexpected message");
+
+const PROTOCOL_V1: ProtocolId = *b"cop1";
+
+/// Identifies a collation request made by a validator, unique per validator.
+type RequestId = u64;
+
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+enum WireMessage {
+	/// Declare the intent to advertise collations for the given para under the given
+	/// collator ID. Sent by collators to every peer they connect to.
+	#[codec(index = "0")]
+	Declare(CollatorId, ParaId),
+	/// Advertise a collation built on top of the given relay-parent. Only valid after
+	/// the collator has declared itself.
+	#[codec(index = "1")]
+	AdvertiseCollation(Hash),
+	/// Request the collation advertised for the given relay-parent and para.
+	#[codec(index = "2")]
+	RequestCollation(RequestId, Hash, ParaId),
+	/// A requested collation.
+	#[codec(index = "3")]
+	Collation(RequestId, CandidateReceipt, PoV),
+}
+
+/// The side of the collator protocol run by a node.
+#[derive(Debug, Clone)]
+pub enum ProtocolSide {
+	/// Validators fetch collations for the paras assigned to them.
+	Validator,
+	/// Collators advertise and serve collations for the given para, under the given ID.
+	Collator(CollatorId, ParaId),
+}
+
+/// The Collator Protocol Subsystem.
+pub struct CollatorProtocolSubsystem {
+	protocol_side: ProtocolSide,
+}
+
+impl CollatorProtocolSubsystem {
+	/// Create a new `CollatorProtocolSubsystem` running the given side of the protocol.
+	pub fn new(protocol_side: ProtocolSide) -> Self {
+		Self { protocol_side }
+	}
+}
+
+impl<C> Subsystem<C> for CollatorProtocolSubsystem
+	where C: SubsystemContext<Message = CollatorProtocolMessage>
+{
+	fn start(self, ctx: C) -> SpawnedSubsystem {
+		// Swallow error because failure is fatal to the node and we log with more precision
+		// within `run`.
+		SpawnedSubsystem(run(ctx, self.protocol_side).map(|_| ()).boxed())
+	}
+}
+
+fn network_update_message(update: NetworkBridgeEvent) -> AllMessages {
+	AllMessages::CollatorProtocol(CollatorProtocolMessage::NetworkBridgeUpdate(update))
+}
+
+async fn run(
+	mut ctx: impl SubsystemContext<Message = CollatorProtocolMessage>,
+	protocol_side: ProtocolSide,
+) -> SubsystemResult<()> {
+	// startup: register the network protocol with the bridge.
+	ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::RegisterEventProducer(
+		PROTOCOL_V1,
+		network_update_message,
+	))).await?;
+
+	match protocol_side {
+		ProtocolSide::Validator => validator_side::run(ctx).await,
+		ProtocolSide::Collator(id, para_id) => collator_side::run(ctx, id, para_id).await,
+	}
+}
+
+/// Report a reputation change for a peer.
+async fn report_peer(
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	peer: PeerId,
+	rep: Rep,
+) -> SubsystemResult<()> {
+	ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::ReportPeer(peer, rep))).await
+}
+
+/// Send a message to the given peers over the collator protocol.
+async fn send_message(
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	peers: Vec<PeerId>,
+	message: WireMessage,
+) -> SubsystemResult<()> {
+	if peers.is_empty() { return Ok(()) }
+
+	ctx.send_message(AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(
+		peers,
+		PROTOCOL_V1,
+		message.encode(),
+	))).await
+}
@@ -0,0 +1,596 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The validator side of the collator protocol.
+//!
+//! Keeps track of the collators we are connected to, forwards their advertisements to
+//! Candidate Selection and fetches the collations it asks for.
+
+use std::collections::{HashMap, HashSet};
+
+use polkadot_primitives::v1::{Hash, CandidateReceipt, PoV, CollatorId, Id as ParaId};
+use polkadot_subsystem::{
+	OverseerSignal, SubsystemContext, SubsystemResult, FromOverseer,
+};
+use polkadot_subsystem::messages::{
+	AllMessages, CandidateSelectionMessage, CollatorProtocolMessage, NetworkBridgeEvent,
+	ReputationChange as Rep, PeerId,
+};
+use node_primitives::View;
+
+use futures::channel::oneshot;
+use parity_scale_codec::Decode;
+
+use super::{
+	WireMessage, RequestId, report_peer, send_message,
+	COST_MALFORMED_MESSAGE, COST_UNEXPECTED_MESSAGE,
+};
+
+const COST_UNDECLARED_COLLATOR: Rep = Rep::new(-100, "Collator advertised before declaring itself");
+const COST_INVALID_COLLATION: Rep = Rep::new(-500, "Collator sent a collation not matching the request");
+const COST_REPORT_BAD: Rep = Rep::new(-1000, "Collator provided an invalid collation");
+
+const BENEFIT_REQUESTED_COLLATION: Rep = Rep::new(25, "Collator provided a requested collation");
+
+#[derive(Default)]
+struct PeerData {
+	/// The collator ID and para the peer has declared, if any.
+	declared: Option<(CollatorId, ParaId)>,
+	/// The relay-parents the peer has advertised collations for.
+	advertisements: HashSet<Hash>,
+}
+
+/// A collation request awaiting a response from a collator.
+struct PendingRequest {
+	peer: PeerId,
+	relay_parent: Hash,
+	para_id: ParaId,
+	collator: CollatorId,
+	response_sender: oneshot::Sender<(CandidateReceipt, PoV)>,
+}
+
+#[derive(Default)]
+struct State {
+	our_view: View,
+	peers: HashMap<PeerId, PeerData>,
+	requests: HashMap<RequestId, PendingRequest>,
+	next_request_id: RequestId,
+}
+
+/// Handles a collator declaring its ID and para.
+async fn handle_declare(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	peer: PeerId,
+	collator: CollatorId,
+	para_id: ParaId,
+) -> SubsystemResult<()> {
+	let peer_data = match state.peers.get_mut(&peer) {
+		Some(data) => data,
+		None => return Ok(()),
+	};
+
+	match peer_data.declared {
+		None => {
+			peer_data.declared = Some((collator, para_id));
+			Ok(())
+		}
+		Some((ref id, ref para)) if *id == collator && *para == para_id => Ok(()),
+		// collators are not allowed to change their declaration.
+		Some(_) => report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await,
+	}
+}
+
+/// Handles a collator advertising a collation, notifying Candidate Selection.
+async fn handle_advertisement(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	peer: PeerId,
+	relay_parent: Hash,
+) -> SubsystemResult<()> {
+	let peer_data = match state.peers.get_mut(&peer) {
+		Some(data) => data,
+		None => return Ok(()),
+	};
+
+	let (collator, para_id) = match peer_data.declared {
+		Some(ref declared) => declared.clone(),
+		None => return report_peer(ctx, peer, COST_UNDECLARED_COLLATOR).await,
+	};
+
+	if !state.our_view.contains(&relay_parent) || !peer_data.advertisements.insert(relay_parent) {
+		return report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await;
+	}
+
+	ctx.send_message(AllMessages::CandidateSelection(
+		CandidateSelectionMessage::Collation(relay_parent, para_id, collator)
+	)).await
+}
+
+/// Handles a `FetchCollation` message by requesting the collation from a peer which has
+/// advertised it.
+///
+/// If there is no such peer, the response sender is dropped.
+async fn handle_fetch_collation(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	relay_parent: Hash,
+	collator: CollatorId,
+	para_id: ParaId,
+	response_sender: oneshot::Sender<(CandidateReceipt, PoV)>,
+) -> SubsystemResult<()> {
+	let peer = state.peers.iter()
+		.find(|(_, data)| {
+			data.declared.as_ref() == Some(&(collator.clone(), para_id))
+				&& data.advertisements.contains(&relay_parent)
+		})
+		.map(|(peer, _)| peer.clone());
+
+	let peer = match peer {
+		Some(peer) => peer,
+		None => {
+			log::debug!(
+				"No peer advertised a collation by {:?} for para {:?} at {}",
+				collator,
+				para_id,
+				relay_parent,
+			);
+			return Ok(());
+		}
+	};
+
+	let request_id = state.next_request_id;
+	state.next_request_id += 1;
+
+	state.requests.insert(request_id, PendingRequest {
+		peer: peer.clone(),
+		relay_parent,
+		para_id,
+		collator,
+		response_sender,
+	});
+
+	send_message(
+		ctx,
+		vec![peer],
+		WireMessage::RequestCollation(request_id, relay_parent, para_id),
+	).await
+}
+
+/// Handles a collation sent to us by a collator, completing the matching request.
+async fn handle_collation(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	peer: PeerId,
+	request_id: RequestId,
+	receipt: CandidateReceipt,
+	pov: PoV,
+) -> SubsystemResult<()> {
+	let request = match state.requests.remove(&request_id) {
+		Some(request) if request.peer == peer => request,
+		Some(request) => {
+			// not the peer we asked; keep waiting for the one we did.
+			state.requests.insert(request_id, request);
+			return report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await;
+		}
+		None => return report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await,
+	};
+
+	let descriptor = &receipt.descriptor;
+	let matches_request = descriptor.relay_parent == request.relay_parent
+		&& descriptor.para_id == request.para_id
+		&& descriptor.collator == request.collator
+		&& descriptor.pov_hash == pov.hash()
+		&& descriptor.check_collator_signature().is_ok();
+
+	if !matches_request {
+		return report_peer(ctx, peer, COST_INVALID_COLLATION).await;
+	}
+
+	let _ = request.response_sender.send((receipt, pov));
+
+	report_peer(ctx, peer, BENEFIT_REQUESTED_COLLATION).await
+}
+
+/// Handles a report about a collator having provided an invalid collation.
+async fn handle_report_collator(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	collator: CollatorId,
+) -> SubsystemResult<()> {
+	let peers: Vec<_> = state.peers.iter()
+		.filter(|(_, data)| data.declared.as_ref().map_or(false, |(id, _)| *id == collator))
+		.map(|(peer, _)| peer.clone())
+		.collect();
+
+	for peer in peers {
+		report_peer(ctx, peer, COST_REPORT_BAD).await?;
+	}
+
+	Ok(())
+}
+
+/// Handles a network bridge update.
+async fn handle_network_update(
+	state: &mut State,
+	ctx: &mut impl SubsystemContext<Message = CollatorProtocolMessage>,
+	update: NetworkBridgeEvent,
+) -> SubsystemResult<()> {
+	match update {
+		NetworkBridgeEvent::PeerConnected(peer, _observed_role) => {
+			state.peers.insert(peer, PeerData::default());
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerDisconnected(peer) => {
+			state.peers.remove(&peer);
+			// dropping the response senders lets the requesters know.
+			state.requests.retain(|_, request| request.peer != peer);
+			Ok(())
+		}
+		NetworkBridgeEvent::PeerViewChange(_, _) => Ok(()),
+		NetworkBridgeEvent::PeerMessage(peer, bytes) => {
+			match WireMessage::decode(&mut &bytes[..]) {
+				Ok(WireMessage::Declare(collator, para_id)) =>
+					handle_declare(state, ctx, peer, collator, para_id).await,
+				Ok(WireMessage::AdvertiseCollation(relay_parent)) =>
+					handle_advertisement(state, ctx, peer, relay_parent).await,
+				Ok(WireMessage::Collation(request_id, receipt, pov)) =>
+					handle_collation(state, ctx, peer, request_id, receipt, pov).await,
+				// collators do not request collations.
+				Ok(WireMessage::RequestCollation(..)) =>
+					report_peer(ctx, peer, COST_UNEXPECTED_MESSAGE).await,
+				Err(_) => report_peer(ctx, peer, COST_MALFORMED_MESSAGE).await,
+			}
+		}
+		NetworkBridgeEvent::OurViewChange(view) => {
+			for peer_data in state.peers.values_mut() {
+				peer_data.advertisements.retain(|relay_parent| view.contains(relay_parent));
+			}
+			state.requests.retain(|_, request| view.contains(&request.relay_parent));
+			state.our_view = view;
+			Ok(())
+		}
+	}
+}
+
+/// Run the validator side of the protocol.
+pub(crate) async fn run(
+	mut ctx: impl SubsystemContext<Message = CollatorProtocolMessage>,
+) -> SubsystemResult<()> {
+	let mut state = State::default();
+
+	loop {
+		match ctx.recv().await? {
+			FromOverseer::Signal(OverseerSignal::StartWork(_)) => {}
+			FromOverseer::Signal(OverseerSignal::StopWork(_)) => {
+				// do nothing - we will handle this when our view changes.
+			}
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(_)) => {}
+			FromOverseer::Signal(OverseerSignal::Conclude) => return Ok(()),
+			FromOverseer::Communication { msg } => match msg {
+				CollatorProtocolMessage::DistributeCollation(..) =>
+					log::warn!("Received a collator-side message on the validator side of the protocol"),
+				CollatorProtocolMessage::FetchCollation(relay_parent, collator, para_id, tx) =>
+					handle_fetch_collation(&mut state, &mut ctx, relay_parent, collator, para_id, tx).await?,
+				CollatorProtocolMessage::ReportCollator(collator) =>
+					handle_report_collator(&mut state, &mut ctx, collator).await?,
+				CollatorProtocolMessage::NetworkBridgeUpdate(event) =>
+					handle_network_update(&mut state, &mut ctx, event).await?,
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::{self, ThreadPool};
+	use polkadot_primitives::v1::{BlockData, CandidateDescriptor, collator_signature_payload};
+	use polkadot_subsystem::messages::NetworkBridgeMessage;
+	use parity_scale_codec::Encode;
+	use sp_keyring::Sr25519Keyring;
+	use assert_matches::assert_matches;
+
+	fn make_collation(
+		collator: Sr25519Keyring,
+		para_id: ParaId,
+		relay_parent: Hash,
+	) -> (CandidateReceipt, PoV) {
+		let pov = PoV { block_data: BlockData(vec![1, 2, 3]) };
+		let pov_hash = pov.hash();
+		let payload = collator_signature_payload(&relay_parent, &para_id, &pov_hash);
+
+		let receipt = CandidateReceipt {
+			descriptor: CandidateDescriptor {
+				para_id,
+				relay_parent,
+				collator: collator.public().into(),
+				signature: collator.sign(&payload[..]).into(),
+				pov_hash,
+			},
+			commitments_hash: Default::default(),
+		};
+
+		(receipt, pov)
+	}
+
+	fn declared_peer(collator: Sr25519Keyring, para_id: ParaId, advertised: Vec<Hash>) -> PeerData {
+		PeerData {
+			declared: Some((collator.public().into(), para_id)),
+			advertisements: advertised.into_iter().collect(),
+		}
+	}
+
+	#[test]
+	fn advertisements_are_forwarded_to_candidate_selection() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		let hash_a: Hash = [0; 32].into();
+		let hash_b: Hash = [1; 32].into();
+		let para_id: ParaId = 1.into();
+		let collator = Sr25519Keyring::Alice;
+
+		let peer = PeerId::random();
+		let undeclared_peer = PeerId::random();
+
+		let mut state = State::default();
+		state.our_view = View(vec![hash_a]);
+		state.peers.insert(peer.clone(), PeerData::default());
+		state.peers.insert(undeclared_peer.clone(), PeerData::default());
+
+		executor::block_on(async move {
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					peer.clone(),
+					WireMessage::Declare(collator.public().into(), para_id).encode(),
+				),
+			).await.unwrap();
+
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					peer.clone(),
+					WireMessage::AdvertiseCollation(hash_a).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::CandidateSelection(
+					CandidateSelectionMessage::Collation(relay_parent, p, c)
+				) => {
+					assert_eq!(relay_parent, hash_a);
+					assert_eq!(p, para_id);
+					assert_eq!(c, CollatorId::from(collator.public()));
+				}
+			);
+
+			// advertising outside of our view is not allowed.
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					peer.clone(),
+					WireMessage::AdvertiseCollation(hash_b).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(p, rep)
+				) => {
+					assert_eq!(p, peer);
+					assert_eq!(rep, COST_UNEXPECTED_MESSAGE);
+				}
+			);
+
+			// neither is advertising without declaring first.
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					undeclared_peer.clone(),
+					WireMessage::AdvertiseCollation(hash_a).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(p, rep)
+				) => {
+					assert_eq!(p, undeclared_peer);
+					assert_eq!(rep, COST_UNDECLARED_COLLATOR);
+				}
+			);
+		});
+	}
+
+	#[test]
+	fn fetches_collation_from_advertising_peer() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		let hash_a: Hash = [0; 32].into();
+		let para_id: ParaId = 1.into();
+		let collator = Sr25519Keyring::Alice;
+
+		let peer = PeerId::random();
+		let other_peer = PeerId::random();
+
+		let mut state = State::default();
+		state.our_view = View(vec![hash_a]);
+		state.peers.insert(peer.clone(), declared_peer(collator, para_id, vec![hash_a]));
+		state.peers.insert(other_peer.clone(), declared_peer(Sr25519Keyring::Bob, para_id, vec![hash_a]));
+
+		let (receipt, pov) = make_collation(collator, para_id, hash_a);
+		let (tx, rx) = oneshot::channel();
+
+		executor::block_on(async move {
+			handle_fetch_collation(
+				&mut state,
+				&mut ctx,
+				hash_a,
+				collator.public().into(),
+				para_id,
+				tx,
+			).await.unwrap();
+
+			let request_id = assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::SendMessage(peers, _, msg)
+				) => {
+					assert_eq!(peers, vec![peer.clone()]);
+					assert_matches!(
+						WireMessage::decode(&mut &msg[..]),
+						Ok(WireMessage::RequestCollation(request_id, relay_parent, p)) => {
+							assert_eq!(relay_parent, hash_a);
+							assert_eq!(p, para_id);
+							request_id
+						}
+					)
+				}
+			);
+
+			// a response from a peer we did not ask is not accepted.
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					other_peer.clone(),
+					WireMessage::Collation(request_id, receipt.clone(), pov.clone()).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(p, rep)
+				) => {
+					assert_eq!(p, other_peer);
+					assert_eq!(rep, COST_UNEXPECTED_MESSAGE);
+				}
+			);
+
+			handle_network_update(
+				&mut state,
+				&mut ctx,
+				NetworkBridgeEvent::PeerMessage(
+					peer.clone(),
+					WireMessage::Collation(request_id, receipt.clone(), pov.clone()).encode(),
+				),
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(p, rep)
+				) => {
+					assert_eq!(p, peer);
+					assert_eq!(rep, BENEFIT_REQUESTED_COLLATION);
+				}
+			);
+
+			assert_eq!(rx.await.unwrap(), (receipt, pov));
+			assert!(state.requests.is_empty());
+		});
+	}
+
+	#[test]
+	fn collation_not_matching_request_costs_reputation() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		let hash_a: Hash = [0; 32].into();
+		let para_id: ParaId = 1.into();
+		let collator = Sr25519Keyring::Alice;
+		let peer = PeerId::random();
+
+		let mut state = State::default();
+		state.our_view = View(vec![hash_a]);
+		state.peers.insert(peer.clone(), declared_peer(collator, para_id, vec![hash_a]));
+
+		// signed by someone other than the declared collator.
+		let (receipt, pov) = make_collation(Sr25519Keyring::Bob, para_id, hash_a);
+		let (tx, rx) = oneshot::channel();
+
+		executor::block_on(async move {
+			handle_fetch_collation(
+				&mut state,
+				&mut ctx,
+				hash_a,
+				collator.public().into(),
+				para_id,
+				tx,
+			).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(NetworkBridgeMessage::SendMessage(..))
+			);
+
+			handle_collation(&mut state, &mut ctx, peer.clone(), 0, receipt, pov).await.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(p, rep)
+				) => {
+					assert_eq!(p, peer);
+					assert_eq!(rep, COST_INVALID_COLLATION);
+				}
+			);
+
+			assert!(rx.await.is_err());
+		});
+	}
+
+	#[test]
+	fn reported_collators_cost_reputation() {
+		let pool = ThreadPool::new().unwrap();
+		let (mut ctx, mut handle) = subsystem_test::make_subsystem_context(pool);
+
+		let para_id: ParaId = 1.into();
+		let peer_a = PeerId::random();
+		let peer_b = PeerId::random();
+
+		let mut state = State::default();
+		state.peers.insert(peer_a.clone(), declared_peer(Sr25519Keyring::Alice, para_id, vec![]));
+		state.peers.insert(peer_b.clone(), declared_peer(Sr25519Keyring::Bob, para_id, vec![]));
+
+		executor::block_on(async move {
+			handle_report_collator(&mut state, &mut ctx, Sr25519Keyring::Alice.public().into())
+				.await
+				.unwrap();
+
+			assert_matches!(
+				handle.recv().await,
+				AllMessages::NetworkBridge(
+					NetworkBridgeMessage::ReportPeer(p, rep)
+				) => {
+					assert_eq!(p, peer_a);
+					assert_eq!(rep, COST_REPORT_BAD);
+				}
+			);
+		});
+	}
+}
@@ -124,6 +124,7 @@ async fn handle_signal(
 
 			Ok(false)
 		}
+		OverseerSignal::BlockFinalized(_) => Ok(false),
 	}
 }
 
@@ -26,9 +26,9 @@ use polkadot_subsystem::{
 use polkadot_subsystem::messages::{
 	AllMessages, NetworkBridgeMessage, NetworkBridgeEvent, StatementDistributionMessage,
 	PeerId, ReputationChange as Rep, CandidateBackingMessage, RuntimeApiMessage,
-	RuntimeApiRequest,
+	RuntimeApiRequest, MisbehaviorArbitrationMessage,
 };
-use node_primitives::{ProtocolId, View, SignedFullStatement};
+use node_primitives::{ProtocolId, View, SignedFullStatement, Statement, MisbehaviorReport};
 use polkadot_primitives::v1::{
 	Hash, CompactStatement, ValidatorIndex, ValidatorId, SigningContext, ValidatorSignature,
 };
@@ -448,6 +448,38 @@ impl ActiveHeadData {
 		}
 	}
 
+	/// Find a statement we already have which conflicts with the given one, issued by the same
+	/// validator, and turn both into a misbehavior report.
+	///
+	/// The statement is assumed to have been checked in the same way as for `note_statement`.
+	fn misbehavior_report(&self, statement: &SignedFullStatement) -> Option<MisbehaviorReport> {
+		let compact = statement.payload().to_compact();
+		let conflicting = self.statements()
+			.filter(|s| s.statement.validator_index() == statement.validator_index())
+			.find(|s| s.compact().conflicts_with(&compact))?;
+
+		let (first, second) = (conflicting.statement.clone(), statement.clone());
+		match (first.payload(), second.payload()) {
+			(Statement::Seconded(_), Statement::Seconded(_)) =>
+				Some(MisbehaviorReport::DoubleVote(first, second)),
+			(Statement::Seconded(receipt), _) | (_, Statement::Seconded(receipt)) => {
+				let receipt = receipt.to_plain();
+				Some(MisbehaviorReport::SelfContradiction(receipt, first, second))
+			}
+			_ => {
+				// A `Valid` and an `Invalid` statement. The candidate is only known through
+				// a `Seconded` statement, which will also have been stored.
+				let receipt = self.statements_about(compact.candidate_hash().clone())
+					.find_map(|s| match s.statement.payload() {
+						Statement::Seconded(receipt) => Some(receipt.to_plain()),
+						_ => None,
+					})?;
+
+				Some(MisbehaviorReport::SelfContradiction(receipt, first, second))
+			}
+		}
+	}
+
 	/// Get an iterator over all statements for the active head. Seconded statements come first.
 	fn statements(&self) -> impl Iterator<Item = &'_ StoredStatement> + '_ {
 		self.statements.iter()
@@ -692,6 +724,9 @@ async fn handle_incoming_message<'a>(
 		Ok(false) => {}
 	}
 
+	// Statements conflicting with ones we already have are evidence of misbehavior.
+	let misbehavior = active_head.misbehavior_report(&statement);
+
 	// Note: `peer_data.receive` already ensures that the statement is not an unbounded equivocation
 	// or unpinned to a seconded candidate. So it is safe to place it into the storage.
 	match active_head.note_statement(statement) {
@@ -702,6 +737,13 @@ async fn handle_incoming_message<'a>(
 		}
 		NotedStatement::Fresh(statement) => {
 			report_peer(ctx, peer, BENEFIT_VALID_STATEMENT_FIRST).await?;
+
+			if let Some(report) = misbehavior {
+				ctx.send_message(AllMessages::MisbehaviorArbitration(
+					MisbehaviorArbitrationMessage::Report(relay_parent, report)
+				)).await?;
+			}
+
 			Ok(Some((relay_parent, statement)))
 		}
 	}
@@ -862,6 +904,7 @@ async fn run(
 			FromOverseer::Signal(OverseerSignal::StopWork(_relay_parent)) => {
 				// do nothing - we will handle this when our view changes.
 			}
+			FromOverseer::Signal(OverseerSignal::BlockFinalized(_block_hash)) => {}
 			FromOverseer::Signal(OverseerSignal::Conclude) => break,
 			FromOverseer::Communication { msg } => match msg {
 				StatementDistributionMessage::Share(relay_parent, statement) =>
@@ -889,7 +932,6 @@ async fn run(
 mod tests {
 	use super::*;
 	use sp_keyring::Sr25519Keyring;
-	use node_primitives::Statement;
 	use polkadot_primitives::v1::CommittedCandidateReceipt;
 	use assert_matches::assert_matches;
 	use futures::executor::{self, ThreadPool};
@@ -989,6 +1031,74 @@ mod tests {
 		assert_matches!(noted, NotedStatement::Fresh(_));
 	}
 
+	#[test]
+	fn active_head_detects_conflicting_statements() {
+		let validators = vec![
+			Sr25519Keyring::Alice.public().into(),
+			Sr25519Keyring::Bob.public().into(),
+		];
+		let parent_hash: Hash = [1; 32].into();
+
+		let session_index = 1;
+		let signing_context = SigningContext {
+			parent_hash,
+			session_index,
+		};
+
+		let candidate_a = {
+			let mut c = CommittedCandidateReceipt::default();
+			c.descriptor.relay_parent = parent_hash;
+			c.descriptor.para_id = 1.into();
+			c
+		};
+
+		let candidate_b = {
+			let mut c = CommittedCandidateReceipt::default();
+			c.descriptor.relay_parent = parent_hash;
+			c.descriptor.para_id = 2.into();
+			c
+		};
+
+		let sign = |statement, index: ValidatorIndex, key: Sr25519Keyring| SignedFullStatement::sign(
+			statement,
+			&signing_context,
+			index,
+			&key.pair().into(),
+		);
+
+		let mut head_data = ActiveHeadData::new(validators, session_index);
+
+		let a_seconded_val_0 = sign(Statement::Seconded(candidate_a.clone()), 0, Sr25519Keyring::Alice);
+		assert!(head_data.misbehavior_report(&a_seconded_val_0).is_none());
+		head_data.note_statement(a_seconded_val_0);
+
+		// another validator agreeing is fine.
+		let a_valid_val_1 = sign(Statement::Valid(candidate_a.hash()), 1, Sr25519Keyring::Bob);
+		assert!(head_data.misbehavior_report(&a_valid_val_1).is_none());
+		head_data.note_statement(a_valid_val_1);
+
+		// seconding a second candidate is a double vote.
+		let b_seconded_val_0 = sign(Statement::Seconded(candidate_b.clone()), 0, Sr25519Keyring::Alice);
+		assert_matches!(
+			head_data.misbehavior_report(&b_seconded_val_0),
+			Some(MisbehaviorReport::DoubleVote(s1, s2)) => {
+				assert_eq!(s1.payload(), &Statement::Seconded(candidate_a.clone()));
+				assert_eq!(s2, b_seconded_val_0);
+			}
+		);
+
+		// declaring a candidate one has attested to invalid is a self-contradiction.
+		let a_invalid_val_1 = sign(Statement::Invalid(candidate_a.hash()), 1, Sr25519Keyring::Bob);
+		assert_matches!(
+			head_data.misbehavior_report(&a_invalid_val_1),
+			Some(MisbehaviorReport::SelfContradiction(receipt, s1, s2)) => {
+				assert_eq!(receipt, candidate_a.to_plain());
+				assert_eq!(s1.payload(), &Statement::Valid(candidate_a.hash()));
+				assert_eq!(s2, a_invalid_val_1);
+			}
+		);
+	}
+
 	#[test]
 	fn note_local_works() {
 		let hash_a: Hash = [1; 32].into();
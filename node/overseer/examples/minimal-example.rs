@@ -136,11 +136,15 @@ fn main() {
 			statement_distribution: DummySubsystem,
 			availability_distribution: DummySubsystem,
 			bitfield_distribution: DummySubsystem,
+			bitfield_signing: DummySubsystem,
 			provisioner: DummySubsystem,
 			pov_distribution: DummySubsystem,
 			runtime_api: DummySubsystem,
 			availability_store: DummySubsystem,
 			network_bridge: DummySubsystem,
+			collation_generation: DummySubsystem,
+			collator_protocol: DummySubsystem,
+			misbehavior_arbitration: DummySubsystem,
 		};
 		let (overseer, _handler) = Overseer::new(
 			vec![],
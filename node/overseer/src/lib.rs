@@ -78,9 +78,10 @@ use client::{BlockImportNotification, BlockchainEvents, FinalityNotification};
 use polkadot_subsystem::messages::{
 	CandidateValidationMessage, CandidateBackingMessage,
 	CandidateSelectionMessage, StatementDistributionMessage,
-	AvailabilityDistributionMessage, BitfieldDistributionMessage,
+	AvailabilityDistributionMessage, BitfieldDistributionMessage, BitfieldSigningMessage,
 	ProvisionerMessage, PoVDistributionMessage, RuntimeApiMessage,
-	AvailabilityStoreMessage, NetworkBridgeMessage, AllMessages,
+	AvailabilityStoreMessage, NetworkBridgeMessage, AllMessages, CollationGenerationMessage,
+	CollatorProtocolMessage, MisbehaviorArbitrationMessage,
 };
 pub use polkadot_subsystem::{
 	Subsystem, SubsystemContext, OverseerSignal, FromOverseer, SubsystemError, SubsystemResult,
@@ -341,6 +342,9 @@ pub struct Overseer<S: Spawn> {
 	/// A bitfield distribution subsystem.
 	bitfield_distribution_subsystem: OverseenSubsystem<BitfieldDistributionMessage>,
 
+	/// A bitfield signing subsystem.
+	bitfield_signing_subsystem: OverseenSubsystem<BitfieldSigningMessage>,
+
 	/// A provisioner subsystem.
 	provisioner_subsystem: OverseenSubsystem<ProvisionerMessage>,
 
@@ -356,6 +360,15 @@ pub struct Overseer<S: Spawn> {
 	/// A network bridge subsystem.
 	network_bridge_subsystem: OverseenSubsystem<NetworkBridgeMessage>,
 
+	/// A Collation Generation subsystem.
+	collation_generation_subsystem: OverseenSubsystem<CollationGenerationMessage>,
+
+	/// A Collator Protocol subsystem.
+	collator_protocol_subsystem: OverseenSubsystem<CollatorProtocolMessage>,
+
+	/// A Misbehavior Arbitration subsystem.
+	misbehavior_arbitration_subsystem: OverseenSubsystem<MisbehaviorArbitrationMessage>,
+
 
 	/// Spawner to spawn tasks to.
 	s: S,
@@ -389,7 +402,7 @@ pub struct Overseer<S: Spawn> {
 ///
 /// [`Subsystem`]: trait.Subsystem.html
 /// [`DummySubsystem`]: struct.DummySubsystem.html
-pub struct AllSubsystems<CV, CB, CS, SD, AD, BD, P, PoVD, RA, AS, NB> {
+pub struct AllSubsystems<CV, CB, CS, SD, AD, BD, BS, P, PoVD, RA, AS, NB, CG, CP, MA> {
 	/// A candidate validation subsystem.
 	pub candidate_validation: CV,
 	/// A candidate backing subsystem.
@@ -402,6 +415,8 @@ pub struct AllSubsystems<CV, CB, CS, SD, AD, BD, P, PoVD, RA, AS, NB> {
 	pub availability_distribution: AD,
 	/// A bitfield distribution subsystem.
 	pub bitfield_distribution: BD,
+	/// A bitfield signing subsystem.
+	pub bitfield_signing: BS,
 	/// A provisioner subsystem.
 	pub provisioner: P,
 	/// A PoV distribution subsystem.
@@ -412,6 +427,12 @@ pub struct AllSubsystems<CV, CB, CS, SD, AD, BD, P, PoVD, RA, AS, NB> {
 	pub availability_store: AS,
 	/// A network bridge subsystem.
 	pub network_bridge: NB,
+	/// A Collation Generation subsystem.
+	pub collation_generation: CG,
+	/// A Collator Protocol subsystem.
+	pub collator_protocol: CP,
+	/// A Misbehavior Arbitration subsystem.
+	pub misbehavior_arbitration: MA,
 }
 
 impl<S> Overseer<S>
@@ -484,11 +505,15 @@ where
 	///     statement_distribution: DummySubsystem,
 	///     availability_distribution: DummySubsystem,
 	///     bitfield_distribution: DummySubsystem,
+	///     bitfield_signing: DummySubsystem,
 	///     provisioner: DummySubsystem,
 	///     pov_distribution: DummySubsystem,
 	///     runtime_api: DummySubsystem,
 	///     availability_store: DummySubsystem,
 	///     network_bridge: DummySubsystem,
+	///     collation_generation: DummySubsystem,
+	///     collator_protocol: DummySubsystem,
+	///     misbehavior_arbitration: DummySubsystem,
 	/// };
 	/// let (overseer, _handler) = Overseer::new(
 	///     vec![],
@@ -509,9 +534,9 @@ where
 	/// #
 	/// # }); }
 	/// ```
-	pub fn new<CV, CB, CS, SD, AD, BD, P, PoVD, RA, AS, NB>(
+	pub fn new<CV, CB, CS, SD, AD, BD, BS, P, PoVD, RA, AS, NB, CG, CP, MA>(
 		leaves: impl IntoIterator<Item = BlockInfo>,
-		all_subsystems: AllSubsystems<CV, CB, CS, SD, AD, BD, P, PoVD, RA, AS, NB>,
+		all_subsystems: AllSubsystems<CV, CB, CS, SD, AD, BD, BS, P, PoVD, RA, AS, NB, CG, CP, MA>,
 		mut s: S,
 	) -> SubsystemResult<(Self, OverseerHandler)>
 	where
@@ -521,11 +546,15 @@ where
 		SD: Subsystem<OverseerSubsystemContext<StatementDistributionMessage>> + Send,
 		AD: Subsystem<OverseerSubsystemContext<AvailabilityDistributionMessage>> + Send,
 		BD: Subsystem<OverseerSubsystemContext<BitfieldDistributionMessage>> + Send,
+		BS: Subsystem<OverseerSubsystemContext<BitfieldSigningMessage>> + Send,
 		P: Subsystem<OverseerSubsystemContext<ProvisionerMessage>> + Send,
 		PoVD: Subsystem<OverseerSubsystemContext<PoVDistributionMessage>> + Send,
 		RA: Subsystem<OverseerSubsystemContext<RuntimeApiMessage>> + Send,
 		AS: Subsystem<OverseerSubsystemContext<AvailabilityStoreMessage>> + Send,
 		NB: Subsystem<OverseerSubsystemContext<NetworkBridgeMessage>> + Send,
+		CG: Subsystem<OverseerSubsystemContext<CollationGenerationMessage>> + Send,
+		CP: Subsystem<OverseerSubsystemContext<CollatorProtocolMessage>> + Send,
+		MA: Subsystem<OverseerSubsystemContext<MisbehaviorArbitrationMessage>> + Send,
 	{
 		let (events_tx, events_rx) = mpsc::channel(CHANNEL_CAPACITY);
 
@@ -578,6 +607,13 @@ where
 			all_subsystems.bitfield_distribution,
 		)?;
 
+		let bitfield_signing_subsystem = spawn(
+			&mut s,
+			&mut running_subsystems,
+			&mut running_subsystems_rx,
+			all_subsystems.bitfield_signing,
+		)?;
+
 		let provisioner_subsystem = spawn(
 			&mut s,
 			&mut running_subsystems,
@@ -613,6 +649,27 @@ where
 			all_subsystems.network_bridge,
 		)?;
 
+		let collation_generation_subsystem = spawn(
+			&mut s,
+			&mut running_subsystems,
+			&mut running_subsystems_rx,
+			all_subsystems.collation_generation,
+		)?;
+
+		let collator_protocol_subsystem = spawn(
+			&mut s,
+			&mut running_subsystems,
+			&mut running_subsystems_rx,
+			all_subsystems.collator_protocol,
+		)?;
+
+		let misbehavior_arbitration_subsystem = spawn(
+			&mut s,
+			&mut running_subsystems,
+			&mut running_subsystems_rx,
+			all_subsystems.misbehavior_arbitration,
+		)?;
+
 		let active_leaves = HashSet::new();
 
 		let leaves = leaves
@@ -627,11 +684,15 @@ where
 			statement_distribution_subsystem,
 			availability_distribution_subsystem,
 			bitfield_distribution_subsystem,
+			bitfield_signing_subsystem,
 			provisioner_subsystem,
 			pov_distribution_subsystem,
 			runtime_api_subsystem,
 			availability_store_subsystem,
 			network_bridge_subsystem,
+			collation_generation_subsystem,
+			collator_protocol_subsystem,
+			misbehavior_arbitration_subsystem,
 			s,
 			running_subsystems,
 			running_subsystems_rx,
@@ -669,6 +730,10 @@ where
 			let _ = s.tx.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
 		}
 
+		if let Some(ref mut s) = self.bitfield_signing_subsystem.instance {
+			let _ = s.tx.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}
+
 		if let Some(ref mut s) = self.provisioner_subsystem.instance {
 			let _ = s.tx.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
 		}
@@ -689,6 +754,18 @@ where
 			let _ = s.tx.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
 		}
 
+		if let Some(ref mut s) = self.collation_generation_subsystem.instance {
+			let _ = s.tx.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}
+
+		if let Some(ref mut s) = self.collator_protocol_subsystem.instance {
+			let _ = s.tx.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}
+
+		if let Some(ref mut s) = self.misbehavior_arbitration_subsystem.instance {
+			let _ = s.tx.send(FromOverseer::Signal(OverseerSignal::Conclude)).await;
+		}
+
 		let mut stop_delay = Delay::new(Duration::from_secs(STOP_DELAY)).fuse();
 
 		loop {
@@ -786,6 +863,8 @@ where
 			self.broadcast_signal(OverseerSignal::StopWork(hash)).await?
 		}
 
+		self.broadcast_signal(OverseerSignal::BlockFinalized(block.hash)).await?;
+
 		Ok(())
 	}
 
@@ -814,6 +893,10 @@ where
 			s.tx.send(FromOverseer::Signal(signal.clone())).await?;
 		}
 
+		if let Some(ref mut s) = self.bitfield_signing_subsystem.instance {
+			s.tx.send(FromOverseer::Signal(signal.clone())).await?;
+		}
+
 		if let Some(ref mut s) = self.provisioner_subsystem.instance {
 			s.tx.send(FromOverseer::Signal(signal.clone())).await?;
 		}
@@ -831,6 +914,18 @@ where
 		}
 
 		if let Some(ref mut s) = self.network_bridge_subsystem.instance {
+			s.tx.send(FromOverseer::Signal(signal.clone())).await?;
+		}
+
+		if let Some(ref mut s) = self.collation_generation_subsystem.instance {
+			s.tx.send(FromOverseer::Signal(signal.clone())).await?;
+		}
+
+		if let Some(ref mut s) = self.collator_protocol_subsystem.instance {
+			s.tx.send(FromOverseer::Signal(signal.clone())).await?;
+		}
+
+		if let Some(ref mut s) = self.misbehavior_arbitration_subsystem.instance {
 			s.tx.send(FromOverseer::Signal(signal)).await?;
 		}
 
@@ -869,6 +964,11 @@ where
 					let _ = s.tx.send(FromOverseer::Communication { msg }).await;
 				}
 			}
+			AllMessages::BitfieldSigning(msg) => {
+				if let Some(ref mut s) = self.bitfield_signing_subsystem.instance {
+					let _ = s.tx.send(FromOverseer::Communication { msg }).await;
+				}
+			}
 			AllMessages::Provisioner(msg) => {
 				if let Some(ref mut s) = self.provisioner_subsystem.instance {
 					let _ = s.tx.send(FromOverseer::Communication { msg }).await;
@@ -894,6 +994,21 @@ where
 					let _ = s.tx.send(FromOverseer::Communication { msg }).await;
 				}
 			}
+			AllMessages::CollationGeneration(msg) => {
+				if let Some(ref mut s) = self.collation_generation_subsystem.instance {
+					let _ = s.tx.send(FromOverseer::Communication { msg }).await;
+				}
+			}
+			AllMessages::CollatorProtocol(msg) => {
+				if let Some(ref mut s) = self.collator_protocol_subsystem.instance {
+					let _ = s.tx.send(FromOverseer::Communication { msg }).await;
+				}
+			}
+			AllMessages::MisbehaviorArbitration(msg) => {
+				if let Some(ref mut s) = self.misbehavior_arbitration_subsystem.instance {
+					let _ = s.tx.send(FromOverseer::Communication { msg }).await;
+				}
+			}
 		}
 	}
 
@@ -1033,11 +1148,15 @@ mod tests {
 				statement_distribution: DummySubsystem,
 				availability_distribution: DummySubsystem,
 				bitfield_distribution: DummySubsystem,
+				bitfield_signing: DummySubsystem,
 				provisioner: DummySubsystem,
 				pov_distribution: DummySubsystem,
 				runtime_api: DummySubsystem,
 				availability_store: DummySubsystem,
 				network_bridge: DummySubsystem,
+				collation_generation: DummySubsystem,
+				collator_protocol: DummySubsystem,
+				misbehavior_arbitration: DummySubsystem,
 			};
 			let (overseer, mut handler) = Overseer::new(
 				vec![],
@@ -1095,11 +1214,15 @@ mod tests {
 				statement_distribution: DummySubsystem,
 				availability_distribution: DummySubsystem,
 				bitfield_distribution: DummySubsystem,
+				bitfield_signing: DummySubsystem,
 				provisioner: DummySubsystem,
 				pov_distribution: DummySubsystem,
 				runtime_api: DummySubsystem,
 				availability_store: DummySubsystem,
 				network_bridge: DummySubsystem,
+				collation_generation: DummySubsystem,
+				collator_protocol: DummySubsystem,
+				misbehavior_arbitration: DummySubsystem,
 			};
 			let (overseer, _handle) = Overseer::new(
 				vec![],
@@ -1204,11 +1327,15 @@ mod tests {
 				statement_distribution: DummySubsystem,
 				availability_distribution: DummySubsystem,
 				bitfield_distribution: DummySubsystem,
+				bitfield_signing: DummySubsystem,
 				provisioner: DummySubsystem,
 				pov_distribution: DummySubsystem,
 				runtime_api: DummySubsystem,
 				availability_store: DummySubsystem,
 				network_bridge: DummySubsystem,
+				collation_generation: DummySubsystem,
+				collator_protocol: DummySubsystem,
+				misbehavior_arbitration: DummySubsystem,
 			};
 			let (overseer, mut handler) = Overseer::new(
 				vec![first_block],
@@ -1300,11 +1427,15 @@ mod tests {
 				statement_distribution: DummySubsystem,
 				availability_distribution: DummySubsystem,
 				bitfield_distribution: DummySubsystem,
+				bitfield_signing: DummySubsystem,
 				provisioner: DummySubsystem,
 				pov_distribution: DummySubsystem,
 				runtime_api: DummySubsystem,
 				availability_store: DummySubsystem,
 				network_bridge: DummySubsystem,
+				collation_generation: DummySubsystem,
+				collator_protocol: DummySubsystem,
+				misbehavior_arbitration: DummySubsystem,
 			};
 			// start with two forks of different height.
 			let (overseer, mut handler) = Overseer::new(
@@ -1327,6 +1458,7 @@ mod tests {
 				OverseerSignal::StartWork(second_block_hash),
 				OverseerSignal::StopWork(first_block_hash),
 				OverseerSignal::StopWork(second_block_hash),
+				OverseerSignal::BlockFinalized(third_block_hash),
 			];
 
 			loop {
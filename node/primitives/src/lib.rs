@@ -20,12 +20,15 @@
 //! not shared between the node and the runtime. This crate builds on top of the primitives defined
 //! there.
 
+use std::future::Future;
+use std::sync::Arc;
+
 use parity_scale_codec::{Decode, Encode};
 use polkadot_primitives::v1::{
 	Hash, CommittedCandidateReceipt, CandidateReceipt, CompactStatement,
 	EncodeAs, Signed, SigningContext, ValidatorIndex, ValidatorId,
 	UpwardMessage, Balance, ValidationCode, GlobalValidationSchedule, LocalValidationData,
-	HeadData,
+	HeadData, PoV, CollatorPair, Id as ParaId, OutboundHrmpMessage, BlockNumber,
 };
 use polkadot_statement_table::{
 	generic::{
@@ -81,7 +84,7 @@ impl EncodeAs<CompactStatement> for Statement {
 pub type SignedFullStatement = Signed<Statement, CompactStatement>;
 
 /// A misbehaviour report.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum MisbehaviorReport {
 	/// These validator nodes disagree on this candidate's validity, please figure it out
 	///
@@ -125,6 +128,12 @@ pub struct ValidationOutputs {
 	pub fees: Balance,
 	/// The new validation code submitted by the execution, if any.
 	pub new_validation_code: Option<ValidationCode>,
+	/// The number of messages processed from the para's downward message queue.
+	pub processed_downward_messages: u32,
+	/// Messages sent to other paras over HRMP channels, sorted by recipient.
+	pub horizontal_messages: Vec<OutboundHrmpMessage>,
+	/// The relay-chain block number up to which all inbound HRMP messages were processed.
+	pub hrmp_watermark: BlockNumber,
 }
 
 /// Result of the validation of the candidate.
@@ -257,3 +266,57 @@ impl View {
 		self.0.contains(hash)
 	}
 }
+
+/// The output of a collator.
+///
+/// This differs from `CandidateCommitments` in two ways:
+///
+/// - does not contain the erasure root; that's computed at the Polkadot level, not at Cumulus
+/// - contains a proof of validity.
+#[derive(Clone, Encode, Decode)]
+pub struct Collation {
+	/// Fees paid from the chain to the relay chain validators.
+	pub fees: Balance,
+	/// Messages destined to be interpreted by the Relay chain itself.
+	pub upward_messages: Vec<UpwardMessage>,
+	/// New validation code.
+	pub new_validation_code: Option<ValidationCode>,
+	/// The head-data produced as a result of execution.
+	pub head_data: HeadData,
+	/// The number of messages processed from the para's downward message queue.
+	pub processed_downward_messages: u32,
+	/// Messages sent to other paras over HRMP channels, sorted by recipient.
+	pub horizontal_messages: Vec<OutboundHrmpMessage>,
+	/// The relay-chain block number up to which all inbound HRMP messages were processed.
+	pub hrmp_watermark: BlockNumber,
+	/// Proof that this block is valid.
+	pub proof_of_validity: PoV,
+}
+
+/// The type of a collation function.
+///
+/// Given the global and local validation data for a para in the context of a relay-parent,
+/// it produces a future resolving to the collation to be submitted, or `None` if the
+/// collator has nothing to offer at that relay-parent.
+pub type CollatorFn = Box<
+	dyn Fn(&GlobalValidationSchedule, &LocalValidationData)
+		-> Box<dyn Future<Output = Option<Collation>> + Unpin + Send>
+	+ Send
+	+ Sync
+>;
+
+/// Configuration for the collation generator
+pub struct CollationGenerationConfig {
+	/// Collator's authentication key, so it can sign things.
+	pub key: Arc<CollatorPair>,
+	/// Collation function.
+	pub collator: CollatorFn,
+	/// The parachain that this collator collates for
+	pub para_id: ParaId,
+}
+
+impl std::fmt::Debug for CollationGenerationConfig {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "CollationGenerationConfig {{ ... }}")
+	}
+}
@@ -17,12 +17,13 @@
 //! Polkadot service. Specialized wrapper over substrate service.
 
 pub mod chain_spec;
-mod grandpa_support;
+pub mod grandpa_support;
 mod client;
 
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
-use polkadot_primitives::v1::{AccountId, Nonce, Balance};
+use polkadot_primitives::v1::{AccountId, Nonce, Balance, Hash};
 #[cfg(feature = "full-node")]
 use service::{error::Error as ServiceError, ServiceBuilder};
 use grandpa::{self, FinalityProofProvider as GrandpaFinalityProofProvider};
@@ -32,6 +33,22 @@ use sp_blockchain::HeaderBackend;
 use polkadot_overseer::{self as overseer, AllSubsystems, BlockInfo, Overseer, OverseerHandler};
 use polkadot_subsystem::DummySubsystem;
 use polkadot_node_core_proposer::ProposerFactory;
+use polkadot_node_core_candidate_validation::CandidateValidationSubsystem;
+use polkadot_node_core_runtime_api::RuntimeApiSubsystem;
+use polkadot_node_core_provisioner::ProvisioningSubsystem;
+use polkadot_node_core_bitfield_signing::{self as bitfield_signing, BitfieldSigningSubsystem};
+use polkadot_node_core_misbehavior_arbitration::MisbehaviorArbitrationSubsystem;
+use polkadot_node_collation_generation::CollationGenerationSubsystem;
+use polkadot_bitfield_distribution::BitfieldDistribution;
+use polkadot_availability_distribution::AvailabilityDistribution;
+use av_store::{AvailabilityStoreSubsystem, Config as AvailabilityConfig};
+use polkadot_collator_protocol::{CollatorProtocolSubsystem, ProtocolSide};
+use polkadot_node_core_candidate_selection::CandidateSelectionSubsystem;
+use polkadot_node_core_backing::CandidateBackingSubsystem;
+use polkadot_statement_distribution::StatementDistribution;
+use polkadot_pov_distribution::PoVDistribution;
+use polkadot_network_bridge::{self as network_bridge, NetworkBridge};
+use sc_keystore::KeyStorePtr;
 use sp_trie::PrefixedMemoryDB;
 pub use service::{
 	Role, PruningMode, TransactionPoolOptions, Error, RuntimeGenesis,
@@ -45,7 +62,7 @@ pub use sc_consensus::LongestChain;
 pub use sp_api::{ApiRef, Core as CoreApi, ConstructRuntimeApi, ProvideRuntimeApi, StateBackend};
 pub use sp_runtime::traits::{DigestFor, HashFor, NumberFor};
 pub use consensus_common::{Proposal, SelectChain, BlockImport, RecordProof, block_validation::Chain};
-pub use polkadot_primitives::v1::{Block, BlockId, CollatorId, Id as ParaId};
+pub use polkadot_primitives::v1::{Block, BlockId, CollatorId, Id as ParaId, ParachainHost};
 pub use sp_runtime::traits::{Block as BlockT, self as runtime_traits, BlakeTwo256};
 pub use chain_spec::{PolkadotChainSpec, KusamaChainSpec, WestendChainSpec};
 #[cfg(feature = "full-node")]
@@ -148,10 +165,23 @@ fn set_prometheus_registry(config: &mut Configuration) -> Result<(), ServiceErro
 ///
 /// Use this macro if you don't actually need the full service, but just the builder in order to
 /// be able to perform chain operations.
+#[macro_export]
 macro_rules! new_full_start {
 	($config:expr, $runtime:ty, $executor:ty) => {{
 		set_prometheus_registry(&mut $config)?;
 
+		let grandpa_hard_forks = if $config.chain_spec.is_kusama() {
+			grandpa_support::kusama_hard_forks()
+		} else {
+			Vec::new()
+		};
+
+		new_full_start!(with_grandpa_hard_forks $config, $runtime, $executor, grandpa_hard_forks)
+	}};
+	(test $config:expr, $runtime:ty, $executor:ty) => {{
+		new_full_start!(with_grandpa_hard_forks $config, $runtime, $executor, Vec::new())
+	}};
+	(with_grandpa_hard_forks $config:expr, $runtime:ty, $executor:ty, $grandpa_hard_forks:expr) => {{
 		let mut import_setup = None;
 		let mut rpc_setup = None;
 		let inherent_data_providers = inherents::InherentDataProviders::new();
@@ -176,7 +206,7 @@ macro_rules! new_full_start {
 				Ok(pool)
 			})?
 			.with_import_queue(|
-				config,
+				_config,
 				client,
 				mut select_chain,
 				_,
@@ -186,18 +216,12 @@ macro_rules! new_full_start {
 				let select_chain = select_chain.take()
 					.ok_or_else(|| service::Error::SelectChainRequired)?;
 
-				let grandpa_hard_forks = if config.chain_spec.is_kusama() {
-					grandpa_support::kusama_hard_forks()
-				} else {
-					Vec::new()
-				};
-
 				let (grandpa_block_import, grandpa_link) =
 					grandpa::block_import_with_authority_set_hard_forks(
 						client.clone(),
 						&(client.clone() as Arc<_>),
 						select_chain.clone(),
-						grandpa_hard_forks,
+						$grandpa_hard_forks,
 					)?;
 
 				let justification_import = grandpa_block_import.clone();
@@ -269,7 +293,11 @@ macro_rules! new_full_start {
 	}}
 }
 
-fn real_overseer<S: futures::task::Spawn>(
+/// Create an overseer for a node whose runtime doesn't implement the v1 `ParachainHost` API.
+///
+/// Nothing can answer runtime API requests on such a node, so every subsystem which issues them
+/// is left as a dummy.
+fn real_overseer<S: futures::task::Spawn + Clone + Send + 'static>(
 	leaves: impl IntoIterator<Item = BlockInfo>,
 	s: S,
 ) -> Result<(Overseer<S>, OverseerHandler), ServiceError> {
@@ -280,14 +308,77 @@ fn real_overseer<S: futures::task::Spawn>(
 		statement_distribution: DummySubsystem,
 		availability_distribution: DummySubsystem,
 		bitfield_distribution: DummySubsystem,
+		bitfield_signing: DummySubsystem,
 		provisioner: DummySubsystem,
 		pov_distribution: DummySubsystem,
 		runtime_api: DummySubsystem,
 		availability_store: DummySubsystem,
 		network_bridge: DummySubsystem,
+		collation_generation: DummySubsystem,
+		collator_protocol: DummySubsystem,
+		misbehavior_arbitration: DummySubsystem,
 	};
 	Overseer::new(
-		leaves, 
+		leaves,
+		all_subsystems,
+		s,
+	).map_err(|e| ServiceError::Other(format!("Failed to create an Overseer: {:?}", e)))
+}
+
+/// Create an overseer for a node whose runtime implements the v1 `ParachainHost` API.
+///
+/// Runtime API requests are answered against the given client, candidates are backed and
+/// availability bitfields are signed with the keys in the keystore, and the networking subsystems
+/// talk to peers through the given network service, on which the notifications protocol of the
+/// network bridge is registered. Erasure chunks and available data are persisted under
+/// `av_store_path`, or kept in memory if the node has no database path. The collator side of the
+/// collator protocol is run if the node is `collating_for` a para, the validator side otherwise.
+pub fn v1_overseer<S, Client>(
+	leaves: impl IntoIterator<Item = BlockInfo>,
+	client: Arc<Client>,
+	network: Arc<sc_network::NetworkService<Block, Hash>>,
+	keystore: KeyStorePtr,
+	av_store_path: Option<PathBuf>,
+	collating_for: Option<(CollatorId, ParaId)>,
+	s: S,
+) -> Result<(Overseer<S>, OverseerHandler), ServiceError>
+where
+	S: futures::task::Spawn + Clone + Send + 'static,
+	Client: ProvideRuntimeApi<Block> + Send + Sync + 'static,
+	Client::Api: ParachainHost<Block>,
+{
+	let availability_store = match av_store_path {
+		Some(path) => AvailabilityStoreSubsystem::new(AvailabilityConfig { cache_size: None, path })
+			.map_err(|e| ServiceError::Other(format!("Failed to open the availability store: {:?}", e)))?,
+		None => AvailabilityStoreSubsystem::new_in_memory(),
+	};
+	let protocol_side = match collating_for {
+		Some((collator_id, para_id)) => ProtocolSide::Collator(collator_id, para_id),
+		None => ProtocolSide::Validator,
+	};
+
+	let (engine_id, protocol_name) = network_bridge::notifications_protocol_info();
+	network.register_notifications_protocol(engine_id, protocol_name);
+
+	let all_subsystems = AllSubsystems {
+		candidate_validation: CandidateValidationSubsystem,
+		candidate_backing: CandidateBackingSubsystem::new(keystore.clone(), s.clone()),
+		candidate_selection: CandidateSelectionSubsystem::new(keystore.clone(), s.clone()),
+		statement_distribution: StatementDistribution,
+		availability_distribution: AvailabilityDistribution::new(keystore.clone()),
+		bitfield_distribution: BitfieldDistribution,
+		bitfield_signing: BitfieldSigningSubsystem::new(keystore, s.clone(), bitfield_signing::JOB_DELAY),
+		provisioner: ProvisioningSubsystem::new(s.clone()),
+		pov_distribution: PoVDistribution,
+		runtime_api: RuntimeApiSubsystem::new(client),
+		availability_store,
+		network_bridge: NetworkBridge::new(network),
+		collation_generation: CollationGenerationSubsystem,
+		collator_protocol: CollatorProtocolSubsystem::new(protocol_side),
+		misbehavior_arbitration: MisbehaviorArbitrationSubsystem,
+	};
+	Overseer::new(
+		leaves,
 		all_subsystems,
 		s,
 	).map_err(|e| ServiceError::Other(format!("Failed to create an Overseer: {:?}", e)))
@@ -297,12 +388,13 @@ fn real_overseer<S: futures::task::Spawn>(
 #[macro_export]
 macro_rules! new_full {
 	(
+		with_full_start
 		$config:expr,
 		$collating_for:expr,
 		$authority_discovery_enabled:expr,
 		$grandpa_pause:expr,
-		$runtime:ty,
-		$dispatch:ty,
+		$new_full_start:expr,
+		$new_overseer:expr $(,)?
 	) => {{
 		use sc_client_api::ExecutorProvider;
 		use sp_core::traits::BareCryptoStorePtr;
@@ -313,13 +405,14 @@ macro_rules! new_full {
 		let force_authoring = $config.force_authoring;
 		let disable_grandpa = $config.disable_grandpa;
 		let name = $config.network.node_name.clone();
+		let av_store_path = $config.database.path()
+			.map(|path| path.join("parachains").join("availability"));
 
-		let (builder, mut import_setup, inherent_data_providers, mut rpc_setup) =
-			new_full_start!($config, $runtime, $dispatch);
+		let (builder, mut import_setup, inherent_data_providers, mut rpc_setup) = $new_full_start;
 
 		let ServiceComponents {
 			client, network, select_chain, keystore, transaction_pool, prometheus_registry,
-			task_manager, telemetry_on_connect_sinks, ..
+			task_manager, telemetry_on_connect_sinks, rpc_handlers, ..
 		} = builder
 			.with_finality_proof_provider(|client, backend| {
 				let provider = client as Arc<dyn grandpa::StorageAndProofProvider<_, _>>;
@@ -351,8 +444,19 @@ macro_rules! new_full {
 			})
 			.collect();
 
-		let (overseer, handler) = real_overseer(leaves, spawner)?;
+		let (overseer, handler) = $new_overseer(
+			leaves,
+			client.clone(),
+			network.clone(),
+			keystore.clone(),
+			av_store_path,
+			$collating_for.clone(),
+			spawner,
+		)?;
 		let handler_clone = handler.clone();
+		let handles = FullNodeHandles {
+			overseer_handler: handler.clone(),
+		};
 
 		task_manager.spawn_essential_handle().spawn_blocking("overseer", Box::pin(async move {
 			use futures::{pin_mut, select, FutureExt};
@@ -440,7 +544,7 @@ macro_rules! new_full {
 					);
 
 					grandpa::VotingRulesBuilder::default()
-						.add(grandpa_support::PauseAfterBlockFor(block, delay))
+						.add($crate::grandpa_support::PauseAfterBlockFor(block, delay))
 						.build()
 				},
 				None =>
@@ -471,11 +575,52 @@ macro_rules! new_full {
 			)?;
 		}
 
-		(task_manager, client)
-	}}
+		(task_manager, client, network, rpc_handlers, handles)
+	}};
+	(
+		$config:expr,
+		$collating_for:expr,
+		$authority_discovery_enabled:expr,
+		$grandpa_pause:expr,
+		$runtime:ty,
+		$dispatch:ty,
+	) => {{
+		new_full!(with_full_start
+			$config,
+			$collating_for,
+			$authority_discovery_enabled,
+			$grandpa_pause,
+			new_full_start!($config, $runtime, $dispatch),
+			|leaves, _client, _network, _keystore, _av_store_path, _collating_for, spawner| {
+				real_overseer(leaves, spawner)
+			},
+		)
+	}};
+	(
+		test
+		$config:expr,
+		$collating_for:expr,
+		$authority_discovery_enabled:expr,
+		$runtime:ty,
+		$dispatch:ty,
+	) => {{
+		new_full!(with_full_start
+			$config,
+			$collating_for,
+			$authority_discovery_enabled,
+			None,
+			new_full_start!(test $config, $runtime, $dispatch),
+			$crate::v1_overseer,
+		)
+	}};
 }
 
-pub struct FullNodeHandles;
+/// Handles to the subsystems of a full node, for use by the embedder.
+pub struct FullNodeHandles {
+	/// A handle to the overseer, which can be used to send messages to subsystems,
+	/// e.g. to initialize the collation generation subsystem of a collator.
+	pub overseer_handler: OverseerHandler,
+}
 
 /// Builds a new service for a light client.
 #[macro_export]
@@ -614,7 +759,7 @@ pub fn polkadot_new_full(
 		FullNodeHandles,
 	), ServiceError>
 {
-	let (components, client) = new_full!(
+	let (components, client, _, _, handles) = new_full!(
 		config,
 		collating_for,
 		authority_discovery_enabled,
@@ -623,7 +768,7 @@ pub fn polkadot_new_full(
 		PolkadotExecutor,
 	);
 
-	Ok((components, client, FullNodeHandles))
+	Ok((components, client, handles))
 }
 
 /// Create a new Kusama service for a full node.
@@ -646,7 +791,7 @@ pub fn kusama_new_full(
 		FullNodeHandles,
 	), ServiceError>
 {
-	let (components, client) = new_full!(
+	let (components, client, _, _, handles) = new_full!(
 		config,
 		collating_for,
 		authority_discovery_enabled,
@@ -655,7 +800,7 @@ pub fn kusama_new_full(
 		KusamaExecutor,
 	);
 
-	Ok((components, client, FullNodeHandles))
+	Ok((components, client, handles))
 }
 
 /// Create a new Kusama service for a full node.
@@ -678,7 +823,7 @@ pub fn westend_new_full(
 		FullNodeHandles,
 	), ServiceError>
 {
-	let (components, client) = new_full!(
+	let (components, client, _, _, handles) = new_full!(
 		config,
 		collating_for,
 		authority_discovery_enabled,
@@ -687,7 +832,7 @@ pub fn westend_new_full(
 		WestendExecutor,
 	);
 
-	Ok((components, client, FullNodeHandles))
+	Ok((components, client, handles))
 }
 
 /// Create a new Polkadot service for a light client.
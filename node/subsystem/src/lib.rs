@@ -40,6 +40,8 @@ pub enum OverseerSignal {
 	StartWork(Hash),
 	/// `Subsystem` should stop working on block-based work specified by the relay-chain block hash.
 	StopWork(Hash),
+	/// The given relay-chain block has been finalized.
+	BlockFinalized(Hash),
 	/// Conclude the work of the `Overseer` and all `Subsystem`s.
 	Conclude,
 }
@@ -28,16 +28,20 @@ use polkadot_primitives::v1::{
 	BlockNumber, Hash,
 	CandidateReceipt, PoV, ErasureChunk, BackedCandidate, Id as ParaId,
 	SignedAvailabilityBitfield, SigningContext, ValidatorId, ValidationCode, ValidatorIndex,
-	CoreAssignment, CoreOccupied, HeadData, CandidateDescriptor,
-	ValidatorSignature, OmittedValidationData,
+	HeadData, CandidateDescriptor, OmittedValidationData,
+	GlobalValidationSchedule, LocalValidationData, CommittedCandidateReceipt, AvailableData,
+	CollatorId, DownwardMessage, DoubleVoteReport, SignedStatement, CoreState, SessionIndex,
+	SessionInfo,
 };
 use polkadot_node_primitives::{
 	MisbehaviorReport, SignedFullStatement, View, ProtocolId, ValidationResult,
+	CollationGenerationConfig,
 };
 
 use std::sync::Arc;
 
 pub use sc_network::{ObservedRole, ReputationChange, PeerId};
+pub use polkadot_primitives::v1::SchedulerRoster;
 
 /// A notification of a new backed candidate.
 #[derive(Debug)]
@@ -46,6 +50,9 @@ pub struct NewBackedCandidate(pub BackedCandidate);
 /// Messages received by the Candidate Selection subsystem.
 #[derive(Debug)]
 pub enum CandidateSelectionMessage {
+	/// A collation for the given para, under the given relay-parent, can be fetched from the given
+	/// collator and should be considered for seconding.
+	Collation(Hash, ParaId, CollatorId),
 	/// We recommended a particular candidate to be seconded, but it was invalid; penalize the collator.
 	/// The hash is the relay parent.
 	Invalid(Hash, CandidateReceipt),
@@ -91,11 +98,14 @@ pub enum CandidateValidationMessage {
 	),
 	/// Validate a candidate with provided, exhaustive parameters for validation.
 	///
-	/// Explicitly provide the `OmittedValidationData` and `ValidationCode` so this can do full
-	/// validation without needing to access the state of the relay-chain.
+	/// Explicitly provide the `OmittedValidationData`, `ValidationCode` and the para's downward
+	/// messages so this can do full validation without needing to access the state of the
+	/// relay-chain. The messages must match the `downward_messages_hash` of the
+	/// `LocalValidationData`, or the candidate is invalid.
 	ValidateFromExhaustive(
 		OmittedValidationData,
 		ValidationCode,
+		Vec<DownwardMessage>,
 		CandidateDescriptor,
 		Arc<PoV>,
 		oneshot::Sender<Result<ValidationResult, ValidationFailed>>,
@@ -157,30 +167,31 @@ pub enum BitfieldDistributionMessage {
 	NetworkBridgeUpdate(NetworkBridgeEvent),
 }
 
+/// Bitfield signing message.
+///
+/// Currently non-instantiable.
+#[derive(Debug)]
+pub enum BitfieldSigningMessage {}
+
 /// Availability store subsystem message.
 #[derive(Debug)]
 pub enum AvailabilityStoreMessage {
-	/// Query a `PoV` from the AV store.
+	/// Query a `PoV` from the AV store by candidate hash.
 	QueryPoV(Hash, oneshot::Sender<Option<PoV>>),
 
-	/// Query an `ErasureChunk` from the AV store.
-	QueryChunk(Hash, ValidatorIndex, oneshot::Sender<ErasureChunk>),
+	/// Query an `ErasureChunk` from the AV store by candidate hash and validator index.
+	QueryChunk(Hash, ValidatorIndex, oneshot::Sender<Option<ErasureChunk>>),
 
-	/// Store an `ErasureChunk` in the AV store.
-	StoreChunk(Hash, ValidatorIndex, ErasureChunk),
-}
+	/// Query the indices of the `ErasureChunk`s of a candidate which are in the AV store.
+	QueryAvailableChunks(Hash, oneshot::Sender<Vec<ValidatorIndex>>),
 
-/// The information on scheduler assignments that some somesystems may be querying.
-#[derive(Debug, Clone)]
-pub struct SchedulerRoster {
-	/// Validator-to-groups assignments.
-	pub validator_groups: Vec<Vec<ValidatorIndex>>,
-	/// All scheduled paras.
-	pub scheduled: Vec<CoreAssignment>,
-	/// Upcoming paras (chains and threads).
-	pub upcoming: Vec<ParaId>,
-	/// Occupied cores.
-	pub availability_cores: Vec<Option<CoreOccupied>>,
+	/// Store an `ErasureChunk` in the AV store, given the candidate hash and the hash of the
+	/// relay-parent of the candidate.
+	StoreChunk(Hash, Hash, ValidatorIndex, ErasureChunk),
+
+	/// Store the `AvailableData` of a candidate in the AV store, given the candidate hash and
+	/// the hash of the relay-parent of the candidate.
+	StoreAvailableData(Hash, Hash, AvailableData),
 }
 
 /// A request to the Runtime API subsystem.
@@ -192,12 +203,29 @@ pub enum RuntimeApiRequest {
 	ValidatorGroups(oneshot::Sender<SchedulerRoster>),
 	/// Get a signing context for bitfields and statements.
 	SigningContext(oneshot::Sender<SigningContext>),
+	/// Get the global validation schedule for candidates executed in the context of the relay-parent.
+	GlobalValidationSchedule(oneshot::Sender<GlobalValidationSchedule>),
+	/// Get the local validation data for a specific para, if the para is registered.
+	LocalValidationData(ParaId, oneshot::Sender<Option<LocalValidationData>>),
 	/// Get the validation code for a specific para, assuming execution under given block number, and
 	/// an optional block number representing an intermediate parablock executed in the context of
-	/// that block.
-	ValidationCode(ParaId, BlockNumber, Option<BlockNumber>, oneshot::Sender<ValidationCode>),
-	/// Get head data for a specific para.
-	HeadData(ParaId, oneshot::Sender<HeadData>),
+	/// that block. This is `None` if the code is unknown or has been pruned.
+	ValidationCode(ParaId, BlockNumber, Option<BlockNumber>, oneshot::Sender<Option<ValidationCode>>),
+	/// Get the hash of the validation code which would be returned by `ValidationCode` for the same
+	/// parameters, if any. This allows looking up code which is already known without fetching it.
+	ValidationCodeHash(ParaId, BlockNumber, Option<BlockNumber>, oneshot::Sender<Option<Hash>>),
+	/// Get the validation code with the given hash, if it is still stored.
+	ValidationCodeByHash(Hash, oneshot::Sender<Option<ValidationCode>>),
+	/// Get head data for a specific para, if the para is registered.
+	HeadData(ParaId, oneshot::Sender<Option<HeadData>>),
+	/// Get the receipt of the candidate pending availability for a specific para, if any.
+	CandidatePendingAvailability(ParaId, oneshot::Sender<Option<CommittedCandidateReceipt>>),
+	/// Get the downward messages queued for a specific para.
+	DownwardMessages(ParaId, oneshot::Sender<Vec<DownwardMessage>>),
+	/// Get the state of all availability cores, as predicted for a child of the relay-parent.
+	AvailabilityCores(oneshot::Sender<Vec<CoreState>>),
+	/// Get information about a session, if it has started and its information has not been pruned.
+	SessionInfo(SessionIndex, oneshot::Sender<Option<SessionInfo>>),
 }
 
 /// A message to the Runtime API subsystem.
@@ -218,16 +246,19 @@ pub enum StatementDistributionMessage {
 }
 
 /// This data becomes intrinsics or extrinsics which should be included in a future relay chain block.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ProvisionableData {
 	/// This bitfield indicates the availability of various candidate blocks.
 	Bitfield(Hash, SignedAvailabilityBitfield),
 	/// The Candidate Backing subsystem believes that this candidate is valid, pending availability.
 	BackedCandidate(BackedCandidate),
 	/// Misbehavior reports are self-contained proofs of validator misbehavior.
-	MisbehaviorReport(Hash, MisbehaviorReport),
+	MisbehaviorReport(Hash, DoubleVoteReport),
 	/// Disputes trigger a broad dispute resolution process.
-	Dispute(Hash, ValidatorSignature),
+	///
+	/// The second hash is that of the disputed candidate, and the statements are the
+	/// conflicting votes on its validity.
+	Dispute(Hash, Hash, Vec<SignedStatement>),
 }
 
 /// This data needs to make its way from the provisioner into the InherentData.
@@ -253,6 +284,37 @@ pub enum ProvisionerMessage {
 	ProvisionableData(ProvisionableData),
 }
 
+/// Message to the Misbehavior Arbitration subsystem.
+#[derive(Debug)]
+pub enum MisbehaviorArbitrationMessage {
+	/// Report a suspected misbehavior, witnessed in the context of the given relay-parent.
+	///
+	/// The report is verified before any action is taken on it.
+	Report(Hash, MisbehaviorReport),
+}
+
+/// Message to the Collation Generation subsystem.
+#[derive(Debug)]
+pub enum CollationGenerationMessage {
+	/// Initialize the collation generation subsystem.
+	///
+	/// Until this is received, the subsystem does nothing.
+	Initialize(CollationGenerationConfig),
+}
+
+/// Messages received by the Collator Protocol subsystem.
+#[derive(Debug)]
+pub enum CollatorProtocolMessage {
+	/// Provide a collation to distribute to validators.
+	DistributeCollation(CandidateReceipt, PoV),
+	/// Fetch a collation for the given para under the given relay-parent from the given collator.
+	FetchCollation(Hash, CollatorId, ParaId, oneshot::Sender<(CandidateReceipt, PoV)>),
+	/// Report a collator as having provided an invalid collation.
+	ReportCollator(CollatorId),
+	/// Event from the network bridge.
+	NetworkBridgeUpdate(NetworkBridgeEvent),
+}
+
 /// Message to the PoV Distribution Subsystem.
 #[derive(Debug)]
 pub enum PoVDistributionMessage {
@@ -283,6 +345,8 @@ pub enum AllMessages {
 	AvailabilityDistribution(AvailabilityDistributionMessage),
 	/// Message for the bitfield distribution subsystem.
 	BitfieldDistribution(BitfieldDistributionMessage),
+	/// Message for the bitfield signing subsystem.
+	BitfieldSigning(BitfieldSigningMessage),
 	/// Message for the Provisioner subsystem.
 	Provisioner(ProvisionerMessage),
 	/// Message for the PoV Distribution subsystem.
@@ -293,4 +357,10 @@ pub enum AllMessages {
 	AvailabilityStore(AvailabilityStoreMessage),
 	/// Message for the network bridge subsystem.
 	NetworkBridge(NetworkBridgeMessage),
+	/// Message for the Collation Generation subsystem.
+	CollationGeneration(CollationGenerationMessage),
+	/// Message for the Collator Protocol subsystem.
+	CollatorProtocol(CollatorProtocolMessage),
+	/// Message for the Misbehavior Arbitration subsystem.
+	MisbehaviorArbitration(MisbehaviorArbitrationMessage),
 }
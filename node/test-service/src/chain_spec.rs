@@ -17,15 +17,19 @@
 use babe_primitives::AuthorityId as BabeId;
 use grandpa::AuthorityId as GrandpaId;
 use pallet_staking::Forcing;
-use polkadot_primitives::v0::{ValidatorId, AccountId};
+use polkadot_primitives::v1::{ValidatorId, AccountId, HeadData, Id as ParaId, ValidationCode};
+use polkadot_runtime_parachains::{configuration::HostConfiguration, paras::ParaGenesisArgs};
 use polkadot_service::chain_spec::{get_account_id_from_seed, get_from_seed, Extensions};
-use polkadot_test_runtime::constants::currency::DOTS;
+use polkadot_test_runtime::constants::{currency::DOTS, time::MINUTES};
 use sc_chain_spec::{ChainSpec, ChainType};
 use sp_core::{sr25519, ChangesTrieConfiguration};
-use sp_runtime::Perbill;
+use sp_runtime::{codec::Encode, Perbill};
 
 const DEFAULT_PROTOCOL_ID: &str = "dot";
 
+/// The ID of the adder test parachain, which is registered at genesis.
+pub const ADDER_PARA_ID: u32 = 100;
+
 /// The `ChainSpec parametrised for polkadot runtime`.
 pub type PolkadotChainSpec =
 	service::GenericChainSpec<polkadot_test_runtime::GenesisConfig, Extensions>;
@@ -61,6 +65,21 @@ pub fn polkadot_local_testnet_genesis(
 	)
 }
 
+/// The genesis arguments of the adder test parachain.
+pub fn adder_genesis_args() -> ParaGenesisArgs {
+	let genesis_head = test_parachain_adder::HeadData {
+		number: 0,
+		parent_hash: [0; 32],
+		post_state: test_parachain_adder::hash_state(0),
+	};
+
+	ParaGenesisArgs {
+		genesis_head: HeadData(genesis_head.encode()),
+		validation_code: ValidationCode(test_parachain_adder::WASM_BINARY.to_vec()),
+		parachain: true,
+	}
+}
+
 /// Helper function to generate stash, controller and session key from seed
 fn get_authority_keys_from_seed(
 	seed: &str,
@@ -155,11 +174,38 @@ fn polkadot_testnet_genesis(
 		babe: Some(Default::default()),
 		grandpa: Some(Default::default()),
 		authority_discovery: Some(polkadot::AuthorityDiscoveryConfig { keys: vec![] }),
-		parachains: Some(polkadot::ParachainsConfig {
-			authorities: vec![],
+		configuration: Some(polkadot::ConfigurationConfig {
+			config: HostConfiguration {
+				validation_upgrade_frequency: 2,
+				validation_upgrade_delay: 1,
+				acceptance_period: 1 * MINUTES,
+				max_code_size: 10 * 1024 * 1024, // 10 MB
+				max_head_data_size: 20 * 1024, // 20 KB
+				parathread_cores: 0,
+				parathread_retries: 0,
+				parathread_claim_fee: 0,
+				parachain_rotation_frequency: 20,
+				chain_availability_period: 4,
+				thread_availability_period: 4,
+				scheduling_lookahead: 1,
+				max_upward_queue_count: 10,
+				watermark_upward_queue_size: 20 * 1024, // 20 KB
+				max_upward_dispatch_weight: 100_000_000_000, // 0.1s of execution
+				max_downward_queue_count: 10,
+				hrmp_open_request_ttl: 2,
+				hrmp_sender_deposit: 0,
+				hrmp_recipient_deposit: 0,
+				hrmp_channel_max_capacity: 8,
+				hrmp_channel_max_message_size: 1024,
+				hrmp_max_parachain_inbound_channels: 4,
+				hrmp_max_parachain_outbound_channels: 4,
+				hrmp_max_message_num_per_candidate: 10,
+				needed_approvals: 2,
+				dispute_period: 6,
+			},
 		}),
-		registrar: Some(polkadot::RegistrarConfig {
-			parachains: vec![],
+		paras: Some(polkadot::ParasConfig {
+			paras: vec![(ParaId::from(ADDER_PARA_ID), adder_genesis_args())],
 			_phdata: Default::default(),
 		}),
 		claims: Some(polkadot::ClaimsConfig {
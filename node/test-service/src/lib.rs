@@ -21,19 +21,20 @@
 mod chain_spec;
 
 pub use chain_spec::*;
-use consensus_common::{block_validation::Chain, SelectChain};
+use consensus_common::SelectChain;
 use futures::future::Future;
 use grandpa::FinalityProofProvider as GrandpaFinalityProofProvider;
 use log::info;
-use polkadot_network::{legacy::gossip::Known, protocol as network_protocol};
-use polkadot_primitives::v0::{
+use polkadot_node_core_proposer::ProposerFactory;
+use polkadot_overseer::{self as overseer, BlockInfo};
+use polkadot_primitives::v1::{
 	Block, BlockId, Hash, CollatorId, Id as ParaId,
 };
-use polkadot_runtime_common::{parachains, registrar, BlockHashCount};
+use polkadot_runtime_common::BlockHashCount;
 use polkadot_service::{
 	new_full, new_full_start, FullNodeHandles, PolkadotClient, ServiceComponents,
 };
-use polkadot_test_runtime::{RestrictFunctionality, Runtime, SignedExtra, SignedPayload, VERSION};
+use polkadot_test_runtime::{Runtime, SignedExtra, SignedPayload, VERSION};
 use sc_chain_spec::ChainSpec;
 use sc_client_api::{execution_extensions::ExecutionStrategies, BlockchainEvents};
 use sc_executor::native_executor_instance;
@@ -65,12 +66,15 @@ native_executor_instance!(
 );
 
 /// Create a new Polkadot test service for a full node.
+///
+/// The test runtime implements the v1 `ParachainHost` API, so the node runs on the overseer along
+/// with the subsystems which talk to the runtime.
 pub fn polkadot_test_new_full(
 	config: Configuration,
 	collating_for: Option<(CollatorId, ParaId)>,
-	max_block_data_size: Option<u64>,
+	_max_block_data_size: Option<u64>,
 	authority_discovery_enabled: bool,
-	slot_duration: u64,
+	_slot_duration: u64,
 ) -> Result<
 	(
 		TaskManager,
@@ -81,12 +85,10 @@ pub fn polkadot_test_new_full(
 	),
 	ServiceError,
 > {
-	let (task_manager, client, handles, network, rpc_handlers) = new_full!(test
+	let (task_manager, client, network, rpc_handlers, handles) = new_full!(test
 		config,
 		collating_for,
-		max_block_data_size,
 		authority_discovery_enabled,
-		slot_duration,
 		polkadot_test_runtime::RuntimeApi,
 		PolkadotTestExecutor,
 	);
@@ -201,12 +203,43 @@ pub fn run_test_node(
 ) -> PolkadotTestNode<
 	TaskManager,
 	impl PolkadotClient<Block, TFullBackend<Block>, polkadot_test_runtime::RuntimeApi>,
+> {
+	run_node(task_executor, key, storage_update_func, boot_nodes, None)
+}
+
+/// Run a Polkadot test node which collates for the para `para_id` with the collator key
+/// `collator_id`. The collation generation subsystem of the node still needs to be initialized
+/// through the overseer handler before any collations are produced. See [`run_test_node`] for the
+/// other arguments.
+pub fn run_collator_node(
+	task_executor: TaskExecutor,
+	key: Sr25519Keyring,
+	storage_update_func: impl Fn(),
+	boot_nodes: Vec<MultiaddrWithPeerId>,
+	collator_id: CollatorId,
+	para_id: ParaId,
+) -> PolkadotTestNode<
+	TaskManager,
+	impl PolkadotClient<Block, TFullBackend<Block>, polkadot_test_runtime::RuntimeApi>,
+> {
+	run_node(task_executor, key, storage_update_func, boot_nodes, Some((collator_id, para_id)))
+}
+
+fn run_node(
+	task_executor: TaskExecutor,
+	key: Sr25519Keyring,
+	storage_update_func: impl Fn(),
+	boot_nodes: Vec<MultiaddrWithPeerId>,
+	collating_for: Option<(CollatorId, ParaId)>,
+) -> PolkadotTestNode<
+	TaskManager,
+	impl PolkadotClient<Block, TFullBackend<Block>, polkadot_test_runtime::RuntimeApi>,
 > {
 	let config = node_config(storage_update_func, task_executor, key, boot_nodes);
 	let multiaddr = config.network.listen_addresses[0].clone();
 	let authority_discovery_enabled = false;
 	let (task_manager, client, handles, network, rpc_handlers) =
-		polkadot_test_new_full(config, None, None, authority_discovery_enabled, 6000)
+		polkadot_test_new_full(config, collating_for, None, authority_discovery_enabled, 6000)
 			.expect("could not create Polkadot test service");
 
 	let peer_id = network.local_peer_id().clone();
@@ -255,7 +288,6 @@ where
 			.unwrap_or(2) as u64;
 		let tip = 0;
 		let extra: SignedExtra = (
-			RestrictFunctionality,
 			frame_system::CheckSpecVersion::<Runtime>::new(),
 			frame_system::CheckTxVersion::<Runtime>::new(),
 			frame_system::CheckGenesis::<Runtime>::new(),
@@ -263,14 +295,11 @@ where
 			frame_system::CheckNonce::<Runtime>::from(nonce),
 			frame_system::CheckWeight::<Runtime>::new(),
 			pallet_transaction_payment::ChargeTransactionPayment::<Runtime>::from(tip),
-			registrar::LimitParathreadCommits::<Runtime>::new(),
-			parachains::ValidateDoubleVoteReports::<Runtime>::new(),
 		);
 		let raw_payload = SignedPayload::from_raw(
 			function.clone(),
 			extra.clone(),
 			(
-				(),
 				VERSION.spec_version,
 				VERSION.transaction_version,
 				genesis_block,
@@ -278,15 +307,13 @@ where
 				(),
 				(),
 				(),
-				(),
-				(),
 			),
 		);
 		let signature = raw_payload.using_encoded(|e| caller.sign(e));
 		let extrinsic = polkadot_test_runtime::UncheckedExtrinsic::new_signed(
 			function.clone(),
 			polkadot_test_runtime::Address::Id(caller.public().into()),
-			polkadot_primitives::v0::Signature::Sr25519(signature.clone()),
+			polkadot_primitives::v1::Signature::Sr25519(signature.clone()),
 			extra.clone(),
 		);
 
@@ -0,0 +1,152 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+use tokio::{time::delay_for as sleep, task::spawn};
+use futures::{future, pin_mut, select, FutureExt as _, StreamExt as _};
+use polkadot_node_primitives::{Collation, CollationGenerationConfig, CollatorFn};
+use polkadot_node_subsystem::messages::{AllMessages, CollationGenerationMessage};
+use polkadot_primitives::v1::{BlockData, CollatorPair, HeadData, Id as ParaId, PoV};
+use polkadot_service::{BlockId, ParachainHost, ProvideRuntimeApi};
+use polkadot_test_service::*;
+use sc_client_api::BlockchainEvents;
+use service::TaskExecutor;
+use sp_core::Pair;
+use sp_keyring::Sr25519Keyring;
+use sp_runtime::codec::{Decode, Encode};
+use std::{sync::Arc, time::Duration};
+use test_parachain_adder::{BlockData as AdderBody, HeadData as AdderHead};
+
+static INTEGRATION_TEST_ALLOWED_TIME: Option<&str> = option_env!("INTEGRATION_TEST_ALLOWED_TIME");
+
+/// A collation function for the adder parachain which adds nothing, so the state of the para
+/// stays at its genesis value and every block can be built without remembering past states.
+fn adder_collation_function() -> CollatorFn {
+	Box::new(|global_validation, local_validation| {
+		let parent_head = AdderHead::decode(&mut &local_validation.parent_head.0[..]).ok();
+		let collation = parent_head.and_then(|parent_head| {
+			let block_data = AdderBody { state: 0, add: 0, send: Vec::new() };
+			let head = test_parachain_adder::execute(
+				parent_head.hash(),
+				parent_head,
+				&block_data,
+				&local_validation.inbound_hrmp_messages,
+			).ok()?;
+
+			Some(Collation {
+				fees: 0,
+				upward_messages: Vec::new(),
+				new_validation_code: None,
+				head_data: HeadData(head.encode()),
+				processed_downward_messages: 0,
+				horizontal_messages: Vec::new(),
+				hrmp_watermark: global_validation.block_number,
+				proof_of_validity: PoV { block_data: BlockData(block_data.encode()) },
+			})
+		});
+
+		Box::new(future::ready(collation))
+	})
+}
+
+#[tokio::test]
+async fn ensure_backed_candidates_get_included() {
+	let task_executor: TaskExecutor = (move |fut, _| {
+		spawn(fut);
+	})
+	.into();
+	let para_id = ParaId::from(ADDER_PARA_ID);
+	let collator_key = Arc::new(CollatorPair::generate().0);
+	let mut alice = run_test_node(
+		task_executor.clone(),
+		Sr25519Keyring::Alice,
+		|| {},
+		Vec::new(),
+	);
+	let mut bob = run_test_node(
+		task_executor.clone(),
+		Sr25519Keyring::Bob,
+		|| {},
+		vec![alice.addr.clone()],
+	);
+	let mut collator = run_collator_node(
+		task_executor.clone(),
+		Sr25519Keyring::Dave,
+		|| {},
+		vec![alice.addr.clone(), bob.addr.clone()],
+		collator_key.public(),
+		para_id,
+	);
+
+	let config = CollationGenerationConfig {
+		key: collator_key,
+		collator: adder_collation_function(),
+		para_id,
+	};
+	collator.handles.overseer_handler
+		.send_msg(AllMessages::CollationGeneration(CollationGenerationMessage::Initialize(config)))
+		.await
+		.expect("the overseer of the collator is running");
+
+	let t1 = sleep(Duration::from_secs(
+		INTEGRATION_TEST_ALLOWED_TIME
+			.and_then(|x| x.parse().ok())
+			.unwrap_or(600),
+	))
+	.fuse();
+	let t2 = async {
+		{
+			let client = alice.client.clone();
+			let t1 = alice.client.import_notification_stream()
+				.filter(|notification| {
+					let head = client.runtime_api()
+						.head_data(&BlockId::Hash(notification.hash), para_id)
+						.ok()
+						.flatten()
+						.and_then(|head| AdderHead::decode(&mut &head.0[..]).ok());
+
+					future::ready(head.map_or(false, |head| head.number > 0))
+				})
+				.into_future()
+				.fuse();
+			let t2 = alice.task_manager.future().fuse();
+			let t3 = bob.task_manager.future().fuse();
+			let t4 = collator.task_manager.future().fuse();
+
+			pin_mut!(t1, t2, t3, t4);
+
+			select! {
+				_ = t1 => {},
+				_ = t2 => panic!("service Alice failed"),
+				_ = t3 => panic!("service Bob failed"),
+				_ = t4 => panic!("service of the collator failed"),
+			}
+		}
+
+		alice.task_manager.terminate();
+		bob.task_manager.terminate();
+		collator.task_manager.terminate();
+	}
+	.fuse();
+
+	pin_mut!(t1, t2);
+
+	select! {
+		_ = t1 => {
+			panic!("the test took too long, maybe no candidate has been included");
+		},
+		_ = t2 => {},
+	}
+}
@@ -53,6 +53,13 @@ impl From<Vec<u8>> for ValidationCode {
 	}
 }
 
+impl ValidationCode {
+	/// Get the blake2-256 hash of the validation code.
+	pub fn hash(&self) -> polkadot_core_primitives::Hash {
+		sp_core::hashing::blake2_256(&self.encode()).into()
+	}
+}
+
 /// Parachain block data.
 ///
 /// Contains everything required to validate para-block, may contain block and witness data.
@@ -193,6 +200,28 @@ pub struct UpwardMessage {
 	pub data: Vec<u8>,
 }
 
+/// A message sent by a parachain to another parachain over an HRMP channel.
+#[derive(Clone, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct OutboundHrmpMessage {
+	/// The para which should receive the message.
+	pub recipient: Id,
+	/// The message data.
+	pub data: Vec<u8>,
+}
+
+/// A message received by a parachain from another parachain over an HRMP channel.
+#[derive(Clone, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub struct InboundHrmpMessage {
+	/// The para which sent the message.
+	pub sender: Id,
+	/// The relay-chain block number at which the message was sent.
+	pub sent_at: RelayChainBlockNumber,
+	/// The message data.
+	pub data: Vec<u8>,
+}
+
 /// Validation parameters for evaluating the parachain validity function.
 // TODO: balance downloads (https://github.com/paritytech/polkadot/issues/220)
 #[derive(PartialEq, Eq, Decode)]
@@ -214,6 +243,12 @@ pub struct ValidationParams {
 	/// with `relay_chain_height` at least this value, if `Some`. if `None`, issue
 	/// no upgrade.
 	pub code_upgrade_allowed: Option<polkadot_core_primitives::BlockNumber>,
+	/// The messages waiting in the para's downward message queue, in the order they should be
+	/// processed.
+	pub downward_messages: Vec<polkadot_core_primitives::DownwardMessage>,
+	/// The messages waiting in the para's inbound HRMP channels, ordered by sender and then
+	/// by the block they were sent at.
+	pub inbound_hrmp_messages: Vec<InboundHrmpMessage>,
 }
 
 /// The result of parachain validation.
@@ -231,4 +266,10 @@ pub struct ValidationResult {
 	///
 	/// It is expected that the Parachain processes them from first to last.
 	pub processed_downward_messages: u32,
+	/// Messages sent by the Parachain to other parachains, sorted by recipient.
+	pub horizontal_messages: Vec<OutboundHrmpMessage>,
+	/// The relay-chain block number up to which all inbound HRMP messages were processed.
+	///
+	/// Inbound messages sent at or before this block number are removed from the channels.
+	pub hrmp_watermark: RelayChainBlockNumber,
 }
@@ -0,0 +1,158 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Collator for the adder test parachain.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use adder::{HeadData as AdderHead, BlockData as AdderBody};
+use codec::{Encode, Decode};
+use futures::future;
+use parking_lot::Mutex;
+use primitives::v1::{HeadData, BlockData, PoV, GlobalValidationSchedule, LocalValidationData};
+use polkadot_node_primitives::{Collation, CollatorFn};
+
+/// The head of the adder parachain at genesis.
+pub const GENESIS: AdderHead = AdderHead {
+	number: 0,
+	parent_hash: [0; 32],
+	post_state: [
+		1, 27, 77, 3, 221, 140, 1, 241, 4, 145, 67, 207, 156, 76, 129, 126, 75,
+		22, 127, 29, 27, 131, 229, 198, 240, 241, 13, 137, 186, 30, 123, 206
+	],
+};
+
+/// The state of the adder parachain at genesis.
+const GENESIS_STATE: u64 = 0;
+
+/// The collator of the adder parachain.
+///
+/// Keeps the post-states of all the blocks it has built, so that it can build on top of them.
+#[derive(Clone, Default)]
+pub struct Collator {
+	db: Arc<Mutex<HashMap<AdderHead, u64>>>,
+}
+
+impl Collator {
+	/// Create a new collator, starting from genesis.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The encoded genesis head of the parachain.
+	pub fn genesis_head(&self) -> Vec<u8> {
+		GENESIS.encode()
+	}
+
+	/// The number of distinct blocks this collator has built so far.
+	pub fn built_blocks(&self) -> usize {
+		self.db.lock().len()
+	}
+
+	/// Create the collation function to be passed to the collation generation subsystem.
+	pub fn create_collation_function(&self) -> CollatorFn {
+		let this = self.clone();
+
+		Box::new(move |global_validation, local_validation| {
+			Box::new(future::ready(this.produce_collation(global_validation, local_validation)))
+		})
+	}
+
+	/// Build the next block on top of the parent head in the given validation data.
+	///
+	/// All messages waiting in the inbound HRMP channels are processed by the block.
+	fn produce_collation(
+		&self,
+		global_validation: &GlobalValidationSchedule,
+		local_validation: &LocalValidationData,
+	) -> Option<Collation> {
+		let adder_head = AdderHead::decode(&mut &local_validation.parent_head.0[..]).ok()?;
+
+		let mut db = self.db.lock();
+
+		let last_state = if adder_head == GENESIS {
+			GENESIS_STATE
+		} else {
+			*db.get(&adder_head)
+				.expect("All past states stored since this is the only collator")
+		};
+
+		let next_body = AdderBody {
+			state: last_state,
+			add: adder_head.number % 100,
+			send: Vec::new(),
+		};
+
+		let inbound_messages = &local_validation.inbound_hrmp_messages;
+		let next_state = adder::post_state(&next_body, inbound_messages);
+		let next_head = adder::execute(adder_head.hash(), adder_head, &next_body, inbound_messages)
+			.expect("good execution params; qed");
+
+		let collation = Collation {
+			fees: 0,
+			upward_messages: Vec::new(),
+			new_validation_code: None,
+			head_data: HeadData(next_head.encode()),
+			processed_downward_messages: 0,
+			horizontal_messages: adder::horizontal_messages(&next_body),
+			hrmp_watermark: global_validation.block_number,
+			proof_of_validity: PoV { block_data: BlockData(next_body.encode()) },
+		};
+
+		println!("Created collation for #{}, post-state={}", next_head.number, next_state);
+
+		db.insert(next_head, next_state);
+		Some(collation)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	fn validation_data(parent_head: Vec<u8>) -> LocalValidationData {
+		LocalValidationData {
+			parent_head: HeadData(parent_head),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn collations_build_on_each_other() {
+		let collator = Collator::new();
+		let collation_function = collator.create_collation_function();
+		let global_validation = GlobalValidationSchedule::default();
+
+		let mut parent_head = collator.genesis_head();
+
+		for number in 1..=3 {
+			let collation = block_on(
+				collation_function(&global_validation, &validation_data(parent_head.clone()))
+			).expect("collator produces a collation on top of a known head");
+
+			let parent = AdderHead::decode(&mut &parent_head[..]).unwrap();
+			let body = AdderBody::decode(&mut &collation.proof_of_validity.block_data.0[..]).unwrap();
+			let head = adder::execute(parent.hash(), parent, &body, &[]).unwrap();
+
+			assert_eq!(head.number, number);
+			assert_eq!(collation.head_data.0, head.encode());
+
+			parent_head = collation.head_data.0;
+		}
+	}
+}
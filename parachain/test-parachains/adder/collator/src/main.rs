@@ -15,109 +15,30 @@
 // along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Collator for polkadot
+//!
+//! The collator runs on the Polkadot test runtime, which registers the adder parachain at genesis.
 
-use std::collections::HashMap;
 use std::sync::Arc;
 
-use adder::{HeadData as AdderHead, BlockData as AdderBody};
+use futures::future::{self, FutureExt};
 use sp_core::Pair;
-use codec::{Encode, Decode};
-use primitives::v0::{
-	Hash, DownwardMessage,
-	HeadData, BlockData, Id as ParaId, LocalValidationData, GlobalValidationSchedule,
-};
-use collator::{ParachainContext, Network, BuildParachainContext, Cli, SubstrateCli};
-use parking_lot::Mutex;
-use futures::future::{Ready, ready, FutureExt};
-
-const GENESIS: AdderHead = AdderHead {
-	number: 0,
-	parent_hash: [0; 32],
-	post_state: [
-		1, 27, 77, 3, 221, 140, 1, 241, 4, 145, 67, 207, 156, 76, 129, 126, 75,
-		22, 127, 29, 27, 131, 229, 198, 240, 241, 13, 137, 186, 30, 123, 206
-	],
-};
-
-const GENESIS_BODY: AdderBody = AdderBody {
-	state: 0,
-	add: 0,
-};
-
-#[derive(Clone)]
-struct AdderContext {
-	db: Arc<Mutex<HashMap<AdderHead, AdderBody>>>,
-	/// We store it here to make sure that our interfaces require the correct bounds.
-	_network: Option<Arc<dyn Network>>,
-}
-
-/// The parachain context.
-impl ParachainContext for AdderContext {
-	type ProduceCandidate = Ready<Option<(BlockData, HeadData)>>;
-
-	fn produce_candidate(
-		&mut self,
-		_relay_parent: Hash,
-		_global_validation: GlobalValidationSchedule,
-		local_validation: LocalValidationData,
-		_: Vec<DownwardMessage>,
-	) -> Self::ProduceCandidate
-	{
-		let adder_head = match AdderHead::decode(&mut &local_validation.parent_head.0[..]).ok() {
-			Some(res) => res,
-			None => return ready(None),
-		};
-
-		let mut db = self.db.lock();
-
-		let last_body = if adder_head == GENESIS {
-			GENESIS_BODY
-		} else {
-			db.get(&adder_head)
-				.expect("All past bodies stored since this is the only collator")
-				.clone()
-		};
-
-		let next_body = AdderBody {
-			state: last_body.state.overflowing_add(last_body.add).0,
-			add: adder_head.number % 100,
-		};
-
-		let next_head = adder::execute(adder_head.hash(), adder_head, &next_body)
-			.expect("good execution params; qed");
-
-		let encoded_head = HeadData(next_head.encode());
-		let encoded_body = BlockData(next_body.encode());
-
-		println!("Created collation for #{}, post-state={}",
-			next_head.number, next_body.state.overflowing_add(next_body.add).0);
-
-		db.insert(next_head.clone(), next_body);
-		ready(Some((encoded_body, encoded_head)))
-	}
-}
-
-impl BuildParachainContext for AdderContext {
-	type ParachainContext = Self;
-
-	fn build<Client, SP, Extrinsic>(
-		self,
-		_: Arc<Client>,
-		_: SP,
-		network: impl Network + Clone + 'static,
-	) -> Result<Self::ParachainContext, ()> {
-		Ok(Self { _network: Some(Arc::new(network)), ..self })
-	}
-}
+use primitives::v1::{CollatorPair, Id as ParaId};
+use polkadot_cli::Cli;
+use polkadot_test_service::{polkadot_local_testnet_config, polkadot_test_new_full, ADDER_PARA_ID};
+use polkadot_node_primitives::CollationGenerationConfig;
+use polkadot_subsystem::messages::{AllMessages, CollationGenerationMessage};
+use sc_cli::SubstrateCli;
+use test_parachain_adder_collator::Collator;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-	let key = Arc::new(Pair::from_seed(&[1; 32]));
-	let id: ParaId = 100.into();
+	let key = Arc::new(CollatorPair::from_seed(&[1; 32]));
+	let id = ParaId::from(ADDER_PARA_ID);
+	let collator = Collator::new();
 
 	println!("Starting adder collator with genesis: ");
 
 	{
-		let encoded = GENESIS.encode();
+		let encoded = collator.genesis_head();
 		println!("Dec: {:?}", encoded);
 		print!("Hex: 0x");
 		for byte in encoded {
@@ -127,21 +48,36 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 		println!();
 	}
 
-	let context = AdderContext {
-		db: Arc::new(Mutex::new(HashMap::new())),
-		_network: None,
-	};
-
 	let cli = Cli::from_iter(&["-dev"]);
 	let runner = cli.create_runner(&cli.run.base)?;
-	runner.async_run(|config| {
-		let (future, task_manager) = collator::start_collator(
-			context,
-			id,
-			key,
+	runner.async_run(|mut config| {
+		config.chain_spec = Box::new(polkadot_local_testnet_config());
+
+		let (task_manager, _client, handles, _network, _rpc_handlers) = polkadot_test_new_full(
 			config,
+			Some((key.public(), id)),
+			None,
+			false,
+			6000,
 		)?;
 
+		let config = CollationGenerationConfig {
+			key,
+			collator: collator.create_collation_function(),
+			para_id: id,
+		};
+
+		let mut overseer_handler = handles.overseer_handler;
+		let future = async move {
+			let msg = AllMessages::CollationGeneration(CollationGenerationMessage::Initialize(config));
+			if let Err(e) = overseer_handler.send_msg(msg).await {
+				log::error!("Failed to initialize collation generation: {:?}", e);
+				return;
+			}
+
+			future::pending::<()>().await
+		};
+
 		Ok((future.map(Ok), task_manager))
 	})?;
 
@@ -0,0 +1,113 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{sync::Arc, time::Duration};
+
+use futures::{pin_mut, select, FutureExt as _};
+use polkadot_node_primitives::CollationGenerationConfig;
+use polkadot_subsystem::messages::{AllMessages, CollationGenerationMessage};
+use polkadot_test_service::{run_collator_node, run_test_node, ADDER_PARA_ID};
+use primitives::v1::{CollatorPair, Id as ParaId};
+use sc_service::TaskExecutor;
+use sp_core::Pair;
+use sp_keyring::Sr25519Keyring;
+use test_parachain_adder_collator::Collator;
+use tokio::{task::spawn, time::delay_for as sleep};
+
+static INTEGRATION_TEST_ALLOWED_TIME: Option<&str> = option_env!("INTEGRATION_TEST_ALLOWED_TIME");
+
+#[tokio::test]
+async fn collator_generates_collations() {
+	let task_executor: TaskExecutor = (move |fut, _| {
+		spawn(fut);
+	})
+	.into();
+	let para_id = ParaId::from(ADDER_PARA_ID);
+	let collator_key = Arc::new(CollatorPair::generate().0);
+	let mut alice = run_test_node(
+		task_executor.clone(),
+		Sr25519Keyring::Alice,
+		|| {},
+		Vec::new(),
+	);
+	let mut bob = run_test_node(
+		task_executor.clone(),
+		Sr25519Keyring::Bob,
+		|| {},
+		vec![alice.addr.clone()],
+	);
+	let mut collator_node = run_collator_node(
+		task_executor.clone(),
+		Sr25519Keyring::Dave,
+		|| {},
+		vec![alice.addr.clone(), bob.addr.clone()],
+		collator_key.public(),
+		para_id,
+	);
+
+	let collator = Collator::new();
+	let config = CollationGenerationConfig {
+		key: collator_key,
+		collator: collator.create_collation_function(),
+		para_id,
+	};
+	collator_node.handles.overseer_handler
+		.send_msg(AllMessages::CollationGeneration(CollationGenerationMessage::Initialize(config)))
+		.await
+		.expect("the overseer of the collator is running");
+
+	let t1 = sleep(Duration::from_secs(
+		INTEGRATION_TEST_ALLOWED_TIME
+			.and_then(|x| x.parse().ok())
+			.unwrap_or(600),
+	))
+	.fuse();
+	let t2 = async {
+		{
+			let t1 = async {
+				while collator.built_blocks() == 0 {
+					sleep(Duration::from_secs(1)).await;
+				}
+			}.fuse();
+			let t2 = alice.task_manager.future().fuse();
+			let t3 = bob.task_manager.future().fuse();
+			let t4 = collator_node.task_manager.future().fuse();
+
+			pin_mut!(t1, t2, t3, t4);
+
+			select! {
+				_ = t1 => {},
+				_ = t2 => panic!("service Alice failed"),
+				_ = t3 => panic!("service Bob failed"),
+				_ = t4 => panic!("service of the collator failed"),
+			}
+		}
+
+		alice.task_manager.terminate();
+		bob.task_manager.terminate();
+		collator_node.task_manager.terminate();
+	}
+	.fuse();
+
+	pin_mut!(t1, t2);
+
+	select! {
+		_ = t1 => {
+			panic!("the test took too long, maybe no collation has been generated");
+		},
+		_ = t2 => {},
+	}
+}
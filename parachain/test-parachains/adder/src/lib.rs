@@ -15,12 +15,17 @@
 // along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
 
 //! Basic parachain that adds a number as part of its state.
+//!
+//! Amounts can be sent to other adder parachains over HRMP channels. Each amount received is
+//! added to the state of the receiving parachain.
 
 #![no_std]
 
 #![cfg_attr(not(feature = "std"), feature(core_intrinsics, lang_items, core_panic_info, alloc_error_handler))]
 
 use codec::{Encode, Decode};
+use parachain::primitives::{Id as ParaId, InboundHrmpMessage, OutboundHrmpMessage};
+use sp_std::vec::Vec;
 
 #[cfg(not(feature = "std"))]
 mod wasm_validation;
@@ -57,6 +62,8 @@ pub struct BlockData {
 	pub state: u64,
 	/// Amount to add (overflowing)
 	pub add: u64,
+	/// Amounts to send to other parachains, as `(recipient, amount)` pairs.
+	pub send: Vec<(u32, u64)>,
 }
 
 pub fn hash_state(state: u64) -> [u8; 32] {
@@ -67,12 +74,38 @@ pub fn hash_state(state: u64) -> [u8; 32] {
 #[derive(Debug)]
 pub struct StateMismatch;
 
+/// The state after executing the block body, given the messages received over HRMP.
+///
+/// Inbound messages which don't decode as an amount are ignored.
+pub fn post_state(block_data: &BlockData, inbound_messages: &[InboundHrmpMessage]) -> u64 {
+	inbound_messages.iter()
+		.filter_map(|m| u64::decode(&mut &m.data[..]).ok())
+		.fold(
+			block_data.state.overflowing_add(block_data.add).0,
+			|state, amount| state.overflowing_add(amount).0,
+		)
+}
+
+/// The HRMP messages sent by the block body, sorted by recipient.
+pub fn horizontal_messages(block_data: &BlockData) -> Vec<OutboundHrmpMessage> {
+	let mut send = block_data.send.clone();
+	send.sort_by_key(|&(recipient, _)| recipient);
+
+	send.into_iter()
+		.map(|(recipient, amount)| OutboundHrmpMessage {
+			recipient: ParaId::from(recipient),
+			data: amount.encode(),
+		})
+		.collect()
+}
+
 /// Execute a block body on top of given parent head, producing new parent head
 /// if valid.
 pub fn execute(
 	parent_hash: [u8; 32],
 	parent_head: HeadData,
 	block_data: &BlockData,
+	inbound_messages: &[InboundHrmpMessage],
 ) -> Result<HeadData, StateMismatch> {
 	debug_assert_eq!(parent_hash, parent_head.hash());
 
@@ -80,7 +113,7 @@ pub fn execute(
 		return Err(StateMismatch);
 	}
 
-	let new_state = block_data.state.overflowing_add(block_data.add).0;
+	let new_state = post_state(block_data, inbound_messages);
 
 	Ok(HeadData {
 		number: parent_head.number + 1,
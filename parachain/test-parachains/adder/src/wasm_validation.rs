@@ -32,13 +32,16 @@ pub extern fn validate_block(params: *const u8, len: usize) -> u64 {
 
 	let parent_hash = tiny_keccak::keccak256(&params.parent_head.0[..]);
 
-	match crate::execute(parent_hash, parent_head, &block_data) {
+	match crate::execute(parent_hash, parent_head, &block_data, &params.inbound_hrmp_messages) {
 		Ok(new_head) => parachain::write_result(
 			&ValidationResult {
 				head_data: GenericHeadData(new_head.encode()),
 				new_validation_code: None,
 				upward_messages: sp_std::vec::Vec::new(),
 				processed_downward_messages: 0,
+				horizontal_messages: crate::horizontal_messages(&block_data),
+				// all inbound messages are processed.
+				hrmp_watermark: params.relay_chain_height,
 			}
 		),
 		Err(_) => panic!("execution failure"),
@@ -20,6 +20,9 @@ use parachain::primitives::{
 	RelayChainBlockNumber,
 	BlockData as GenericBlockData,
 	HeadData as GenericHeadData,
+	Id as ParaId,
+	InboundHrmpMessage,
+	OutboundHrmpMessage,
 	ValidationParams,
 };
 use codec::{Decode, Encode};
@@ -42,6 +45,8 @@ struct BlockData {
 	state: u64,
 	/// Amount to add (overflowing)
 	add: u64,
+	/// Amounts to send to other parachains, as `(recipient, amount)` pairs.
+	send: Vec<(u32, u64)>,
 }
 
 const TEST_CODE: &[u8] = adder::WASM_BINARY;
@@ -65,6 +70,7 @@ pub fn execute_good_on_parent() {
 	let block_data = BlockData {
 		state: 0,
 		add: 512,
+		send: Vec::new(),
 	};
 
 	let pool = parachain::wasm_executor::ValidationPool::new();
@@ -78,6 +84,8 @@ pub fn execute_good_on_parent() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 	).unwrap();
@@ -106,6 +114,7 @@ fn execute_good_chain_on_parent() {
 		let block_data = BlockData {
 			state: last_state,
 			add,
+			send: Vec::new(),
 		};
 
 		let ret = parachain::wasm_executor::validate_candidate(
@@ -117,6 +126,8 @@ fn execute_good_chain_on_parent() {
 				max_head_data_size: 1024,
 				relay_chain_height: number as RelayChainBlockNumber + 1,
 				code_upgrade_allowed: None,
+				downward_messages: Vec::new(),
+				inbound_hrmp_messages: Vec::new(),
 			},
 			parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 		).unwrap();
@@ -146,6 +157,7 @@ fn execute_bad_on_parent() {
 	let block_data = BlockData {
 		state: 256, // start state is wrong.
 		add: 256,
+		send: Vec::new(),
 	};
 
 	let _ret = parachain::wasm_executor::validate_candidate(
@@ -157,7 +169,88 @@ fn execute_bad_on_parent() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 	).unwrap_err();
 }
+
+#[test]
+fn exchange_horizontal_messages() {
+	let pool = parachain::wasm_executor::ValidationPool::new();
+
+	let para_a = ParaId::from(100);
+	let para_b = ParaId::from(200);
+
+	let genesis_head = HeadData {
+		number: 0,
+		parent_hash: [0; 32],
+		post_state: hash_state(0),
+	};
+
+	// para A adds 1 to its own state and sends 5 and 7 to para B.
+	let block_data = BlockData {
+		state: 0,
+		add: 1,
+		send: vec![(u32::from(para_b), 7), (u32::from(para_b), 5)],
+	};
+
+	let ret_a = parachain::wasm_executor::validate_candidate(
+		TEST_CODE,
+		ValidationParams {
+			parent_head: GenericHeadData(genesis_head.encode()),
+			block_data: GenericBlockData(block_data.encode()),
+			max_code_size: 1024,
+			max_head_data_size: 1024,
+			relay_chain_height: 1,
+			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
+		},
+		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
+	).unwrap();
+
+	let new_head_a = HeadData::decode(&mut &ret_a.head_data.0[..]).unwrap();
+	assert_eq!(new_head_a.post_state, hash_state(1));
+	assert_eq!(ret_a.hrmp_watermark, 1);
+	assert_eq!(
+		ret_a.horizontal_messages,
+		vec![
+			OutboundHrmpMessage { recipient: para_b, data: 7u64.encode() },
+			OutboundHrmpMessage { recipient: para_b, data: 5u64.encode() },
+		],
+	);
+
+	// the relay chain delivers the messages of para A to para B in the next block.
+	let inbound_hrmp_messages = ret_a.horizontal_messages.into_iter()
+		.map(|m| InboundHrmpMessage { sender: para_a, sent_at: 2, data: m.data })
+		.collect();
+
+	// para B adds 3 to its own state and receives the amounts sent by para A.
+	let block_data = BlockData {
+		state: 0,
+		add: 3,
+		send: Vec::new(),
+	};
+
+	let ret_b = parachain::wasm_executor::validate_candidate(
+		TEST_CODE,
+		ValidationParams {
+			parent_head: GenericHeadData(genesis_head.encode()),
+			block_data: GenericBlockData(block_data.encode()),
+			max_code_size: 1024,
+			max_head_data_size: 1024,
+			relay_chain_height: 2,
+			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages,
+		},
+		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
+	).unwrap();
+
+	let new_head_b = HeadData::decode(&mut &ret_b.head_data.0[..]).unwrap();
+	assert_eq!(new_head_b.post_state, hash_state(3 + 7 + 5));
+	assert_eq!(ret_b.hrmp_watermark, 2);
+	assert!(ret_b.horizontal_messages.is_empty());
+}
@@ -50,6 +50,8 @@ pub fn execute_good_no_upgrade() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 	).unwrap();
@@ -86,6 +88,8 @@ pub fn execute_good_with_upgrade() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: Some(20),
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 	).unwrap();
@@ -129,6 +133,8 @@ pub fn code_upgrade_not_allowed() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 	).unwrap();
@@ -159,6 +165,8 @@ pub fn applies_code_upgrade_after_delay() {
 				max_head_data_size: 1024,
 				relay_chain_height: 1,
 				code_upgrade_allowed: Some(2),
+				downward_messages: Vec::new(),
+				inbound_hrmp_messages: Vec::new(),
 			},
 			parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 		).unwrap();
@@ -194,6 +202,8 @@ pub fn applies_code_upgrade_after_delay() {
 				max_head_data_size: 1024,
 				relay_chain_height: 2,
 				code_upgrade_allowed: None,
+				downward_messages: Vec::new(),
+				inbound_hrmp_messages: Vec::new(),
 			},
 			parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 		).unwrap();
@@ -38,6 +38,8 @@ fn terminates_on_timeout() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 	);
@@ -67,6 +69,8 @@ fn parallel_execution() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool2),
 	).ok());
@@ -79,6 +83,8 @@ fn parallel_execution() {
 			max_head_data_size: 1024,
 			relay_chain_height: 1,
 			code_upgrade_allowed: None,
+			downward_messages: Vec::new(),
+			inbound_hrmp_messages: Vec::new(),
 		},
 		parachain::wasm_executor::ExecutionMode::RemoteTest(&pool),
 	);
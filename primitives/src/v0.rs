@@ -648,6 +648,22 @@ impl CompactStatement {
 				=> h
 		}
 	}
+
+	/// Whether a validator issuing both this and the other statement would be misbehaving.
+	///
+	/// This is the case for seconding two different candidates and for attesting to both the
+	/// validity and invalidity of the same candidate.
+	pub fn conflicts_with(&self, other: &CompactStatement) -> bool {
+		match (self, other) {
+			(CompactStatement::Candidate(a), CompactStatement::Candidate(b)) => a != b,
+			(CompactStatement::Invalid(a), CompactStatement::Candidate(b))
+				| (CompactStatement::Invalid(a), CompactStatement::Valid(b))
+				| (CompactStatement::Candidate(a), CompactStatement::Invalid(b))
+				| (CompactStatement::Valid(a), CompactStatement::Invalid(b))
+				=> a == b,
+			_ => false,
+		}
+	}
 }
 
 /// A signed compact statement, suitable to be sent to the chain.
@@ -879,6 +895,26 @@ impl<Payload: EncodeAs<RealPayload>, RealPayload: Encode> Signed<Payload, RealPa
 		}
 	}
 
+	/// Sign this payload with the given context and the key matching `key` in the keystore of the
+	/// current externalities, storing the validator index. Returns `None` if there is no such key.
+	///
+	/// Unlike `sign`, this is available within the runtime, e.g. when benchmarking.
+	pub fn sign_with_keystore<H: Encode>(
+		payload: Payload,
+		context: &SigningContext<H>,
+		validator_index: ValidatorIndex,
+		key: &ValidatorId,
+	) -> Option<Self> {
+		let data = Self::payload_data(&payload, context);
+		let signature = application_crypto::RuntimeAppPublic::sign(key, &data)?;
+		Some(Self {
+			payload,
+			validator_index,
+			signature,
+			real_payload: sp_std::marker::PhantomData,
+		})
+	}
+
 	/// Validate the payload given the context and public key.
 	pub fn check_signature<H: Encode>(&self, context: &SigningContext<H>, key: &ValidatorId) -> Result<(), ()> {
 		let data = Self::payload_data(&self.payload, context);
@@ -36,7 +36,7 @@ pub use polkadot_core_primitives::v1::{
 // Export some polkadot-parachain primitives
 pub use polkadot_parachain::primitives::{
 	Id, ParachainDispatchOrigin, LOWEST_USER_ID, UpwardMessage, HeadData, BlockData,
-	ValidationCode,
+	ValidationCode, InboundHrmpMessage, OutboundHrmpMessage,
 };
 
 // Export some basic parachain primitives from v0.
@@ -46,6 +46,12 @@ pub use crate::v0::{
 	CompactStatement, SignedStatement, ErasureChunk, EncodeAs,
 };
 
+// Export the index of a session, which signing contexts and validator sets are tied to.
+pub use sp_staking::SessionIndex;
+
+// Export the key validators are discovered on the network by.
+pub use authority_discovery_primitives::AuthorityId as AuthorityDiscoveryId;
+
 // More exports from v0 for std.
 #[cfg(feature = "std")]
 pub use crate::v0::{ValidatorPair, CollatorPair};
@@ -221,6 +227,20 @@ pub struct LocalValidationData {
 	/// which case the code upgrade should be applied at the end of the signaling
 	/// block.
 	pub code_upgrade_allowed: Option<BlockNumber>,
+	/// The blake2-256 hash of the para's downward message queue, as of the relay-parent.
+	/// See `downward_messages_hash`.
+	///
+	/// The candidate should process messages from the front of this queue.
+	pub downward_messages_hash: Hash,
+	/// The messages waiting in the para's inbound HRMP channels as of the relay-parent, ordered
+	/// by sender and then by the block they were sent at.
+	pub inbound_hrmp_messages: Vec<InboundHrmpMessage>,
+}
+
+/// Compute the hash of a para's downward message queue, as committed to in the
+/// `downward_messages_hash` of its `LocalValidationData`.
+pub fn downward_messages_hash(messages: &[DownwardMessage]) -> Hash {
+	BlakeTwo256::hash_of(&messages)
 }
 
 /// Extra data that is needed along with the other fields in a `CandidateReceipt`
@@ -252,6 +272,13 @@ pub struct CandidateCommitments {
 	pub new_validation_code: Option<ValidationCode>,
 	/// The head-data produced as a result of execution.
 	pub head_data: HeadData,
+	/// The number of messages, from the front of the para's downward message queue, which were
+	/// processed by the candidate.
+	pub processed_downward_messages: u32,
+	/// Messages sent to other paras over HRMP channels, sorted by recipient.
+	pub horizontal_messages: Vec<OutboundHrmpMessage>,
+	/// The relay-chain block number up to which all inbound HRMP messages were processed.
+	pub hrmp_watermark: BlockNumber,
 }
 
 impl CandidateCommitments {
@@ -362,6 +389,38 @@ pub fn check_candidate_backing<H: AsRef<[u8]> + Clone + Encode>(
 	Ok(signed)
 }
 
+/// A report of a validator having issued two conflicting statements in the same signing context.
+///
+/// The report is self-contained and is verified with `check`.
+#[derive(Clone, PartialEq, Eq, Encode, Decode, RuntimeDebug)]
+pub struct DoubleVoteReport {
+	/// The signing context of both statements.
+	pub signing_context: SigningContext,
+	/// The public key of the validator that issued the statements.
+	pub identity: ValidatorId,
+	/// The first statement.
+	pub first: SignedStatement,
+	/// The second statement, which conflicts with the first.
+	pub second: SignedStatement,
+}
+
+impl DoubleVoteReport {
+	/// Check that both statements are signed by the same validator with the reported key,
+	/// in the signing context of the report, and that they conflict with each other.
+	pub fn check(&self) -> Result<(), ()> {
+		if self.first.validator_index() != self.second.validator_index() {
+			return Err(())
+		}
+
+		if !self.first.payload().conflicts_with(self.second.payload()) {
+			return Err(())
+		}
+
+		self.first.check_signature(&self.signing_context, &self.identity)?;
+		self.second.check_signature(&self.signing_context, &self.identity)
+	}
+}
+
 /// The unique (during session) index of a core.
 #[derive(Encode, Decode, Default, PartialOrd, Ord, Eq, PartialEq, Clone, Copy)]
 #[cfg_attr(feature = "std", derive(Debug))]
@@ -476,3 +535,162 @@ pub struct AvailableData {
 	/// The omitted validation data.
 	pub omitted_validation: OmittedValidationData,
 }
+
+/// The information on scheduler assignments that some subsystems may be querying.
+#[derive(Clone, Encode, Decode, Default)]
+#[cfg_attr(feature = "std", derive(PartialEq, Debug))]
+pub struct SchedulerRoster {
+	/// Validator-to-groups assignments.
+	pub validator_groups: Vec<Vec<ValidatorIndex>>,
+	/// All scheduled paras.
+	pub scheduled: Vec<CoreAssignment>,
+	/// Upcoming paras (chains and threads).
+	pub upcoming: Vec<Id>,
+	/// Occupied cores.
+	pub availability_cores: Vec<Option<CoreOccupied>>,
+}
+
+/// A para scheduled on an availability core in the next block, along with the validation data
+/// a candidate of the para backed in that block is expected to be validated under.
+#[derive(Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(PartialEq, Debug))]
+pub struct ScheduledCore {
+	/// The assignment of the para to the core.
+	pub assignment: CoreAssignment,
+	/// The validation data for a candidate of the para using this block as its relay-parent.
+	pub omitted_validation: OmittedValidationData,
+}
+
+/// An availability core occupied by a candidate pending availability.
+#[derive(Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(PartialEq, Debug))]
+pub struct OccupiedCore<H = Hash, N = BlockNumber> {
+	/// The hash of the candidate occupying the core.
+	pub candidate_hash: Hash,
+	/// The descriptor of the candidate occupying the core.
+	pub candidate_descriptor: CandidateDescriptor<H>,
+	/// The availability votes received for the candidate so far. One bit per validator.
+	pub availability: BitVec<bitvec::order::Lsb0, u8>,
+	/// The block number the candidate was backed in.
+	pub occupied_since: N,
+	/// The block number at which the candidate times out, unless it becomes available before.
+	/// `None` if it cannot time out under the current configuration.
+	pub time_out_at: Option<N>,
+	/// The para assigned to the core in the next block if the candidate becomes available in it.
+	pub next_up_on_available: Option<CoreAssignment>,
+}
+
+/// The state of an availability core, as predicted for the block after the one it is queried at.
+#[derive(Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(PartialEq, Debug))]
+pub enum CoreState<H = Hash, N = BlockNumber> {
+	/// The core is free, and the given para, if any, is scheduled on it in the next block.
+	Free(Option<ScheduledCore>),
+	/// The core is occupied by a candidate which times out in the next block, unless it becomes
+	/// available in it. The core is then freed, and the given para, if any, is scheduled on it.
+	Freeing(OccupiedCore<H, N>, Option<ScheduledCore>),
+	/// The core is occupied by a candidate pending availability, which does not time out in the
+	/// next block.
+	Occupied(OccupiedCore<H, N>),
+}
+
+impl<H, N> CoreState<H, N> {
+	/// The para which may have a candidate backed on this core in the next block, if any.
+	pub fn scheduled(&self) -> Option<&ScheduledCore> {
+		match self {
+			CoreState::Free(scheduled) | CoreState::Freeing(_, scheduled) => scheduled.as_ref(),
+			CoreState::Occupied(_) => None,
+		}
+	}
+
+	/// The candidate pending availability on this core, if any.
+	pub fn occupied(&self) -> Option<&OccupiedCore<H, N>> {
+		match self {
+			CoreState::Free(_) => None,
+			CoreState::Freeing(occupied, _) | CoreState::Occupied(occupied) => Some(occupied),
+		}
+	}
+}
+
+/// Information about a session, kept on-chain for a bounded number of sessions after it ended.
+///
+/// This allows candidates of past sessions to be checked, for instance in approval checking,
+/// disputes and availability recovery, after the validator set has changed.
+#[derive(Clone, Encode, Decode, Default)]
+#[cfg_attr(feature = "std", derive(PartialEq, Debug))]
+pub struct SessionInfo {
+	/// The validators of the session, by their parachain session keys.
+	pub validators: Vec<ValidatorId>,
+	/// The authority discovery keys of the validators of the session, in the same order as
+	/// `validators`, as far as they are known.
+	pub discovery_keys: Vec<AuthorityDiscoveryId>,
+	/// The assignment of validators to groups at the start of the session.
+	pub validator_groups: Vec<Vec<ValidatorIndex>>,
+	/// The number of availability cores in the session.
+	pub n_cores: u32,
+	/// The number of approvals a candidate of the session needs to be considered approved.
+	pub needed_approvals: u32,
+}
+
+sp_api::decl_runtime_apis! {
+	/// The API for querying the state of parachains on-chain.
+	pub trait ParachainHost {
+		/// Get the current validators.
+		fn validators() -> Vec<ValidatorId>;
+
+		/// Get the assignments of validators to groups, along with the state of the
+		/// availability cores.
+		fn validator_groups() -> SchedulerRoster;
+
+		/// Get the session index that a child of this block will have.
+		///
+		/// Combined with the hash of this block, this forms the `SigningContext` used for
+		/// statements and bitfields in a child of this block.
+		fn session_index_for_child() -> sp_staking::SessionIndex;
+
+		/// Get the global validation schedule that all candidates executed in the context of
+		/// this block should be validated under.
+		fn global_validation_schedule() -> GlobalValidationSchedule;
+
+		/// Get the local validation data for a candidate of the given para executed in the context
+		/// of this block. Returns `None` if the para is not registered.
+		fn local_validation_data(para_id: Id) -> Option<LocalValidationData>;
+
+		/// Get the validation code of a para, assuming execution under the given relay-chain block
+		/// number, and an optional block number representing an intermediate parablock executed
+		/// in the context of that block. Returns `None` if the code is unknown or pruned.
+		fn validation_code(
+			para_id: Id,
+			at: BlockNumber,
+			assume_intermediate: Option<BlockNumber>,
+		) -> Option<ValidationCode>;
+
+		/// Get the hash of the validation code that `validation_code` would return for the same
+		/// parameters. This allows looking up code which is already known without fetching it.
+		fn validation_code_hash(
+			para_id: Id,
+			at: BlockNumber,
+			assume_intermediate: Option<BlockNumber>,
+		) -> Option<Hash>;
+
+		/// Get the validation code with the given hash, if it is still stored. Validation code is
+		/// stored once per hash, for as long as any para uses it as current, past or future code.
+		fn validation_code_by_hash(hash: Hash) -> Option<ValidationCode>;
+
+		/// Get the head data of a para.
+		fn head_data(para_id: Id) -> Option<HeadData>;
+
+		/// Get the receipt of the candidate pending availability for a para, if any.
+		fn candidate_pending_availability(para_id: Id) -> Option<CommittedCandidateReceipt>;
+
+		/// Get the downward messages queued for a para, in the order they should be processed.
+		fn downward_messages(para_id: Id) -> Vec<DownwardMessage>;
+
+		/// Get the state of all availability cores, as predicted for a child of this block.
+		fn availability_cores() -> Vec<CoreState>;
+
+		/// Get the information stored about the given session. Returns `None` if the session
+		/// has not started yet or its information has been pruned.
+		fn session_info(index: SessionIndex) -> Option<SessionInfo>;
+	}
+}
@@ -19,18 +19,24 @@
 //! Configuration can change only at session boundaries and is buffered until then.
 
 use sp_std::prelude::*;
-use primitives::v1::ValidatorId;
+use sp_runtime::RuntimeDebug;
+use primitives::v1::{Balance, ValidatorId, SessionIndex};
 use frame_support::{
-	decl_storage, decl_module, decl_error,
+	decl_storage, decl_module, decl_event, decl_error,
 	dispatch::DispatchResult,
+	traits::Get,
 	weights::{DispatchClass, Weight},
 };
 use codec::{Encode, Decode};
 use system::ensure_root;
 
+#[cfg(feature = "std")]
+use serde::{Serialize, Deserialize};
+
 /// All configuration of the runtime with respect to parachains and parathreads.
 #[derive(Clone, Encode, Decode, PartialEq, Default)]
 #[cfg_attr(test, derive(Debug))]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub struct HostConfiguration<BlockNumber> {
 	/// The minimum frequency at which parachains can update their validation code.
 	pub validation_upgrade_frequency: BlockNumber,
@@ -47,6 +53,9 @@ pub struct HostConfiguration<BlockNumber> {
 	pub parathread_cores: u32,
 	/// The number of retries that a parathread author has to submit their block.
 	pub parathread_retries: u32,
+	/// The fee for a parathread claim when the parathread queue is empty. Each claim already in
+	/// the queue adds the same amount again.
+	pub parathread_claim_fee: Balance,
 	/// How often parachain groups should be rotated across parachains. Must be non-zero.
 	pub parachain_rotation_frequency: BlockNumber,
 	/// The availability period, in blocks, for parachains. This is the amount of blocks
@@ -58,9 +67,304 @@ pub struct HostConfiguration<BlockNumber> {
 	pub thread_availability_period: BlockNumber,
 	/// The amount of blocks ahead to schedule parachains and parathreads.
 	pub scheduling_lookahead: u32,
+	/// The maximum number of messages allowed in a para's upward message queue.
+	pub max_upward_queue_count: u32,
+	/// The total size of messages, in bytes, allowed in a para's upward message queue. A single
+	/// message may exceed this if the queue is otherwise empty.
+	pub watermark_upward_queue_size: u32,
+	/// The maximum total weight of upward messages dispatched in a single block.
+	pub max_upward_dispatch_weight: Weight,
+	/// The maximum number of messages allowed in a para's downward message queue.
+	pub max_downward_queue_count: u32,
+	/// The number of sessions an unaccepted request to open an HRMP channel stays valid for.
+	pub hrmp_open_request_ttl: u32,
+	/// The deposit the sender of an HRMP channel has to reserve when requesting to open it.
+	pub hrmp_sender_deposit: Balance,
+	/// The deposit the recipient of an HRMP channel has to reserve when accepting it.
+	pub hrmp_recipient_deposit: Balance,
+	/// The maximum number of messages allowed in an HRMP channel at once.
+	pub hrmp_channel_max_capacity: u32,
+	/// The maximum size of a message sent over an HRMP channel, in bytes.
+	pub hrmp_channel_max_message_size: u32,
+	/// The maximum number of inbound HRMP channels a para may have.
+	pub hrmp_max_parachain_inbound_channels: u32,
+	/// The maximum number of outbound HRMP channels a para may have.
+	pub hrmp_max_parachain_outbound_channels: u32,
+	/// The maximum number of HRMP messages a single candidate may send.
+	pub hrmp_max_message_num_per_candidate: u32,
+	/// The number of approvals a candidate needs to be considered approved.
+	pub needed_approvals: u32,
+	/// The number of sessions after a session ends during which candidates of that session may
+	/// still be checked, and so information about the session is kept.
+	pub dispute_period: SessionIndex,
+}
+
+/// The host configuration as it was stored before the upward message parameters were introduced.
+#[derive(Default, Encode, Decode)]
+struct HostConfigurationV0<BlockNumber> {
+	validation_upgrade_frequency: BlockNumber,
+	validation_upgrade_delay: BlockNumber,
+	acceptance_period: BlockNumber,
+	max_code_size: u32,
+	max_head_data_size: u32,
+	parathread_cores: u32,
+	parathread_retries: u32,
+	parachain_rotation_frequency: BlockNumber,
+	chain_availability_period: BlockNumber,
+	thread_availability_period: BlockNumber,
+	scheduling_lookahead: u32,
+}
+
+impl<BlockNumber: Default> HostConfigurationV0<BlockNumber> {
+	/// Convert to the next layout, leaving the new parameters at their defaults.
+	fn upgrade(self) -> HostConfigurationV1<BlockNumber> {
+		HostConfigurationV1 {
+			validation_upgrade_frequency: self.validation_upgrade_frequency,
+			validation_upgrade_delay: self.validation_upgrade_delay,
+			acceptance_period: self.acceptance_period,
+			max_code_size: self.max_code_size,
+			max_head_data_size: self.max_head_data_size,
+			parathread_cores: self.parathread_cores,
+			parathread_retries: self.parathread_retries,
+			parachain_rotation_frequency: self.parachain_rotation_frequency,
+			chain_availability_period: self.chain_availability_period,
+			thread_availability_period: self.thread_availability_period,
+			scheduling_lookahead: self.scheduling_lookahead,
+			..Default::default()
+		}
+	}
+}
+
+/// The host configuration as it was stored before the downward message parameters were introduced.
+#[derive(Default, Encode, Decode)]
+struct HostConfigurationV1<BlockNumber> {
+	validation_upgrade_frequency: BlockNumber,
+	validation_upgrade_delay: BlockNumber,
+	acceptance_period: BlockNumber,
+	max_code_size: u32,
+	max_head_data_size: u32,
+	parathread_cores: u32,
+	parathread_retries: u32,
+	parachain_rotation_frequency: BlockNumber,
+	chain_availability_period: BlockNumber,
+	thread_availability_period: BlockNumber,
+	scheduling_lookahead: u32,
+	max_upward_queue_count: u32,
+	watermark_upward_queue_size: u32,
+	max_upward_dispatch_weight: Weight,
+}
+
+impl<BlockNumber: Default> HostConfigurationV1<BlockNumber> {
+	/// Convert to the next layout, leaving the new parameters at their defaults.
+	fn upgrade(self) -> HostConfigurationV2<BlockNumber> {
+		HostConfigurationV2 {
+			validation_upgrade_frequency: self.validation_upgrade_frequency,
+			validation_upgrade_delay: self.validation_upgrade_delay,
+			acceptance_period: self.acceptance_period,
+			max_code_size: self.max_code_size,
+			max_head_data_size: self.max_head_data_size,
+			parathread_cores: self.parathread_cores,
+			parathread_retries: self.parathread_retries,
+			parachain_rotation_frequency: self.parachain_rotation_frequency,
+			chain_availability_period: self.chain_availability_period,
+			thread_availability_period: self.thread_availability_period,
+			scheduling_lookahead: self.scheduling_lookahead,
+			max_upward_queue_count: self.max_upward_queue_count,
+			watermark_upward_queue_size: self.watermark_upward_queue_size,
+			max_upward_dispatch_weight: self.max_upward_dispatch_weight,
+			..Default::default()
+		}
+	}
+}
+
+/// The host configuration as it was stored before the HRMP parameters were introduced.
+#[derive(Default, Encode, Decode)]
+struct HostConfigurationV2<BlockNumber> {
+	validation_upgrade_frequency: BlockNumber,
+	validation_upgrade_delay: BlockNumber,
+	acceptance_period: BlockNumber,
+	max_code_size: u32,
+	max_head_data_size: u32,
+	parathread_cores: u32,
+	parathread_retries: u32,
+	parachain_rotation_frequency: BlockNumber,
+	chain_availability_period: BlockNumber,
+	thread_availability_period: BlockNumber,
+	scheduling_lookahead: u32,
+	max_upward_queue_count: u32,
+	watermark_upward_queue_size: u32,
+	max_upward_dispatch_weight: Weight,
+	max_downward_queue_count: u32,
+}
+
+impl<BlockNumber: Default> HostConfigurationV2<BlockNumber> {
+	/// Convert to the next layout, leaving the new parameters at their defaults.
+	fn upgrade(self) -> HostConfigurationV3<BlockNumber> {
+		HostConfigurationV3 {
+			validation_upgrade_frequency: self.validation_upgrade_frequency,
+			validation_upgrade_delay: self.validation_upgrade_delay,
+			acceptance_period: self.acceptance_period,
+			max_code_size: self.max_code_size,
+			max_head_data_size: self.max_head_data_size,
+			parathread_cores: self.parathread_cores,
+			parathread_retries: self.parathread_retries,
+			parachain_rotation_frequency: self.parachain_rotation_frequency,
+			chain_availability_period: self.chain_availability_period,
+			thread_availability_period: self.thread_availability_period,
+			scheduling_lookahead: self.scheduling_lookahead,
+			max_upward_queue_count: self.max_upward_queue_count,
+			watermark_upward_queue_size: self.watermark_upward_queue_size,
+			max_upward_dispatch_weight: self.max_upward_dispatch_weight,
+			max_downward_queue_count: self.max_downward_queue_count,
+			..Default::default()
+		}
+	}
+}
+
+/// The host configuration as it was stored before the parathread claim fee was introduced.
+#[derive(Default, Encode, Decode)]
+struct HostConfigurationV3<BlockNumber> {
+	validation_upgrade_frequency: BlockNumber,
+	validation_upgrade_delay: BlockNumber,
+	acceptance_period: BlockNumber,
+	max_code_size: u32,
+	max_head_data_size: u32,
+	parathread_cores: u32,
+	parathread_retries: u32,
+	parachain_rotation_frequency: BlockNumber,
+	chain_availability_period: BlockNumber,
+	thread_availability_period: BlockNumber,
+	scheduling_lookahead: u32,
+	max_upward_queue_count: u32,
+	watermark_upward_queue_size: u32,
+	max_upward_dispatch_weight: Weight,
+	max_downward_queue_count: u32,
+	hrmp_open_request_ttl: u32,
+	hrmp_sender_deposit: Balance,
+	hrmp_recipient_deposit: Balance,
+	hrmp_channel_max_capacity: u32,
+	hrmp_channel_max_message_size: u32,
+	hrmp_max_parachain_inbound_channels: u32,
+	hrmp_max_parachain_outbound_channels: u32,
+	hrmp_max_message_num_per_candidate: u32,
 }
 
-pub trait Trait: system::Trait { }
+impl<BlockNumber: Default> HostConfigurationV3<BlockNumber> {
+	/// Convert to the next layout, leaving the new parameters at their defaults.
+	fn upgrade(self) -> HostConfigurationV4<BlockNumber> {
+		HostConfigurationV4 {
+			validation_upgrade_frequency: self.validation_upgrade_frequency,
+			validation_upgrade_delay: self.validation_upgrade_delay,
+			acceptance_period: self.acceptance_period,
+			max_code_size: self.max_code_size,
+			max_head_data_size: self.max_head_data_size,
+			parathread_cores: self.parathread_cores,
+			parathread_retries: self.parathread_retries,
+			parachain_rotation_frequency: self.parachain_rotation_frequency,
+			chain_availability_period: self.chain_availability_period,
+			thread_availability_period: self.thread_availability_period,
+			scheduling_lookahead: self.scheduling_lookahead,
+			max_upward_queue_count: self.max_upward_queue_count,
+			watermark_upward_queue_size: self.watermark_upward_queue_size,
+			max_upward_dispatch_weight: self.max_upward_dispatch_weight,
+			max_downward_queue_count: self.max_downward_queue_count,
+			hrmp_open_request_ttl: self.hrmp_open_request_ttl,
+			hrmp_sender_deposit: self.hrmp_sender_deposit,
+			hrmp_recipient_deposit: self.hrmp_recipient_deposit,
+			hrmp_channel_max_capacity: self.hrmp_channel_max_capacity,
+			hrmp_channel_max_message_size: self.hrmp_channel_max_message_size,
+			hrmp_max_parachain_inbound_channels: self.hrmp_max_parachain_inbound_channels,
+			hrmp_max_parachain_outbound_channels: self.hrmp_max_parachain_outbound_channels,
+			hrmp_max_message_num_per_candidate: self.hrmp_max_message_num_per_candidate,
+			..Default::default()
+		}
+	}
+}
+
+/// The host configuration as it was stored before the approval and dispute parameters were introduced.
+#[derive(Default, Encode, Decode)]
+struct HostConfigurationV4<BlockNumber> {
+	validation_upgrade_frequency: BlockNumber,
+	validation_upgrade_delay: BlockNumber,
+	acceptance_period: BlockNumber,
+	max_code_size: u32,
+	max_head_data_size: u32,
+	parathread_cores: u32,
+	parathread_retries: u32,
+	parathread_claim_fee: Balance,
+	parachain_rotation_frequency: BlockNumber,
+	chain_availability_period: BlockNumber,
+	thread_availability_period: BlockNumber,
+	scheduling_lookahead: u32,
+	max_upward_queue_count: u32,
+	watermark_upward_queue_size: u32,
+	max_upward_dispatch_weight: Weight,
+	max_downward_queue_count: u32,
+	hrmp_open_request_ttl: u32,
+	hrmp_sender_deposit: Balance,
+	hrmp_recipient_deposit: Balance,
+	hrmp_channel_max_capacity: u32,
+	hrmp_channel_max_message_size: u32,
+	hrmp_max_parachain_inbound_channels: u32,
+	hrmp_max_parachain_outbound_channels: u32,
+	hrmp_max_message_num_per_candidate: u32,
+}
+
+impl<BlockNumber: Default> HostConfigurationV4<BlockNumber> {
+	/// Convert to the next layout, leaving the new parameters at their defaults.
+	fn upgrade(self) -> HostConfiguration<BlockNumber> {
+		HostConfiguration {
+			validation_upgrade_frequency: self.validation_upgrade_frequency,
+			validation_upgrade_delay: self.validation_upgrade_delay,
+			acceptance_period: self.acceptance_period,
+			max_code_size: self.max_code_size,
+			max_head_data_size: self.max_head_data_size,
+			parathread_cores: self.parathread_cores,
+			parathread_retries: self.parathread_retries,
+			parathread_claim_fee: self.parathread_claim_fee,
+			parachain_rotation_frequency: self.parachain_rotation_frequency,
+			chain_availability_period: self.chain_availability_period,
+			thread_availability_period: self.thread_availability_period,
+			scheduling_lookahead: self.scheduling_lookahead,
+			max_upward_queue_count: self.max_upward_queue_count,
+			watermark_upward_queue_size: self.watermark_upward_queue_size,
+			max_upward_dispatch_weight: self.max_upward_dispatch_weight,
+			max_downward_queue_count: self.max_downward_queue_count,
+			hrmp_open_request_ttl: self.hrmp_open_request_ttl,
+			hrmp_sender_deposit: self.hrmp_sender_deposit,
+			hrmp_recipient_deposit: self.hrmp_recipient_deposit,
+			hrmp_channel_max_capacity: self.hrmp_channel_max_capacity,
+			hrmp_channel_max_message_size: self.hrmp_channel_max_message_size,
+			hrmp_max_parachain_inbound_channels: self.hrmp_max_parachain_inbound_channels,
+			hrmp_max_parachain_outbound_channels: self.hrmp_max_parachain_outbound_channels,
+			hrmp_max_message_num_per_candidate: self.hrmp_max_message_num_per_candidate,
+			..Default::default()
+		}
+	}
+}
+
+// A value placed in storage that represents the current version of the configuration storage.
+// This value is used by `on_runtime_upgrade` to determine whether we run storage migration logic.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+enum Releases {
+	V0,
+	V1,
+	V2,
+	V3,
+	V4,
+	V5,
+}
+
+impl Default for Releases {
+	fn default() -> Self {
+		Releases::V0
+	}
+}
+
+pub trait Trait: system::Trait {
+	/// The overarching event type.
+	type Event: From<Event> + Into<<Self as system::Trait>::Event>;
+}
 
 decl_storage! {
 	trait Store for Module<T: Trait> as Configuration {
@@ -68,6 +372,20 @@ decl_storage! {
 		Config get(fn config) config(): HostConfiguration<T::BlockNumber>;
 		/// Pending configuration (if any) for the next session.
 		PendingConfig: Option<HostConfiguration<T::BlockNumber>>;
+
+		/// Storage version of the module.
+		///
+		/// This is set to the latest version for new networks.
+		StorageVersion build(|_: &GenesisConfig<T>| Releases::V5): Releases;
+	}
+}
+
+decl_event! {
+	pub enum Event {
+		/// A configuration change was made, which will take effect at the next session.
+		PendingConfigUpdated,
+		/// The pending configuration became the active configuration.
+		ConfigApplied,
 	}
 }
 
@@ -80,6 +398,8 @@ decl_module! {
 	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
 		type Error = Error<T>;
 
+		fn deposit_event() = default;
+
 		/// Set the validation upgrade frequency.
 		#[weight = (1_000, DispatchClass::Operational)]
 		pub fn set_validation_upgrade_frequency(origin, new: T::BlockNumber) -> DispatchResult {
@@ -150,6 +470,16 @@ decl_module! {
 			Ok(())
 		}
 
+		/// Set the base fee for parathread claims.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_parathread_claim_fee(origin, new: Balance) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.parathread_claim_fee, new) != new
+			});
+			Ok(())
+		}
+
 
 		/// Set the parachain validator-group rotation frequency
 		#[weight = (1_000, DispatchClass::Operational)]
@@ -190,6 +520,146 @@ decl_module! {
 			});
 			Ok(())
 		}
+
+		/// Set the maximum number of messages in a para's upward message queue.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_max_upward_queue_count(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.max_upward_queue_count, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the total size of messages allowed in a para's upward message queue.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_watermark_upward_queue_size(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.watermark_upward_queue_size, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the maximum total weight of upward messages dispatched in a single block.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_max_upward_dispatch_weight(origin, new: Weight) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.max_upward_dispatch_weight, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the maximum number of messages in a para's downward message queue.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_max_downward_queue_count(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.max_downward_queue_count, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the number of sessions an unaccepted HRMP channel open request stays valid for.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_open_request_ttl(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_open_request_ttl, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the deposit reserved by the sender of an HRMP channel.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_sender_deposit(origin, new: Balance) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_sender_deposit, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the deposit reserved by the recipient of an HRMP channel.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_recipient_deposit(origin, new: Balance) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_recipient_deposit, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the maximum number of messages allowed in an HRMP channel.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_channel_max_capacity(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_channel_max_capacity, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the maximum size of a message sent over an HRMP channel.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_channel_max_message_size(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_channel_max_message_size, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the maximum number of inbound HRMP channels a para may have.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_max_parachain_inbound_channels(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_max_parachain_inbound_channels, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the maximum number of outbound HRMP channels a para may have.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_max_parachain_outbound_channels(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_max_parachain_outbound_channels, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the maximum number of HRMP messages a single candidate may send.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_hrmp_max_message_num_per_candidate(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.hrmp_max_message_num_per_candidate, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the number of approvals a candidate needs to be considered approved.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_needed_approvals(origin, new: u32) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.needed_approvals, new) != new
+			});
+			Ok(())
+		}
+
+		/// Set the number of sessions information about a past session is kept for.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn set_dispute_period(origin, new: SessionIndex) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::update_config_member(|config| {
+				sp_std::mem::replace(&mut config.dispute_period, new) != new
+			});
+			Ok(())
+		}
 	}
 }
 
@@ -206,7 +676,36 @@ impl<T: Trait> Module<T> {
 	pub(crate) fn initializer_on_new_session(_validators: &[ValidatorId], _queued: &[ValidatorId]) {
 		if let Some(pending) = <Self as Store>::PendingConfig::take() {
 			<Self as Store>::Config::set(pending);
+			Self::deposit_event(Event::ConfigApplied);
+		}
+	}
+
+	/// Migrate the active and pending configurations to the current layout. Parameters introduced
+	/// since they were stored are set to their defaults and have to be configured by governance.
+	pub(crate) fn migrate() -> Weight {
+		match <Self as Store>::StorageVersion::get() {
+			Releases::V0 => Self::translate(|old: HostConfigurationV0<_>| {
+				old.upgrade().upgrade().upgrade().upgrade().upgrade()
+			}),
+			Releases::V1 => Self::translate(|old: HostConfigurationV1<_>| {
+				old.upgrade().upgrade().upgrade().upgrade()
+			}),
+			Releases::V2 => Self::translate(|old: HostConfigurationV2<_>| {
+				old.upgrade().upgrade().upgrade()
+			}),
+			Releases::V3 => Self::translate(|old: HostConfigurationV3<_>| old.upgrade().upgrade()),
+			Releases::V4 => Self::translate(HostConfigurationV4::upgrade),
+			Releases::V5 => return T::DbWeight::get().reads(1),
 		}
+		<Self as Store>::StorageVersion::put(Releases::V5);
+
+		T::DbWeight::get().reads_writes(3, 3)
+	}
+
+	// translate the active and pending configurations from an older layout.
+	fn translate<O: Decode>(upgrade: impl Fn(O) -> HostConfiguration<T::BlockNumber>) {
+		let _ = <Self as Store>::Config::translate(|old: Option<O>| old.map(&upgrade));
+		let _ = <Self as Store>::PendingConfig::translate(|old: Option<O>| old.map(&upgrade));
 	}
 
 	fn update_config_member(
@@ -217,6 +716,7 @@ impl<T: Trait> Module<T> {
 
 		if updater(&mut prev) {
 			<Self as Store>::PendingConfig::set(Some(prev));
+			Self::deposit_event(Event::PendingConfigUpdated);
 		}
 	}
 }
@@ -224,13 +724,15 @@ impl<T: Trait> Module<T> {
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use crate::mock::{new_test_ext, Initializer, Configuration, Origin};
+	use crate::mock::{new_test_ext, events, Initializer, Configuration, Origin, System, TestEvent};
 
 	use frame_support::traits::{OnFinalize, OnInitialize};
 
 	#[test]
 	fn config_changes_on_session_boundary() {
 		new_test_ext(Default::default()).execute_with(|| {
+			System::set_block_number(1);
+
 			let old_config = Configuration::config();
 			let mut config = old_config.clone();
 			config.validation_upgrade_delay = 100;
@@ -250,12 +752,15 @@ mod tests {
 
 			assert_eq!(Configuration::config(), config);
 			assert!(<Configuration as Store>::PendingConfig::get().is_none());
+			assert_eq!(events(), vec![TestEvent::configuration(Event::ConfigApplied)]);
 		})
 	}
 
 	#[test]
 	fn setting_pending_config_members() {
 		new_test_ext(Default::default()).execute_with(|| {
+			System::set_block_number(1);
+
 			let new_config = HostConfiguration {
 				validation_upgrade_frequency: 100,
 				validation_upgrade_delay: 10,
@@ -264,10 +769,25 @@ mod tests {
 				max_head_data_size: 1_000,
 				parathread_cores: 2,
 				parathread_retries: 5,
+				parathread_claim_fee: 100,
 				parachain_rotation_frequency: 20,
 				chain_availability_period: 10,
 				thread_availability_period: 8,
 				scheduling_lookahead: 3,
+				max_upward_queue_count: 10,
+				watermark_upward_queue_size: 20_000,
+				max_upward_dispatch_weight: 1_000_000,
+				max_downward_queue_count: 10,
+				hrmp_open_request_ttl: 2,
+				hrmp_sender_deposit: 1_000,
+				hrmp_recipient_deposit: 2_000,
+				hrmp_channel_max_capacity: 8,
+				hrmp_channel_max_message_size: 512,
+				hrmp_max_parachain_inbound_channels: 4,
+				hrmp_max_parachain_outbound_channels: 4,
+				hrmp_max_message_num_per_candidate: 10,
+				needed_approvals: 20,
+				dispute_period: 6,
 			};
 
 			assert!(<Configuration as Store>::PendingConfig::get().is_none());
@@ -293,6 +813,9 @@ mod tests {
 			Configuration::set_parathread_retries(
 				Origin::root(), new_config.parathread_retries,
 			).unwrap();
+			Configuration::set_parathread_claim_fee(
+				Origin::root(), new_config.parathread_claim_fee,
+			).unwrap();
 			Configuration::set_parachain_rotation_frequency(
 				Origin::root(), new_config.parachain_rotation_frequency,
 			).unwrap();
@@ -305,8 +828,55 @@ mod tests {
 			Configuration::set_scheduling_lookahead(
 				Origin::root(), new_config.scheduling_lookahead,
 			).unwrap();
+			Configuration::set_max_upward_queue_count(
+				Origin::root(), new_config.max_upward_queue_count,
+			).unwrap();
+			Configuration::set_watermark_upward_queue_size(
+				Origin::root(), new_config.watermark_upward_queue_size,
+			).unwrap();
+			Configuration::set_max_upward_dispatch_weight(
+				Origin::root(), new_config.max_upward_dispatch_weight,
+			).unwrap();
+			Configuration::set_max_downward_queue_count(
+				Origin::root(), new_config.max_downward_queue_count,
+			).unwrap();
+			Configuration::set_hrmp_open_request_ttl(
+				Origin::root(), new_config.hrmp_open_request_ttl,
+			).unwrap();
+			Configuration::set_hrmp_sender_deposit(
+				Origin::root(), new_config.hrmp_sender_deposit,
+			).unwrap();
+			Configuration::set_hrmp_recipient_deposit(
+				Origin::root(), new_config.hrmp_recipient_deposit,
+			).unwrap();
+			Configuration::set_hrmp_channel_max_capacity(
+				Origin::root(), new_config.hrmp_channel_max_capacity,
+			).unwrap();
+			Configuration::set_hrmp_channel_max_message_size(
+				Origin::root(), new_config.hrmp_channel_max_message_size,
+			).unwrap();
+			Configuration::set_hrmp_max_parachain_inbound_channels(
+				Origin::root(), new_config.hrmp_max_parachain_inbound_channels,
+			).unwrap();
+			Configuration::set_hrmp_max_parachain_outbound_channels(
+				Origin::root(), new_config.hrmp_max_parachain_outbound_channels,
+			).unwrap();
+			Configuration::set_hrmp_max_message_num_per_candidate(
+				Origin::root(), new_config.hrmp_max_message_num_per_candidate,
+			).unwrap();
+			Configuration::set_needed_approvals(
+				Origin::root(), new_config.needed_approvals,
+			).unwrap();
+			Configuration::set_dispute_period(
+				Origin::root(), new_config.dispute_period,
+			).unwrap();
 
 			assert_eq!(<Configuration as Store>::PendingConfig::get(), Some(new_config));
+
+			// every setter changed the pending configuration.
+			let events = events();
+			assert_eq!(events.len(), 26);
+			assert!(events.iter().all(|e| e == &TestEvent::configuration(Event::PendingConfigUpdated)));
 		})
 	}
 
@@ -320,8 +890,69 @@ mod tests {
 	#[test]
 	fn setting_config_to_same_as_current_is_noop() {
 		new_test_ext(Default::default()).execute_with(|| {
+			System::set_block_number(1);
+
 			Configuration::set_validation_upgrade_delay(Origin::root(), Default::default()).unwrap();
-			assert!(<Configuration as Store>::PendingConfig::get().is_none())
+			assert!(<Configuration as Store>::PendingConfig::get().is_none());
+			assert!(events().is_empty());
+		});
+	}
+
+	#[test]
+	fn migrates_v0_configuration() {
+		new_test_ext(Default::default()).execute_with(|| {
+			let old = HostConfigurationV0::<primitives::v1::BlockNumber> {
+				validation_upgrade_frequency: 10,
+				validation_upgrade_delay: 20,
+				acceptance_period: 30,
+				max_code_size: 40,
+				max_head_data_size: 50,
+				parathread_cores: 2,
+				parathread_retries: 3,
+				parachain_rotation_frequency: 5,
+				chain_availability_period: 6,
+				thread_availability_period: 7,
+				scheduling_lookahead: 8,
+			};
+
+			frame_support::storage::unhashed::put(
+				&<Configuration as Store>::Config::hashed_key(),
+				&old,
+			);
+			frame_support::storage::unhashed::put(
+				&<Configuration as Store>::PendingConfig::hashed_key(),
+				&HostConfigurationV0 { scheduling_lookahead: 9, ..old },
+			);
+			<Configuration as Store>::StorageVersion::put(Releases::V0);
+
+			Configuration::migrate();
+
+			let expected = HostConfiguration {
+				validation_upgrade_frequency: 10,
+				validation_upgrade_delay: 20,
+				acceptance_period: 30,
+				max_code_size: 40,
+				max_head_data_size: 50,
+				parathread_cores: 2,
+				parathread_retries: 3,
+				parachain_rotation_frequency: 5,
+				chain_availability_period: 6,
+				thread_availability_period: 7,
+				scheduling_lookahead: 8,
+				..Default::default()
+			};
+
+			assert_eq!(Configuration::config(), expected);
+			assert_eq!(
+				<Configuration as Store>::PendingConfig::get(),
+				Some(HostConfiguration { scheduling_lookahead: 9, ..expected }),
+			);
+			assert_eq!(<Configuration as Store>::StorageVersion::get(), Releases::V5);
+
+			// the migration only runs once.
+			let config = Configuration::config();
+			Configuration::migrate();
+			assert_eq!(Configuration::config(), config);
 		});
 	}
 }
@@ -23,20 +23,23 @@
 use sp_std::prelude::*;
 use primitives::v1::{
 	ValidatorId, CommittedCandidateReceipt, ValidatorIndex, Id as ParaId,
-	AvailabilityBitfield as AvailabilityBitfield, SignedAvailabilityBitfields, SigningContext,
-	BackedCandidate, CoreIndex, GroupIndex, CoreAssignment,
+	AvailabilityBitfield as AvailabilityBitfield, SignedAvailabilityBitfield,
+	SignedAvailabilityBitfields, SigningContext,
+	BackedCandidate, CoreIndex, GroupIndex, CoreAssignment, Hash,
 };
 use frame_support::{
-	decl_storage, decl_module, decl_error, ensure, dispatch::DispatchResult, IterableStorageMap,
-	weights::Weight,
+	decl_storage, decl_module, decl_event, decl_error, ensure, dispatch::DispatchResult,
+	IterableStorageMap,
+	weights::{DispatchClass, Weight},
 	traits::Get,
 };
+use system::ensure_root;
 use codec::{Encode, Decode};
 use bitvec::{order::Lsb0 as BitOrderLsb0, vec::BitVec};
 use sp_staking::SessionIndex;
 use sp_runtime::{DispatchError, traits::{One, Saturating}};
 
-use crate::{configuration, paras};
+use crate::{configuration, paras, router, validity};
 
 /// A bitfield signed by a validator indicating that it is keeping its piece of the erasure-coding
 /// for any backed candidates referred to by a `1` bit available.
@@ -66,9 +69,33 @@ pub struct CandidatePendingAvailability<H, N> {
 	relay_parent_number: N,
 	/// The block number of the relay-chain block this was backed in.
 	backed_in_number: N,
+	/// The validators which backed the candidate, by their index in the validator set.
+	backers: Vec<ValidatorIndex>,
 }
 
-pub trait Trait: system::Trait + paras::Trait + configuration::Trait { }
+impl<H, N: Clone> CandidatePendingAvailability<H, N> {
+	/// Get the candidate receipt.
+	pub(crate) fn receipt(&self) -> &CommittedCandidateReceipt<H> {
+		&self.receipt
+	}
+
+	/// Get the availability votes received for the candidate so far.
+	pub(crate) fn availability_votes(&self) -> &BitVec<BitOrderLsb0, u8> {
+		&self.availability_votes
+	}
+
+	/// Get the block number of the relay-chain block this was backed in.
+	pub(crate) fn backed_in_number(&self) -> N {
+		self.backed_in_number.clone()
+	}
+}
+
+pub trait Trait:
+	system::Trait + paras::Trait + router::Trait + configuration::Trait + validity::Trait
+{
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+}
 
 decl_storage! {
 	trait Store for Module<T: Trait> as ParaInclusion {
@@ -84,7 +111,36 @@ decl_storage! {
 		Validators get(fn validators) config(validators): Vec<ValidatorId>;
 
 		/// The current session index.
-		CurrentSessionIndex: SessionIndex;
+		CurrentSessionIndex get(fn session_index): SessionIndex;
+
+		/// The cores whose candidates pending availability were cleared by root since the last
+		/// inclusion inherent, which are yet to be freed in the scheduler.
+		ClearedCores: Vec<CoreIndex>;
+	}
+}
+
+decl_event! {
+	pub enum Event<T> where RelayHash = <T as system::Trait>::Hash {
+		/// A candidate was backed and is now pending availability on the given core.
+		/// [para_id, candidate_hash, core_index, relay_parent]
+		CandidateBacked(ParaId, Hash, CoreIndex, RelayHash),
+		/// A candidate pending availability became available and was included.
+		/// [para_id, candidate_hash, core_index, relay_parent]
+		CandidateIncluded(ParaId, Hash, CoreIndex, RelayHash),
+		/// A candidate pending availability timed out before becoming available and was dropped.
+		/// [para_id, candidate_hash, core_index, relay_parent]
+		CandidateTimedOut(ParaId, Hash, CoreIndex, RelayHash),
+		/// A bitfield was dropped from the inclusion inherent because it failed a check.
+		/// [validator_index, error]
+		BitfieldDropped(ValidatorIndex, DispatchError),
+		/// A backed candidate was dropped from the inclusion inherent because it failed a check, or
+		/// a candidate pending availability became available after its para was frozen and was
+		/// dropped instead of being included.
+		/// [para_id, candidate_hash, error]
+		CandidateDropped(ParaId, Hash, DispatchError),
+		/// A candidate pending availability was forcibly cleared by root.
+		/// [para_id, candidate_hash, core_index, relay_parent]
+		CandidateCleared(ParaId, Hash, CoreIndex, RelayHash),
 	}
 }
 
@@ -120,6 +176,10 @@ decl_error! {
 		InvalidBacking,
 		/// Collator did not sign PoV.
 		NotCollatorSigned,
+		/// The para is frozen because of a candidate confirmed invalid.
+		ParaFrozen,
+		/// The para has no candidate pending availability.
+		NoCandidatePendingAvailability,
 		/// Internal error only returned when compiled with debug assertions.
 		InternalError,
 	}
@@ -129,6 +189,29 @@ decl_module! {
 	/// The parachain-candidate inclusion module.
 	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
 		type Error = Error<T>;
+
+		fn deposit_event() = default;
+
+		/// Drop the candidate pending availability of a para, without enacting it. Must be
+		/// called by root.
+		///
+		/// The core of the candidate is freed by the next inclusion inherent.
+		#[weight = (10_000_000, DispatchClass::Operational)]
+		pub fn force_clear_pending_availability(origin, para: ParaId) -> DispatchResult {
+			ensure_root(origin)?;
+
+			let pending = <PendingAvailability<T>>::take(&para)
+				.ok_or(Error::<T>::NoCandidatePendingAvailability)?;
+			ClearedCores::append(pending.core);
+
+			Self::deposit_event(RawEvent::CandidateCleared(
+				para,
+				pending.receipt.hash(),
+				pending.core,
+				pending.receipt.descriptor.relay_parent,
+			));
+			Ok(())
+		}
 	}
 }
 
@@ -148,6 +231,7 @@ impl<T: Trait> Module<T> {
 		// and require consumption.
 		for _ in <PendingAvailability<T>>::drain() { }
 		for _ in <AvailabilityBitfields<T>>::drain() { }
+		ClearedCores::kill();
 
 		Validators::set(notification.validators.clone()); // substrate forces us to clone, stupidly.
 		CurrentSessionIndex::set(notification.session_index);
@@ -155,85 +239,49 @@ impl<T: Trait> Module<T> {
 
 	/// Process a set of incoming bitfields. Return a vec of cores freed by candidates
 	/// becoming available.
+	///
+	/// Each bitfield which fails a check is dropped on its own, depositing a `BitfieldDropped`
+	/// event, so that the others still count.
 	pub(crate) fn process_bitfields(
 		signed_bitfields: SignedAvailabilityBitfields,
 		core_lookup: impl Fn(CoreIndex) -> Option<ParaId>,
 	) -> Result<Vec<CoreIndex>, DispatchError> {
-		let validators = Validators::get();
-		let session_index = CurrentSessionIndex::get();
-		let config = <configuration::Module<T>>::config();
-		let parachains = <paras::Module<T>>::parachains();
-
-		let n_bits = parachains.len() + config.parathread_cores as usize;
-
-		let mut assigned_paras_record: Vec<_> = (0..n_bits)
-			.map(|bit_index| core_lookup(CoreIndex::from(bit_index as u32)))
-			.map(|core_para| core_para.map(|p| (p, PendingAvailability::<T>::get(&p))))
-			.collect();
+		let mut context = BitfieldCheckContext::<T>::new(core_lookup);
 
-		// do sanity checks on the bitfields:
-		// 1. no more than one bitfield per validator
-		// 2. bitfields are ascending by validator index.
-		// 3. each bitfield has exactly `n_bits`
-		// 4. signature is valid.
-		{
-			let occupied_bitmask: BitVec<BitOrderLsb0, u8> = assigned_paras_record.iter()
-				.map(|p| p.as_ref()
-					.map_or(false, |(_id, pending_availability)| pending_availability.is_some())
-				)
-				.collect();
+		let now = <system::Module<T>>::block_number();
+		let mut last_index = None;
 
-			let mut last_index = None;
+		for signed_bitfield in signed_bitfields {
+			let validator_index = signed_bitfield.validator_index();
 
-			let signing_context = SigningContext {
-				parent_hash: <system::Module<T>>::parent_hash(),
-				session_index,
+			// do sanity checks on the bitfield:
+			// 1. no more than one bitfield per validator
+			// 2. bitfields are ascending by validator index.
+			// 3. each bitfield has exactly `n_bits`
+			// 4. signature is valid.
+			let checked = if last_index.map_or(true, |last| last < validator_index) {
+				context.check(&signed_bitfield)
+			} else {
+				Err(Error::<T>::BitfieldDuplicateOrUnordered.into())
 			};
 
-			for signed_bitfield in &signed_bitfields {
-				ensure!(
-					signed_bitfield.payload().0.len() == n_bits,
-					Error::<T>::WrongBitfieldSize,
-				);
-
-				ensure!(
-					last_index.map_or(true, |last| last < signed_bitfield.validator_index()),
-					Error::<T>::BitfieldDuplicateOrUnordered,
-				);
-
-				ensure!(
-					signed_bitfield.validator_index() < validators.len() as ValidatorIndex,
-					Error::<T>::ValidatorIndexOutOfBounds,
-				);
-
-				ensure!(
-					occupied_bitmask.clone() & signed_bitfield.payload().0.clone() == signed_bitfield.payload().0,
-					Error::<T>::UnoccupiedBitInBitfield,
-				);
-
-				let validator_public = &validators[signed_bitfield.validator_index() as usize];
-
-				signed_bitfield.check_signature(
-					&signing_context,
-					validator_public,
-				).map_err(|_| Error::<T>::InvalidBitfieldSignature)?;
-
-				last_index = Some(signed_bitfield.validator_index());
+			if let Err(e) = checked {
+				Self::deposit_event(RawEvent::BitfieldDropped(validator_index, e));
+				continue;
 			}
-		}
 
-		let now = <system::Module<T>>::block_number();
-		for signed_bitfield in signed_bitfields {
+			last_index = Some(validator_index);
+
 			for (bit_idx, _)
 				in signed_bitfield.payload().0.iter().enumerate().filter(|(_, is_av)| **is_av)
 			{
-				let record = assigned_paras_record[bit_idx]
+				let record = context.assigned_paras_record[bit_idx]
 					.as_mut()
 					.expect("validator bitfields checked not to contain bits corresponding to unoccupied cores; qed");
 
 				// defensive check - this is constructed by loading the availability bitfield record,
 				// which is always `Some` if the core is occupied - that's why we're here.
-				let val_idx = signed_bitfield.validator_index() as usize;
+				let val_idx = validator_index as usize;
 				if let Some(mut bit) = record.1.as_mut()
 					.and_then(|r| r.availability_votes.get_mut(val_idx))
 				{
@@ -243,7 +291,6 @@ impl<T: Trait> Module<T> {
 				}
 			}
 
-			let validator_index = signed_bitfield.validator_index();
 			let record = AvailabilityBitfieldRecord {
 				bitfield: signed_bitfield.into_payload(),
 				submitted_at: now,
@@ -252,6 +299,8 @@ impl<T: Trait> Module<T> {
 			<AvailabilityBitfields<T>>::insert(&validator_index, record);
 		}
 
+		let BitfieldCheckContext { validators, n_bits, assigned_paras_record, .. } = context;
+
 		let threshold = availability_threshold(validators.len());
 
 		let mut freed_cores = Vec::with_capacity(n_bits);
@@ -261,10 +310,28 @@ impl<T: Trait> Module<T> {
 		{
 			if pending_availability.availability_votes.count_ones() >= threshold {
 				<PendingAvailability<T>>::remove(&para_id);
-				Self::enact_candidate(
-					pending_availability.relay_parent_number,
-					pending_availability.receipt,
-				);
+
+				// candidates of paras frozen while pending availability are dropped.
+				if <validity::Module<T>>::is_frozen(para_id) {
+					Self::deposit_event(RawEvent::CandidateDropped(
+						para_id,
+						pending_availability.receipt.hash(),
+						Error::<T>::ParaFrozen.into(),
+					));
+				} else {
+					Self::deposit_event(RawEvent::CandidateIncluded(
+						para_id,
+						pending_availability.receipt.hash(),
+						pending_availability.core,
+						pending_availability.receipt.descriptor.relay_parent.clone(),
+					));
+
+					Self::enact_candidate(
+						pending_availability.relay_parent_number,
+						pending_availability.receipt,
+						pending_availability.backers,
+					);
+				}
 
 				freed_cores.push(pending_availability.core);
 			} else {
@@ -272,16 +339,15 @@ impl<T: Trait> Module<T> {
 			}
 		}
 
-		// TODO: pass available candidates onwards to validity module once implemented.
-		// https://github.com/paritytech/polkadot/issues/1251
-
 		Ok(freed_cores)
 	}
 
 	/// Process candidates that have been backed. Provide a set of candidates and scheduled cores.
 	///
 	/// Both should be sorted ascending by core index, and the candidates should be a subset of
-	/// scheduled cores. If these conditions are not met, the execution of the function fails.
+	/// scheduled cores. Each candidate which fails a check, including candidates which are not
+	/// scheduled or appear out of order, is dropped on its own, depositing a `CandidateDropped`
+	/// event. The execution of the function fails only if the scheduled cores are out of order.
 	pub(crate) fn process_candidates(
 		candidates: Vec<BackedCandidate<T::Hash>>,
 		scheduled: Vec<CoreAssignment>,
@@ -289,151 +355,70 @@ impl<T: Trait> Module<T> {
 	)
 		-> Result<Vec<CoreIndex>, DispatchError>
 	{
-		ensure!(candidates.len() <= scheduled.len(), Error::<T>::UnscheduledCandidate);
-
-		if scheduled.is_empty() {
-			return Ok(Vec::new());
-		}
+		ensure!(
+			scheduled.windows(2).all(|pair| pair[0].core < pair[1].core),
+			Error::<T>::ScheduledOutOfOrder,
+		);
 
-		let validators = Validators::get();
-		let parent_hash = <system::Module<T>>::parent_hash();
-		let config = <configuration::Module<T>>::config();
+		let context = CandidateCheckContext::<T>::new();
 		let now = <system::Module<T>>::block_number();
-		let relay_parent_number = now - One::one();
-
-		// do all checks before writing storage.
-		let core_indices = {
-			let mut skip = 0;
-			let mut core_indices = Vec::with_capacity(candidates.len());
-			let mut last_core = None;
-
-			let mut check_assignment_in_order = |assignment: &CoreAssignment| -> DispatchResult {
-				ensure!(
-					last_core.map_or(true, |core| assignment.core > core),
-					Error::<T>::ScheduledOutOfOrder,
-				);
 
-				last_core = Some(assignment.core);
-				Ok(())
-			};
+		let mut skip = 0;
+		let mut core_indices = Vec::with_capacity(candidates.len());
 
-			let signing_context = SigningContext {
-				parent_hash,
-				session_index: CurrentSessionIndex::get(),
-			};
+		// Each candidate is looked up in the section of `scheduled` just after the core of the
+		// last candidate accepted. If the candidates appear in the same order as they appear in
+		// `scheduled`, then they should always be found. Otherwise the candidate was either not
+		// scheduled or didn't appear in `candidates` in the correct order.
+		for candidate in candidates {
+			let para_id = candidate.descriptor().para_id;
 
-			// We combine an outer loop over candidates with an inner loop over the scheduled,
-			// where each iteration of the outer loop picks up at the position
-			// in scheduled just after the past iteration left off.
-			//
-			// If the candidates appear in the same order as they appear in `scheduled`,
-			// then they should always be found. If the end of `scheduled` is reached,
-			// then the candidate was either not scheduled or out-of-order.
-			//
-			// In the meantime, we do certain sanity checks on the candidates and on the scheduled
-			// list.
-			'a:
-			for candidate in &candidates {
-				let para_id = candidate.descriptor().para_id;
-
-				// we require that the candidate is in the context of the parent block.
-				ensure!(
-					candidate.descriptor().relay_parent == parent_hash,
-					Error::<T>::CandidateNotInParentContext,
-				);
+			let checked = context.check_candidate(&candidate).and_then(|()| {
+				let (i, assignment) = scheduled[skip..].iter()
+					.enumerate()
+					.find(|(_, assignment)| assignment.para_id == para_id)
+					.ok_or(Error::<T>::UnscheduledCandidate)?;
 
-				let code_upgrade_allowed = <paras::Module<T>>::last_code_upgrade(para_id, true)
-					.map_or(
-						true,
-						|last| last <= relay_parent_number &&
-							relay_parent_number.saturating_sub(last) >= config.validation_upgrade_frequency,
-					);
+				let backers = context.check_assignment(&candidate, assignment, &group_validators)?;
 
-				ensure!(code_upgrade_allowed, Error::<T>::PrematureCodeUpgrade);
-				ensure!(
-					candidate.descriptor().check_collator_signature().is_ok(),
-					Error::<T>::NotCollatorSigned,
-				);
+				Ok((i, assignment.core, backers))
+			});
 
-				for (i, assignment) in scheduled[skip..].iter().enumerate() {
-					check_assignment_in_order(assignment)?;
-
-					if candidate.descriptor().para_id == assignment.para_id {
-						if let Some(required_collator) = assignment.required_collator() {
-							ensure!(
-								required_collator == &candidate.descriptor().collator,
-								Error::<T>::WrongCollator,
-							);
-						}
-
-						ensure!(
-							<PendingAvailability<T>>::get(&assignment.para_id).is_none(),
-							Error::<T>::CandidateScheduledBeforeParaFree,
-						);
-
-						// account for already skipped, and then skip this one.
-						skip = i + skip + 1;
-
-						let group_vals = group_validators(assignment.group_idx)
-							.ok_or_else(|| Error::<T>::InvalidGroupIndex)?;
-
-						// check the signatures in the backing and that it is a majority.
-						{
-							let maybe_amount_validated
-								= primitives::v1::check_candidate_backing(
-									&candidate,
-									&signing_context,
-									group_vals.len(),
-									|idx| group_vals.get(idx)
-										.and_then(|i| validators.get(*i as usize))
-										.map(|v| v.clone()),
-								);
-
-							match maybe_amount_validated {
-								Ok(amount_validated) => ensure!(
-									amount_validated * 2 > group_vals.len(),
-									Error::<T>::InsufficientBacking,
-								),
-								Err(()) => { Err(Error::<T>::InvalidBacking)?; }
-							}
-						}
-
-						core_indices.push(assignment.core);
-						continue 'a;
-					}
+			let (i, core, backers) = match checked {
+				Ok(accepted) => accepted,
+				Err(e) => {
+					Self::deposit_event(RawEvent::CandidateDropped(
+						para_id,
+						candidate.candidate.hash(),
+						e,
+					));
+					continue;
 				}
-
-				// end of loop reached means that the candidate didn't appear in the non-traversed
-				// section of the `scheduled` slice. either it was not scheduled or didn't appear in
-				// `candidates` in the correct order.
-				ensure!(
-					false,
-					Error::<T>::UnscheduledCandidate,
-				);
 			};
 
-			// check remainder of scheduled cores, if any.
-			for assignment in scheduled[skip..].iter() {
-				check_assignment_in_order(assignment)?;
-			}
-
-			core_indices
-		};
+			// account for already skipped, and then skip this one.
+			skip = i + skip + 1;
 
-		// one more sweep for actually writing to storage.
-		for (candidate, core) in candidates.into_iter().zip(core_indices.iter().cloned()) {
-			let para_id = candidate.descriptor().para_id;
+			Self::deposit_event(RawEvent::CandidateBacked(
+				para_id,
+				candidate.candidate.hash(),
+				core,
+				candidate.descriptor().relay_parent.clone(),
+			));
 
 			// initialize all availability votes to 0.
 			let availability_votes: BitVec<BitOrderLsb0, u8>
-				= bitvec::bitvec![BitOrderLsb0, u8; 0; validators.len()];
+				= bitvec::bitvec![BitOrderLsb0, u8; 0; context.validators.len()];
 			<PendingAvailability<T>>::insert(&para_id, CandidatePendingAvailability {
 				core,
 				receipt: candidate.candidate,
 				availability_votes,
-				relay_parent_number,
+				relay_parent_number: context.relay_parent_number,
 				backed_in_number: now,
+				backers,
 			});
+
+			core_indices.push(core);
 		}
 
 		Ok(core_indices)
@@ -442,12 +427,20 @@ impl<T: Trait> Module<T> {
 	fn enact_candidate(
 		relay_parent_number: T::BlockNumber,
 		receipt: CommittedCandidateReceipt<T::Hash>,
+		backers: Vec<ValidatorIndex>,
 	) -> Weight {
+		let candidate_hash = receipt.hash();
 		let commitments = receipt.commitments;
 		let config = <configuration::Module<T>>::config();
 
 		// initial weight is config read.
 		let mut weight = T::DbWeight::get().reads_writes(1, 0);
+		weight += <validity::Module<T>>::note_included(
+			candidate_hash,
+			receipt.descriptor.para_id,
+			receipt.descriptor.relay_parent.clone(),
+			backers,
+		);
 		if let Some(new_code) = commitments.new_validation_code {
 			weight += <paras::Module<T>>::schedule_code_upgrade(
 				receipt.descriptor.para_id,
@@ -456,6 +449,23 @@ impl<T: Trait> Module<T> {
 			);
 		}
 
+		weight += <router::Module<T>>::queue_upward_messages(
+			receipt.descriptor.para_id,
+			commitments.upward_messages,
+		);
+		weight += <router::Module<T>>::prune_downward_messages(
+			receipt.descriptor.para_id,
+			commitments.processed_downward_messages,
+		);
+		weight += <router::Module<T>>::prune_hrmp(
+			receipt.descriptor.para_id,
+			commitments.hrmp_watermark,
+		);
+		weight += <router::Module<T>>::queue_outbound_hrmp(
+			receipt.descriptor.para_id,
+			commitments.horizontal_messages,
+		);
+
 		weight + <paras::Module<T>>::note_new_head(
 			receipt.descriptor.para_id,
 			commitments.head_data,
@@ -470,25 +480,247 @@ impl<T: Trait> Module<T> {
 	///
 	/// Returns a vector of cleaned-up core IDs.
 	pub(crate) fn collect_pending(pred: impl Fn(CoreIndex, T::BlockNumber) -> bool) -> Vec<CoreIndex> {
-		let mut cleaned_up_ids = Vec::new();
+		let mut cleaned_up = Vec::new();
 		let mut cleaned_up_cores = Vec::new();
 
 		for (para_id, pending_record) in <PendingAvailability<T>>::iter() {
 			if pred(pending_record.core, pending_record.backed_in_number) {
-				cleaned_up_ids.push(para_id);
 				cleaned_up_cores.push(pending_record.core);
+				cleaned_up.push((para_id, pending_record));
 			}
 		}
 
-		for para_id in cleaned_up_ids {
+		for (para_id, pending_record) in cleaned_up {
 			<PendingAvailability<T>>::remove(&para_id);
+
+			Self::deposit_event(RawEvent::CandidateTimedOut(
+				para_id,
+				pending_record.receipt.hash(),
+				pending_record.core,
+				pending_record.receipt.descriptor.relay_parent,
+			));
 		}
 
 		cleaned_up_cores
 	}
+
+	/// Take the cores whose candidates pending availability were cleared by root, to be freed.
+	pub(crate) fn take_cleared_cores() -> Vec<CoreIndex> {
+		ClearedCores::take()
+	}
+
+	/// Returns the `CommittedCandidateReceipt` pending availability for the para provided, if any.
+	pub(crate) fn candidate_pending_availability(para: ParaId)
+		-> Option<CommittedCandidateReceipt<T::Hash>>
+	{
+		<PendingAvailability<T>>::get(&para).map(|p| p.receipt)
+	}
+
+	/// Returns the metadata around the candidate pending availability for the para provided, if any.
+	pub(crate) fn pending_availability(para: ParaId)
+		-> Option<CandidatePendingAvailability<T::Hash, T::BlockNumber>>
+	{
+		<PendingAvailability<T>>::get(&para)
+	}
+}
+
+/// The state against which backed candidates are checked.
+struct CandidateCheckContext<T: Trait> {
+	config: configuration::HostConfiguration<T::BlockNumber>,
+	validators: Vec<ValidatorId>,
+	parent_hash: T::Hash,
+	relay_parent_number: T::BlockNumber,
+	signing_context: SigningContext<T::Hash>,
+}
+
+impl<T: Trait> CandidateCheckContext<T> {
+	fn new() -> Self {
+		let parent_hash = <system::Module<T>>::parent_hash();
+
+		CandidateCheckContext {
+			config: <configuration::Module<T>>::config(),
+			validators: Validators::get(),
+			parent_hash: parent_hash.clone(),
+			relay_parent_number: <system::Module<T>>::block_number() - One::one(),
+			signing_context: SigningContext {
+				parent_hash,
+				session_index: CurrentSessionIndex::get(),
+			},
+		}
+	}
+
+	/// Check those properties of a candidate which don't depend on the core it is assigned to.
+	fn check_candidate(&self, candidate: &BackedCandidate<T::Hash>) -> DispatchResult {
+		let para_id = candidate.descriptor().para_id;
+		let relay_parent_number = self.relay_parent_number;
+
+		// we require that the candidate is in the context of the parent block.
+		ensure!(
+			candidate.descriptor().relay_parent == self.parent_hash,
+			Error::<T>::CandidateNotInParentContext,
+		);
+
+		let code_upgrade_allowed = <paras::Module<T>>::last_code_upgrade(para_id, true)
+			.map_or(
+				true,
+				|last| last <= relay_parent_number &&
+					relay_parent_number.saturating_sub(last) >= self.config.validation_upgrade_frequency,
+			);
+
+		ensure!(code_upgrade_allowed, Error::<T>::PrematureCodeUpgrade);
+		ensure!(!<validity::Module<T>>::is_frozen(para_id), Error::<T>::ParaFrozen);
+		<router::Module<T>>::check_upward_messages(
+			&self.config,
+			para_id,
+			&candidate.candidate.commitments.upward_messages,
+		)?;
+		<router::Module<T>>::check_processed_downward_messages(
+			para_id,
+			candidate.candidate.commitments.processed_downward_messages,
+		)?;
+		<router::Module<T>>::check_hrmp_watermark(
+			para_id,
+			relay_parent_number,
+			candidate.candidate.commitments.hrmp_watermark,
+		)?;
+		<router::Module<T>>::check_outbound_hrmp(
+			&self.config,
+			para_id,
+			&candidate.candidate.commitments.horizontal_messages,
+		)?;
+		ensure!(
+			candidate.descriptor().check_collator_signature().is_ok(),
+			Error::<T>::NotCollatorSigned,
+		);
+
+		Ok(())
+	}
+
+	/// Check a candidate against the core assignment of its para. Returns the validators which
+	/// backed the candidate, by their index in the validator set.
+	fn check_assignment(
+		&self,
+		candidate: &BackedCandidate<T::Hash>,
+		assignment: &CoreAssignment,
+		group_validators: impl Fn(GroupIndex) -> Option<Vec<ValidatorIndex>>,
+	) -> Result<Vec<ValidatorIndex>, DispatchError> {
+		if let Some(required_collator) = assignment.required_collator() {
+			ensure!(
+				required_collator == &candidate.descriptor().collator,
+				Error::<T>::WrongCollator,
+			);
+		}
+
+		ensure!(
+			<PendingAvailability<T>>::get(&assignment.para_id).is_none(),
+			Error::<T>::CandidateScheduledBeforeParaFree,
+		);
+
+		let group_vals = group_validators(assignment.group_idx)
+			.ok_or_else(|| Error::<T>::InvalidGroupIndex)?;
+
+		// check the signatures in the backing and that it is a majority.
+		{
+			let maybe_amount_validated
+				= primitives::v1::check_candidate_backing(
+					&candidate,
+					&self.signing_context,
+					group_vals.len(),
+					|idx| group_vals.get(idx)
+						.and_then(|i| self.validators.get(*i as usize))
+						.map(|v| v.clone()),
+				);
+
+			match maybe_amount_validated {
+				Ok(amount_validated) => ensure!(
+					amount_validated * 2 > group_vals.len(),
+					Error::<T>::InsufficientBacking,
+				),
+				Err(()) => { Err(Error::<T>::InvalidBacking)?; }
+			}
+		}
+
+		Ok(
+			candidate.validator_indices.iter()
+				.enumerate()
+				.filter(|(_, signed)| **signed)
+				.filter_map(|(i, _)| group_vals.get(i).cloned())
+				.collect()
+		)
+	}
+}
+
+/// The state against which incoming bitfields are checked.
+struct BitfieldCheckContext<T: Trait> {
+	validators: Vec<ValidatorId>,
+	n_bits: usize,
+	assigned_paras_record: Vec<Option<(ParaId, Option<CandidatePendingAvailability<T::Hash, T::BlockNumber>>)>>,
+	occupied_bitmask: BitVec<BitOrderLsb0, u8>,
+	signing_context: SigningContext<T::Hash>,
+}
+
+impl<T: Trait> BitfieldCheckContext<T> {
+	fn new(core_lookup: impl Fn(CoreIndex) -> Option<ParaId>) -> Self {
+		let validators = Validators::get();
+		let config = <configuration::Module<T>>::config();
+		let parachains = <paras::Module<T>>::parachains();
+
+		let n_bits = parachains.len() + config.parathread_cores as usize;
+
+		let assigned_paras_record: Vec<_> = (0..n_bits)
+			.map(|bit_index| core_lookup(CoreIndex::from(bit_index as u32)))
+			.map(|core_para| core_para.map(|p| (p, PendingAvailability::<T>::get(&p))))
+			.collect();
+
+		let occupied_bitmask = assigned_paras_record.iter()
+			.map(|p| p.as_ref()
+				.map_or(false, |(_id, pending_availability)| pending_availability.is_some())
+			)
+			.collect();
+
+		let signing_context = SigningContext {
+			parent_hash: <system::Module<T>>::parent_hash(),
+			session_index: CurrentSessionIndex::get(),
+		};
+
+		BitfieldCheckContext {
+			validators,
+			n_bits,
+			assigned_paras_record,
+			occupied_bitmask,
+			signing_context,
+		}
+	}
+
+	/// Check a single bitfield on its own. The order of the bitfields is checked by the caller.
+	fn check(&self, signed_bitfield: &SignedAvailabilityBitfield) -> DispatchResult {
+		ensure!(
+			signed_bitfield.payload().0.len() == self.n_bits,
+			Error::<T>::WrongBitfieldSize,
+		);
+
+		ensure!(
+			signed_bitfield.validator_index() < self.validators.len() as ValidatorIndex,
+			Error::<T>::ValidatorIndexOutOfBounds,
+		);
+
+		ensure!(
+			self.occupied_bitmask.clone() & signed_bitfield.payload().0.clone() == signed_bitfield.payload().0,
+			Error::<T>::UnoccupiedBitInBitfield,
+		);
+
+		let validator_public = &self.validators[signed_bitfield.validator_index() as usize];
+
+		signed_bitfield.check_signature(
+			&self.signing_context,
+			validator_public,
+		).map_err(|_| Error::<T>::InvalidBitfieldSignature)?;
+
+		Ok(())
+	}
 }
 
-const fn availability_threshold(n_validators: usize) -> usize {
+pub(crate) const fn availability_threshold(n_validators: usize) -> usize {
 	let mut threshold = (n_validators * 2) / 3;
 	threshold += (n_validators * 2) % 3;
 	threshold
@@ -498,18 +730,19 @@ const fn availability_threshold(n_validators: usize) -> usize {
 mod tests {
 	use super::*;
 
-	use primitives::v1::{BlockNumber, Hash};
+	use primitives::v1::BlockNumber;
 	use primitives::v1::{
 		SignedAvailabilityBitfield, CompactStatement as Statement, ValidityAttestation, CollatorId,
 		CandidateCommitments, SignedStatement, CandidateDescriptor, HeadData, ValidationCode,
-		AssignmentKind,
+		AssignmentKind, UpwardMessage, ParachainDispatchOrigin, DownwardMessage,
+		OutboundHrmpMessage, InboundHrmpMessage,
 	};
-	use frame_support::traits::{OnFinalize, OnInitialize};
+	use frame_support::{assert_noop, assert_ok, traits::{OnFinalize, OnInitialize}};
 	use keyring::Sr25519Keyring;
 
 	use crate::mock::{
-		new_test_ext, Configuration, Paras, System, Inclusion,
-		GenesisConfig as MockGenesisConfig, Test,
+		new_test_ext, events, Configuration, Origin, Paras, System, Inclusion, Router, Validity,
+		GenesisConfig as MockGenesisConfig, Test, TestEvent,
 	};
 	use crate::initializer::SessionChangeNotification;
 	use crate::configuration::HostConfiguration;
@@ -518,6 +751,12 @@ mod tests {
 	fn default_config() -> HostConfiguration<BlockNumber> {
 		let mut config = HostConfiguration::default();
 		config.parathread_cores = 1;
+		config.max_downward_queue_count = 10;
+		config.hrmp_channel_max_capacity = 10;
+		config.hrmp_channel_max_message_size = 100;
+		config.hrmp_max_parachain_inbound_channels = 2;
+		config.hrmp_max_parachain_outbound_channels = 2;
+		config.hrmp_max_message_num_per_candidate = 10;
 		config
 	}
 
@@ -648,6 +887,25 @@ mod tests {
 		}
 	}
 
+	fn inclusion_events() -> Vec<RawEvent<Hash>> {
+		events().into_iter().filter_map(|event| match event {
+			TestEvent::inclusion(event) => Some(event),
+			_ => None,
+		}).collect()
+	}
+
+	// the errors of the bitfields and candidates dropped since the events were last reset, which
+	// they are by this.
+	fn take_drop_errors() -> Vec<DispatchError> {
+		let errors = inclusion_events().into_iter().filter_map(|event| match event {
+			RawEvent::BitfieldDropped(_, e) | RawEvent::CandidateDropped(_, _, e) => Some(e),
+			_ => None,
+		}).collect();
+
+		System::reset_events();
+		errors
+	}
+
 	fn default_bitfield() -> AvailabilityBitfield {
 		let n_bits = Paras::parachains().len() + Configuration::config().parathread_cores as usize;
 
@@ -678,6 +936,10 @@ mod tests {
 		)
 	}
 
+	fn upward_message(data: Vec<u8>) -> UpwardMessage {
+		UpwardMessage { origin: ParachainDispatchOrigin::Parachain, data }
+	}
+
 	#[derive(Default)]
 	struct TestCandidateBuilder {
 		para_id: ParaId,
@@ -685,6 +947,10 @@ mod tests {
 		pov_hash: Hash,
 		relay_parent: Hash,
 		new_validation_code: Option<ValidationCode>,
+		upward_messages: Vec<UpwardMessage>,
+		processed_downward_messages: u32,
+		horizontal_messages: Vec<OutboundHrmpMessage>,
+		hrmp_watermark: BlockNumber,
 	}
 
 	impl TestCandidateBuilder {
@@ -699,6 +965,10 @@ mod tests {
 				commitments: CandidateCommitments {
 					head_data: self.head_data,
 					new_validation_code: self.new_validation_code,
+					upward_messages: self.upward_messages,
+					processed_downward_messages: self.processed_downward_messages,
+					horizontal_messages: self.horizontal_messages,
+					hrmp_watermark: self.hrmp_watermark,
 					..Default::default()
 				},
 			}
@@ -719,6 +989,7 @@ mod tests {
 				availability_votes: default_availability_votes(),
 				relay_parent_number: 0,
 				backed_in_number: 0,
+				backers: Vec::new(),
 			});
 
 			<PendingAvailability<Test>>::insert(chain_b, CandidatePendingAvailability {
@@ -727,6 +998,7 @@ mod tests {
 				availability_votes: default_availability_votes(),
 				relay_parent_number: 0,
 				backed_in_number: 0,
+				backers: Vec::new(),
 			});
 
 			run_to_block(5, |_| None);
@@ -738,6 +1010,56 @@ mod tests {
 
 			assert!(<PendingAvailability<Test>>::get(&chain_a).is_none());
 			assert!(<PendingAvailability<Test>>::get(&chain_b).is_some());
+
+			assert_eq!(inclusion_events(), vec![RawEvent::CandidateTimedOut(
+				chain_a,
+				CommittedCandidateReceipt::<Hash>::default().hash(),
+				CoreIndex::from(0),
+				Default::default(),
+			)]);
+		});
+	}
+
+	#[test]
+	fn force_clear_pending_availability_frees_core() {
+		let chain_a = ParaId::from(1);
+		let chain_b = ParaId::from(2);
+
+		let paras = vec![(chain_a, true), (chain_b, true)];
+		new_test_ext(genesis_config(paras)).execute_with(|| {
+			<PendingAvailability<Test>>::insert(chain_a, CandidatePendingAvailability {
+				core: CoreIndex::from(0),
+				receipt: Default::default(),
+				availability_votes: default_availability_votes(),
+				relay_parent_number: 0,
+				backed_in_number: 0,
+				backers: Vec::new(),
+			});
+
+			run_to_block(5, |_| None);
+
+			assert_noop!(
+				Inclusion::force_clear_pending_availability(Origin::signed(1), chain_a),
+				DispatchError::BadOrigin,
+			);
+			assert_noop!(
+				Inclusion::force_clear_pending_availability(Origin::root(), chain_b),
+				Error::<Test>::NoCandidatePendingAvailability,
+			);
+
+			assert_ok!(Inclusion::force_clear_pending_availability(Origin::root(), chain_a));
+
+			assert!(<PendingAvailability<Test>>::get(&chain_a).is_none());
+			assert_eq!(inclusion_events(), vec![RawEvent::CandidateCleared(
+				chain_a,
+				CommittedCandidateReceipt::<Hash>::default().hash(),
+				CoreIndex::from(0),
+				Default::default(),
+			)]);
+
+			// the core is freed once, by the next inclusion inherent.
+			assert_eq!(Inclusion::take_cleared_cores(), vec![CoreIndex::from(0)]);
+			assert!(Inclusion::take_cleared_cores().is_empty());
 		});
 	}
 
@@ -761,6 +1083,9 @@ mod tests {
 			Validators::set(validator_public.clone());
 			CurrentSessionIndex::set(5);
 
+			// events are not deposited in the genesis block.
+			System::set_block_number(1);
+
 			let signing_context = SigningContext {
 				parent_hash: System::parent_hash(),
 				session_index: 5,
@@ -784,10 +1109,11 @@ mod tests {
 					&signing_context,
 				);
 
-				assert!(Inclusion::process_bitfields(
-					vec![signed],
-					&core_lookup,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_bitfields(vec![signed], &core_lookup),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::WrongBitfieldSize.into()]);
 			}
 
 			// duplicate.
@@ -800,10 +1126,14 @@ mod tests {
 					&signing_context,
 				);
 
-				assert!(Inclusion::process_bitfields(
-					vec![signed.clone(), signed],
-					&core_lookup,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_bitfields(vec![signed.clone(), signed], &core_lookup),
+					Ok(Vec::new()),
+				);
+				assert_eq!(
+					take_drop_errors(),
+					vec![Error::<Test>::BitfieldDuplicateOrUnordered.into()],
+				);
 			}
 
 			// out of order.
@@ -823,10 +1153,14 @@ mod tests {
 					&signing_context,
 				);
 
-				assert!(Inclusion::process_bitfields(
-					vec![signed_1, signed_0],
-					&core_lookup,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_bitfields(vec![signed_1, signed_0], &core_lookup),
+					Ok(Vec::new()),
+				);
+				assert_eq!(
+					take_drop_errors(),
+					vec![Error::<Test>::BitfieldDuplicateOrUnordered.into()],
+				);
 			}
 
 			// non-pending bit set.
@@ -840,10 +1174,11 @@ mod tests {
 					&signing_context,
 				);
 
-				assert!(Inclusion::process_bitfields(
-					vec![signed],
-					&core_lookup,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_bitfields(vec![signed], &core_lookup),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::UnoccupiedBitInBitfield.into()]);
 			}
 
 			// empty bitfield signed: always OK, but kind of useless.
@@ -860,6 +1195,7 @@ mod tests {
 					vec![signed],
 					&core_lookup,
 				).is_ok());
+				assert!(take_drop_errors().is_empty());
 			}
 
 			// bitfield signed with pending bit signed.
@@ -874,6 +1210,7 @@ mod tests {
 					availability_votes: default_availability_votes(),
 					relay_parent_number: 0,
 					backed_in_number: 0,
+					backers: Vec::new(),
 				});
 
 				*bare_bitfield.0.get_mut(0).unwrap() = true;
@@ -888,6 +1225,7 @@ mod tests {
 					vec![signed],
 					&core_lookup,
 				).is_ok());
+				assert!(take_drop_errors().is_empty());
 			}
 		});
 	}
@@ -924,16 +1262,35 @@ mod tests {
 				_ => panic!("Core out of bounds for 2 parachains and 1 parathread core."),
 			};
 
+			let config = Configuration::config();
+			for i in 0..2 {
+				Router::queue_downward_message(&config, chain_a, DownwardMessage::Opaque(vec![i]))
+					.unwrap();
+			}
+
+			// open an HRMP channel from chain A to chain B.
+			Router::init_open_channel(chain_a, chain_b, 10, 100).unwrap();
+			Router::accept_open_channel(chain_b, chain_a).unwrap();
+			Router::initializer_on_new_session(&Default::default(), &[]);
+
+			let candidate_a = TestCandidateBuilder {
+				para_id: chain_a,
+				head_data: vec![1, 2, 3, 4].into(),
+				upward_messages: vec![upward_message(vec![1])],
+				processed_downward_messages: 1,
+				horizontal_messages: vec![
+					OutboundHrmpMessage { recipient: chain_b, data: vec![3] },
+				],
+				..Default::default()
+			}.build();
+
 			<PendingAvailability<Test>>::insert(chain_a, CandidatePendingAvailability {
 				core: CoreIndex::from(0),
-				receipt: TestCandidateBuilder {
-					para_id: chain_a,
-					head_data: vec![1, 2, 3, 4].into(),
-					..Default::default()
-				}.build(),
+				receipt: candidate_a.clone(),
 				availability_votes: default_availability_votes(),
 				relay_parent_number: 0,
 				backed_in_number: 0,
+				backers: Vec::new(),
 			});
 
 			<PendingAvailability<Test>>::insert(chain_b, CandidatePendingAvailability {
@@ -941,11 +1298,13 @@ mod tests {
 				receipt: TestCandidateBuilder {
 					para_id: chain_b,
 					head_data: vec![5, 6, 7, 8].into(),
+					upward_messages: vec![upward_message(vec![2])],
 					..Default::default()
 				}.build(),
 				availability_votes: default_availability_votes(),
 				relay_parent_number: 0,
 				backed_in_number: 0,
+				backers: Vec::new(),
 			});
 
 			// this bitfield signals that a and b are available.
@@ -1012,16 +1371,40 @@ mod tests {
 
 			// and check that chain head was enacted.
 			assert_eq!(Paras::para_head(&chain_a), Some(vec![1, 2, 3, 4].into()));
+
+			// and that only the upward messages of chain A were queued.
+			assert_eq!(Router::relay_dispatch_queue(&chain_a), vec![upward_message(vec![1])]);
+			assert!(Router::relay_dispatch_queue(&chain_b).is_empty());
+
+			// and that the downward messages processed by chain A were pruned.
+			assert_eq!(Router::downward_messages(&chain_a), vec![DownwardMessage::Opaque(vec![1])]);
+
+			// and that the horizontal messages of chain A were sent to chain B.
+			assert_eq!(
+				Router::inbound_hrmp_messages(chain_b),
+				vec![InboundHrmpMessage {
+					sender: chain_a,
+					sent_at: System::block_number(),
+					data: vec![3],
+				}],
+			);
+
+			// only chain A was included.
+			assert_eq!(inclusion_events(), vec![RawEvent::CandidateIncluded(
+				chain_a,
+				candidate_a.hash(),
+				CoreIndex::from(0),
+				candidate_a.descriptor.relay_parent,
+			)]);
 		});
 	}
 
 	#[test]
-	fn candidate_checks() {
+	fn available_candidates_of_frozen_paras_are_dropped() {
 		let chain_a = ParaId::from(1);
 		let chain_b = ParaId::from(2);
-		let thread_a = ParaId::from(3);
 
-		let paras = vec![(chain_a, true), (chain_b, true), (thread_a, false)];
+		let paras = vec![(chain_a, true), (chain_b, true)];
 		let validators = vec![
 			Sr25519Keyring::Alice,
 			Sr25519Keyring::Bob,
@@ -1035,29 +1418,123 @@ mod tests {
 			Validators::set(validator_public.clone());
 			CurrentSessionIndex::set(5);
 
-			run_to_block(5, |_| None);
-
 			let signing_context = SigningContext {
 				parent_hash: System::parent_hash(),
 				session_index: 5,
 			};
 
-			let group_validators = |group_index: GroupIndex| match group_index {
-				group_index if group_index == GroupIndex::from(0) => Some(vec![0, 1]),
-				group_index if group_index == GroupIndex::from(1) => Some(vec![2, 3]),
-				group_index if group_index == GroupIndex::from(2) => Some(vec![4]),
-				_ => panic!("Group index out of bounds for 2 parachains and 1 parathread core"),
+			let core_lookup = |core| match core {
+				core if core == CoreIndex::from(0) => Some(chain_a),
+				core if core == CoreIndex::from(1) => Some(chain_b),
+				_ => panic!("Core out of bounds for 2 parachains."),
 			};
 
-			let thread_collator: CollatorId = Sr25519Keyring::Two.public().into();
-
-			let chain_a_assignment = CoreAssignment {
-				core: CoreIndex::from(0),
+			let candidate_a = TestCandidateBuilder {
+				para_id: chain_a,
+				head_data: vec![1, 2, 3, 4].into(),
+				..Default::default()
+			}.build();
+			let candidate_b = TestCandidateBuilder {
 				para_id: chain_b,
-				kind: AssignmentKind::Parachain,
-				group_idx: GroupIndex::from(0),
-			};
-
+				head_data: vec![5, 6, 7, 8].into(),
+				..Default::default()
+			}.build();
+
+			for (id, core, candidate) in vec![(chain_a, 0, &candidate_a), (chain_b, 1, &candidate_b)] {
+				<PendingAvailability<Test>>::insert(id, CandidatePendingAvailability {
+					core: CoreIndex::from(core),
+					receipt: candidate.clone(),
+					availability_votes: default_availability_votes(),
+					relay_parent_number: 0,
+					backed_in_number: 0,
+					backers: Vec::new(),
+				});
+			}
+
+			// chain B is frozen while its candidate is pending availability.
+			Validity::freeze_para(chain_b, Hash::from([2; 32]));
+
+			let a_and_b_available = {
+				let mut bare_bitfield = default_bitfield();
+				*bare_bitfield.0.get_mut(0).unwrap() = true;
+				*bare_bitfield.0.get_mut(1).unwrap() = true;
+
+				bare_bitfield
+			};
+
+			let signed_bitfields = validators.iter().enumerate().map(|(i, key)| sign_bitfield(
+				key,
+				i as ValidatorIndex,
+				a_and_b_available.clone(),
+				&signing_context,
+			)).collect();
+
+			assert_eq!(
+				Inclusion::process_bitfields(signed_bitfields, &core_lookup),
+				Ok(vec![CoreIndex::from(0), CoreIndex::from(1)]),
+			);
+
+			// both cores are freed, but only chain A's candidate is enacted.
+			assert!(<PendingAvailability<Test>>::get(&chain_a).is_none());
+			assert!(<PendingAvailability<Test>>::get(&chain_b).is_none());
+			assert_eq!(Paras::para_head(&chain_a), Some(vec![1, 2, 3, 4].into()));
+			assert_ne!(Paras::para_head(&chain_b), Some(vec![5, 6, 7, 8].into()));
+
+			assert_eq!(inclusion_events(), vec![
+				RawEvent::CandidateIncluded(
+					chain_a,
+					candidate_a.hash(),
+					CoreIndex::from(0),
+					candidate_a.descriptor.relay_parent,
+				),
+				RawEvent::CandidateDropped(chain_b, candidate_b.hash(), Error::<Test>::ParaFrozen.into()),
+			]);
+		});
+	}
+
+	#[test]
+	fn candidate_checks() {
+		let chain_a = ParaId::from(1);
+		let chain_b = ParaId::from(2);
+		let thread_a = ParaId::from(3);
+
+		let paras = vec![(chain_a, true), (chain_b, true), (thread_a, false)];
+		let validators = vec![
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+			Sr25519Keyring::Ferdie,
+		];
+		let validator_public = validator_pubkeys(&validators);
+
+		new_test_ext(genesis_config(paras)).execute_with(|| {
+			Validators::set(validator_public.clone());
+			CurrentSessionIndex::set(5);
+
+			run_to_block(5, |_| None);
+
+			let signing_context = SigningContext {
+				parent_hash: System::parent_hash(),
+				session_index: 5,
+			};
+
+			let group_validators = |group_index: GroupIndex| match group_index {
+				group_index if group_index == GroupIndex::from(0) => Some(vec![0, 1]),
+				group_index if group_index == GroupIndex::from(1) => Some(vec![2, 3]),
+				group_index if group_index == GroupIndex::from(2) => Some(vec![4]),
+				_ => panic!("Group index out of bounds for 2 parachains and 1 parathread core"),
+			};
+
+			let thread_collator: CollatorId = Sr25519Keyring::Two.public().into();
+
+			let chain_a_assignment = CoreAssignment {
+				core: CoreIndex::from(0),
+				para_id: chain_a,
+				kind: AssignmentKind::Parachain,
+				group_idx: GroupIndex::from(0),
+			};
+
 			let chain_b_assignment = CoreAssignment {
 				core: CoreIndex::from(1),
 				para_id: chain_b,
@@ -1067,7 +1544,7 @@ mod tests {
 
 			let thread_a_assignment = CoreAssignment {
 				core: CoreIndex::from(2),
-				para_id: chain_b,
+				para_id: thread_a,
 				kind: AssignmentKind::Parathread(thread_collator.clone(), 0),
 				group_idx: GroupIndex::from(2),
 			};
@@ -1093,14 +1570,19 @@ mod tests {
 					BackingKind::Threshold,
 				);
 
-				assert!(Inclusion::process_candidates(
-					vec![backed],
-					vec![chain_b_assignment.clone()],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_b_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::UnscheduledCandidate.into()]);
 			}
 
-			// candidates out of order.
+			// candidates out of order: the first is accepted, and the second is dropped as there
+			// is no assignment for it after the core of the first.
 			{
 				let mut candidate_a = TestCandidateBuilder {
 					para_id: chain_a,
@@ -1141,11 +1623,17 @@ mod tests {
 					BackingKind::Threshold,
 				);
 
-				assert!(Inclusion::process_candidates(
-					vec![backed_b, backed_a],
-					vec![chain_a_assignment.clone(), chain_b_assignment.clone()],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed_b, backed_a],
+						vec![chain_a_assignment.clone(), chain_b_assignment.clone()],
+						&group_validators,
+					),
+					Ok(vec![CoreIndex::from(1)]),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::UnscheduledCandidate.into()]);
+
+				<PendingAvailability<Test>>::remove(&chain_b);
 			}
 
 			// candidate not backed.
@@ -1169,11 +1657,15 @@ mod tests {
 					BackingKind::Lacking,
 				);
 
-				assert!(Inclusion::process_candidates(
-					vec![backed],
-					vec![chain_a_assignment.clone()],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_a_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::InsufficientBacking.into()]);
 			}
 
 			// candidate not in parent context.
@@ -1200,11 +1692,15 @@ mod tests {
 					BackingKind::Threshold,
 				);
 
-				assert!(Inclusion::process_candidates(
-					vec![backed],
-					vec![chain_a_assignment.clone()],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_a_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::CandidateNotInParentContext.into()]);
 			}
 
 			// candidate has wrong collator.
@@ -1230,15 +1726,19 @@ mod tests {
 					BackingKind::Threshold,
 				);
 
-				assert!(Inclusion::process_candidates(
-					vec![backed],
-					vec![
-						chain_a_assignment.clone(),
-						chain_b_assignment.clone(),
-						thread_a_assignment.clone(),
-					],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![
+							chain_a_assignment.clone(),
+							chain_b_assignment.clone(),
+							thread_a_assignment.clone(),
+						],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::WrongCollator.into()]);
 			}
 
 			// candidate not well-signed by collator.
@@ -1267,11 +1767,15 @@ mod tests {
 					BackingKind::Threshold,
 				);
 
-				assert!(Inclusion::process_candidates(
-					vec![backed],
-					vec![thread_a_assignment.clone()],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![thread_a_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::NotCollatorSigned.into()]);
 			}
 
 			// para occupied - reject.
@@ -1302,13 +1806,18 @@ mod tests {
 					availability_votes: default_availability_votes(),
 					relay_parent_number: 3,
 					backed_in_number: 4,
+					backers: Vec::new(),
 				});
 
-				assert!(Inclusion::process_candidates(
-					vec![backed],
-					vec![chain_a_assignment.clone()],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_a_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::CandidateScheduledBeforeParaFree.into()]);
 
 				<PendingAvailability<Test>>::remove(&chain_a);
 			}
@@ -1344,12 +1853,449 @@ mod tests {
 
 				assert_eq!(Paras::last_code_upgrade(chain_a, true), Some(10));
 
-				assert!(Inclusion::process_candidates(
-					vec![backed],
-					vec![thread_a_assignment.clone()],
-					&group_validators,
-				).is_err());
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![thread_a_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::PrematureCodeUpgrade.into()]);
+			}
+
+			// upward messages exceeding the queue limits - reject
+			{
+				let mut candidate = TestCandidateBuilder {
+					para_id: chain_b,
+					relay_parent: System::parent_hash(),
+					pov_hash: Hash::from([1; 32]),
+					upward_messages: vec![upward_message(vec![1]), upward_message(vec![2])],
+					..Default::default()
+				}.build();
+
+				collator_sign_candidate(
+					Sr25519Keyring::One,
+					&mut candidate,
+				);
+
+				let backed = back_candidate(
+					candidate,
+					&validators,
+					group_validators(GroupIndex::from(1)).unwrap().as_ref(),
+					&signing_context,
+					BackingKind::Threshold,
+				);
+
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_b_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![router::Error::<Test>::QueueFull.into()]);
+			}
+
+			// processing more downward messages than are queued - reject
+			{
+				let mut candidate = TestCandidateBuilder {
+					para_id: chain_b,
+					relay_parent: System::parent_hash(),
+					pov_hash: Hash::from([1; 32]),
+					processed_downward_messages: 1,
+					..Default::default()
+				}.build();
+
+				collator_sign_candidate(
+					Sr25519Keyring::One,
+					&mut candidate,
+				);
+
+				let backed = back_candidate(
+					candidate,
+					&validators,
+					group_validators(GroupIndex::from(1)).unwrap().as_ref(),
+					&signing_context,
+					BackingKind::Threshold,
+				);
+
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_b_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![router::Error::<Test>::InvalidProcessedDownwardMessages.into()]);
+			}
+
+			// HRMP watermark ahead of the relay-parent - reject
+			{
+				let mut candidate = TestCandidateBuilder {
+					para_id: chain_b,
+					relay_parent: System::parent_hash(),
+					pov_hash: Hash::from([1; 32]),
+					hrmp_watermark: System::block_number(),
+					..Default::default()
+				}.build();
+
+				collator_sign_candidate(
+					Sr25519Keyring::One,
+					&mut candidate,
+				);
+
+				let backed = back_candidate(
+					candidate,
+					&validators,
+					group_validators(GroupIndex::from(1)).unwrap().as_ref(),
+					&signing_context,
+					BackingKind::Threshold,
+				);
+
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_b_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![router::Error::<Test>::HrmpWatermarkAheadOfRelayParent.into()]);
+			}
+
+			// horizontal message over a channel which doesn't exist - reject
+			{
+				let mut candidate = TestCandidateBuilder {
+					para_id: chain_b,
+					relay_parent: System::parent_hash(),
+					pov_hash: Hash::from([1; 32]),
+					horizontal_messages: vec![
+						OutboundHrmpMessage { recipient: chain_a, data: vec![1] },
+					],
+					..Default::default()
+				}.build();
+
+				collator_sign_candidate(
+					Sr25519Keyring::One,
+					&mut candidate,
+				);
+
+				let backed = back_candidate(
+					candidate,
+					&validators,
+					group_validators(GroupIndex::from(1)).unwrap().as_ref(),
+					&signing_context,
+					BackingKind::Threshold,
+				);
+
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_b_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![router::Error::<Test>::NoSuchHrmpChannel.into()]);
+			}
+
+			// para frozen because of a candidate confirmed invalid - reject
+			{
+				let mut candidate = TestCandidateBuilder {
+					para_id: chain_b,
+					relay_parent: System::parent_hash(),
+					pov_hash: Hash::from([1; 32]),
+					..Default::default()
+				}.build();
+
+				collator_sign_candidate(
+					Sr25519Keyring::One,
+					&mut candidate,
+				);
+
+				let backed = back_candidate(
+					candidate,
+					&validators,
+					group_validators(GroupIndex::from(1)).unwrap().as_ref(),
+					&signing_context,
+					BackingKind::Threshold,
+				);
+
+				Validity::freeze_para(chain_b, Hash::from([2; 32]));
+
+				assert_eq!(
+					Inclusion::process_candidates(
+						vec![backed],
+						vec![chain_b_assignment.clone()],
+						&group_validators,
+					),
+					Ok(Vec::new()),
+				);
+				assert_eq!(take_drop_errors(), vec![Error::<Test>::ParaFrozen.into()]);
+			}
+		});
+	}
+
+	#[test]
+	fn invalid_bitfields_are_dropped() {
+		let chain_a = ParaId::from(1);
+		let chain_b = ParaId::from(2);
+		let thread_a = ParaId::from(3);
+
+		let paras = vec![(chain_a, true), (chain_b, true), (thread_a, false)];
+		let validators = vec![
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+			Sr25519Keyring::Ferdie,
+		];
+		let validator_public = validator_pubkeys(&validators);
+
+		new_test_ext(genesis_config(paras)).execute_with(|| {
+			Validators::set(validator_public.clone());
+			CurrentSessionIndex::set(5);
+
+			// events are not deposited in the genesis block.
+			System::set_block_number(1);
+
+			let signing_context = SigningContext {
+				parent_hash: System::parent_hash(),
+				session_index: 5,
+			};
+
+			let core_lookup = |core| match core {
+				core if core == CoreIndex::from(0) => Some(chain_a),
+				core if core == CoreIndex::from(1) => Some(chain_b),
+				core if core == CoreIndex::from(2) => Some(thread_a),
+				_ => panic!("Core out of bounds for 2 parachains and 1 parathread core."),
+			};
+
+			<PendingAvailability<Test>>::insert(chain_a, CandidatePendingAvailability {
+				core: CoreIndex::from(0),
+				receipt: Default::default(),
+				availability_votes: default_availability_votes(),
+				relay_parent_number: 0,
+				backed_in_number: 0,
+				backers: Vec::new(),
+			});
+
+			let valid = sign_bitfield(
+				&validators[0],
+				0,
+				default_bitfield(),
+				&signing_context,
+			);
+
+			let duplicate = valid.clone();
+
+			let wrong_size = {
+				let mut bare_bitfield = default_bitfield();
+				bare_bitfield.0.push(false);
+				sign_bitfield(&validators[1], 1, bare_bitfield, &signing_context)
+			};
+
+			let unoccupied_bit = {
+				let mut bare_bitfield = default_bitfield();
+				*bare_bitfield.0.get_mut(1).unwrap() = true;
+				sign_bitfield(&validators[2], 2, bare_bitfield, &signing_context)
+			};
+
+			let bad_signature = sign_bitfield(
+				&validators[4],
+				3,
+				default_bitfield(),
+				&signing_context,
+			);
+
+			let pending_bit = {
+				let mut bare_bitfield = default_bitfield();
+				*bare_bitfield.0.get_mut(0).unwrap() = true;
+				sign_bitfield(&validators[4], 4, bare_bitfield, &signing_context)
+			};
+
+			assert_eq!(
+				Inclusion::process_bitfields(
+					vec![valid.clone(), duplicate, wrong_size, unoccupied_bit, bad_signature, pending_bit.clone()],
+					&core_lookup,
+				),
+				Ok(Vec::new()),
+			);
+
+			assert_eq!(inclusion_events(), vec![
+				RawEvent::BitfieldDropped(0, Error::<Test>::BitfieldDuplicateOrUnordered.into()),
+				RawEvent::BitfieldDropped(1, Error::<Test>::WrongBitfieldSize.into()),
+				RawEvent::BitfieldDropped(2, Error::<Test>::UnoccupiedBitInBitfield.into()),
+				RawEvent::BitfieldDropped(3, Error::<Test>::InvalidBitfieldSignature.into()),
+			]);
+
+			// only the valid bitfields are recorded, and count towards availability.
+			assert_eq!(
+				<AvailabilityBitfields<Test>>::get(&0).map(|record| record.bitfield),
+				Some(valid.into_payload()),
+			);
+			for i in 1..4 {
+				assert!(<AvailabilityBitfields<Test>>::get(&i).is_none());
 			}
+			assert_eq!(
+				<AvailabilityBitfields<Test>>::get(&4).map(|record| record.bitfield),
+				Some(pending_bit.into_payload()),
+			);
+			assert_eq!(
+				<PendingAvailability<Test>>::get(&chain_a).unwrap().availability_votes.count_ones(),
+				1,
+			);
+		});
+	}
+
+	#[test]
+	fn invalid_candidates_are_dropped() {
+		let chain_a = ParaId::from(1);
+		let chain_b = ParaId::from(2);
+		let thread_a = ParaId::from(3);
+
+		let paras = vec![(chain_a, true), (chain_b, true), (thread_a, false)];
+		let validators = vec![
+			Sr25519Keyring::Alice,
+			Sr25519Keyring::Bob,
+			Sr25519Keyring::Charlie,
+			Sr25519Keyring::Dave,
+			Sr25519Keyring::Ferdie,
+		];
+		let validator_public = validator_pubkeys(&validators);
+
+		new_test_ext(genesis_config(paras)).execute_with(|| {
+			Validators::set(validator_public.clone());
+			CurrentSessionIndex::set(5);
+
+			run_to_block(5, |_| None);
+
+			let signing_context = SigningContext {
+				parent_hash: System::parent_hash(),
+				session_index: 5,
+			};
+
+			let group_validators = |group_index: GroupIndex| match group_index {
+				group_index if group_index == GroupIndex::from(0) => Some(vec![0, 1]),
+				group_index if group_index == GroupIndex::from(1) => Some(vec![2, 3]),
+				group_index if group_index == GroupIndex::from(2) => Some(vec![4]),
+				_ => panic!("Group index out of bounds for 2 parachains and 1 parathread core"),
+			};
+
+			let thread_collator: CollatorId = Sr25519Keyring::Two.public().into();
+
+			let scheduled = vec![
+				CoreAssignment {
+					core: CoreIndex::from(0),
+					para_id: chain_a,
+					kind: AssignmentKind::Parachain,
+					group_idx: GroupIndex::from(0),
+				},
+				CoreAssignment {
+					core: CoreIndex::from(1),
+					para_id: chain_b,
+					kind: AssignmentKind::Parachain,
+					group_idx: GroupIndex::from(1),
+				},
+				CoreAssignment {
+					core: CoreIndex::from(2),
+					para_id: thread_a,
+					kind: AssignmentKind::Parathread(thread_collator, 0),
+					group_idx: GroupIndex::from(2),
+				},
+			];
+
+			let make_candidate = |para_id, pov_hash: Hash, collator| {
+				let mut candidate = TestCandidateBuilder {
+					para_id,
+					relay_parent: System::parent_hash(),
+					pov_hash,
+					..Default::default()
+				}.build();
+				collator_sign_candidate(collator, &mut candidate);
+				candidate
+			};
+
+			let candidate_a = make_candidate(chain_a, Hash::from([1; 32]), Sr25519Keyring::One);
+			// a second candidate for a para whose core has already been filled.
+			let candidate_a_2 = make_candidate(chain_a, Hash::from([2; 32]), Sr25519Keyring::One);
+			let candidate_b = make_candidate(chain_b, Hash::from([3; 32]), Sr25519Keyring::One);
+			let candidate_c = make_candidate(thread_a, Hash::from([4; 32]), Sr25519Keyring::Two);
+
+			let dropped_hashes = [candidate_a_2.hash(), candidate_b.hash()];
+
+			let backed_a = back_candidate(
+				candidate_a,
+				&validators,
+				group_validators(GroupIndex::from(0)).unwrap().as_ref(),
+				&signing_context,
+				BackingKind::Threshold,
+			);
+
+			let backed_a_2 = back_candidate(
+				candidate_a_2,
+				&validators,
+				group_validators(GroupIndex::from(0)).unwrap().as_ref(),
+				&signing_context,
+				BackingKind::Threshold,
+			);
+
+			let backed_b = back_candidate(
+				candidate_b,
+				&validators,
+				group_validators(GroupIndex::from(1)).unwrap().as_ref(),
+				&signing_context,
+				BackingKind::Lacking,
+			);
+
+			let backed_c = back_candidate(
+				candidate_c,
+				&validators,
+				group_validators(GroupIndex::from(2)).unwrap().as_ref(),
+				&signing_context,
+				BackingKind::Threshold,
+			);
+
+			let backed_hashes = [backed_a.candidate.hash(), backed_c.candidate.hash()];
+
+			let occupied_cores = Inclusion::process_candidates(
+				vec![backed_a, backed_a_2, backed_b, backed_c],
+				scheduled,
+				&group_validators,
+			).expect("invalid candidates are dropped");
+
+			assert_eq!(occupied_cores, vec![CoreIndex::from(0), CoreIndex::from(2)]);
+			assert_eq!(inclusion_events(), vec![
+				RawEvent::CandidateBacked(
+					chain_a,
+					backed_hashes[0],
+					CoreIndex::from(0),
+					System::parent_hash(),
+				),
+				RawEvent::CandidateDropped(
+					chain_a,
+					dropped_hashes[0],
+					Error::<Test>::UnscheduledCandidate.into(),
+				),
+				RawEvent::CandidateDropped(
+					chain_b,
+					dropped_hashes[1],
+					Error::<Test>::InsufficientBacking.into(),
+				),
+				RawEvent::CandidateBacked(
+					thread_a,
+					backed_hashes[1],
+					CoreIndex::from(2),
+					System::parent_hash(),
+				),
+			]);
+
+			assert!(<PendingAvailability<Test>>::get(&chain_a).is_some());
+			assert!(<PendingAvailability<Test>>::get(&chain_b).is_none());
+			assert!(<PendingAvailability<Test>>::get(&thread_a).is_some());
 		});
 	}
 
@@ -1467,6 +2413,8 @@ mod tests {
 				BackingKind::Threshold,
 			);
 
+			let candidate_hashes = [candidate_a.hash(), candidate_b.hash(), candidate_c.hash()];
+
 			let occupied_cores = Inclusion::process_candidates(
 				vec![backed_a, backed_b, backed_c],
 				vec![
@@ -1487,6 +2435,7 @@ mod tests {
 					availability_votes: default_availability_votes(),
 					relay_parent_number: System::block_number() - 1,
 					backed_in_number: System::block_number(),
+					backers: vec![0, 1],
 				})
 			);
 
@@ -1498,6 +2447,7 @@ mod tests {
 					availability_votes: default_availability_votes(),
 					relay_parent_number: System::block_number() - 1,
 					backed_in_number: System::block_number(),
+					backers: vec![2, 3],
 				})
 			);
 
@@ -1509,8 +2459,21 @@ mod tests {
 					availability_votes: default_availability_votes(),
 					relay_parent_number: System::block_number() - 1,
 					backed_in_number: System::block_number(),
+					backers: vec![4],
 				})
 			);
+
+			assert_eq!(inclusion_events(), vec![
+				RawEvent::CandidateBacked(
+					chain_a, candidate_hashes[0], CoreIndex::from(0), System::parent_hash(),
+				),
+				RawEvent::CandidateBacked(
+					chain_b, candidate_hashes[1], CoreIndex::from(1), System::parent_hash(),
+				),
+				RawEvent::CandidateBacked(
+					thread_a, candidate_hashes[2], CoreIndex::from(2), System::parent_hash(),
+				),
+			]);
 		});
 	}
 
@@ -1574,6 +2537,7 @@ mod tests {
 				availability_votes: default_availability_votes(),
 				relay_parent_number: 5,
 				backed_in_number: 6,
+				backers: Vec::new(),
 			});
 
 			<PendingAvailability<Test>>::insert(&chain_b, CandidatePendingAvailability {
@@ -1582,6 +2546,7 @@ mod tests {
 				availability_votes: default_availability_votes(),
 				relay_parent_number: 6,
 				backed_in_number: 7,
+				backers: Vec::new(),
 			});
 
 			run_to_block(11, |_| None);
@@ -28,7 +28,7 @@ use primitives::v1::{
 use frame_support::{
 	decl_error, decl_module, decl_storage, ensure,
 	dispatch::DispatchResult,
-	weights::{DispatchClass, Weight},
+	weights::{DispatchClass, Weight, constants::RocksDbWeight},
 	traits::Get,
 };
 use system::ensure_none;
@@ -38,7 +38,42 @@ use crate::{
 };
 use inherents::{InherentIdentifier, InherentData, MakeFatalError, ProvideInherent};
 
-pub trait Trait: inclusion::Trait + scheduler::Trait {}
+/// The most validators the weight of the inclusion inherent accounts for.
+///
+/// The inherent is weighed before it is dispatched, without reading the current validator set,
+/// so its weight is that of a session with this many validators.
+pub const MAX_VALIDATORS: u32 = 1_000;
+
+pub trait Trait: inclusion::Trait + scheduler::Trait {
+	/// The weights of the entry-points of this module, as generated by its benchmarks.
+	type WeightInfo: WeightInfo;
+}
+
+/// The weights of the entry-points of the inclusion inherent module.
+pub trait WeightInfo {
+	/// The weight of `inclusion` with `b` bitfields, `c` backed candidates without a code upgrade,
+	/// `u` backed candidates with a code upgrade and `v` validators.
+	fn inclusion(b: u32, c: u32, u: u32, v: u32) -> Weight;
+}
+
+/// Conservative weights for runtimes which haven't run the benchmarks of this module.
+impl WeightInfo for () {
+	fn inclusion(b: u32, c: u32, u: u32, v: u32) -> Weight {
+		(1_000_000_000 as Weight)
+			.saturating_add((100_000_000 as Weight).saturating_mul(b as Weight))
+			.saturating_add((500_000_000 as Weight).saturating_mul(c as Weight))
+			.saturating_add((3_000_000_000 as Weight).saturating_mul(u as Weight))
+			.saturating_add((5_000_000 as Weight).saturating_mul(v as Weight))
+			.saturating_add(RocksDbWeight::get().reads(50 as Weight))
+			.saturating_add(RocksDbWeight::get().reads((1 as Weight).saturating_mul(b as Weight)))
+			.saturating_add(RocksDbWeight::get().reads((8 as Weight).saturating_mul(c as Weight)))
+			.saturating_add(RocksDbWeight::get().reads((8 as Weight).saturating_mul(u as Weight)))
+			.saturating_add(RocksDbWeight::get().writes(50 as Weight))
+			.saturating_add(RocksDbWeight::get().writes((1 as Weight).saturating_mul(b as Weight)))
+			.saturating_add(RocksDbWeight::get().writes((1 as Weight).saturating_mul(c as Weight)))
+			.saturating_add(RocksDbWeight::get().writes((1 as Weight).saturating_mul(u as Weight)))
+	}
+}
 
 decl_storage! {
 	trait Store for Module<T: Trait> as ParaInclusionInherent {
@@ -75,7 +110,19 @@ decl_module! {
 		}
 
 		/// Include backed candidates and bitfields.
-		#[weight = (1_000_000_000, DispatchClass::Mandatory)]
+		#[weight = (
+			<T as Trait>::WeightInfo::inclusion(
+				signed_bitfields.len() as u32,
+				backed_candidates.iter()
+					.filter(|c| c.candidate.commitments.new_validation_code.is_none())
+					.count() as u32,
+				backed_candidates.iter()
+					.filter(|c| c.candidate.commitments.new_validation_code.is_some())
+					.count() as u32,
+				MAX_VALIDATORS,
+			),
+			DispatchClass::Mandatory,
+		)]
 		pub fn inclusion(
 			origin,
 			signed_bitfields: SignedAvailabilityBitfields,
@@ -85,7 +132,7 @@ decl_module! {
 			ensure!(!<Included>::exists(), Error::<T>::TooManyInclusionInherents);
 
 			// Process new availability bitfields, yielding any availability cores whose
-			// work has now concluded.
+			// work has now concluded. Bitfields which fail their checks are dropped.
 			let freed_concluded = <inclusion::Module<T>>::process_bitfields(
 				signed_bitfields,
 				<scheduler::Module<T>>::core_para,
@@ -99,13 +146,18 @@ decl_module! {
 				Vec::new()
 			};
 
+			// Cores whose candidates were cleared by root are freed as if they had timed out.
+			let freed_cleared = <inclusion::Module<T>>::take_cleared_cores();
+
 			// Schedule paras again, given freed cores, and reasons for freeing.
 			let freed = freed_concluded.into_iter().map(|c| (c, FreedReason::Concluded))
-				.chain(freed_timeout.into_iter().map(|c| (c, FreedReason::TimedOut)));
+				.chain(freed_timeout.into_iter().map(|c| (c, FreedReason::TimedOut)))
+				.chain(freed_cleared.into_iter().map(|c| (c, FreedReason::TimedOut)));
 
 			<scheduler::Module<T>>::schedule(freed.collect());
 
-			// Process backed candidates according to scheduled cores.
+			// Process backed candidates according to scheduled cores. Candidates which fail their
+			// checks are dropped.
 			let occupied = <inclusion::Module<T>>::process_candidates(
 				backed_candidates,
 				<scheduler::Module<T>>::scheduled(),
@@ -131,14 +183,244 @@ impl<T: Trait> ProvideInherent for Module<T> {
 	fn create_inherent(data: &InherentData) -> Option<Self::Call> {
 		data.get_data(&Self::INHERENT_IDENTIFIER)
 			.expect("inclusion inherent data failed to decode")
+			// Bitfields and candidates which are invalid, e.g. because of a session change, are
+			// dropped by the call itself rather than invalidating the inherent.
+			// See github.com/paritytech/polkadot/issues/1327
 			.map(|(signed_bitfields, backed_candidates): (SignedAvailabilityBitfields, Vec<BackedCandidate<T::Hash>>)| {
-				// Sanity check: session changes can invalidate an inherent, and we _really_ don't want that to happen.
-				// See github.com/paritytech/polkadot/issues/1327
-				if Self::inclusion(system::RawOrigin::None.into(), signed_bitfields.clone(), backed_candidates.clone()).is_ok() {
-					Call::inclusion(signed_bitfields, backed_candidates)
-				} else {
-					Call::inclusion(Vec::new().into(), Vec::new())
-				}
+				Call::inclusion(signed_bitfields, backed_candidates)
+			})
+	}
+}
+
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking {
+	use super::*;
+	use system as frame_system; // NOTE: required for the benchmarks! macro
+	use system::RawOrigin;
+	use frame_benchmarking::benchmarks;
+	use sp_runtime::{RuntimeAppPublic, traits::One};
+	use bitvec::order::Lsb0 as BitOrderLsb0;
+	use primitives::v1::{
+		AvailabilityBitfield, CandidateCommitments, CandidateDescriptor, CollatorId,
+		CommittedCandidateReceipt, CompactStatement, CoreAssignment, HeadData, Id as ParaId,
+		SignedAvailabilityBitfield, SignedStatement, SigningContext, ValidationCode, ValidatorId,
+		ValidityAttestation,
+	};
+	use crate::{configuration, paras::{self, ParaGenesisArgs}, initializer::SessionChangeNotification};
+
+	// The number of cores occupied by a candidate upgrading the code of its para, all of which
+	// become available in the benchmarked block.
+	const PENDING_CORES: u32 = 5;
+	// The number of cores which are free for new candidates in the benchmarked block.
+	const FREE_CORES: u32 = 10;
+	// The most bitfields in the inherent.
+	const MAX_BITFIELDS: u32 = 50;
+	// The fewest validators, leaving enough of them to make the candidates pending availability
+	// all but available before the benchmarked block without overlapping the signed bitfields.
+	const MIN_VALIDATORS: u32 = 3 * MAX_BITFIELDS;
+	// The size of the new validation code of a candidate.
+	const CODE_SIZE: u32 = 1024 * 1024;
+
+	// Register the parachains and start a session with `v` validators, whose keys are stored in
+	// the keystore and returned.
+	fn start_session<T: Trait>(v: u32) -> Result<Vec<ValidatorId>, &'static str> {
+		configuration::Module::<T>::set_max_code_size(RawOrigin::Root.into(), CODE_SIZE)?;
+		configuration::Module::<T>::set_validation_upgrade_delay(RawOrigin::Root.into(), 10u32.into())?;
+		configuration::Module::<T>::set_parathread_cores(RawOrigin::Root.into(), 0)?;
+		configuration::Module::<T>::set_parachain_rotation_frequency(RawOrigin::Root.into(), 100u32.into())?;
+		configuration::Module::<T>::set_chain_availability_period(RawOrigin::Root.into(), 10u32.into())?;
+		configuration::Module::<T>::initializer_on_new_session(&[], &[]);
+
+		for i in 0 .. PENDING_CORES + FREE_CORES {
+			paras::Module::<T>::schedule_para_initialize(ParaId::from(1000 + i), ParaGenesisArgs {
+				genesis_head: HeadData(vec![0; 32]),
+				validation_code: ValidationCode(vec![0; 32]),
+				parachain: true,
+			});
+		}
+
+		let validators: Vec<_> = (0 .. v).map(|_| ValidatorId::generate_pair(None)).collect();
+		let notification = SessionChangeNotification {
+			validators: validators.clone(),
+			queued: validators.clone(),
+			new_config: <configuration::Module<T>>::config(),
+			session_index: 1,
+			..Default::default()
+		};
+
+		<system::Module<T>>::set_block_number(One::one());
+		paras::Module::<T>::initializer_on_new_session(&notification);
+		scheduler::Module::<T>::initializer_on_new_session(&notification);
+		inclusion::Module::<T>::initializer_on_new_session(&notification);
+
+		Ok(validators)
+	}
+
+	fn signing_context<T: Trait>() -> SigningContext<T::Hash> {
+		SigningContext {
+			parent_hash: <system::Module<T>>::parent_hash(),
+			session_index: <inclusion::Module<T>>::session_index(),
+		}
+	}
+
+	// Back a candidate for the given assignment, unanimously by the group assigned to its core.
+	fn back_candidate<T: Trait>(
+		validators: &[ValidatorId],
+		collator: &CollatorId,
+		assignment: &CoreAssignment,
+		new_validation_code: Option<ValidationCode>,
+	) -> BackedCandidate<T::Hash> {
+		let relay_parent = <system::Module<T>>::parent_hash();
+		let pov_hash = Default::default();
+		let payload = primitives::v1::collator_signature_payload(
+			&relay_parent,
+			&assignment.para_id,
+			&pov_hash,
+		);
+
+		let candidate = CommittedCandidateReceipt {
+			descriptor: CandidateDescriptor {
+				para_id: assignment.para_id,
+				relay_parent,
+				collator: collator.clone(),
+				signature: collator.sign(&payload).expect("collator key is in the keystore; qed"),
+				pov_hash,
+			},
+			commitments: CandidateCommitments {
+				head_data: HeadData(vec![1; 32]),
+				new_validation_code,
+				..Default::default()
+			},
+		};
+
+		let group = <scheduler::Module<T>>::group_validators(assignment.group_idx)
+			.expect("the group is assigned to a scheduled core; qed");
+		let candidate_hash = candidate.hash();
+		let signing_context = signing_context::<T>();
+		let validity_votes = group.iter()
+			.map(|i| {
+				let signature = SignedStatement::sign_with_keystore(
+					CompactStatement::Valid(candidate_hash),
+					&signing_context,
+					*i,
+					&validators[*i as usize],
+				).expect("validator keys are in the keystore; qed").signature().clone();
+
+				ValidityAttestation::Explicit(signature)
 			})
+			.collect();
+
+		BackedCandidate {
+			candidate,
+			validity_votes,
+			validator_indices: bitvec::bitvec![BitOrderLsb0, u8; 1; group.len()],
+		}
+	}
+
+	// Sign a bitfield marking all occupied cores as available.
+	fn sign_bitfield<T: Trait>(validators: &[ValidatorId], index: u32) -> SignedAvailabilityBitfield {
+		let bitfield = AvailabilityBitfield(
+			<scheduler::Module<T>>::availability_cores().iter().map(|core| core.is_some()).collect(),
+		);
+
+		SignedAvailabilityBitfield::sign_with_keystore(
+			bitfield,
+			&signing_context::<T>(),
+			index,
+			&validators[index as usize],
+		).expect("validator keys are in the keystore; qed")
+	}
+
+	benchmarks! {
+		_ { }
+
+		// Benchmark `inclusion` with `b` bitfields, which make the candidates pending availability
+		// available, `c` backed candidates without and `u` backed candidates with a code upgrade,
+		// and `v` validators.
+		inclusion {
+			let b in 1 .. MAX_BITFIELDS;
+			let c in 0 .. FREE_CORES / 2;
+			let u in 0 .. FREE_CORES / 2;
+			let v in MIN_VALIDATORS .. MAX_VALIDATORS;
+
+			let validators = start_session::<T>(v)?;
+			let collator = CollatorId::generate_pair(None);
+			let new_code = || Some(ValidationCode(vec![0; CODE_SIZE as usize]));
+
+			// back candidates upgrading the code of their paras on the first cores.
+			<system::Module<T>>::set_block_number(2u32.into());
+			<scheduler::Module<T>>::initializer_initialize(2u32.into());
+			let scheduled = <scheduler::Module<T>>::scheduled();
+			let upgrading: Vec<_> = scheduled.iter()
+				.take(PENDING_CORES as usize)
+				.map(|assignment| assignment.para_id)
+				.collect();
+			let pending = scheduled.iter()
+				.take(PENDING_CORES as usize)
+				.map(|assignment| back_candidate::<T>(&validators, &collator, assignment, new_code()))
+				.collect();
+			let occupied = <inclusion::Module<T>>::process_candidates(
+				pending,
+				scheduled,
+				<scheduler::Module<T>>::group_validators,
+			)?;
+			<scheduler::Module<T>>::occupied(&occupied);
+			<scheduler::Module<T>>::initializer_finalize();
+
+			<system::Module<T>>::set_block_number(3u32.into());
+			<scheduler::Module<T>>::initializer_initialize(3u32.into());
+
+			// all but one of the votes needed for availability come from the last validators.
+			let threshold = inclusion::availability_threshold(v as usize) as u32;
+			let votes = (v + 1 - threshold .. v)
+				.map(|i| sign_bitfield::<T>(&validators, i))
+				.collect();
+			<inclusion::Module<T>>::process_bitfields(votes, <scheduler::Module<T>>::core_para)?;
+
+			let signed_bitfields: Vec<_> = (0 .. b)
+				.map(|i| sign_bitfield::<T>(&validators, i))
+				.collect();
+
+			let scheduled = <scheduler::Module<T>>::scheduled();
+			let backed: Vec<_> = scheduled.iter()
+				.take((c + u) as usize)
+				.map(|assignment| assignment.para_id)
+				.collect();
+			let backed_candidates: Vec<_> = scheduled.iter()
+				.take((c + u) as usize)
+				.enumerate()
+				.map(|(i, assignment)| back_candidate::<T>(
+					&validators,
+					&collator,
+					assignment,
+					if (i as u32) < u { new_code() } else { None },
+				))
+				.collect();
+		}: _(RawOrigin::None, signed_bitfields, backed_candidates)
+		verify {
+			for para_id in upgrading {
+				assert!(<paras::Module<T>>::future_code_upgrade_at(para_id).is_some());
+			}
+			for para_id in backed {
+				assert!(<inclusion::Module<T>>::pending_availability(para_id).is_some());
+			}
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use crate::mock::{new_test_ext, Test};
+		use frame_support::assert_ok;
+		use sp_core::{testing::KeyStore, traits::KeystoreExt};
+
+		#[test]
+		fn test_benchmarks() {
+			let mut ext = new_test_ext(Default::default());
+			ext.register_extension(KeystoreExt(KeyStore::new()));
+			ext.execute_with(|| {
+				assert_ok!(test_benchmark_inclusion::<Test>());
+			});
+		}
 	}
 }
@@ -20,14 +20,16 @@
 //! This module can throw fatal errors if session-change notifications are received after initialization.
 
 use sp_std::prelude::*;
-use frame_support::weights::Weight;
+use frame_support::weights::{Weight, constants::RocksDbWeight};
 use primitives::v1::ValidatorId;
 use frame_support::{
-	decl_storage, decl_module, decl_error, traits::Randomness,
+	decl_storage, decl_module, decl_error, traits::{Get, Randomness},
 };
 use sp_runtime::traits::One;
 use codec::{Encode, Decode};
-use crate::{configuration::{self, HostConfiguration}, paras, scheduler, inclusion};
+use crate::{
+	configuration::{self, HostConfiguration}, paras, scheduler, session_info, inclusion, validity, router,
+};
 
 /// Information about a session change that has just occurred.
 #[derive(Default, Clone)]
@@ -55,10 +57,33 @@ struct BufferedSessionChange<N> {
 }
 
 pub trait Trait:
-	system::Trait + configuration::Trait + paras::Trait + scheduler::Trait + inclusion::Trait
+	system::Trait + configuration::Trait + paras::Trait + scheduler::Trait + session_info::Trait
+	+ inclusion::Trait + validity::Trait + router::Trait
 {
 	/// A randomness beacon.
 	type Randomness: Randomness<Self::Hash>;
+
+	/// The weights of the routines of this module, as generated by its benchmarks.
+	type WeightInfo: WeightInfo;
+}
+
+/// The weights of the routines of the initializer module.
+pub trait WeightInfo {
+	/// The weight of applying a session change with `v` validators and `p` parachains.
+	fn new_session(v: u32, p: u32) -> Weight;
+}
+
+/// Conservative weights for runtimes which haven't run the benchmarks of this module.
+impl WeightInfo for () {
+	fn new_session(v: u32, p: u32) -> Weight {
+		(100_000_000 as Weight)
+			.saturating_add((1_000_000 as Weight).saturating_mul(v as Weight))
+			.saturating_add((5_000_000 as Weight).saturating_mul(p as Weight))
+			.saturating_add(RocksDbWeight::get().reads(20 as Weight))
+			.saturating_add(RocksDbWeight::get().reads((1 as Weight).saturating_mul(p as Weight)))
+			.saturating_add(RocksDbWeight::get().writes(20 as Weight))
+			.saturating_add(RocksDbWeight::get().writes((1 as Weight).saturating_mul(p as Weight)))
+	}
 }
 
 decl_storage! {
@@ -92,19 +117,32 @@ decl_module! {
 	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
 		type Error = Error<T>;
 
+		fn on_runtime_upgrade() -> Weight {
+			configuration::Module::<T>::migrate() + paras::Module::<T>::migrate_to_v1()
+		}
+
 		fn on_initialize(now: T::BlockNumber) -> Weight {
 			// Apply buffered session changes before initializing modules, so they
 			// can be initialized with respect to the current validator set.
-			<BufferedSessionChanges<T>>::mutate(|v| {
+			let session_change_weight = <BufferedSessionChanges<T>>::mutate(|v| {
 				let drain_up_to = v.iter().take_while(|b| b.apply_at <= now).count();
 
 				// apply only the last session as all others lasted less than a block (weirdly).
 				if let Some(buffered) = v.drain(..drain_up_to).last() {
+					let weight = <T as Trait>::WeightInfo::new_session(
+						buffered.validators.len() as u32,
+						<paras::Module<T>>::parachains().len() as u32,
+					);
+
 					Self::apply_new_session(
 						buffered.session_index,
 						buffered.validators,
 						buffered.queued,
 					);
+
+					weight
+				} else {
+					0
 				}
 			});
 
@@ -112,12 +150,18 @@ decl_module! {
 			// - Configuration
 			// - Paras
 			// - Scheduler
+			// - Session Info
 			// - Inclusion
 			// - Validity
-			let total_weight = configuration::Module::<T>::initializer_initialize(now) +
+			// - Router
+			let total_weight = session_change_weight +
+				configuration::Module::<T>::initializer_initialize(now) +
 				paras::Module::<T>::initializer_initialize(now) +
 				scheduler::Module::<T>::initializer_initialize(now) +
-				inclusion::Module::<T>::initializer_initialize(now);
+				session_info::Module::<T>::initializer_initialize(now) +
+				inclusion::Module::<T>::initializer_initialize(now) +
+				validity::Module::<T>::initializer_initialize(now) +
+				router::Module::<T>::initializer_initialize(now);
 
 			HasInitialized::set(Some(()));
 
@@ -127,7 +171,10 @@ decl_module! {
 		fn on_finalize() {
 			// reverse initialization order.
 
+			router::Module::<T>::initializer_finalize();
+			validity::Module::<T>::initializer_finalize();
 			inclusion::Module::<T>::initializer_finalize();
+			session_info::Module::<T>::initializer_finalize();
 			scheduler::Module::<T>::initializer_finalize();
 			paras::Module::<T>::initializer_finalize();
 			configuration::Module::<T>::initializer_finalize();
@@ -137,6 +184,19 @@ decl_module! {
 }
 
 impl<T: Trait> Module<T> {
+	/// The session index that a child of the current block will be in. This accounts for any
+	/// session change buffered in this block, which will be applied on initialization of the child.
+	pub(crate) fn session_index_for_child() -> sp_staking::SessionIndex {
+		let next_block = <system::Module<T>>::block_number() + One::one();
+
+		<BufferedSessionChanges<T>>::get()
+			.iter()
+			.rev()
+			.find(|b| b.apply_at <= next_block)
+			.map(|b| b.session_index)
+			.unwrap_or_else(<inclusion::Module<T>>::session_index)
+	}
+
 	fn apply_new_session(
 		session_index: sp_staking::SessionIndex,
 		validators: Vec<ValidatorId>,
@@ -167,9 +227,11 @@ impl<T: Trait> Module<T> {
 			session_index,
 		};
 
-		paras::Module::<T>::initializer_on_new_session(&notification);
+		let outgoing_paras = paras::Module::<T>::initializer_on_new_session(&notification);
 		scheduler::Module::<T>::initializer_on_new_session(&notification);
+		session_info::Module::<T>::initializer_on_new_session(&notification);
 		inclusion::Module::<T>::initializer_on_new_session(&notification);
+		router::Module::<T>::initializer_on_new_session(&notification, &outgoing_paras);
 	}
 
 	/// Should be called when a new session occurs. Buffers the session notification to be applied
@@ -205,10 +267,12 @@ impl<T: Trait> sp_runtime::BoundToRuntimeAppPublic for Module<T> {
 impl<T: session::Trait + Trait> session::OneSessionHandler<T::AccountId> for Module<T> {
 	type Key = ValidatorId;
 
-	fn on_genesis_session<'a, I: 'a>(_validators: I)
+	fn on_genesis_session<'a, I: 'a>(validators: I)
 		where I: Iterator<Item=(&'a T::AccountId, Self::Key)>
 	{
-
+		// buffer the genesis validator set like any other session change, so that it is applied
+		// once the chain starts producing blocks.
+		<Module<T>>::on_new_session(false, 0, validators, None);
 	}
 
 	fn on_new_session<'a, I: 'a>(changed: bool, validators: I, queued: I)
@@ -268,6 +332,31 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn session_index_for_child_accounts_for_buffered_change() {
+		new_test_ext(Default::default()).execute_with(|| {
+			Initializer::on_initialize(1);
+
+			assert_eq!(Initializer::session_index_for_child(), 0);
+
+			Initializer::on_new_session(
+				false,
+				1,
+				Vec::new().into_iter(),
+				Some(Vec::new().into_iter()),
+			);
+
+			assert_eq!(Initializer::session_index_for_child(), 1);
+
+			Initializer::on_finalize(1);
+
+			System::set_block_number(2);
+			Initializer::on_initialize(2);
+
+			assert_eq!(Initializer::session_index_for_child(), 1);
+		});
+	}
+
 	#[test]
 	fn sets_flag_on_initialize() {
 		new_test_ext(Default::default()).execute_with(|| {
@@ -287,3 +376,67 @@ mod tests {
 		})
 	}
 }
+
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking {
+	use super::*;
+	use system as frame_system; // NOTE: required for the benchmarks! macro
+	use frame_benchmarking::benchmarks;
+	use primitives::v1::Id as ParaId;
+	use sp_core::sr25519;
+	use crate::paras::ParaGenesisArgs;
+
+	// The most validators in a session.
+	const MAX_VALIDATORS: u32 = 1_000;
+	// The most parachains in a session.
+	const MAX_PARACHAINS: u32 = 100;
+
+	fn validator(i: u32) -> ValidatorId {
+		let mut raw = [0u8; 32];
+		raw[..4].copy_from_slice(&i.to_le_bytes());
+		sr25519::Public::from_raw(raw).into()
+	}
+
+	benchmarks! {
+		_ { }
+
+		// Benchmark applying a session change with `v` validators and `p` parachains.
+		new_session {
+			let v in 1 .. MAX_VALIDATORS;
+			let p in 0 .. MAX_PARACHAINS;
+
+			for i in 0 .. p {
+				paras::Module::<T>::schedule_para_initialize(ParaId::from(i), ParaGenesisArgs {
+					genesis_head: Default::default(),
+					validation_code: Default::default(),
+					parachain: true,
+				});
+			}
+
+			let validators: Vec<_> = (0 .. v).map(validator).collect();
+
+			// the parachains are onboarded in the first session.
+			Module::<T>::apply_new_session(1, validators.clone(), validators.clone());
+		}: {
+			Module::<T>::apply_new_session(2, validators.clone(), validators);
+		}
+		verify {
+			assert_eq!(<inclusion::Module<T>>::session_index(), 2);
+			assert_eq!(<paras::Module<T>>::parachains().len() as u32, p);
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use crate::mock::{new_test_ext, Test};
+		use frame_support::assert_ok;
+
+		#[test]
+		fn test_benchmarks() {
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_new_session::<Test>());
+			});
+		}
+	}
+}
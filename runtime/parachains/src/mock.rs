@@ -16,18 +16,21 @@
 
 //! Mocks for all the traits.
 
+use std::cell::RefCell;
 use sp_io::TestExternalities;
 use sp_core::{H256};
 use sp_runtime::{
-	Perbill,
+	Perbill, KeyTypeId,
 	traits::{
 		BlakeTwo256, IdentityLookup,
 	},
 };
-use primitives::v1::{BlockNumber, Header};
+use sp_staking::{SessionIndex, offence::{ReportOffence, OffenceError}};
+use primitives::v1::{BlockNumber, Header, ValidatorId};
+use crate::{configuration, paras, scheduler, inclusion, router, validity::InvalidCandidateOffence};
 use frame_support::{
-	impl_outer_origin, impl_outer_dispatch, parameter_types,
-	weights::Weight, traits::Randomness as RandomnessT,
+	impl_outer_origin, impl_outer_dispatch, impl_outer_event, parameter_types,
+	weights::Weight, traits::{Randomness as RandomnessT, KeyOwnerProofSystem},
 };
 
 /// A test runtime struct.
@@ -35,15 +38,29 @@ use frame_support::{
 pub struct Test;
 
 impl_outer_origin! {
-	pub enum Origin for Test { }
+	pub enum Origin for Test {
+		router
+	}
 }
 
 impl_outer_dispatch! {
 	pub enum Call for Test where origin: Origin {
+		system::System,
 		initializer::Initializer,
 	}
 }
 
+impl_outer_event! {
+	pub enum TestEvent for Test {
+		system<T>,
+		balances<T>,
+		configuration,
+		paras<T>,
+		scheduler<T>,
+		inclusion<T>,
+	}
+}
+
 pub struct TestRandomness;
 
 impl RandomnessT<H256> for TestRandomness {
@@ -52,11 +69,60 @@ impl RandomnessT<H256> for TestRandomness {
 	}
 }
 
+/// Identifies validators by their parachain keys.
+pub struct TestKeyOwnerProofSystem;
+
+impl KeyOwnerProofSystem<(KeyTypeId, ValidatorId)> for TestKeyOwnerProofSystem {
+	type Proof = ValidatorId;
+	type IdentificationTuple = ValidatorId;
+
+	fn prove(key: (KeyTypeId, ValidatorId)) -> Option<Self::Proof> {
+		Some(key.1)
+	}
+
+	fn check_proof(key: (KeyTypeId, ValidatorId), proof: Self::Proof) -> Option<ValidatorId> {
+		if key.1 == proof {
+			Some(proof)
+		} else {
+			None
+		}
+	}
+}
+
+thread_local! {
+	static OFFENCES: RefCell<Vec<(Vec<u64>, InvalidCandidateOffence<ValidatorId>)>>
+		= RefCell::new(Vec::new());
+}
+
+/// Records all reported offences, which can be retrieved with `reported_offences`.
+pub struct TestReportOffence;
+
+impl ReportOffence<u64, ValidatorId, InvalidCandidateOffence<ValidatorId>> for TestReportOffence {
+	fn report_offence(
+		reporters: Vec<u64>,
+		offence: InvalidCandidateOffence<ValidatorId>,
+	) -> Result<(), OffenceError> {
+		OFFENCES.with(|o| o.borrow_mut().push((reporters, offence)));
+		Ok(())
+	}
+
+	fn is_known_offence(_offenders: &[ValidatorId], _time_slot: &SessionIndex) -> bool {
+		false
+	}
+}
+
+/// All offences reported so far, along with their reporters.
+pub fn reported_offences() -> Vec<(Vec<u64>, InvalidCandidateOffence<ValidatorId>)> {
+	OFFENCES.with(|o| o.borrow().clone())
+}
+
 parameter_types! {
 	pub const BlockHashCount: u32 = 250;
 	pub const MaximumBlockWeight: Weight = 4 * 1024 * 1024;
 	pub const MaximumBlockLength: u32 = 4 * 1024 * 1024;
 	pub const AvailableBlockRatio: Perbill = Perbill::from_percent(75);
+	pub const ExistentialDeposit: u128 = 1;
+	pub const ParathreadDeposit: u128 = 10;
 }
 
 impl system::Trait for Test {
@@ -70,7 +136,7 @@ impl system::Trait for Test {
 	type AccountId = u64;
 	type Lookup = IdentityLookup<u64>;
 	type Header = Header;
-	type Event = ();
+	type Event = TestEvent;
 	type BlockHashCount = BlockHashCount;
 	type MaximumBlockWeight = MaximumBlockWeight;
 	type DbWeight = ();
@@ -87,20 +153,69 @@ impl system::Trait for Test {
 	type SystemWeightInfo = ();
 }
 
+impl balances::Trait for Test {
+	type Balance = u128;
+	type DustRemoval = ();
+	type Event = TestEvent;
+	type ExistentialDeposit = ExistentialDeposit;
+	type AccountStore = System;
+	type WeightInfo = ();
+}
+
 impl crate::initializer::Trait for Test {
 	type Randomness = TestRandomness;
+	type WeightInfo = ();
 }
 
-impl crate::configuration::Trait for Test { }
+impl crate::configuration::Trait for Test {
+	type Event = TestEvent;
+}
+
+impl crate::paras::Trait for Test {
+	type Event = TestEvent;
+	type WeightInfo = ();
+}
+
+impl crate::scheduler::Trait for Test {
+	type Event = TestEvent;
+	type Currency = Balances;
+	type WeightInfo = ();
+}
+
+impl crate::session_info::Trait for Test {
+	type AuthorityDiscovery = ();
+}
 
-impl crate::paras::Trait for Test { }
+impl crate::inclusion::Trait for Test {
+	type Event = TestEvent;
+}
 
-impl crate::scheduler::Trait for Test { }
+impl crate::inclusion_inherent::Trait for Test {
+	type WeightInfo = ();
+}
 
-impl crate::inclusion::Trait for Test { }
+impl crate::validity::Trait for Test {
+	type KeyOwnerProofSystem = TestKeyOwnerProofSystem;
+	type IdentificationTuple = ValidatorId;
+	type ReportOffence = TestReportOffence;
+}
+
+impl crate::router::Trait for Test {
+	type Origin = Origin;
+	type Call = Call;
+	type Currency = Balances;
+	type WeightInfo = ();
+}
+
+impl crate::registrar::Trait for Test {
+	type Currency = Balances;
+	type ParathreadDeposit = ParathreadDeposit;
+}
 
 pub type System = system::Module<Test>;
 
+pub type Balances = balances::Module<Test>;
+
 /// Mocked initializer.
 pub type Initializer = crate::initializer::Module<Test>;
 
@@ -113,14 +228,32 @@ pub type Paras = crate::paras::Module<Test>;
 /// Mocked scheduler.
 pub type Scheduler = crate::scheduler::Module<Test>;
 
+/// Mocked session info.
+pub type SessionInfo = crate::session_info::Module<Test>;
+
 /// Mocked inclusion module.
 pub type Inclusion = crate::inclusion::Module<Test>;
 
+/// Mocked validity module.
+pub type Validity = crate::validity::Module<Test>;
+
+/// Mocked router.
+pub type Router = crate::router::Module<Test>;
+
+/// Mocked registrar.
+pub type Registrar = crate::registrar::Module<Test>;
+
+/// All events deposited so far, in the order they were deposited.
+pub fn events() -> Vec<TestEvent> {
+	System::events().into_iter().map(|record| record.event).collect()
+}
+
 /// Create a new set of test externalities.
 pub fn new_test_ext(state: GenesisConfig) -> TestExternalities {
 	let mut t = state.system.build_storage::<Test>().unwrap();
 	state.configuration.assimilate_storage(&mut t).unwrap();
 	state.paras.assimilate_storage(&mut t).unwrap();
+	state.balances.assimilate_storage(&mut t).unwrap();
 
 	t.into()
 }
@@ -130,4 +263,5 @@ pub struct GenesisConfig {
 	pub system: system::GenesisConfig,
 	pub configuration: crate::configuration::GenesisConfig<Test>,
 	pub paras: crate::paras::GenesisConfig<Test>,
+	pub balances: balances::GenesisConfig<Test>,
 }
@@ -25,22 +25,87 @@
 
 use sp_std::prelude::*;
 use sp_std::marker::PhantomData;
-use sp_runtime::traits::One;
+use sp_runtime::{RuntimeDebug, traits::One};
 use primitives::v1::{
-	Id as ParaId, ValidationCode, HeadData,
+	Id as ParaId, ValidationCode, HeadData, Hash,
 };
 use frame_support::{
-	decl_storage, decl_module, decl_error,
+	decl_storage, decl_module, decl_event, decl_error, ensure,
+	dispatch::DispatchResult,
+	storage::migration::StorageIterator,
 	traits::Get,
-	weights::Weight,
+	weights::{DispatchClass, Weight, constants::RocksDbWeight},
 };
+use system::ensure_root;
 use codec::{Encode, Decode};
 use crate::{configuration, initializer::SessionChangeNotification};
 
 #[cfg(feature = "std")]
 use serde::{Serialize, Deserialize};
 
-pub trait Trait: system::Trait + configuration::Trait { }
+// A value placed in storage that represents the current version of the paras storage.
+// This value is used by `on_runtime_upgrade` to determine whether we run storage migration logic.
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, RuntimeDebug)]
+enum Releases {
+	V0,
+	V1,
+}
+
+impl Default for Releases {
+	fn default() -> Self {
+		Releases::V0
+	}
+}
+
+pub trait Trait: system::Trait + configuration::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+
+	/// The weights of the routines of this module, as generated by its benchmarks.
+	type WeightInfo: WeightInfo;
+}
+
+/// The weights of the routines of the paras module.
+pub trait WeightInfo {
+	/// The weight of `prune_old_code` pruning `p` pieces of past code.
+	fn prune_old_code(p: u32) -> Weight;
+	/// The weight of `force_set_current_code` with new code of `c` bytes.
+	fn force_set_current_code(c: u32) -> Weight;
+	/// The weight of `force_schedule_code_upgrade` with new code of `c` bytes.
+	fn force_schedule_code_upgrade(c: u32) -> Weight;
+	/// The weight of `force_queue_action` with an action of `a` bytes.
+	fn force_queue_action(a: u32) -> Weight;
+}
+
+/// Conservative weights for runtimes which haven't run the benchmarks of this module.
+impl WeightInfo for () {
+	fn prune_old_code(p: u32) -> Weight {
+		(10_000_000 as Weight)
+			.saturating_add((30_000_000 as Weight).saturating_mul(p as Weight))
+			.saturating_add(RocksDbWeight::get().reads(2 as Weight))
+			.saturating_add(RocksDbWeight::get().reads((3 as Weight).saturating_mul(p as Weight)))
+			.saturating_add(RocksDbWeight::get().writes(1 as Weight))
+			.saturating_add(RocksDbWeight::get().writes((4 as Weight).saturating_mul(p as Weight)))
+	}
+	fn force_set_current_code(c: u32) -> Weight {
+		(50_000_000 as Weight)
+			.saturating_add((3_000 as Weight).saturating_mul(c as Weight))
+			.saturating_add(RocksDbWeight::get().reads(11 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(11 as Weight))
+	}
+	fn force_schedule_code_upgrade(c: u32) -> Weight {
+		(40_000_000 as Weight)
+			.saturating_add((3_000 as Weight).saturating_mul(c as Weight))
+			.saturating_add(RocksDbWeight::get().reads(7 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(7 as Weight))
+	}
+	fn force_queue_action(a: u32) -> Weight {
+		(30_000_000 as Weight)
+			.saturating_add((1_000 as Weight).saturating_mul(a as Weight))
+			.saturating_add(RocksDbWeight::get().reads(4 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(2 as Weight))
+	}
+}
 
 // the two key times necessary to track for every code replacement.
 #[derive(Default, Encode, Decode)]
@@ -65,7 +130,7 @@ pub struct ParaPastCodeMeta<N> {
 	/// was actually replaced, respectively. The first is used to do accurate lookups
 	/// of historic code in historic contexts, whereas the second is used to do
 	/// pruning on an accurate timeframe. These can be used as indices
-	/// into the `PastCode` map along with the `ParaId` to fetch the code itself.
+	/// into the `PastCodeHash` map along with the `ParaId` to fetch the hash of the code itself.
 	upgrade_times: Vec<ReplacementTimes<N>>,
 	/// Tracks the highest pruned code-replacement, if any. This is the `expected_at` value,
 	/// not the `activated_at` value.
@@ -84,8 +149,18 @@ enum UseCodeAt<N> {
 
 impl<N: Ord + Copy> ParaPastCodeMeta<N> {
 	// note a replacement has occurred at a given block number.
+	//
+	// the upgrade times are kept sorted by `expected_at`, and a replacement noted again at the same
+	// block, e.g. by forcing the code of a para twice, only replaces the times noted before.
 	fn note_replacement(&mut self, expected_at: N, activated_at: N) {
-		self.upgrade_times.push(ReplacementTimes { expected_at, activated_at })
+		let idx = self.upgrade_times.iter()
+			.rposition(|t| t.expected_at <= expected_at)
+			.map_or(0, |i| i + 1);
+
+		match idx.checked_sub(1).and_then(|i| self.upgrade_times.get_mut(i)) {
+			Some(times) if times.expected_at == expected_at => times.activated_at = activated_at,
+			_ => self.upgrade_times.insert(idx, ReplacementTimes { expected_at, activated_at }),
+		}
 	}
 
 	// Yields an identifier that should be used for validating a
@@ -155,7 +230,7 @@ impl<N: Ord + Copy> ParaPastCodeMeta<N> {
 }
 
 /// Arguments for initializing a para.
-#[derive(Encode, Decode)]
+#[derive(PartialEq, Eq, Clone, Encode, Decode, RuntimeDebug)]
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub struct ParaGenesisArgs {
 	/// The initial head data to use.
@@ -166,6 +241,17 @@ pub struct ParaGenesisArgs {
 	pub parachain: bool,
 }
 
+/// An action on a para, applied at the start of the next session.
+#[derive(PartialEq, Eq, Clone, Encode, Decode, RuntimeDebug)]
+pub enum ParaAction {
+	/// Initialize the para with the given arguments, as by `schedule_para_initialize`.
+	Initialize(ParaGenesisArgs),
+	/// Clean up the para, as by `schedule_para_cleanup`.
+	Cleanup,
+	/// Swap the para with the given para, as by `schedule_para_swap`.
+	Swap(ParaId),
+}
+
 
 decl_storage! {
 	trait Store for Module<T: Trait> as Paras {
@@ -175,10 +261,11 @@ decl_storage! {
 		Parathreads: map hasher(twox_64_concat) ParaId => Option<()>;
 		/// The head-data of every registered para.
 		Heads get(fn para_head): map hasher(twox_64_concat) ParaId => Option<HeadData>;
-		/// The validation code of every live para.
-		CurrentCode get(fn current_code): map hasher(twox_64_concat) ParaId => Option<ValidationCode>;
-		/// Actual past code, indicated by the para id as well as the block number at which it became outdated.
-		PastCode: map hasher(twox_64_concat) (ParaId, T::BlockNumber) => Option<ValidationCode>;
+		/// The hash of the validation code of every live para.
+		CurrentCodeHash get(fn current_code_hash): map hasher(twox_64_concat) ParaId => Option<Hash>;
+		/// The hash of actual past code, indicated by the para id as well as the block number at which it
+		/// became outdated.
+		PastCodeHash: map hasher(twox_64_concat) (ParaId, T::BlockNumber) => Option<Hash>;
 		/// Past code of parachains. The parachains themselves may not be registered anymore,
 		/// but we also keep their code on-chain for the same amount of time as outdated code
 		/// to keep it available for secondary checkers.
@@ -195,8 +282,14 @@ decl_storage! {
 		/// The change will be applied after the first parablock for this ID included which executes
 		/// in the context of a relay chain block with a number >= `expected_at`.
 		FutureCodeUpgrades get(fn future_code_upgrade_at): map hasher(twox_64_concat) ParaId => Option<T::BlockNumber>;
-		/// The actual future code of a para.
-		FutureCode: map hasher(twox_64_concat) ParaId => Option<ValidationCode>;
+		/// The hash of the actual future code of a para.
+		FutureCodeHash: map hasher(twox_64_concat) ParaId => Option<Hash>;
+		/// Validation code stored by its hash. Code shared by several paras, or used by a para at
+		/// several times, is only stored once.
+		CodeByHash get(fn code_by_hash): map hasher(identity) Hash => Option<ValidationCode>;
+		/// The number of uses of the validation code with the given hash as the current, past or
+		/// future code of a para. The code is pruned once this reaches zero.
+		CodeByHashRefs: map hasher(identity) Hash => u32;
 
 		/// Upcoming paras (chains and threads). These are only updated on session change. Corresponds to an
 		/// entry in the upcoming-genesis map.
@@ -205,7 +298,14 @@ decl_storage! {
 		UpcomingParasGenesis: map hasher(twox_64_concat) ParaId => Option<ParaGenesisArgs>;
 		/// Paras that are to be cleaned up at the end of the session.
 		OutgoingParas: Vec<ParaId>;
-
+		/// Pairs of paras which are to swap being a parachain and being a parathread at the end of
+		/// the session.
+		UpcomingSwaps: Vec<(ParaId, ParaId)>;
+
+		/// Storage version of the module.
+		///
+		/// This is set to v1 for new networks.
+		StorageVersion build(|_: &GenesisConfig<T>| Releases::V1): Releases;
 	}
 	add_extra_genesis {
 		config(paras): Vec<(ParaId, ParaGenesisArgs)>;
@@ -229,19 +329,149 @@ fn build<T: Trait>(config: &GenesisConfig<T>) {
 	Parachains::put(&parachains);
 
 	for (id, genesis_args) in &config.paras {
-		<Module<T> as Store>::CurrentCode::insert(&id, &genesis_args.validation_code);
+		let code_hash = genesis_args.validation_code.hash();
+		<Module<T> as Store>::CurrentCodeHash::insert(&id, &code_hash);
+		<Module<T>>::increase_code_ref(&code_hash, &genesis_args.validation_code);
 		<Module<T> as Store>::Heads::insert(&id, &genesis_args.genesis_head);
 	}
 }
 
+decl_event! {
+	pub enum Event<T> where BlockNumber = <T as system::Trait>::BlockNumber {
+		/// A para was onboarded at the start of the session.
+		ParaOnboarded(ParaId),
+		/// A para was offboarded at the start of the session.
+		ParaOffboarded(ParaId),
+		/// A parachain and a parathread swapped their kinds at the start of the session.
+		/// The parachain is given first, followed by the parathread, as they are after the swap.
+		ParasSwapped(ParaId, ParaId),
+		/// A code upgrade of a para was scheduled, to be applied once a candidate of the para
+		/// executed in the context of a relay-chain block with the given number or later is included.
+		CodeUpgradeScheduled(ParaId, BlockNumber),
+		/// A scheduled code upgrade of a para was applied. The given block number is the one the
+		/// upgrade was expected at.
+		CodeUpgradeApplied(ParaId, BlockNumber),
+		/// A new head was noted for a para.
+		NewHeadNoted(ParaId),
+		/// The current code of a para was forcibly replaced.
+		CurrentCodeUpdated(ParaId),
+		/// The head of a para was forcibly replaced.
+		CurrentHeadUpdated(ParaId),
+		/// An action on a para was forcibly queued for the start of the next session.
+		ActionQueued(ParaId),
+	}
+}
+
 decl_error! {
-	pub enum Error for Module<T: Trait> { }
+	pub enum Error for Module<T: Trait> {
+		/// The para is not a live parachain or parathread.
+		NotLive,
+		/// The para is live or is to be initialized at the start of the next session.
+		ParaAlreadyExists,
+	}
 }
 
 decl_module! {
 	/// The parachains configuration module.
 	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
 		type Error = Error<T>;
+
+		fn deposit_event() = default;
+
+		/// Replace the current code of a live para. Must be called by root.
+		///
+		/// The replaced code is kept as past code for the acceptance period, as if the para
+		/// had upgraded in the context of the current block. A code upgrade scheduled for the para
+		/// is discarded.
+		#[weight = (
+			<T as Trait>::WeightInfo::force_set_current_code(new_code.0.len() as u32),
+			DispatchClass::Operational,
+		)]
+		pub fn force_set_current_code(origin, para: ParaId, new_code: ValidationCode) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::is_valid_para(para), Error::<T>::NotLive);
+
+			Self::discard_code_upgrade(para);
+
+			let new_code_hash = new_code.hash();
+			Self::increase_code_ref(&new_code_hash, &new_code);
+
+			if let Some(prior_code_hash) = <Self as Store>::CurrentCodeHash::get(&para) {
+				let now = <system::Module<T>>::block_number();
+				Self::note_past_code(para, now, now, prior_code_hash);
+			}
+			<Self as Store>::CurrentCodeHash::insert(&para, new_code_hash);
+
+			Self::deposit_event(RawEvent::CurrentCodeUpdated(para));
+			Ok(())
+		}
+
+		/// Replace the head of a live para. Must be called by root.
+		#[weight = (10_000_000, DispatchClass::Operational)]
+		pub fn force_set_current_head(origin, para: ParaId, new_head: HeadData) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::is_valid_para(para), Error::<T>::NotLive);
+
+			<Self as Store>::Heads::insert(&para, new_head);
+
+			Self::deposit_event(RawEvent::CurrentHeadUpdated(para));
+			Ok(())
+		}
+
+		/// Schedule a code upgrade of a live para, to be applied after inclusion of a block of the
+		/// para executed in the context of a relay-chain block with number >= `expected_at`.
+		/// Must be called by root.
+		///
+		/// Unlike upgrades signalled by the para, this replaces any upgrade which is already scheduled.
+		#[weight = (
+			<T as Trait>::WeightInfo::force_schedule_code_upgrade(new_code.0.len() as u32),
+			DispatchClass::Operational,
+		)]
+		pub fn force_schedule_code_upgrade(
+			origin,
+			para: ParaId,
+			new_code: ValidationCode,
+			expected_at: T::BlockNumber,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Self::is_valid_para(para), Error::<T>::NotLive);
+
+			Self::discard_code_upgrade(para);
+			Self::schedule_code_upgrade(para, new_code, expected_at);
+			Ok(())
+		}
+
+		/// Queue an action on a para for the start of the next session, without the checks
+		/// of the registrar. Must be called by root.
+		///
+		/// Initializations can only be queued for paras which are neither live nor upcoming,
+		/// cleanups and swaps only for live paras.
+		#[weight = (
+			<T as Trait>::WeightInfo::force_queue_action(action.encoded_size() as u32),
+			DispatchClass::Operational,
+		)]
+		pub fn force_queue_action(origin, para: ParaId, action: ParaAction) -> DispatchResult {
+			ensure_root(origin)?;
+
+			match action {
+				ParaAction::Initialize(genesis) => {
+					ensure!(!Self::is_valid_or_upcoming_para(para), Error::<T>::ParaAlreadyExists);
+					Self::schedule_para_initialize(para, genesis);
+				}
+				ParaAction::Cleanup => {
+					ensure!(Self::is_valid_para(para), Error::<T>::NotLive);
+					Self::schedule_para_cleanup(para);
+				}
+				ParaAction::Swap(other) => {
+					ensure!(Self::is_valid_para(para), Error::<T>::NotLive);
+					ensure!(Self::is_valid_para(other), Error::<T>::NotLive);
+					Self::schedule_para_swap(para, other);
+				}
+			}
+
+			Self::deposit_event(RawEvent::ActionQueued(para));
+			Ok(())
+		}
 	}
 }
 
@@ -254,20 +484,95 @@ impl<T: Trait> Module<T> {
 	/// Called by the initializer to finalize the configuration module.
 	pub(crate) fn initializer_finalize() { }
 
+	/// Migrate the current, past and future validation code of all paras from the maps keyed by
+	/// para, where each holds its own copy of the code, to the code stored by its hash.
+	pub(crate) fn migrate_to_v1() -> Weight {
+		if <Self as Store>::StorageVersion::get() != Releases::V0 {
+			return T::DbWeight::get().reads(1);
+		}
+
+		let migrated = [
+			Self::migrate_code_to_hashes(b"CurrentCode", |id: &ParaId, code_hash| {
+				<Self as Store>::CurrentCodeHash::insert(id, code_hash)
+			}),
+			Self::migrate_code_to_hashes(b"PastCode", |at: &(ParaId, T::BlockNumber), code_hash| {
+				<Self as Store>::PastCodeHash::insert(at, code_hash)
+			}),
+			Self::migrate_code_to_hashes(b"FutureCode", |id: &ParaId, code_hash| {
+				<Self as Store>::FutureCodeHash::insert(id, code_hash)
+			}),
+		];
+
+		<Self as Store>::StorageVersion::put(Releases::V1);
+
+		// on top of the storage version, which was read and written.
+		let (reads, writes) = migrated.iter()
+			.fold((1, 1), |(reads, writes), (r, w)| (reads + r, writes + w));
+
+		T::DbWeight::get().reads_writes(reads, writes)
+	}
+
+	// drain one of the old maps of validation code, noting the hash of each code with `note_hash`
+	// and storing the code by its hash. Returns the number of storage reads and writes.
+	fn migrate_code_to_hashes<K: Decode>(
+		item: &[u8],
+		note_hash: impl Fn(&K, &Hash),
+	) -> (Weight, Weight) {
+		let (mut reads, mut writes) = (0, 0);
+
+		for (key, code) in StorageIterator::<ValidationCode>::new(b"Paras", item).drain() {
+			// the old entry is read and removed.
+			reads += 1;
+			writes += 1;
+
+			// all of the old maps were hashed with `twox_64_concat`, so the encoded key follows the
+			// 8 bytes of the hash.
+			let key = match key.get(8..).and_then(|mut key| K::decode(&mut key).ok()) {
+				Some(key) => key,
+				None => continue,
+			};
+
+			let code_hash = code.hash();
+			note_hash(&key, &code_hash);
+			writes += 1;
+
+			let refs = <Self as Store>::CodeByHashRefs::mutate(&code_hash, |refs| {
+				*refs += 1;
+				*refs
+			});
+			reads += 1;
+			writes += 1;
+
+			if refs == 1 {
+				<Self as Store>::CodeByHash::insert(&code_hash, &code);
+				writes += 1;
+			}
+		}
+
+		(reads, writes)
+	}
+
 	/// Called by the initializer to note that a new session has started.
-	pub(crate) fn initializer_on_new_session(_notification: &SessionChangeNotification<T::BlockNumber>) {
+	///
+	/// Returns the paras which were cleaned up.
+	pub(crate) fn initializer_on_new_session(
+		_notification: &SessionChangeNotification<T::BlockNumber>,
+	) -> Vec<ParaId> {
 		let now = <system::Module<T>>::block_number();
-		let mut parachains = Self::clean_up_outgoing(now);
+		let outgoing = <Self as Store>::OutgoingParas::take();
+		let mut parachains = Self::clean_up_outgoing(now, &outgoing);
 		Self::apply_incoming(&mut parachains);
+		Self::apply_swaps(&mut parachains);
 		<Self as Store>::Parachains::set(parachains);
+
+		outgoing
 	}
 
 	/// Cleans up all outgoing paras. Returns the new set of parachains
-	fn clean_up_outgoing(now: T::BlockNumber) -> Vec<ParaId> {
+	fn clean_up_outgoing(now: T::BlockNumber, outgoing: &[ParaId]) -> Vec<ParaId> {
 		let mut parachains = <Self as Store>::Parachains::get();
-		let outgoing = <Self as Store>::OutgoingParas::take();
 
-		for outgoing_para in outgoing {
+		for &outgoing_para in outgoing {
 			if let Ok(i) = parachains.binary_search(&outgoing_para) {
 				parachains.remove(i);
 			} else {
@@ -275,13 +580,14 @@ impl<T: Trait> Module<T> {
 			}
 
 			<Self as Store>::Heads::remove(&outgoing_para);
-			<Self as Store>::FutureCodeUpgrades::remove(&outgoing_para);
-			<Self as Store>::FutureCode::remove(&outgoing_para);
+			Self::discard_code_upgrade(outgoing_para);
 
-			let removed_code = <Self as Store>::CurrentCode::take(&outgoing_para);
-			if let Some(removed_code) = removed_code {
-				Self::note_past_code(outgoing_para, now, now, removed_code);
+			let removed_code_hash = <Self as Store>::CurrentCodeHash::take(&outgoing_para);
+			if let Some(removed_code_hash) = removed_code_hash {
+				Self::note_past_code(outgoing_para, now, now, removed_code_hash);
 			}
+
+			Self::deposit_event(RawEvent::ParaOffboarded(outgoing_para));
 		}
 
 		parachains
@@ -304,13 +610,66 @@ impl<T: Trait> Module<T> {
 				<Self as Store>::Parathreads::insert(&upcoming_para, ());
 			}
 
+			let code_hash = genesis_data.validation_code.hash();
+			Self::increase_code_ref(&code_hash, &genesis_data.validation_code);
+
+			// a para which is still live is re-initialized, so its current code becomes past code
+			// and an upgrade scheduled for its prior code is discarded.
+			Self::discard_code_upgrade(upcoming_para);
+			if let Some(prior_code_hash) = <Self as Store>::CurrentCodeHash::get(&upcoming_para) {
+				let now = <system::Module<T>>::block_number();
+				Self::note_past_code(upcoming_para, now, now, prior_code_hash);
+			}
+
 			<Self as Store>::Heads::insert(&upcoming_para, genesis_data.genesis_head);
-			<Self as Store>::CurrentCode::insert(&upcoming_para, genesis_data.validation_code);
+			<Self as Store>::CurrentCodeHash::insert(&upcoming_para, code_hash);
+
+			Self::deposit_event(RawEvent::ParaOnboarded(upcoming_para));
+		}
+	}
+
+	/// Applies all upcoming swaps, updating the parachains list and the parathreads set.
+	///
+	/// Swaps of two parachains, two parathreads or paras which aren't live are ignored.
+	fn apply_swaps(parachains: &mut Vec<ParaId>) {
+		for (one, other) in <Self as Store>::UpcomingSwaps::take() {
+			let (chain, thread) = match (parachains.binary_search(&one), parachains.binary_search(&other)) {
+				(Ok(_), Err(_)) => (one, other),
+				(Err(_), Ok(_)) => (other, one),
+				_ => continue,
+			};
+
+			if !Self::is_parathread(thread) {
+				continue;
+			}
+
+			if let Ok(i) = parachains.binary_search(&chain) {
+				parachains.remove(i);
+			}
+			if let Err(i) = parachains.binary_search(&thread) {
+				parachains.insert(i, thread);
+			}
+
+			<Self as Store>::Parathreads::remove(&thread);
+			<Self as Store>::Parathreads::insert(&chain, ());
+
+			Self::deposit_event(RawEvent::ParasSwapped(thread, chain));
+		}
+	}
+
+	// discard the code upgrade scheduled for the para with the given `id`, if any.
+	fn discard_code_upgrade(id: ParaId) -> Weight {
+		<Self as Store>::FutureCodeUpgrades::remove(&id);
+		match <Self as Store>::FutureCodeHash::take(&id) {
+			Some(future_code_hash) => Self::decrease_code_ref(&future_code_hash)
+				+ T::DbWeight::get().reads_writes(1, 2),
+			None => T::DbWeight::get().reads_writes(1, 1),
 		}
 	}
 
 	// note replacement of the code of para with given `id`, which occured in the
-	// context of the given relay-chain block number. provide the replaced code.
+	// context of the given relay-chain block number. provide the hash of the replaced code,
+	// whose use as current code becomes a use as past code.
 	//
 	// `at` for para-triggered replacement is the block number of the relay-chain
 	// block in whose context the parablock was executed
@@ -319,24 +678,65 @@ impl<T: Trait> Module<T> {
 		id: ParaId,
 		at: T::BlockNumber,
 		now: T::BlockNumber,
-		old_code: ValidationCode,
+		old_code_hash: Hash,
 	) -> Weight {
 
 		<Self as Store>::PastCodeMeta::mutate(&id, |past_meta| {
 			past_meta.note_replacement(at, now);
 		});
 
-		<Self as Store>::PastCode::insert(&(id, at), old_code);
+		// if the code was replaced at the same block before, the code replaced now was never used
+		// to validate a parablock, so the code replaced first is kept.
+		if <Self as Store>::PastCodeHash::contains_key(&(id, at)) {
+			Self::decrease_code_ref(&old_code_hash);
+		} else {
+			<Self as Store>::PastCodeHash::insert(&(id, at), old_code_hash);
+		}
 
 		// Schedule pruning for this past-code to be removed as soon as it
-		// exits the slashing window.
+		// exits the slashing window, unless it is scheduled already.
 		<Self as Store>::PastCodePruning::mutate(|pruning| {
-			let insert_idx = pruning.binary_search_by_key(&at, |&(_, b)| b)
-				.unwrap_or_else(|idx| idx);
-			pruning.insert(insert_idx, (id, now));
+			let later = pruning.iter().rev().take_while(|&&(_, b)| b > now).count();
+			let insert_idx = pruning.len() - later;
+			let scheduled = pruning[..insert_idx].iter()
+				.rev()
+				.take_while(|&&(_, b)| b == now)
+				.any(|&(para, _)| para == id);
+
+			if !scheduled {
+				pruning.insert(insert_idx, (id, now));
+			}
+		});
+
+		T::DbWeight::get().reads_writes(3, 3)
+	}
+
+	// store the given code, unless it is stored already, and note one more use of it.
+	fn increase_code_ref(code_hash: &Hash, code: &ValidationCode) -> Weight {
+		let refs = <Self as Store>::CodeByHashRefs::mutate(code_hash, |refs| {
+			*refs += 1;
+			*refs
 		});
 
-		T::DbWeight::get().reads_writes(2, 3)
+		if refs == 1 {
+			<Self as Store>::CodeByHash::insert(code_hash, code);
+			T::DbWeight::get().reads_writes(1, 2)
+		} else {
+			T::DbWeight::get().reads_writes(1, 1)
+		}
+	}
+
+	// note one less use of the code with the given hash, pruning the code if it isn't used anymore.
+	fn decrease_code_ref(code_hash: &Hash) -> Weight {
+		let refs = <Self as Store>::CodeByHashRefs::get(code_hash);
+		if refs <= 1 {
+			<Self as Store>::CodeByHashRefs::remove(code_hash);
+			<Self as Store>::CodeByHash::remove(code_hash);
+			T::DbWeight::get().reads_writes(1, 2)
+		} else {
+			<Self as Store>::CodeByHashRefs::insert(code_hash, refs - 1);
+			T::DbWeight::get().reads_writes(1, 1)
+		}
 	}
 
 	// looks at old code metadata, compares them to the current acceptance window, and prunes those
@@ -365,7 +765,10 @@ impl<T: Trait> Module<T> {
 				for (para_id, _) in pruning_tasks_to_do {
 					let full_deactivate = <Self as Store>::PastCodeMeta::mutate(&para_id, |meta| {
 						for pruned_repl_at in meta.prune_up_to(pruning_height) {
-							<Self as Store>::PastCode::remove(&(para_id, pruned_repl_at));
+							let pruned_code_hash = <Self as Store>::PastCodeHash::take(&(para_id, pruned_repl_at));
+							if let Some(pruned_code_hash) = pruned_code_hash {
+								Self::decrease_code_ref(&pruned_code_hash);
+							}
 						}
 
 						meta.most_recent_change().is_none() && Self::para_head(&para_id).is_none()
@@ -378,16 +781,13 @@ impl<T: Trait> Module<T> {
 					}
 				}
 
-				pruning_tasks_done as u64
+				pruning_tasks_done as u32
 			});
 
-		// 1 read for the meta for each pruning task, 1 read for the config
-		// 2 writes: updating the meta and pruning the code
-		T::DbWeight::get().reads_writes(1 + pruning_tasks_done, 2 * pruning_tasks_done)
+		T::WeightInfo::prune_old_code(pruning_tasks_done)
 	}
 
 	/// Schedule a para to be initialized at the start of the next session.
-	#[allow(unused)]
 	pub(crate) fn schedule_para_initialize(id: ParaId, genesis: ParaGenesisArgs) -> Weight {
 		let dup = UpcomingParas::mutate(|v| {
 			match v.binary_search(&id) {
@@ -410,7 +810,6 @@ impl<T: Trait> Module<T> {
 	}
 
 	/// Schedule a para to be cleaned up at the start of the next session.
-	#[allow(unused)]
 	pub(crate) fn schedule_para_cleanup(id: ParaId) -> Weight {
 		OutgoingParas::mutate(|v| {
 			match v.binary_search(&id) {
@@ -423,6 +822,33 @@ impl<T: Trait> Module<T> {
 		})
 	}
 
+	/// Cancel the initialization of a para which was scheduled to be onboarded at the start of the
+	/// next session. Returns whether the para was upcoming.
+	pub(crate) fn cancel_para_initialize(id: ParaId) -> bool {
+		let upcoming = UpcomingParas::mutate(|v| {
+			match v.binary_search(&id) {
+				Ok(i) => {
+					v.remove(i);
+					true
+				}
+				Err(_) => false,
+			}
+		});
+
+		if upcoming {
+			UpcomingParasGenesis::remove(&id);
+		}
+
+		upcoming
+	}
+
+	/// Schedule a parachain and a parathread to swap their kinds at the start of the next session.
+	pub(crate) fn schedule_para_swap(one: ParaId, other: ParaId) -> Weight {
+		<Self as Store>::UpcomingSwaps::append((one, other));
+
+		T::DbWeight::get().reads_writes(0, 1)
+	}
+
 	/// Schedule a future code upgrade of the given parachain, to be applied after inclusion
 	/// of a block of the same parachain executed in the context of a relay-chain block
 	/// with number >= `expected_at`
@@ -438,8 +864,13 @@ impl<T: Trait> Module<T> {
 				T::DbWeight::get().reads_writes(1, 0)
 			} else {
 				*up = Some(expected_at);
-				FutureCode::insert(&id, new_code);
-				T::DbWeight::get().reads_writes(1, 2)
+
+				let new_code_hash = new_code.hash();
+				<Self as Store>::FutureCodeHash::insert(&id, &new_code_hash);
+				let weight = Self::increase_code_ref(&new_code_hash, &new_code);
+
+				Self::deposit_event(RawEvent::CodeUpgradeScheduled(id, expected_at));
+				weight + T::DbWeight::get().reads_writes(1, 2)
 			}
 		})
 	}
@@ -453,15 +884,18 @@ impl<T: Trait> Module<T> {
 		execution_context: T::BlockNumber,
 	) -> Weight {
 		Heads::insert(&id, new_head);
+		Self::deposit_event(RawEvent::NewHeadNoted(id));
 
 		if let Some(expected_at) = <Self as Store>::FutureCodeUpgrades::get(&id) {
 			if expected_at <= execution_context {
 				<Self as Store>::FutureCodeUpgrades::remove(&id);
 
 				// Both should always be `Some` in this case, since a code upgrade is scheduled.
-				let new_code = FutureCode::take(&id).unwrap_or_default();
-				let prior_code = CurrentCode::get(&id).unwrap_or_default();
-				CurrentCode::insert(&id, &new_code);
+				// The uses of the future and current code become uses of the current and past code,
+				// so no references change.
+				let new_code_hash = <Self as Store>::FutureCodeHash::take(&id).unwrap_or_default();
+				let prior_code_hash = <Self as Store>::CurrentCodeHash::get(&id).unwrap_or_default();
+				<Self as Store>::CurrentCodeHash::insert(&id, &new_code_hash);
 
 				// `now` is only used for registering pruning as part of `fn note_past_code`
 				let now = <system::Module<T>>::block_number();
@@ -470,9 +904,11 @@ impl<T: Trait> Module<T> {
 					id,
 					expected_at,
 					now,
-					prior_code,
+					prior_code_hash,
 				);
 
+				Self::deposit_event(RawEvent::CodeUpgradeApplied(id, expected_at));
+
 				// add 1 to writes due to heads update.
 				weight + T::DbWeight::get().reads_writes(3, 1 + 3)
 			} else {
@@ -490,12 +926,22 @@ impl<T: Trait> Module<T> {
 	///
 	/// `assume_intermediate`, if provided, must be before `at`. This will return `None` if the validation
 	/// code has been pruned.
-	#[allow(unused)]
 	pub(crate) fn validation_code_at(
 		id: ParaId,
 		at: T::BlockNumber,
 		assume_intermediate: Option<T::BlockNumber>,
 	) -> Option<ValidationCode> {
+		Self::validation_code_hash_at(id, at, assume_intermediate)
+			.and_then(|code_hash| Self::code_by_hash(&code_hash))
+	}
+
+	/// Fetches the hash of the validation code `validation_code_at` would return for the same
+	/// parameters, without loading the code itself.
+	pub(crate) fn validation_code_hash_at(
+		id: ParaId,
+		at: T::BlockNumber,
+		assume_intermediate: Option<T::BlockNumber>,
+	) -> Option<Hash> {
 		let now = <system::Module<T>>::block_number();
 		let config = <configuration::Module<T>>::config();
 
@@ -510,16 +956,27 @@ impl<T: Trait> Module<T> {
 		};
 
 		if upgrade_applied_intermediate {
-			FutureCode::get(&id)
+			<Self as Store>::FutureCodeHash::get(&id)
 		} else {
 			match Self::past_code_meta(&id).code_at(at) {
 				None => None,
-				Some(UseCodeAt::Current) => CurrentCode::get(&id),
-				Some(UseCodeAt::ReplacedAt(replaced)) => <Self as Store>::PastCode::get(&(id, replaced))
+				Some(UseCodeAt::Current) => Self::current_code_hash(&id),
+				Some(UseCodeAt::ReplacedAt(replaced)) => <Self as Store>::PastCodeHash::get(&(id, replaced))
 			}
 		}
 	}
 
+	/// Whether a para ID corresponds to any live parachain or parathread.
+	pub(crate) fn is_valid_para(id: ParaId) -> bool {
+		Self::parachains().binary_search(&id).is_ok() || Self::is_parathread(id)
+	}
+
+	/// Whether a para ID corresponds to any live para or to a para which is to be initialized at the
+	/// start of the next session.
+	pub(crate) fn is_valid_or_upcoming_para(id: ParaId) -> bool {
+		Self::is_valid_para(id) || <Self as Store>::UpcomingParas::get().binary_search(&id).is_ok()
+	}
+
 	/// Whether a para ID corresponds to any live parathread.
 	pub(crate) fn is_parathread(id: ParaId) -> bool {
 		Parathreads::get(&id).is_some()
@@ -542,9 +999,12 @@ impl<T: Trait> Module<T> {
 mod tests {
 	use super::*;
 	use primitives::v1::BlockNumber;
-	use frame_support::traits::{OnFinalize, OnInitialize};
+	use frame_support::{assert_noop, assert_ok, traits::{OnFinalize, OnInitialize}};
+	use sp_runtime::DispatchError;
 
-	use crate::mock::{new_test_ext, Paras, System, GenesisConfig as MockGenesisConfig};
+	use crate::mock::{
+		new_test_ext, events, Origin, Paras, System, Test, TestEvent, GenesisConfig as MockGenesisConfig,
+	};
 	use crate::configuration::HostConfiguration;
 
 	fn run_to_block(to: BlockNumber, new_session: Option<Vec<BlockNumber>>) {
@@ -567,6 +1027,18 @@ mod tests {
 		ReplacementTimes { expected_at, activated_at }
 	}
 
+	fn current_code(id: &ParaId) -> Option<ValidationCode> {
+		Paras::current_code_hash(id).and_then(|code_hash| Paras::code_by_hash(&code_hash))
+	}
+
+	fn past_code(id: &ParaId, at: BlockNumber) -> Option<ValidationCode> {
+		<Paras as Store>::PastCodeHash::get(&(*id, at)).and_then(|code_hash| Paras::code_by_hash(&code_hash))
+	}
+
+	fn future_code(id: &ParaId) -> Option<ValidationCode> {
+		<Paras as Store>::FutureCodeHash::get(id).and_then(|code_hash| Paras::code_by_hash(&code_hash))
+	}
+
 	#[test]
 	fn para_past_code_meta_gives_right_code() {
 		let mut past_code = ParaPastCodeMeta::default();
@@ -590,6 +1062,20 @@ mod tests {
 		assert_eq!(past_code.code_at(6), Some(UseCodeAt::ReplacedAt(10)));
 	}
 
+	#[test]
+	fn para_past_code_meta_keeps_upgrade_times_sorted() {
+		let mut past_code = ParaPastCodeMeta::default();
+		past_code.note_replacement(20u32, 22);
+		past_code.note_replacement(10, 12);
+		past_code.note_replacement(30, 30);
+		past_code.note_replacement(20, 23);
+
+		assert_eq!(
+			past_code.upgrade_times,
+			vec![upgrade_at(10, 12), upgrade_at(20, 23), upgrade_at(30, 30)],
+		);
+	}
+
 	#[test]
 	fn para_past_code_pruning_works_correctly() {
 		let mut past_code = ParaPastCodeMeta::default();
@@ -671,7 +1157,9 @@ mod tests {
 			let at_block: BlockNumber = 10;
 			let included_block: BlockNumber = 12;
 
-			<Paras as Store>::PastCode::insert(&(id, at_block), &ValidationCode(vec![1, 2, 3]));
+			let code = ValidationCode(vec![1, 2, 3]);
+			<Paras as Store>::PastCodeHash::insert(&(id, at_block), &code.hash());
+			Paras::increase_code_ref(&code.hash(), &code);
 			<Paras as Store>::PastCodePruning::put(&vec![(id, included_block)]);
 
 			{
@@ -681,14 +1169,14 @@ mod tests {
 			}
 
 			let pruned_at: BlockNumber = included_block + acceptance_period + 1;
-			assert_eq!(<Paras as Store>::PastCode::get(&(id, at_block)), Some(vec![1, 2, 3].into()));
+			assert_eq!(past_code(&id, at_block), Some(vec![1, 2, 3].into()));
 
 			run_to_block(pruned_at - 1, None);
-			assert_eq!(<Paras as Store>::PastCode::get(&(id, at_block)), Some(vec![1, 2, 3].into()));
+			assert_eq!(past_code(&id, at_block), Some(vec![1, 2, 3].into()));
 			assert_eq!(Paras::past_code_meta(&id).most_recent_change(), Some(at_block));
 
 			run_to_block(pruned_at, None);
-			assert!(<Paras as Store>::PastCode::get(&(id, at_block)).is_none());
+			assert!(past_code(&id, at_block).is_none());
 			assert!(Paras::past_code_meta(&id).most_recent_change().is_none());
 		});
 	}
@@ -759,8 +1247,8 @@ mod tests {
 			let id_a = ParaId::from(0u32);
 			let id_b = ParaId::from(1u32);
 
-			Paras::note_past_code(id_a, 10, 12, vec![1, 2, 3].into());
-			Paras::note_past_code(id_b, 20, 23, vec![4, 5, 6].into());
+			Paras::note_past_code(id_a, 10, 12, ValidationCode(vec![1, 2, 3]).hash());
+			Paras::note_past_code(id_b, 20, 23, ValidationCode(vec![4, 5, 6]).hash());
 
 			assert_eq!(<Paras as Store>::PastCodePruning::get(), vec![(id_a, 12), (id_b, 23)]);
 			assert_eq!(
@@ -811,7 +1299,7 @@ mod tests {
 			let new_code = ValidationCode(vec![4, 5, 6]);
 
 			run_to_block(2, None);
-			assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+			assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
 
 			let expected_at = {
 				// this parablock is in the context of block 1.
@@ -821,8 +1309,12 @@ mod tests {
 
 				assert!(Paras::past_code_meta(&para_id).most_recent_change().is_none());
 				assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(expected_at));
-				assert_eq!(<Paras as Store>::FutureCode::get(&para_id), Some(new_code.clone()));
-				assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+				assert_eq!(future_code(&para_id), Some(new_code.clone()));
+				assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
+				assert_eq!(events(), vec![
+					TestEvent::paras(RawEvent::CodeUpgradeScheduled(para_id, expected_at)),
+					TestEvent::paras(RawEvent::NewHeadNoted(para_id)),
+				]);
 
 				expected_at
 			};
@@ -836,8 +1328,8 @@ mod tests {
 
 				assert!(Paras::past_code_meta(&para_id).most_recent_change().is_none());
 				assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(expected_at));
-				assert_eq!(<Paras as Store>::FutureCode::get(&para_id), Some(new_code.clone()));
-				assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+				assert_eq!(future_code(&para_id), Some(new_code.clone()));
+				assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
 			}
 
 			run_to_block(expected_at + 1, None);
@@ -852,12 +1344,19 @@ mod tests {
 					Some(expected_at),
 				);
 				assert_eq!(
-					<Paras as Store>::PastCode::get(&(para_id, expected_at)),
+					past_code(&para_id, expected_at),
 					Some(vec![1, 2, 3,].into()),
 				);
 				assert!(<Paras as Store>::FutureCodeUpgrades::get(&para_id).is_none());
-				assert!(<Paras as Store>::FutureCode::get(&para_id).is_none());
-				assert_eq!(Paras::current_code(&para_id), Some(new_code));
+				assert!(future_code(&para_id).is_none());
+				assert_eq!(current_code(&para_id), Some(new_code));
+				assert_eq!(events(), vec![
+					TestEvent::paras(RawEvent::CodeUpgradeScheduled(para_id, expected_at)),
+					TestEvent::paras(RawEvent::NewHeadNoted(para_id)),
+					TestEvent::paras(RawEvent::NewHeadNoted(para_id)),
+					TestEvent::paras(RawEvent::NewHeadNoted(para_id)),
+					TestEvent::paras(RawEvent::CodeUpgradeApplied(para_id, expected_at)),
+				]);
 			}
 		});
 	}
@@ -893,7 +1392,7 @@ mod tests {
 			let new_code = ValidationCode(vec![4, 5, 6]);
 
 			run_to_block(2, None);
-			assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+			assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
 
 			let expected_at = {
 				// this parablock is in the context of block 1.
@@ -903,8 +1402,8 @@ mod tests {
 
 				assert!(Paras::past_code_meta(&para_id).most_recent_change().is_none());
 				assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(expected_at));
-				assert_eq!(<Paras as Store>::FutureCode::get(&para_id), Some(new_code.clone()));
-				assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+				assert_eq!(future_code(&para_id), Some(new_code.clone()));
+				assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
 
 				expected_at
 			};
@@ -921,12 +1420,12 @@ mod tests {
 					Some(expected_at),
 				);
 				assert_eq!(
-					<Paras as Store>::PastCode::get(&(para_id, expected_at)),
+					past_code(&para_id, expected_at),
 					Some(vec![1, 2, 3,].into()),
 				);
 				assert!(<Paras as Store>::FutureCodeUpgrades::get(&para_id).is_none());
-				assert!(<Paras as Store>::FutureCode::get(&para_id).is_none());
-				assert_eq!(Paras::current_code(&para_id), Some(new_code));
+				assert!(future_code(&para_id).is_none());
+				assert_eq!(current_code(&para_id), Some(new_code));
 			}
 		});
 	}
@@ -964,11 +1463,14 @@ mod tests {
 
 			Paras::schedule_code_upgrade(para_id, new_code.clone(), 8);
 			assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(8));
-			assert_eq!(<Paras as Store>::FutureCode::get(&para_id), Some(new_code.clone()));
+			assert_eq!(future_code(&para_id), Some(new_code.clone()));
 
 			Paras::schedule_code_upgrade(para_id, newer_code.clone(), 10);
 			assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(8));
-			assert_eq!(<Paras as Store>::FutureCode::get(&para_id), Some(new_code.clone()));
+			assert_eq!(future_code(&para_id), Some(new_code.clone()));
+
+			// only the first upgrade was scheduled.
+			assert_eq!(events(), vec![TestEvent::paras(RawEvent::CodeUpgradeScheduled(para_id, 8))]);
 		});
 	}
 
@@ -1001,7 +1503,7 @@ mod tests {
 			let new_code = ValidationCode(vec![4, 5, 6]);
 
 			run_to_block(2, None);
-			assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+			assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
 
 			let expected_at = {
 				// this parablock is in the context of block 1.
@@ -1011,8 +1513,8 @@ mod tests {
 
 				assert!(Paras::past_code_meta(&para_id).most_recent_change().is_none());
 				assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(expected_at));
-				assert_eq!(<Paras as Store>::FutureCode::get(&para_id), Some(new_code.clone()));
-				assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+				assert_eq!(future_code(&para_id), Some(new_code.clone()));
+				assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
 
 				expected_at
 			};
@@ -1026,8 +1528,8 @@ mod tests {
 
 				assert!(Paras::past_code_meta(&para_id).most_recent_change().is_none());
 				assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(expected_at));
-				assert_eq!(<Paras as Store>::FutureCode::get(&para_id), Some(new_code.clone()));
-				assert_eq!(Paras::current_code(&para_id), Some(vec![1, 2, 3].into()));
+				assert_eq!(future_code(&para_id), Some(new_code.clone()));
+				assert_eq!(current_code(&para_id), Some(vec![1, 2, 3].into()));
 
 				assert_eq!(<Paras as Store>::Heads::get(&para_id), Some(Default::default()));
 			}
@@ -1038,14 +1540,15 @@ mod tests {
 			// cleaning up the parachain should place the current parachain code
 			// into the past code buffer & schedule cleanup.
 			assert_eq!(Paras::past_code_meta(&para_id).most_recent_change(), Some(3));
-			assert_eq!(<Paras as Store>::PastCode::get(&(para_id, 3)), Some(vec![1, 2, 3].into()));
+			assert_eq!(past_code(&para_id, 3), Some(vec![1, 2, 3].into()));
 			assert_eq!(<Paras as Store>::PastCodePruning::get(), vec![(para_id, 3)]);
 
 			// any future upgrades haven't been used to validate yet, so those
 			// are cleaned up immediately.
 			assert!(<Paras as Store>::FutureCodeUpgrades::get(&para_id).is_none());
-			assert!(<Paras as Store>::FutureCode::get(&para_id).is_none());
-			assert!(Paras::current_code(&para_id).is_none());
+			assert!(future_code(&para_id).is_none());
+			assert!(current_code(&para_id).is_none());
+			assert_eq!(events().last(), Some(&TestEvent::paras(RawEvent::ParaOffboarded(para_id))));
 
 			// run to do the final cleanup
 			let cleaned_up_at = 3 + acceptance_period + 1;
@@ -1053,11 +1556,315 @@ mod tests {
 
 			// now the final cleanup: last past code cleaned up, and this triggers meta cleanup.
 			assert_eq!(Paras::past_code_meta(&para_id), Default::default());
-			assert!(<Paras as Store>::PastCode::get(&(para_id, 3)).is_none());
+			assert!(past_code(&para_id, 3).is_none());
 			assert!(<Paras as Store>::PastCodePruning::get().is_empty());
 		});
 	}
 
+	#[test]
+	fn code_is_stored_once_and_pruned_when_unused() {
+		let acceptance_period = 10;
+		let validation_upgrade_delay = 5;
+
+		let paras = vec![
+			(0u32.into(), ParaGenesisArgs {
+				parachain: true,
+				genesis_head: Default::default(),
+				validation_code: vec![1, 2, 3].into(),
+			}),
+			(1u32.into(), ParaGenesisArgs {
+				parachain: true,
+				genesis_head: Default::default(),
+				validation_code: vec![1, 2, 3].into(),
+			}),
+		];
+
+		let genesis_config = MockGenesisConfig {
+			paras: GenesisConfig { paras, ..Default::default() },
+			configuration: crate::configuration::GenesisConfig {
+				config: HostConfiguration {
+					acceptance_period,
+					validation_upgrade_delay,
+					..Default::default()
+				},
+				..Default::default()
+			},
+			..Default::default()
+		};
+
+		new_test_ext(genesis_config).execute_with(|| {
+			let id_a = ParaId::from(0);
+			let id_b = ParaId::from(1);
+			let old_code = ValidationCode(vec![1, 2, 3]);
+			let new_code = ValidationCode(vec![4, 5, 6]);
+			let refs = |code: &ValidationCode| <Paras as Store>::CodeByHashRefs::get(&code.hash());
+
+			// both paras use the same code, which is stored once.
+			assert_eq!(Paras::current_code_hash(&id_a), Some(old_code.hash()));
+			assert_eq!(Paras::current_code_hash(&id_b), Some(old_code.hash()));
+			assert_eq!(Paras::code_by_hash(&old_code.hash()), Some(old_code.clone()));
+			assert_eq!(refs(&old_code), 2);
+
+			run_to_block(2, None);
+			let expected_at = 1 + validation_upgrade_delay;
+			Paras::schedule_code_upgrade(id_a, new_code.clone(), expected_at);
+			assert_eq!(refs(&new_code), 1);
+
+			// the upgrade turns the current code of `a` into past code, which is still used.
+			run_to_block(expected_at + 1, None);
+			Paras::note_new_head(id_a, Default::default(), expected_at);
+			assert_eq!(current_code(&id_a), Some(new_code.clone()));
+			assert_eq!(past_code(&id_a, expected_at), Some(old_code.clone()));
+			assert_eq!(Paras::validation_code_hash_at(id_a, expected_at, None), Some(old_code.hash()));
+			assert_eq!(refs(&old_code), 2);
+			assert_eq!(refs(&new_code), 1);
+
+			// pruning the past code of `a` keeps the code, which is still the current code of `b`.
+			run_to_block(expected_at + 1 + acceptance_period + 1, None);
+			assert!(<Paras as Store>::PastCodeHash::get(&(id_a, expected_at)).is_none());
+			assert_eq!(current_code(&id_b), Some(old_code.clone()));
+			assert_eq!(refs(&old_code), 1);
+
+			// once `b` is offboarded and its past code pruned, the code isn't used anymore.
+			Paras::schedule_para_cleanup(id_b);
+			let offboarded_at = System::block_number() + 1;
+			run_to_block(offboarded_at, Some(vec![offboarded_at]));
+			assert_eq!(refs(&old_code), 1);
+
+			run_to_block(offboarded_at + acceptance_period + 1, None);
+			assert_eq!(refs(&old_code), 0);
+			assert!(Paras::code_by_hash(&old_code.hash()).is_none());
+			assert_eq!(Paras::code_by_hash(&new_code.hash()), Some(new_code.clone()));
+			assert_eq!(refs(&new_code), 1);
+		});
+	}
+
+	fn force_calls_genesis_config() -> MockGenesisConfig {
+		let paras = vec![
+			(0u32.into(), ParaGenesisArgs {
+				parachain: true,
+				genesis_head: Default::default(),
+				validation_code: vec![1, 2, 3].into(),
+			}),
+		];
+
+		MockGenesisConfig {
+			paras: GenesisConfig { paras, ..Default::default() },
+			configuration: crate::configuration::GenesisConfig {
+				config: HostConfiguration {
+					acceptance_period: 10,
+					..Default::default()
+				},
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn force_set_current_code_keeps_replaced_code_as_past_code() {
+		new_test_ext(force_calls_genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(0);
+			let old_code = ValidationCode(vec![1, 2, 3]);
+			let new_code = ValidationCode(vec![4, 5, 6]);
+
+			run_to_block(2, None);
+
+			assert_noop!(
+				Paras::force_set_current_code(Origin::signed(1), para_id, new_code.clone()),
+				DispatchError::BadOrigin,
+			);
+			assert_noop!(
+				Paras::force_set_current_code(Origin::root(), ParaId::from(1), new_code.clone()),
+				Error::<Test>::NotLive,
+			);
+
+			assert_ok!(Paras::force_set_current_code(Origin::root(), para_id, new_code.clone()));
+
+			assert_eq!(current_code(&para_id), Some(new_code.clone()));
+			assert_eq!(past_code(&para_id, 2), Some(old_code.clone()));
+			assert_eq!(Paras::past_code_meta(&para_id).most_recent_change(), Some(2));
+			assert_eq!(<Paras as Store>::PastCodePruning::get(), vec![(para_id, 2)]);
+			assert_eq!(events(), vec![TestEvent::paras(RawEvent::CurrentCodeUpdated(para_id))]);
+
+			// the replaced code is pruned after the acceptance period.
+			run_to_block(2 + 10 + 1, None);
+			assert!(past_code(&para_id, 2).is_none());
+			assert!(Paras::code_by_hash(&old_code.hash()).is_none());
+			assert_eq!(current_code(&para_id), Some(new_code));
+		});
+	}
+
+	#[test]
+	fn force_schedule_code_upgrade_replaces_scheduled_upgrade() {
+		new_test_ext(force_calls_genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(0);
+			let new_code = ValidationCode(vec![4, 5, 6]);
+			let newer_code = ValidationCode(vec![7, 8, 9]);
+
+			run_to_block(1, None);
+
+			Paras::schedule_code_upgrade(para_id, new_code.clone(), 8);
+			assert_ok!(Paras::force_schedule_code_upgrade(Origin::root(), para_id, newer_code.clone(), 10));
+
+			assert_eq!(<Paras as Store>::FutureCodeUpgrades::get(&para_id), Some(10));
+			assert_eq!(future_code(&para_id), Some(newer_code));
+			assert!(Paras::code_by_hash(&new_code.hash()).is_none());
+			assert!(Paras::past_code_meta(&para_id).most_recent_change().is_none());
+			assert_eq!(events(), vec![
+				TestEvent::paras(RawEvent::CodeUpgradeScheduled(para_id, 8)),
+				TestEvent::paras(RawEvent::CodeUpgradeScheduled(para_id, 10)),
+			]);
+		});
+	}
+
+	#[test]
+	fn force_set_current_code_discards_scheduled_upgrade() {
+		new_test_ext(force_calls_genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(0);
+			let upgrade_code = ValidationCode(vec![4, 5, 6]);
+			let new_code = ValidationCode(vec![7, 8, 9]);
+
+			run_to_block(1, None);
+			Paras::schedule_code_upgrade(para_id, upgrade_code.clone(), 8);
+
+			run_to_block(2, None);
+			assert_ok!(Paras::force_set_current_code(Origin::root(), para_id, new_code.clone()));
+
+			assert!(Paras::future_code_upgrade_at(&para_id).is_none());
+			assert!(future_code(&para_id).is_none());
+			assert!(Paras::code_by_hash(&upgrade_code.hash()).is_none());
+			assert_eq!(current_code(&para_id), Some(new_code));
+		});
+	}
+
+	#[test]
+	fn force_set_current_code_twice_in_a_block_keeps_first_replaced_code() {
+		new_test_ext(force_calls_genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(0);
+			let old_code = ValidationCode(vec![1, 2, 3]);
+			let unused_code = ValidationCode(vec![4, 5, 6]);
+			let new_code = ValidationCode(vec![7, 8, 9]);
+
+			run_to_block(2, None);
+
+			assert_ok!(Paras::force_set_current_code(Origin::root(), para_id, unused_code.clone()));
+			assert_ok!(Paras::force_set_current_code(Origin::root(), para_id, new_code.clone()));
+
+			assert_eq!(current_code(&para_id), Some(new_code));
+			assert_eq!(past_code(&para_id, 2), Some(old_code));
+			assert!(Paras::code_by_hash(&unused_code.hash()).is_none());
+			assert_eq!(
+				Paras::past_code_meta(&para_id),
+				ParaPastCodeMeta {
+					upgrade_times: vec![upgrade_at(2, 2)],
+					last_pruned: None,
+				},
+			);
+			assert_eq!(<Paras as Store>::PastCodePruning::get(), vec![(para_id, 2)]);
+		});
+	}
+
+	#[test]
+	fn force_queue_action_rejects_initializing_existing_paras() {
+		new_test_ext(force_calls_genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(0);
+			let new_para_id = ParaId::from(1);
+			let genesis = ParaGenesisArgs {
+				parachain: true,
+				genesis_head: vec![1].into(),
+				validation_code: vec![1].into(),
+			};
+
+			run_to_block(1, None);
+
+			// live.
+			assert_noop!(
+				Paras::force_queue_action(Origin::root(), para_id, ParaAction::Initialize(genesis.clone())),
+				Error::<Test>::ParaAlreadyExists,
+			);
+
+			// upcoming.
+			assert_ok!(Paras::force_queue_action(
+				Origin::root(),
+				new_para_id,
+				ParaAction::Initialize(genesis.clone()),
+			));
+			assert_noop!(
+				Paras::force_queue_action(Origin::root(), new_para_id, ParaAction::Initialize(genesis)),
+				Error::<Test>::ParaAlreadyExists,
+			);
+
+			run_to_block(2, Some(vec![2]));
+			assert_eq!(Paras::parachains(), vec![para_id, new_para_id]);
+			assert!(!Paras::is_parathread(para_id));
+		});
+	}
+
+	#[test]
+	fn reinitialized_para_discards_scheduled_upgrade() {
+		new_test_ext(force_calls_genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(0);
+			let old_code = ValidationCode(vec![1, 2, 3]);
+			let upgrade_code = ValidationCode(vec![4, 5, 6]);
+			let genesis_code = ValidationCode(vec![7, 8, 9]);
+
+			run_to_block(1, None);
+			Paras::schedule_code_upgrade(para_id, upgrade_code.clone(), 8);
+			Paras::schedule_para_initialize(para_id, ParaGenesisArgs {
+				parachain: true,
+				genesis_head: vec![1].into(),
+				validation_code: genesis_code.clone(),
+			});
+
+			run_to_block(2, Some(vec![2]));
+
+			assert!(Paras::future_code_upgrade_at(&para_id).is_none());
+			assert!(future_code(&para_id).is_none());
+			assert!(Paras::code_by_hash(&upgrade_code.hash()).is_none());
+			assert_eq!(current_code(&para_id), Some(genesis_code));
+			assert_eq!(past_code(&para_id, 2), Some(old_code));
+		});
+	}
+
+	#[test]
+	fn force_set_current_head_and_queue_action() {
+		new_test_ext(force_calls_genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(0);
+			let new_para_id = ParaId::from(1);
+
+			run_to_block(1, None);
+
+			assert_ok!(Paras::force_set_current_head(Origin::root(), para_id, vec![4, 5, 6].into()));
+			assert_eq!(Paras::para_head(&para_id), Some(vec![4, 5, 6].into()));
+
+			assert_noop!(
+				Paras::force_queue_action(Origin::root(), new_para_id, ParaAction::Cleanup),
+				Error::<Test>::NotLive,
+			);
+			assert_ok!(Paras::force_queue_action(Origin::root(), para_id, ParaAction::Cleanup));
+			assert_ok!(Paras::force_queue_action(
+				Origin::root(),
+				new_para_id,
+				ParaAction::Initialize(ParaGenesisArgs {
+					parachain: true,
+					genesis_head: vec![1].into(),
+					validation_code: vec![1].into(),
+				}),
+			));
+
+			assert_eq!(events(), vec![
+				TestEvent::paras(RawEvent::CurrentHeadUpdated(para_id)),
+				TestEvent::paras(RawEvent::ActionQueued(para_id)),
+				TestEvent::paras(RawEvent::ActionQueued(new_para_id)),
+			]);
+
+			run_to_block(2, Some(vec![2]));
+			assert_eq!(Paras::parachains(), vec![new_para_id]);
+			assert!(Paras::para_head(&para_id).is_none());
+		});
+	}
+
 	#[test]
 	fn para_incoming_at_session() {
 		new_test_ext(Default::default()).execute_with(|| {
@@ -1113,9 +1920,75 @@ mod tests {
 
 			assert!(<Paras as Store>::Parathreads::get(&a).is_some());
 
-			assert_eq!(Paras::current_code(&a), Some(vec![2].into()));
-			assert_eq!(Paras::current_code(&b), Some(vec![1].into()));
-			assert_eq!(Paras::current_code(&c), Some(vec![3].into()));
+			assert_eq!(current_code(&a), Some(vec![2].into()));
+			assert_eq!(current_code(&b), Some(vec![1].into()));
+			assert_eq!(current_code(&c), Some(vec![3].into()));
+
+			assert_eq!(events(), vec![
+				TestEvent::paras(RawEvent::ParaOnboarded(c)),
+				TestEvent::paras(RawEvent::ParaOnboarded(b)),
+				TestEvent::paras(RawEvent::ParaOnboarded(a)),
+			]);
+		})
+	}
+
+	#[test]
+	fn para_swaps_at_session() {
+		let a = ParaId::from(1);
+		let b = ParaId::from(2);
+		let c = ParaId::from(3);
+
+		let paras = vec![
+			(a, ParaGenesisArgs {
+				parachain: true,
+				genesis_head: Default::default(),
+				validation_code: vec![1].into(),
+			}),
+			(c, ParaGenesisArgs {
+				parachain: true,
+				genesis_head: Default::default(),
+				validation_code: vec![3].into(),
+			}),
+		];
+
+		let genesis_config = MockGenesisConfig {
+			paras: GenesisConfig { paras, ..Default::default() },
+			..Default::default()
+		};
+
+		new_test_ext(genesis_config).execute_with(|| {
+			Paras::schedule_para_initialize(
+				b,
+				ParaGenesisArgs {
+					parachain: false,
+					genesis_head: Default::default(),
+					validation_code: vec![2].into(),
+				},
+			);
+
+			run_to_block(2, Some(vec![2]));
+			assert!(Paras::is_parathread(b));
+
+			Paras::schedule_para_swap(a, b);
+			// two parachains can't swap kinds.
+			Paras::schedule_para_swap(a, c);
+
+			run_to_block(3, None);
+
+			assert_eq!(Paras::parachains(), vec![a, c]);
+			assert!(Paras::is_parathread(b));
+
+			run_to_block(4, Some(vec![4]));
+
+			assert_eq!(Paras::parachains(), vec![b, c]);
+			assert!(Paras::is_parathread(a));
+			assert!(!Paras::is_parathread(b));
+			assert!(<Paras as Store>::UpcomingSwaps::get().is_empty());
+
+			assert_eq!(events(), vec![
+				TestEvent::paras(RawEvent::ParaOnboarded(b)),
+				TestEvent::paras(RawEvent::ParasSwapped(b, a)),
+			]);
 		})
 	}
 
@@ -1233,4 +2106,172 @@ mod tests {
 			assert_eq!(Paras::validation_code_at(para_id, 3, None), Some(new_code.clone()));
 		});
 	}
+
+	#[test]
+	fn migrates_code_to_hashes() {
+		use frame_support::{
+			StorageHasher, Twox64Concat,
+			storage::migration::{get_storage_value, put_storage_value},
+		};
+
+		fn key<K: Encode>(k: &K) -> Vec<u8> {
+			Twox64Concat::hash(&k.encode())
+		}
+
+		new_test_ext(Default::default()).execute_with(|| {
+			let para_a = ParaId::from(111);
+			let para_b = ParaId::from(222);
+			let code_a = ValidationCode(vec![1, 2, 3]);
+			let code_b = ValidationCode(vec![4, 5, 6]);
+
+			put_storage_value(b"Paras", b"CurrentCode", &key(&para_a), code_a.clone());
+			put_storage_value(b"Paras", b"CurrentCode", &key(&para_b), code_a.clone());
+			put_storage_value(b"Paras", b"PastCode", &key(&(para_a, 5 as BlockNumber)), code_b.clone());
+			put_storage_value(b"Paras", b"FutureCode", &key(&para_b), code_b.clone());
+			<Paras as Store>::StorageVersion::put(Releases::V0);
+
+			Paras::migrate_to_v1();
+
+			assert_eq!(Paras::current_code_hash(&para_a), Some(code_a.hash()));
+			assert_eq!(Paras::current_code_hash(&para_b), Some(code_a.hash()));
+			assert_eq!(<Paras as Store>::PastCodeHash::get(&(para_a, 5)), Some(code_b.hash()));
+			assert_eq!(<Paras as Store>::FutureCodeHash::get(&para_b), Some(code_b.hash()));
+
+			assert_eq!(Paras::code_by_hash(&code_a.hash()), Some(code_a.clone()));
+			assert_eq!(Paras::code_by_hash(&code_b.hash()), Some(code_b.clone()));
+			assert_eq!(<Paras as Store>::CodeByHashRefs::get(&code_a.hash()), 2);
+			assert_eq!(<Paras as Store>::CodeByHashRefs::get(&code_b.hash()), 2);
+
+			assert!(get_storage_value::<ValidationCode>(b"Paras", b"CurrentCode", &key(&para_a)).is_none());
+			assert!(get_storage_value::<ValidationCode>(
+				b"Paras", b"PastCode", &key(&(para_a, 5 as BlockNumber)),
+			).is_none());
+			assert!(get_storage_value::<ValidationCode>(b"Paras", b"FutureCode", &key(&para_b)).is_none());
+			assert_eq!(<Paras as Store>::StorageVersion::get(), Releases::V1);
+		});
+	}
+}
+
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking {
+	use super::*;
+	use system as frame_system; // NOTE: required for the benchmarks! macro
+	use system::RawOrigin;
+	use frame_benchmarking::benchmarks;
+	use frame_support::IterableStorageMap;
+
+	// The most pieces of past code to prune in a single block.
+	const MAX_PRUNED: u32 = 1_000;
+	// The largest validation code.
+	const MAX_CODE_SIZE: u32 = 1024 * 1024;
+
+	// Onboard a parachain with the given ID.
+	fn onboard<T: Trait>(id: ParaId) {
+		Module::<T>::schedule_para_initialize(id, ParaGenesisArgs {
+			genesis_head: Default::default(),
+			validation_code: ValidationCode(vec![1; 32]),
+			parachain: true,
+		});
+		Module::<T>::initializer_on_new_session(&Default::default());
+	}
+
+	benchmarks! {
+		_ { }
+
+		// Benchmark `prune_old_code` with `p` pieces of past code leaving the acceptance period,
+		// none of which is used elsewhere.
+		prune_old_code {
+			let p in 0 .. MAX_PRUNED;
+
+			let replaced_at: T::BlockNumber = One::one();
+			for i in 0 .. p {
+				let mut code = vec![0; 1024];
+				code[..4].copy_from_slice(&i.to_le_bytes());
+				let code = ValidationCode(code);
+
+				Module::<T>::increase_code_ref(&code.hash(), &code);
+				Module::<T>::note_past_code(ParaId::from(i), replaced_at, replaced_at, code.hash());
+			}
+
+			let now = <configuration::Module<T>>::config().acceptance_period + replaced_at + One::one();
+		}: {
+			Module::<T>::prune_old_code(now);
+		}
+		verify {
+			assert!(<Module<T> as Store>::PastCodePruning::get().is_empty());
+			assert_eq!(<Module<T> as Store>::CodeByHash::iter().count(), 0);
+		}
+
+		// Benchmark `force_set_current_code` with new code of `c` bytes, which discards an upgrade
+		// scheduled for the para.
+		force_set_current_code {
+			let c in 0 .. MAX_CODE_SIZE;
+
+			let para = ParaId::from(0);
+			onboard::<T>(para);
+			Module::<T>::schedule_code_upgrade(para, ValidationCode(vec![2; 32]), 10u32.into());
+
+			let new_code = ValidationCode(vec![0; c as usize]);
+			let new_code_hash = new_code.hash();
+		}: _(RawOrigin::Root, para, new_code)
+		verify {
+			assert_eq!(Module::<T>::current_code_hash(&para), Some(new_code_hash));
+			assert!(Module::<T>::future_code_upgrade_at(&para).is_none());
+		}
+
+		// Benchmark `force_schedule_code_upgrade` with new code of `c` bytes, which replaces an
+		// upgrade scheduled for the para.
+		force_schedule_code_upgrade {
+			let c in 0 .. MAX_CODE_SIZE;
+
+			let para = ParaId::from(0);
+			onboard::<T>(para);
+			Module::<T>::schedule_code_upgrade(para, ValidationCode(vec![2; 32]), 10u32.into());
+
+			let new_code = ValidationCode(vec![0; c as usize]);
+			let new_code_hash = new_code.hash();
+		}: _(RawOrigin::Root, para, new_code, 20u32.into())
+		verify {
+			assert_eq!(<Module<T> as Store>::FutureCodeHash::get(&para), Some(new_code_hash));
+		}
+
+		// Benchmark `force_queue_action` initializing a para with validation code of `a` bytes,
+		// which is the largest of the actions.
+		force_queue_action {
+			let a in 0 .. MAX_CODE_SIZE;
+
+			let para = ParaId::from(a);
+			let action = ParaAction::Initialize(ParaGenesisArgs {
+				genesis_head: Default::default(),
+				validation_code: ValidationCode(vec![0; a as usize]),
+				parachain: true,
+			});
+		}: _(RawOrigin::Root, para, action)
+		verify {
+			assert!(<Module<T> as Store>::UpcomingParasGenesis::contains_key(&para));
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use crate::mock::{new_test_ext, Test};
+		use frame_support::assert_ok;
+
+		#[test]
+		fn test_benchmarks() {
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_prune_old_code::<Test>());
+			});
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_force_set_current_code::<Test>());
+			});
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_force_schedule_code_upgrade::<Test>());
+			});
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_force_queue_action::<Test>());
+			});
+		}
+	}
 }
@@ -0,0 +1,498 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The registrar module is responsible for registering and deregistering parachains and
+//! parathreads.
+//!
+//! Parachains are registered by root, while anyone can register a parathread by reserving a
+//! deposit. Registration, deregistration and swaps only take effect in the paras module at the
+//! start of the next session.
+
+use sp_std::prelude::*;
+use primitives::v1::{Id as ParaId, ValidationCode, HeadData, LOWEST_USER_ID};
+use frame_support::{
+	decl_storage, decl_module, decl_error, ensure,
+	dispatch::DispatchResult,
+	traits::{Currency, Get, ReservableCurrency},
+	weights::{DispatchClass, Weight},
+};
+use system::{ensure_root, ensure_signed};
+use crate::{
+	configuration,
+	paras::{self, ParaGenesisArgs},
+	router::{self, ensure_parachain},
+};
+
+type BalanceOf<T> = <<T as Trait>::Currency as Currency<<T as system::Trait>::AccountId>>::Balance;
+
+pub trait Trait: paras::Trait + router::Trait {
+	/// The system's currency for parathread deposits.
+	type Currency: ReservableCurrency<Self::AccountId>;
+
+	/// The deposit to be paid to register a parathread.
+	type ParathreadDeposit: Get<BalanceOf<Self>>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Registrar {
+		/// The lowest ParaId value which may be unused, for parathreads. Starts this high in order
+		/// to keep low numbers for system-level chains. Kept above the IDs of all parachains
+		/// registered by root.
+		NextFreeId: ParaId = LOWEST_USER_ID;
+		/// Pending swap operations.
+		PendingSwap: map hasher(twox_64_concat) ParaId => Option<ParaId>;
+		/// The accounts which have paid the deposit of a para, along with the amount reserved.
+		Debtors: map hasher(twox_64_concat) ParaId => Option<(T::AccountId, BalanceOf<T>)>;
+	}
+}
+
+decl_error! {
+	pub enum Error for Module<T: Trait> {
+		/// The para is already registered.
+		ParaAlreadyExists,
+		/// The para is not registered.
+		InvalidParaId,
+		/// The para is not a parathread.
+		InvalidThreadId,
+		/// The validation code is larger than `max_code_size`.
+		CodeTooLarge,
+		/// The head data is larger than `max_head_data_size`.
+		HeadDataTooLarge,
+		/// A swap must be between a live parachain and a live parathread.
+		CannotSwap,
+		/// All para IDs from `NextFreeId` on are taken.
+		NoFreeParaId,
+	}
+}
+
+decl_module! {
+	/// The registrar module.
+	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
+		type Error = Error<T>;
+
+		/// Register a parachain with the given ID. Must be called by root.
+		///
+		/// The parachain is onboarded at the start of the next session.
+		#[weight = (5_000_000_000, DispatchClass::Operational)]
+		pub fn register_para(
+			origin,
+			#[compact] id: ParaId,
+			validation_code: ValidationCode,
+			genesis_head: HeadData,
+		) -> DispatchResult {
+			ensure_root(origin)?;
+			Self::check_para(id, &validation_code, &genesis_head)?;
+
+			// keep parathreads from being assigned this ID.
+			if id >= NextFreeId::get() {
+				NextFreeId::put(ParaId::from(u32::from(id).saturating_add(1)));
+			}
+
+			<paras::Module<T>>::schedule_para_initialize(id, ParaGenesisArgs {
+				genesis_head,
+				validation_code,
+				parachain: true,
+			});
+
+			Ok(())
+		}
+
+		/// Deregister the para with the given ID, releasing its deposit, if any. Must be called
+		/// by root.
+		///
+		/// A live para is offboarded at the start of the next session, while a para which is yet
+		/// to be onboarded won't be.
+		#[weight = (10_000_000, DispatchClass::Operational)]
+		pub fn deregister_para(origin, #[compact] id: ParaId) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(<paras::Module<T>>::is_valid_or_upcoming_para(id), Error::<T>::InvalidParaId);
+
+			Self::deregister(id);
+
+			Ok(())
+		}
+
+		/// Register a parathread under a new ID, reserving `ParathreadDeposit` from the sender.
+		///
+		/// The parathread is onboarded at the start of the next session.
+		#[weight = 150_000_000
+			+ T::DbWeight::get().reads_writes(8, 5)
+			+ validation_code.0.len() as Weight * 10
+			+ genesis_head.0.len() as Weight * 10]
+		pub fn register_parathread(
+			origin,
+			validation_code: ValidationCode,
+			genesis_head: HeadData,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			let id = Self::next_free_id()?;
+			Self::check_para(id, &validation_code, &genesis_head)?;
+
+			let deposit = T::ParathreadDeposit::get();
+			<T as Trait>::Currency::reserve(&who, deposit)?;
+
+			NextFreeId::put(ParaId::from(u32::from(id).saturating_add(1)));
+			<Debtors<T>>::insert(id, (who, deposit));
+			<paras::Module<T>>::schedule_para_initialize(id, ParaGenesisArgs {
+				genesis_head,
+				validation_code,
+				parachain: false,
+			});
+
+			Ok(())
+		}
+
+		/// Deregister a parathread and release its deposit.
+		///
+		/// Must be sent from a `Parachain` origin which is currently a parathread. Ensure that
+		/// any funds you want emptied from the parathread's account are moved out beforehand.
+		#[weight = 100_000_000 + T::DbWeight::get().reads_writes(4, 4)]
+		pub fn deregister_parathread(origin) -> DispatchResult {
+			let id = ensure_parachain(<T as router::Trait>::Origin::from(origin))?;
+			ensure!(<paras::Module<T>>::is_parathread(id), Error::<T>::InvalidThreadId);
+
+			Self::deregister(id);
+
+			Ok(())
+		}
+
+		/// Swap a parachain with a parathread. The origin must be a `Parachain`.
+		///
+		/// The swap will happen only if there is already an opposite swap pending. If there is
+		/// not, the swap will be stored in the pending swaps map, ready for a later confirmatory
+		/// swap.
+		///
+		/// The `ParaId`s remain mapped to the same head data and code, but whether they're a
+		/// parachain or a parathread and their deposits are switched. The paras switch at the
+		/// start of the next session.
+		#[weight = 100_000_000 + T::DbWeight::get().reads_writes(6, 5)]
+		pub fn swap(origin, #[compact] other: ParaId) -> DispatchResult {
+			let id = ensure_parachain(<T as router::Trait>::Origin::from(origin))?;
+			ensure!(<paras::Module<T>>::is_valid_para(id), Error::<T>::InvalidParaId);
+			ensure!(<paras::Module<T>>::is_valid_para(other), Error::<T>::InvalidParaId);
+			ensure!(
+				<paras::Module<T>>::is_parathread(id) != <paras::Module<T>>::is_parathread(other),
+				Error::<T>::CannotSwap,
+			);
+
+			if PendingSwap::get(other) == Some(id) {
+				PendingSwap::remove(other);
+
+				<paras::Module<T>>::schedule_para_swap(id, other);
+				<Debtors<T>>::mutate(id, |i|
+					<Debtors<T>>::mutate(other, |j|
+						sp_std::mem::swap(i, j)
+					)
+				);
+			} else {
+				PendingSwap::insert(id, other);
+			}
+
+			Ok(())
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// The lowest ID from `NextFreeId` on which is neither live nor about to be onboarded, e.g.
+	/// because it was assigned to a para at genesis.
+	fn next_free_id() -> Result<ParaId, Error<T>> {
+		let mut id = NextFreeId::get();
+		while <paras::Module<T>>::is_valid_or_upcoming_para(id) {
+			id = u32::from(id).checked_add(1).ok_or(Error::<T>::NoFreeParaId)?.into();
+		}
+
+		Ok(id)
+	}
+
+	/// Ensure that a para can be registered under the given ID with the given code and head data.
+	fn check_para(
+		id: ParaId,
+		validation_code: &ValidationCode,
+		genesis_head: &HeadData,
+	) -> DispatchResult {
+		ensure!(
+			!<paras::Module<T>>::is_valid_or_upcoming_para(id),
+			Error::<T>::ParaAlreadyExists,
+		);
+
+		let config = <configuration::Module<T>>::config();
+		ensure!(
+			validation_code.0.len() <= config.max_code_size as usize,
+			Error::<T>::CodeTooLarge,
+		);
+		ensure!(
+			genesis_head.0.len() <= config.max_head_data_size as usize,
+			Error::<T>::HeadDataTooLarge,
+		);
+
+		Ok(())
+	}
+
+	/// Schedule the cleanup of a para, or cancel its onboarding if it isn't live yet, and release
+	/// its deposit, if any.
+	fn deregister(id: ParaId) {
+		if !<paras::Module<T>>::cancel_para_initialize(id) {
+			<paras::Module<T>>::schedule_para_cleanup(id);
+		}
+		PendingSwap::remove(id);
+
+		if let Some((debtor, deposit)) = <Debtors<T>>::take(id) {
+			let _ = <T as Trait>::Currency::unreserve(&debtor, deposit);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use primitives::v1::{Balance, BlockNumber};
+	use crate::mock::{
+		new_test_ext, Balances, GenesisConfig as MockGenesisConfig, Origin, Paras, Registrar, System,
+		Test, ParathreadDeposit,
+	};
+	use crate::configuration::HostConfiguration;
+	use crate::paras::ParaGenesisArgs;
+
+	fn run_to_session(n: BlockNumber) {
+		System::set_block_number(n);
+		Paras::initializer_on_new_session(&Default::default());
+	}
+
+	fn para_origin(id: ParaId) -> Origin {
+		router::Origin::Parachain(id).into()
+	}
+
+	fn genesis_config(balances: Vec<(u64, Balance)>) -> MockGenesisConfig {
+		MockGenesisConfig {
+			configuration: crate::configuration::GenesisConfig {
+				config: HostConfiguration {
+					max_code_size: 3,
+					max_head_data_size: 2,
+					..Default::default()
+				},
+				..Default::default()
+			},
+			balances: balances::GenesisConfig { balances },
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn register_and_deregister_parachain() {
+		new_test_ext(genesis_config(Vec::new())).execute_with(|| {
+			let id = ParaId::from(1);
+
+			assert!(Registrar::register_para(Origin::signed(1), id, vec![1].into(), vec![1].into()).is_err());
+			assert_eq!(
+				Registrar::register_para(Origin::root(), id, vec![1; 4].into(), vec![1].into()),
+				Err(Error::<Test>::CodeTooLarge.into()),
+			);
+			assert_eq!(
+				Registrar::register_para(Origin::root(), id, vec![1].into(), vec![1; 3].into()),
+				Err(Error::<Test>::HeadDataTooLarge.into()),
+			);
+
+			assert!(Registrar::register_para(Origin::root(), id, vec![1].into(), vec![2].into()).is_ok());
+			assert_eq!(
+				Registrar::register_para(Origin::root(), id, vec![1].into(), vec![2].into()),
+				Err(Error::<Test>::ParaAlreadyExists.into()),
+			);
+			assert!(Paras::parachains().is_empty());
+
+			run_to_session(1);
+			assert_eq!(Paras::parachains(), vec![id]);
+			assert_eq!(
+				Paras::current_code_hash(&id).and_then(|h| Paras::code_by_hash(&h)),
+				Some(vec![1].into()),
+			);
+			assert_eq!(Paras::para_head(&id), Some(vec![2].into()));
+
+			assert!(Registrar::deregister_para(Origin::root(), id).is_ok());
+			assert_eq!(Paras::parachains(), vec![id]);
+
+			run_to_session(2);
+			assert!(Paras::parachains().is_empty());
+			assert_eq!(
+				Registrar::deregister_para(Origin::root(), id),
+				Err(Error::<Test>::InvalidParaId.into()),
+			);
+		});
+	}
+
+	#[test]
+	fn register_and_deregister_parathread() {
+		new_test_ext(genesis_config(vec![(1, 100)])).execute_with(|| {
+			let deposit = ParathreadDeposit::get();
+
+			assert!(Registrar::register_parathread(Origin::signed(1), vec![1].into(), vec![1].into()).is_ok());
+			assert!(Registrar::register_parathread(Origin::signed(1), vec![2].into(), vec![2].into()).is_ok());
+			assert_eq!(Balances::reserved_balance(1), 2 * deposit);
+
+			// not enough free balance.
+			assert!(Registrar::register_parathread(Origin::signed(2), vec![3].into(), vec![3].into()).is_err());
+
+			let first = LOWEST_USER_ID;
+			let second = ParaId::from(u32::from(LOWEST_USER_ID) + 1);
+
+			run_to_session(1);
+			assert!(Paras::is_parathread(first));
+			assert!(Paras::is_parathread(second));
+			assert!(Paras::parachains().is_empty());
+
+			assert_eq!(
+				Registrar::deregister_parathread(para_origin(ParaId::from(1))),
+				Err(Error::<Test>::InvalidThreadId.into()),
+			);
+			assert!(Registrar::deregister_parathread(para_origin(first)).is_ok());
+			assert_eq!(Balances::reserved_balance(1), deposit);
+			assert_eq!(Balances::free_balance(1), 100 - deposit);
+
+			run_to_session(2);
+			assert!(!Paras::is_parathread(first));
+			assert!(Paras::is_parathread(second));
+		});
+	}
+
+	#[test]
+	fn deregister_upcoming_paras() {
+		new_test_ext(genesis_config(vec![(1, 100)])).execute_with(|| {
+			let chain = ParaId::from(1);
+			let thread = LOWEST_USER_ID;
+
+			assert!(Registrar::register_para(Origin::root(), chain, vec![1].into(), vec![1].into()).is_ok());
+			assert!(Registrar::register_parathread(Origin::signed(1), vec![2].into(), vec![2].into()).is_ok());
+			assert_eq!(Balances::reserved_balance(1), ParathreadDeposit::get());
+
+			assert!(Registrar::deregister_para(Origin::root(), chain).is_ok());
+			assert!(Registrar::deregister_para(Origin::root(), thread).is_ok());
+			assert_eq!(Balances::reserved_balance(1), 0);
+			assert!(!Paras::is_valid_or_upcoming_para(chain));
+			assert!(!Paras::is_valid_or_upcoming_para(thread));
+
+			run_to_session(1);
+			assert!(Paras::parachains().is_empty());
+			assert!(!Paras::is_parathread(thread));
+			assert_eq!(
+				Registrar::deregister_para(Origin::root(), chain),
+				Err(Error::<Test>::InvalidParaId.into()),
+			);
+		});
+	}
+
+	#[test]
+	fn para_ids_do_not_overflow() {
+		new_test_ext(genesis_config(vec![(1, 100)])).execute_with(|| {
+			let last = ParaId::from(u32::max_value());
+
+			assert!(Registrar::register_para(Origin::root(), last, vec![1].into(), vec![1].into()).is_ok());
+			assert_eq!(NextFreeId::get(), last);
+			assert_eq!(
+				Registrar::register_parathread(Origin::signed(1), vec![2].into(), vec![2].into()),
+				Err(Error::<Test>::NoFreeParaId.into()),
+			);
+			assert_eq!(Balances::reserved_balance(1), 0);
+		});
+	}
+
+	#[test]
+	fn parathread_ids_skip_taken_ids() {
+		let mut config = genesis_config(vec![(1, 100)]);
+		config.paras.paras = vec![(LOWEST_USER_ID, ParaGenesisArgs {
+			genesis_head: vec![1].into(),
+			validation_code: vec![1].into(),
+			parachain: true,
+		})];
+
+		new_test_ext(config).execute_with(|| {
+			// the genesis para is skipped.
+			assert!(Registrar::register_parathread(Origin::signed(1), vec![2].into(), vec![2].into()).is_ok());
+			let first_thread = LOWEST_USER_ID + 1;
+			assert_eq!(<Debtors<Test>>::get(first_thread), Some((1, ParathreadDeposit::get())));
+
+			// and so is the para registered by root.
+			let root_id = LOWEST_USER_ID + 2;
+			assert!(Registrar::register_para(Origin::root(), root_id, vec![1].into(), vec![1].into()).is_ok());
+			assert!(Registrar::register_parathread(Origin::signed(1), vec![3].into(), vec![3].into()).is_ok());
+			let second_thread = LOWEST_USER_ID + 3;
+			assert_eq!(<Debtors<Test>>::get(second_thread), Some((1, ParathreadDeposit::get())));
+
+			run_to_session(1);
+			assert!(Paras::is_parathread(first_thread));
+			assert!(Paras::is_parathread(second_thread));
+			assert_eq!(Paras::parachains(), vec![LOWEST_USER_ID, root_id]);
+		});
+	}
+
+	#[test]
+	fn swap_requires_parachain_and_parathread() {
+		new_test_ext(genesis_config(vec![(1, 100)])).execute_with(|| {
+			let chain_a = ParaId::from(1);
+			let chain_b = ParaId::from(2);
+
+			assert!(Registrar::register_para(Origin::root(), chain_a, vec![1].into(), vec![1].into()).is_ok());
+			assert!(Registrar::register_para(Origin::root(), chain_b, vec![1].into(), vec![1].into()).is_ok());
+			run_to_session(1);
+
+			assert_eq!(
+				Registrar::swap(para_origin(chain_a), chain_b),
+				Err(Error::<Test>::CannotSwap.into()),
+			);
+			assert_eq!(
+				Registrar::swap(para_origin(chain_a), ParaId::from(3)),
+				Err(Error::<Test>::InvalidParaId.into()),
+			);
+			assert_eq!(
+				Registrar::swap(para_origin(ParaId::from(3)), chain_a),
+				Err(Error::<Test>::InvalidParaId.into()),
+			);
+			assert_eq!(PendingSwap::get(chain_a), None);
+		});
+	}
+
+	#[test]
+	fn swap_parachain_and_parathread() {
+		new_test_ext(genesis_config(vec![(1, 100)])).execute_with(|| {
+			let chain = ParaId::from(1);
+			let thread = LOWEST_USER_ID;
+
+			assert!(Registrar::register_para(Origin::root(), chain, vec![1].into(), vec![1].into()).is_ok());
+			assert!(Registrar::register_parathread(Origin::signed(1), vec![2].into(), vec![2].into()).is_ok());
+			run_to_session(1);
+
+			assert!(Registrar::swap(para_origin(chain), thread).is_ok());
+			assert_eq!(PendingSwap::get(chain), Some(thread));
+
+			// one half of the swap does not trigger it.
+			run_to_session(2);
+			assert_eq!(Paras::parachains(), vec![chain]);
+
+			assert!(Registrar::swap(para_origin(thread), chain).is_ok());
+			assert_eq!(PendingSwap::get(chain), None);
+			assert_eq!(<Debtors<Test>>::get(chain), Some((1, ParathreadDeposit::get())));
+			assert_eq!(<Debtors<Test>>::get(thread), None);
+
+			run_to_session(3);
+			assert_eq!(Paras::parachains(), vec![thread]);
+			assert!(Paras::is_parathread(chain));
+
+			// the former parachain is now a parathread and holds the deposit.
+			assert!(Registrar::deregister_parathread(para_origin(chain)).is_ok());
+			assert_eq!(Balances::reserved_balance(1), 0);
+		});
+	}
+}
@@ -0,0 +1,987 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The router module is responsible for handling messaging.
+//!
+//! Upward messages of included candidates are queued per para and dispatched onto the relay chain
+//! in FIFO order, within a weight budget per block.
+//!
+//! Downward messages are queued per para until a candidate of that para acknowledges them as
+//! processed, at which point they are pruned from the front of the queue.
+//!
+//! Horizontal messages are passed between paras over HRMP channels. See the `hrmp` module.
+
+use sp_std::prelude::*;
+use sp_runtime::traits::{AccountIdConversion, BadOrigin, Dispatchable};
+use primitives::v1::{
+	Id as ParaId, Balance, BlockNumber, DownwardMessage, InboundHrmpMessage, ParachainDispatchOrigin,
+	Remark, UpwardMessage,
+};
+use frame_support::{
+	decl_storage, decl_module, decl_error, ensure, Parameter,
+	dispatch::DispatchResult,
+	traits::{Currency, ExistenceRequirement, Get, ReservableCurrency},
+	weights::{GetDispatchInfo, Weight, constants::RocksDbWeight},
+};
+use system::ensure_signed;
+use codec::{Encode, Decode};
+use crate::{
+	configuration::{self, HostConfiguration},
+	initializer::SessionChangeNotification,
+	paras,
+};
+
+mod hrmp;
+
+pub use hrmp::{HrmpChannel, HrmpChannelId, HrmpOpenChannelRequest};
+
+/// Origin for the router module.
+#[derive(PartialEq, Eq, Clone, Encode, Decode)]
+#[cfg_attr(feature = "std", derive(Debug))]
+pub enum Origin {
+	/// It comes from a parachain.
+	Parachain(ParaId),
+}
+
+/// Ensure that the origin `o` represents a parachain.
+/// Returns `Ok` with the parachain ID that effected the extrinsic or an `Err` otherwise.
+pub fn ensure_parachain<OuterOrigin>(o: OuterOrigin) -> Result<ParaId, BadOrigin>
+	where OuterOrigin: Into<Result<Origin, OuterOrigin>>
+{
+	match o.into() {
+		Ok(Origin::Parachain(id)) => Ok(id),
+		_ => Err(BadOrigin),
+	}
+}
+
+pub trait Trait: system::Trait + configuration::Trait + paras::Trait {
+	/// The outer origin type.
+	type Origin: From<Origin>
+		+ From<<Self as system::Trait>::Origin>
+		+ Into<Result<Origin, <Self as Trait>::Origin>>;
+
+	/// The outer call dispatch type. Upward messages are decoded as this.
+	type Call: Parameter + Dispatchable<Origin=<Self as Trait>::Origin> + GetDispatchInfo;
+
+	/// The currency used for transfers into paras and for HRMP channel deposits.
+	type Currency: ReservableCurrency<Self::AccountId, Balance = Balance>;
+
+	/// The weights of the calls of this module, as generated by its benchmarks.
+	type WeightInfo: WeightInfo;
+}
+
+/// The weights of the calls of the router module.
+pub trait WeightInfo {
+	/// The weight of `transfer_to_para`.
+	fn transfer_to_para() -> Weight;
+	/// The weight of `hrmp_init_open_channel`.
+	fn hrmp_init_open_channel() -> Weight;
+	/// The weight of `hrmp_accept_open_channel`.
+	fn hrmp_accept_open_channel() -> Weight;
+	/// The weight of `hrmp_close_channel`.
+	fn hrmp_close_channel() -> Weight;
+}
+
+/// Conservative weights for runtimes which haven't run the benchmarks of this module.
+///
+/// None of the calls reads or writes storage whose size depends on the number of messages or
+/// requests queued, so their weights are constant.
+impl WeightInfo for () {
+	fn transfer_to_para() -> Weight {
+		(100_000_000 as Weight)
+			.saturating_add(RocksDbWeight::get().reads(6 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(3 as Weight))
+	}
+	fn hrmp_init_open_channel() -> Weight {
+		(80_000_000 as Weight)
+			.saturating_add(RocksDbWeight::get().reads(9 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(4 as Weight))
+	}
+	fn hrmp_accept_open_channel() -> Weight {
+		(60_000_000 as Weight)
+			.saturating_add(RocksDbWeight::get().reads(5 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(3 as Weight))
+	}
+	fn hrmp_close_channel() -> Weight {
+		(40_000_000 as Weight)
+			.saturating_add(RocksDbWeight::get().reads(2 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(2 as Weight))
+	}
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as Router {
+		/// Messages ready to be dispatched onto the relay chain.
+		/// This is subject to `max_upward_queue_count` and
+		/// `watermark_upward_queue_size` from `HostConfiguration`.
+		RelayDispatchQueues get(fn relay_dispatch_queue): map hasher(twox_64_concat) ParaId => Vec<UpwardMessage>;
+		/// Size of the dispatch queues. Caches sizes of the queues in `RelayDispatchQueues`.
+		/// First item in the tuple is the count of messages and second
+		/// is the total length (in bytes) of the message payloads.
+		RelayDispatchQueueSize: map hasher(twox_64_concat) ParaId => (u32, u32);
+		/// The ordered list of `ParaId`s that have a `RelayDispatchQueues` entry.
+		NeedsDispatch: Vec<ParaId>;
+		/// The para whose queue is dispatched first in the next block, if any. Rotated so that a
+		/// para with a low ID can't take the whole dispatch budget of every block.
+		NextDispatchRoundStartWith: Option<ParaId>;
+		/// Messages waiting to be processed by a para. Subject to `max_downward_queue_count`
+		/// from `HostConfiguration`.
+		DownwardMessageQueues get(fn downward_messages):
+			map hasher(twox_64_concat) ParaId => Vec<DownwardMessage<T::AccountId>>;
+
+		/// Requests to open HRMP channels. A request becomes a channel at the session change
+		/// following its acceptance by the recipient.
+		HrmpOpenChannelRequests:
+			map hasher(twox_64_concat) HrmpChannelId => Option<HrmpOpenChannelRequest>;
+		/// The ordered list of `HrmpChannelId`s that have a `HrmpOpenChannelRequests` entry.
+		HrmpOpenChannelRequestsList: Vec<HrmpChannelId>;
+		/// The number of requests to open channels made by a para which are not channels yet.
+		HrmpOpenChannelRequestCount: map hasher(twox_64_concat) ParaId => u32;
+		/// The number of requests to open channels accepted by a para which are not channels yet.
+		HrmpAcceptedChannelRequestCount: map hasher(twox_64_concat) ParaId => u32;
+		/// Requests to close HRMP channels, applied at the next session change.
+		HrmpCloseChannelRequests: map hasher(twox_64_concat) HrmpChannelId => Option<()>;
+		/// The ordered list of `HrmpChannelId`s that have a `HrmpCloseChannelRequests` entry.
+		HrmpCloseChannelRequestsList: Vec<HrmpChannelId>;
+		/// The relay-chain block number up to which a para has processed its inbound HRMP messages.
+		HrmpWatermarks: map hasher(twox_64_concat) ParaId => Option<BlockNumber>;
+		/// The open HRMP channels.
+		HrmpChannels: map hasher(twox_64_concat) HrmpChannelId => Option<HrmpChannel>;
+		/// The senders of the inbound HRMP channels of a para, sorted ascending.
+		HrmpIngressChannelsIndex: map hasher(twox_64_concat) ParaId => Vec<ParaId>;
+		/// The recipients of the outbound HRMP channels of a para, sorted ascending.
+		HrmpEgressChannelsIndex: map hasher(twox_64_concat) ParaId => Vec<ParaId>;
+		/// The messages waiting in an HRMP channel, in the order they were sent.
+		HrmpChannelContents: map hasher(twox_64_concat) HrmpChannelId => Vec<InboundHrmpMessage>;
+	}
+}
+
+decl_error! {
+	pub enum Error for Module<T: Trait> {
+		/// The upward messages would exceed the limits of the para's queue.
+		QueueFull,
+		/// An upward message was sent with an origin the para is not allowed to use.
+		InvalidMessageOrigin,
+		/// The para's downward message queue is full.
+		DownwardMessageQueueFull,
+		/// A candidate claimed to have processed more downward messages than are queued.
+		InvalidProcessedDownwardMessages,
+		/// Funds were sent to a para which isn't live.
+		TransferToInvalidPara,
+		/// A para tried to open an HRMP channel to itself.
+		OpenHrmpChannelToSelf,
+		/// The recipient of the HRMP channel is not a valid para.
+		OpenHrmpChannelInvalidRecipient,
+		/// The proposed capacity of the HRMP channel is zero.
+		OpenHrmpChannelZeroCapacity,
+		/// The proposed capacity of the HRMP channel exceeds `hrmp_channel_max_capacity`.
+		OpenHrmpChannelCapacityExceedsLimit,
+		/// The proposed maximum message size of the HRMP channel is zero.
+		OpenHrmpChannelZeroMessageSize,
+		/// The proposed maximum message size exceeds `hrmp_channel_max_message_size`.
+		OpenHrmpChannelMessageSizeExceedsLimit,
+		/// The HRMP channel already exists.
+		OpenHrmpChannelAlreadyExists,
+		/// A request to open the HRMP channel already exists.
+		OpenHrmpChannelAlreadyRequested,
+		/// The sender already has the maximum number of outbound HRMP channels.
+		OpenHrmpChannelLimitExceeded,
+		/// There is no request to open the HRMP channel.
+		AcceptHrmpChannelDoesntExist,
+		/// The request to open the HRMP channel was already accepted.
+		AcceptHrmpChannelAlreadyConfirmed,
+		/// The recipient already has the maximum number of inbound HRMP channels.
+		AcceptHrmpChannelLimitExceeded,
+		/// Only the sender or the recipient of an HRMP channel may close it.
+		CloseHrmpChannelUnauthorized,
+		/// The HRMP channel to close doesn't exist.
+		CloseHrmpChannelDoesntExist,
+		/// A request to close the HRMP channel already exists.
+		CloseHrmpChannelAlreadyUnderway,
+		/// The HRMP watermark of a candidate is ahead of its relay-parent.
+		HrmpWatermarkAheadOfRelayParent,
+		/// The HRMP watermark of a candidate is behind the current watermark of the para.
+		HrmpWatermarkRegressed,
+		/// A candidate sent more than `hrmp_max_message_num_per_candidate` HRMP messages.
+		TooManyHorizontalMessages,
+		/// The HRMP messages of a candidate are not sorted by recipient.
+		HorizontalMessagesNotSorted,
+		/// A candidate sent an HRMP message over a channel which doesn't exist.
+		NoSuchHrmpChannel,
+		/// A candidate sent an HRMP message exceeding the channel's maximum message size.
+		HorizontalMessageTooLarge,
+		/// A candidate sent more HRMP messages than fit into the channel.
+		HrmpChannelFull,
+	}
+}
+
+decl_module! {
+	/// The router module.
+	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
+		type Error = Error<T>;
+
+		/// Transfer some funds of the sender into the account of a para and notify the para
+		/// with a downward message.
+		#[weight = <T as Trait>::WeightInfo::transfer_to_para()]
+		pub fn transfer_to_para(
+			origin,
+			to: ParaId,
+			amount: Balance,
+			remark: Remark,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			ensure!(<paras::Module<T>>::is_valid_para(to), Error::<T>::TransferToInvalidPara);
+
+			let config = <configuration::Module<T>>::config();
+			ensure!(
+				DownwardMessageQueues::<T>::decode_len(&to).unwrap_or(0)
+					< config.max_downward_queue_count as usize,
+				Error::<T>::DownwardMessageQueueFull,
+			);
+
+			T::Currency::transfer(&who, &to.into_account(), amount, ExistenceRequirement::AllowDeath)?;
+			Self::queue_downward_message(&config, to, DownwardMessage::TransferInto(who, amount, remark))
+		}
+
+		/// Request to open an HRMP channel from the origin para to the given recipient.
+		///
+		/// The sender deposit is reserved from the account of the origin para. The channel is
+		/// opened at a session change, once the recipient has accepted the request.
+		#[weight = <T as Trait>::WeightInfo::hrmp_init_open_channel()]
+		pub fn hrmp_init_open_channel(
+			origin,
+			recipient: ParaId,
+			proposed_max_capacity: u32,
+			proposed_max_message_size: u32,
+		) -> DispatchResult {
+			let origin = ensure_parachain(<T as Trait>::Origin::from(origin))?;
+			Self::init_open_channel(origin, recipient, proposed_max_capacity, proposed_max_message_size)
+		}
+
+		/// Accept a request to open an HRMP channel from the given sender to the origin para.
+		///
+		/// The recipient deposit is reserved from the account of the origin para.
+		#[weight = <T as Trait>::WeightInfo::hrmp_accept_open_channel()]
+		pub fn hrmp_accept_open_channel(origin, sender: ParaId) -> DispatchResult {
+			let origin = ensure_parachain(<T as Trait>::Origin::from(origin))?;
+			Self::accept_open_channel(origin, sender)
+		}
+
+		/// Request to close an HRMP channel which the origin para is the sender or recipient of.
+		///
+		/// The channel is closed and the deposits are released at the next session change.
+		#[weight = <T as Trait>::WeightInfo::hrmp_close_channel()]
+		pub fn hrmp_close_channel(origin, channel_id: HrmpChannelId) -> DispatchResult {
+			let origin = ensure_parachain(<T as Trait>::Origin::from(origin))?;
+			Self::close_channel(origin, channel_id)
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// Block initialization logic, called by initializer.
+	///
+	/// Upward messages queued in the previous block are dispatched here, so their weight
+	/// can be accounted for.
+	pub(crate) fn initializer_initialize(_now: T::BlockNumber) -> Weight {
+		let config = <configuration::Module<T>>::config();
+
+		// initial weight is config read.
+		T::DbWeight::get().reads_writes(1, 0) + Self::dispatch_upward_messages(&config)
+	}
+
+	/// Block finalization logic, called by initializer.
+	pub(crate) fn initializer_finalize() { }
+
+	/// Called by the initializer to note that a new session has started.
+	///
+	/// The message queues and channels of the outgoing paras are removed, and pending requests
+	/// to open or close HRMP channels are applied.
+	pub(crate) fn initializer_on_new_session(
+		notification: &SessionChangeNotification<T::BlockNumber>,
+		outgoing_paras: &[ParaId],
+	) {
+		Self::clean_up_outgoing(outgoing_paras);
+		Self::clean_up_outgoing_hrmp(outgoing_paras);
+		Self::process_hrmp_open_channel_requests(&notification.new_config);
+		Self::process_hrmp_close_channel_requests();
+	}
+
+	/// Remove the upward and downward message queues of the outgoing paras.
+	fn clean_up_outgoing(outgoing_paras: &[ParaId]) {
+		if outgoing_paras.is_empty() {
+			return;
+		}
+
+		for para in outgoing_paras {
+			RelayDispatchQueues::remove(para);
+			RelayDispatchQueueSize::remove(para);
+			DownwardMessageQueues::<T>::remove(para);
+		}
+
+		NeedsDispatch::mutate(|needs_dispatch| {
+			needs_dispatch.retain(|para| outgoing_paras.binary_search(para).is_err())
+		});
+	}
+
+	/// Ensure the upward messages of a candidate can be queued for the given para.
+	///
+	/// The queue may hold at most `max_upward_queue_count` messages whose payloads have a total size
+	/// of at most `watermark_upward_queue_size`, unless it holds a single message. Only system paras
+	/// may dispatch messages with the `Root` origin.
+	pub(crate) fn check_upward_messages(
+		config: &HostConfiguration<T::BlockNumber>,
+		para: ParaId,
+		upward_messages: &[UpwardMessage],
+	) -> DispatchResult {
+		if upward_messages.is_empty() {
+			return Ok(());
+		}
+
+		let (count, size) = RelayDispatchQueueSize::get(&para);
+		let new_count = count as usize + upward_messages.len();
+		let new_size = upward_messages.iter().fold(size as usize, |a, m| a + m.data.len());
+
+		ensure!(
+			new_count == 1 || (
+				new_count <= config.max_upward_queue_count as usize &&
+				new_size <= config.watermark_upward_queue_size as usize
+			),
+			Error::<T>::QueueFull,
+		);
+
+		if !para.is_system() {
+			ensure!(
+				upward_messages.iter().all(|m| m.origin != ParachainDispatchOrigin::Root),
+				Error::<T>::InvalidMessageOrigin,
+			);
+		}
+
+		Ok(())
+	}
+
+	/// Place the upward messages of an enacted candidate into the para's queue for later dispatch.
+	pub(crate) fn queue_upward_messages(para: ParaId, upward_messages: Vec<UpwardMessage>) -> Weight {
+		if upward_messages.is_empty() {
+			return 0;
+		}
+
+		RelayDispatchQueueSize::mutate(&para, |(count, size)| {
+			*count += upward_messages.len() as u32;
+			*size += upward_messages.iter().fold(0, |a, m| a + m.data.len()) as u32;
+		});
+
+		RelayDispatchQueues::mutate(&para, |queue| queue.extend(upward_messages));
+
+		NeedsDispatch::mutate(|needs_dispatch| {
+			if let Err(i) = needs_dispatch.binary_search(&para) {
+				needs_dispatch.insert(i, para);
+			}
+		});
+
+		T::DbWeight::get().reads_writes(3, 3)
+	}
+
+	/// Place a message at the back of the para's downward message queue.
+	///
+	/// Fails if the queue already holds `max_downward_queue_count` messages.
+	pub fn queue_downward_message(
+		config: &HostConfiguration<T::BlockNumber>,
+		para: ParaId,
+		message: DownwardMessage<T::AccountId>,
+	) -> DispatchResult {
+		ensure!(
+			DownwardMessageQueues::<T>::decode_len(&para).unwrap_or(0)
+				< config.max_downward_queue_count as usize,
+			Error::<T>::DownwardMessageQueueFull,
+		);
+
+		DownwardMessageQueues::<T>::append(&para, message);
+		Ok(())
+	}
+
+	/// Ensure a candidate of the given para does not claim to have processed more downward
+	/// messages than are currently queued.
+	pub(crate) fn check_processed_downward_messages(
+		para: ParaId,
+		processed_downward_messages: u32,
+	) -> DispatchResult {
+		let queue_len = DownwardMessageQueues::<T>::decode_len(&para).unwrap_or(0);
+
+		ensure!(
+			processed_downward_messages as usize <= queue_len,
+			Error::<T>::InvalidProcessedDownwardMessages,
+		);
+
+		Ok(())
+	}
+
+	/// Remove the given number of processed messages from the front of the para's downward
+	/// message queue.
+	pub(crate) fn prune_downward_messages(para: ParaId, processed_downward_messages: u32) -> Weight {
+		if processed_downward_messages == 0 {
+			return 0;
+		}
+
+		DownwardMessageQueues::<T>::mutate(&para, |queue| {
+			let processed = sp_std::cmp::min(processed_downward_messages as usize, queue.len());
+			queue.drain(..processed);
+		});
+
+		T::DbWeight::get().reads_writes(1, 1)
+	}
+
+	/// Dispatch queued upward messages in FIFO order, until the `max_upward_dispatch_weight`
+	/// budget is exhausted. A message which could never fit into the budget is dropped.
+	///
+	/// The paras are visited in ascending order, starting with `NextDispatchRoundStartWith` and
+	/// wrapping around. If the budget is exhausted, the next block starts with the para after the
+	/// last one visited.
+	///
+	/// Returns the weight used.
+	fn dispatch_upward_messages(config: &HostConfiguration<T::BlockNumber>) -> Weight {
+		let budget = config.max_upward_dispatch_weight;
+		let mut needs_dispatch = NeedsDispatch::get();
+		let start_with = NextDispatchRoundStartWith::take();
+
+		let mut weight = T::DbWeight::get().reads_writes(2, 2);
+		if needs_dispatch.is_empty() {
+			return weight;
+		}
+
+		let start = start_with.map_or(0, |start_with| {
+			match needs_dispatch.binary_search(&start_with) {
+				Ok(i) | Err(i) => i % needs_dispatch.len(),
+			}
+		});
+
+		let mut dispatched_weight: Weight = 0;
+		let mut drained_paras = Vec::new();
+		let mut next_start_with = None;
+
+		let len = needs_dispatch.len();
+		'paras:
+		for para in (0..len).map(|i| needs_dispatch[(start + i) % len]) {
+			let queue = RelayDispatchQueues::take(&para);
+			let (count, size) = RelayDispatchQueueSize::take(&para);
+			weight += T::DbWeight::get().reads_writes(2, 2);
+
+			let mut dispatched_count = 0;
+			let mut dispatched_size = 0;
+			let mut exhausted = false;
+
+			for UpwardMessage { origin, data } in &queue {
+				let call = <T as Trait>::Call::decode(&mut &data[..]).ok();
+				let call_weight = call.as_ref().map_or(0, |c| c.get_dispatch_info().weight);
+
+				if call_weight <= budget {
+					if dispatched_weight.saturating_add(call_weight) > budget {
+						exhausted = true;
+						break;
+					}
+
+					if let Some(call) = call {
+						Self::dispatch_upward_message(para, origin.clone(), call);
+						dispatched_weight += call_weight;
+					}
+				}
+
+				dispatched_count += 1;
+				dispatched_size += data.len() as u32;
+			}
+
+			if dispatched_count < queue.len() {
+				RelayDispatchQueues::insert(&para, &queue[dispatched_count..]);
+				RelayDispatchQueueSize::insert(
+					&para,
+					(count - dispatched_count as u32, size - dispatched_size),
+				);
+			} else {
+				drained_paras.push(para);
+			}
+
+			if exhausted {
+				next_start_with = needs_dispatch.iter()
+					.find(|&&p| p > para)
+					.or_else(|| needs_dispatch.first())
+					.copied();
+				break 'paras;
+			}
+		}
+
+		needs_dispatch.retain(|para| !drained_paras.contains(para));
+		NeedsDispatch::put(needs_dispatch);
+		if let Some(next_start_with) = next_start_with {
+			NextDispatchRoundStartWith::put(next_start_with);
+		}
+
+		weight + dispatched_weight
+	}
+
+	/// Dispatch a single upward message of the given para with the requested origin.
+	fn dispatch_upward_message(para: ParaId, origin: ParachainDispatchOrigin, call: <T as Trait>::Call) {
+		let origin: <T as Trait>::Origin = match origin {
+			ParachainDispatchOrigin::Signed => <T as Trait>::Origin::from(
+				<T as system::Trait>::Origin::from(system::RawOrigin::Signed(para.into_account()))
+			),
+			ParachainDispatchOrigin::Parachain => Origin::Parachain(para).into(),
+			ParachainDispatchOrigin::Root => <T as Trait>::Origin::from(
+				<T as system::Trait>::Origin::from(system::RawOrigin::Root)
+			),
+		};
+
+		// Not much to do with the result as it is. It's up to the para to ensure that the
+		// message makes sense.
+		let _ = call.dispatch(origin);
+	}
+}
+
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking {
+	use super::*;
+	use system as frame_system; // NOTE: required for the benchmarks! macro
+	use system::RawOrigin;
+	use frame_benchmarking::{benchmarks, account};
+	use crate::paras::ParaGenesisArgs;
+
+	// The balance every para and the caller start with.
+	const FUNDS: Balance = 1_000_000_000_000;
+
+	// Set a configuration which allows all of the calls and register the parachains `0 .. n`,
+	// funding their accounts.
+	fn set_up<T: Trait>(n: u32) -> Result<(), &'static str> {
+		configuration::Module::<T>::set_max_downward_queue_count(RawOrigin::Root.into(), 10)?;
+		configuration::Module::<T>::set_hrmp_sender_deposit(RawOrigin::Root.into(), 100)?;
+		configuration::Module::<T>::set_hrmp_recipient_deposit(RawOrigin::Root.into(), 100)?;
+		configuration::Module::<T>::set_hrmp_channel_max_capacity(RawOrigin::Root.into(), 8)?;
+		configuration::Module::<T>::set_hrmp_channel_max_message_size(RawOrigin::Root.into(), 1024)?;
+		configuration::Module::<T>::set_hrmp_max_parachain_inbound_channels(RawOrigin::Root.into(), 4)?;
+		configuration::Module::<T>::set_hrmp_max_parachain_outbound_channels(RawOrigin::Root.into(), 4)?;
+		configuration::Module::<T>::initializer_on_new_session(&[], &[]);
+
+		for i in 0 .. n {
+			let para = ParaId::from(i);
+			paras::Module::<T>::schedule_para_initialize(para, ParaGenesisArgs {
+				genesis_head: Default::default(),
+				validation_code: Default::default(),
+				parachain: true,
+			});
+			T::Currency::make_free_balance_be(&para.into_account(), FUNDS);
+		}
+		paras::Module::<T>::initializer_on_new_session(&Default::default());
+
+		Ok(())
+	}
+
+	// The HRMP calls take a parachain origin, which can't be built from a system origin here,
+	// so they are benchmarked through the functions they dispatch to.
+	benchmarks! {
+		_ { }
+
+		transfer_to_para {
+			set_up::<T>(1)?;
+			let to = ParaId::from(0);
+			let caller: T::AccountId = account("caller", 0, 0);
+			T::Currency::make_free_balance_be(&caller, FUNDS);
+		}: _(RawOrigin::Signed(caller), to, 1_000_000, [0; 32])
+		verify {
+			assert_eq!(DownwardMessageQueues::<T>::decode_len(&to), Some(1));
+		}
+
+		hrmp_init_open_channel {
+			set_up::<T>(2)?;
+			let channel_id = HrmpChannelId { sender: ParaId::from(0), recipient: ParaId::from(1) };
+		}: {
+			Module::<T>::init_open_channel(channel_id.sender, channel_id.recipient, 8, 1024)?;
+		}
+		verify {
+			assert!(<Module<T> as Store>::HrmpOpenChannelRequests::contains_key(&channel_id));
+		}
+
+		hrmp_accept_open_channel {
+			set_up::<T>(2)?;
+			let channel_id = HrmpChannelId { sender: ParaId::from(0), recipient: ParaId::from(1) };
+			Module::<T>::init_open_channel(channel_id.sender, channel_id.recipient, 8, 1024)?;
+		}: {
+			Module::<T>::accept_open_channel(channel_id.recipient, channel_id.sender)?;
+		}
+		verify {
+			assert!(
+				<Module<T> as Store>::HrmpOpenChannelRequests::get(&channel_id)
+					.map_or(false, |request| request.confirmed)
+			);
+		}
+
+		hrmp_close_channel {
+			set_up::<T>(2)?;
+			let channel_id = HrmpChannelId { sender: ParaId::from(0), recipient: ParaId::from(1) };
+			Module::<T>::init_open_channel(channel_id.sender, channel_id.recipient, 8, 1024)?;
+			Module::<T>::accept_open_channel(channel_id.recipient, channel_id.sender)?;
+			Module::<T>::process_hrmp_open_channel_requests(&<configuration::Module<T>>::config());
+		}: {
+			Module::<T>::close_channel(channel_id.sender, channel_id.clone())?;
+		}
+		verify {
+			assert!(<Module<T> as Store>::HrmpCloseChannelRequests::contains_key(&channel_id));
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use crate::mock::{new_test_ext, Test};
+		use frame_support::assert_ok;
+
+		#[test]
+		fn test_benchmarks() {
+			// each benchmark opens the same channel, so they can't share the storage.
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_transfer_to_para::<Test>());
+			});
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_hrmp_init_open_channel::<Test>());
+			});
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_hrmp_accept_open_channel::<Test>());
+			});
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_hrmp_close_channel::<Test>());
+			});
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use primitives::v1::ValidationCode;
+	use crate::mock::{
+		new_test_ext, Balances, Call, GenesisConfig as MockGenesisConfig, Origin, Router, Test,
+	};
+	use crate::paras::ParaGenesisArgs;
+
+	fn upward_message(origin: ParachainDispatchOrigin, call: Call) -> UpwardMessage {
+		UpwardMessage { origin, data: call.encode() }
+	}
+
+	fn set_storage_call(key: &[u8], value: &[u8]) -> Call {
+		Call::System(system::Call::set_storage(vec![(key.to_vec(), value.to_vec())]))
+	}
+
+	fn genesis_config(
+		max_upward_queue_count: u32,
+		watermark_upward_queue_size: u32,
+		max_upward_dispatch_weight: Weight,
+	) -> MockGenesisConfig {
+		MockGenesisConfig {
+			configuration: crate::configuration::GenesisConfig {
+				config: HostConfiguration {
+					max_upward_queue_count,
+					watermark_upward_queue_size,
+					max_upward_dispatch_weight,
+					..Default::default()
+				},
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	fn downward_genesis_config(
+		max_downward_queue_count: u32,
+		balances: Vec<(u64, u128)>,
+	) -> MockGenesisConfig {
+		MockGenesisConfig {
+			paras: crate::paras::GenesisConfig {
+				paras: vec![(ParaId::from(1000), ParaGenesisArgs {
+					genesis_head: Vec::new().into(),
+					validation_code: ValidationCode(Vec::new()),
+					parachain: true,
+				})],
+				..Default::default()
+			},
+			configuration: crate::configuration::GenesisConfig {
+				config: HostConfiguration {
+					max_downward_queue_count,
+					..Default::default()
+				},
+				..Default::default()
+			},
+			balances: balances::GenesisConfig { balances },
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn check_upward_messages_respects_queue_limits() {
+		new_test_ext(genesis_config(2, 10, 0)).execute_with(|| {
+			let config = <configuration::Module<Test>>::config();
+			let para = ParaId::from(1000);
+			let message = |len| UpwardMessage {
+				origin: ParachainDispatchOrigin::Parachain,
+				data: vec![0; len],
+			};
+
+			// a single message may exceed the watermark.
+			assert!(Router::check_upward_messages(&config, para, &[message(20)]).is_ok());
+			assert!(Router::check_upward_messages(&config, para, &[message(5), message(5)]).is_ok());
+			assert!(Router::check_upward_messages(&config, para, &[message(5), message(6)]).is_err());
+			assert!(
+				Router::check_upward_messages(&config, para, &[message(1), message(1), message(1)]).is_err()
+			);
+
+			Router::queue_upward_messages(para, vec![message(5)]);
+
+			assert!(Router::check_upward_messages(&config, para, &[message(5)]).is_ok());
+			assert!(Router::check_upward_messages(&config, para, &[message(6)]).is_err());
+			assert!(Router::check_upward_messages(&config, para, &[message(1), message(1)]).is_err());
+		});
+	}
+
+	#[test]
+	fn only_system_paras_may_use_root_origin() {
+		new_test_ext(genesis_config(10, 1000, 0)).execute_with(|| {
+			let config = <configuration::Module<Test>>::config();
+			let root_message = UpwardMessage {
+				origin: ParachainDispatchOrigin::Root,
+				data: vec![1, 2, 3],
+			};
+
+			assert!(Router::check_upward_messages(&config, ParaId::from(1), &[root_message.clone()]).is_ok());
+			assert!(Router::check_upward_messages(&config, ParaId::from(1000), &[root_message]).is_err());
+		});
+	}
+
+	#[test]
+	fn dispatch_uses_requested_origin() {
+		new_test_ext(genesis_config(10, 1000, Weight::max_value())).execute_with(|| {
+			// a `Parachain` origin cannot set storage, `Root` can.
+			Router::queue_upward_messages(ParaId::from(1), vec![
+				upward_message(ParachainDispatchOrigin::Parachain, set_storage_call(b"a", b"1")),
+				upward_message(ParachainDispatchOrigin::Root, set_storage_call(b"b", b"2")),
+			]);
+
+			Router::initializer_initialize(1);
+
+			assert!(sp_io::storage::get(b"a").is_none());
+			assert_eq!(sp_io::storage::get(b"b"), Some(b"2".to_vec()));
+			assert!(<Router as Store>::RelayDispatchQueues::get(&ParaId::from(1)).is_empty());
+			assert_eq!(<Router as Store>::RelayDispatchQueueSize::get(&ParaId::from(1)), (0, 0));
+			assert!(<Router as Store>::NeedsDispatch::get().is_empty());
+		});
+	}
+
+	#[test]
+	fn dispatch_respects_weight_budget() {
+		let call_weight = set_storage_call(b"a", b"1").get_dispatch_info().weight;
+
+		new_test_ext(genesis_config(10, 1000, call_weight * 2)).execute_with(|| {
+			let para_a = ParaId::from(1);
+			let para_b = ParaId::from(2);
+
+			let message = |key: &[u8]| upward_message(
+				ParachainDispatchOrigin::Root,
+				set_storage_call(key, b"1"),
+			);
+
+			Router::queue_upward_messages(para_b, vec![message(b"b1")]);
+			Router::queue_upward_messages(para_a, vec![message(b"a1"), message(b"a2"), message(b"a3")]);
+
+			assert_eq!(<Router as Store>::NeedsDispatch::get(), vec![para_a, para_b]);
+
+			Router::initializer_initialize(1);
+
+			assert!(sp_io::storage::get(b"a1").is_some());
+			assert!(sp_io::storage::get(b"a2").is_some());
+			assert!(sp_io::storage::get(b"a3").is_none());
+			assert!(sp_io::storage::get(b"b1").is_none());
+
+			assert_eq!(<Router as Store>::RelayDispatchQueues::get(&para_a), vec![message(b"a3")]);
+			assert_eq!(
+				<Router as Store>::RelayDispatchQueueSize::get(&para_a),
+				(1, message(b"a3").data.len() as u32),
+			);
+			assert_eq!(<Router as Store>::NeedsDispatch::get(), vec![para_a, para_b]);
+
+			Router::initializer_initialize(2);
+
+			assert!(sp_io::storage::get(b"a3").is_some());
+			assert!(sp_io::storage::get(b"b1").is_some());
+			assert!(<Router as Store>::NeedsDispatch::get().is_empty());
+		});
+	}
+
+	#[test]
+	fn dispatch_rotates_starting_para() {
+		let call_weight = set_storage_call(b"a", b"1").get_dispatch_info().weight;
+
+		new_test_ext(genesis_config(10, 1000, call_weight)).execute_with(|| {
+			let para_a = ParaId::from(1);
+			let para_b = ParaId::from(2);
+
+			let message = |key: &[u8]| upward_message(
+				ParachainDispatchOrigin::Root,
+				set_storage_call(key, b"1"),
+			);
+
+			Router::queue_upward_messages(para_a, vec![message(b"a1"), message(b"a2")]);
+			Router::queue_upward_messages(para_b, vec![message(b"b1")]);
+
+			Router::initializer_initialize(1);
+			assert!(sp_io::storage::get(b"a1").is_some());
+			assert!(sp_io::storage::get(b"b1").is_none());
+			assert_eq!(<Router as Store>::NextDispatchRoundStartWith::get(), Some(para_b));
+
+			// `para_b` goes first, even though `para_a` still has messages queued.
+			Router::initializer_initialize(2);
+			assert!(sp_io::storage::get(b"a2").is_none());
+			assert!(sp_io::storage::get(b"b1").is_some());
+			assert_eq!(<Router as Store>::NeedsDispatch::get(), vec![para_a]);
+
+			Router::initializer_initialize(3);
+			assert!(sp_io::storage::get(b"a2").is_some());
+			assert!(<Router as Store>::NeedsDispatch::get().is_empty());
+			assert!(<Router as Store>::NextDispatchRoundStartWith::get().is_none());
+		});
+	}
+
+	#[test]
+	fn outgoing_paras_queues_are_removed() {
+		new_test_ext(downward_genesis_config(10, vec![])).execute_with(|| {
+			let config = <configuration::Module<Test>>::config();
+			let para_a = ParaId::from(1);
+			let para_b = ParaId::from(2);
+
+			let message = UpwardMessage {
+				origin: ParachainDispatchOrigin::Parachain,
+				data: vec![1, 2, 3],
+			};
+
+			Router::queue_upward_messages(para_a, vec![message.clone()]);
+			Router::queue_upward_messages(para_b, vec![message.clone()]);
+			Router::queue_downward_message(&config, para_a, DownwardMessage::Opaque(vec![1])).unwrap();
+
+			let notification = crate::initializer::SessionChangeNotification {
+				new_config: config,
+				..Default::default()
+			};
+			Router::initializer_on_new_session(&notification, &[para_a]);
+
+			assert!(<Router as Store>::RelayDispatchQueues::get(&para_a).is_empty());
+			assert_eq!(<Router as Store>::RelayDispatchQueueSize::get(&para_a), (0, 0));
+			assert!(Router::downward_messages(&para_a).is_empty());
+			assert_eq!(<Router as Store>::NeedsDispatch::get(), vec![para_b]);
+			assert_eq!(<Router as Store>::RelayDispatchQueues::get(&para_b), vec![message]);
+		});
+	}
+
+	#[test]
+	fn messages_over_budget_are_dropped() {
+		let call_weight = set_storage_call(b"a", b"1").get_dispatch_info().weight;
+		assert!(call_weight > 0);
+
+		new_test_ext(genesis_config(10, 1000, call_weight - 1)).execute_with(|| {
+			let para = ParaId::from(1);
+
+			Router::queue_upward_messages(para, vec![
+				upward_message(ParachainDispatchOrigin::Root, set_storage_call(b"a", b"1")),
+			]);
+
+			Router::initializer_initialize(1);
+
+			assert!(sp_io::storage::get(b"a").is_none());
+			assert!(<Router as Store>::RelayDispatchQueues::get(&para).is_empty());
+			assert!(<Router as Store>::NeedsDispatch::get().is_empty());
+		});
+	}
+
+	#[test]
+	fn downward_message_queue_respects_limit() {
+		new_test_ext(downward_genesis_config(2, vec![])).execute_with(|| {
+			let config = <configuration::Module<Test>>::config();
+			let para = ParaId::from(1000);
+
+			assert!(Router::queue_downward_message(&config, para, DownwardMessage::Opaque(vec![1])).is_ok());
+			assert!(Router::queue_downward_message(&config, para, DownwardMessage::Opaque(vec![2])).is_ok());
+			assert_eq!(
+				Router::queue_downward_message(&config, para, DownwardMessage::Opaque(vec![3])),
+				Err(Error::<Test>::DownwardMessageQueueFull.into()),
+			);
+
+			assert_eq!(
+				Router::downward_messages(&para),
+				vec![DownwardMessage::Opaque(vec![1]), DownwardMessage::Opaque(vec![2])],
+			);
+		});
+	}
+
+	#[test]
+	fn processed_downward_messages_are_checked_and_pruned() {
+		new_test_ext(downward_genesis_config(10, vec![])).execute_with(|| {
+			let config = <configuration::Module<Test>>::config();
+			let para = ParaId::from(1000);
+
+			for i in 0..3 {
+				Router::queue_downward_message(&config, para, DownwardMessage::Opaque(vec![i])).unwrap();
+			}
+
+			assert!(Router::check_processed_downward_messages(para, 0).is_ok());
+			assert!(Router::check_processed_downward_messages(para, 3).is_ok());
+			assert_eq!(
+				Router::check_processed_downward_messages(para, 4),
+				Err(Error::<Test>::InvalidProcessedDownwardMessages.into()),
+			);
+			assert!(Router::check_processed_downward_messages(ParaId::from(1001), 1).is_err());
+
+			Router::prune_downward_messages(para, 2);
+			assert_eq!(Router::downward_messages(&para), vec![DownwardMessage::Opaque(vec![2])]);
+
+			Router::prune_downward_messages(para, 1);
+			assert!(Router::downward_messages(&para).is_empty());
+		});
+	}
+
+	#[test]
+	fn transfer_to_para_moves_funds_and_queues_message() {
+		new_test_ext(downward_genesis_config(1, vec![(1, 100)])).execute_with(|| {
+			let para = ParaId::from(1000);
+			let para_account: u64 = para.into_account();
+
+			assert!(Router::transfer_to_para(Origin::signed(1), para, 40, [1; 32]).is_ok());
+
+			assert_eq!(Balances::free_balance(1), 60);
+			assert_eq!(Balances::free_balance(para_account), 40);
+			assert_eq!(
+				Router::downward_messages(&para),
+				vec![DownwardMessage::TransferInto(1, 40, [1; 32])],
+			);
+
+			// the queue is full, so no funds are moved.
+			assert_eq!(
+				Router::transfer_to_para(Origin::signed(1), para, 10, [2; 32]),
+				Err(Error::<Test>::DownwardMessageQueueFull.into()),
+			);
+			assert_eq!(Balances::free_balance(1), 60);
+
+			// nor can funds be sent to a para which isn't live.
+			assert_eq!(
+				Router::transfer_to_para(Origin::signed(1), ParaId::from(1001), 10, [2; 32]),
+				Err(Error::<Test>::TransferToInvalidPara.into()),
+			);
+			assert_eq!(Balances::free_balance(1), 60);
+		});
+	}
+}
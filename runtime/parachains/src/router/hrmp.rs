@@ -0,0 +1,760 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Horizontal relay-routed message passing (HRMP).
+//!
+//! Paras request, accept and close unidirectional channels between each other, which take effect
+//! at session boundaries. Messages sent by a candidate are placed into the channels when the
+//! candidate is enacted and stay there until the recipient advances its watermark past the block
+//! they were sent at.
+
+use sp_std::prelude::*;
+use sp_runtime::{RuntimeDebug, traits::{AccountIdConversion, SaturatedConversion}};
+use primitives::v1::{Id as ParaId, Balance, BlockNumber, InboundHrmpMessage, OutboundHrmpMessage};
+use frame_support::{
+	ensure,
+	dispatch::DispatchResult,
+	traits::{Get, ReservableCurrency},
+	weights::Weight,
+};
+use codec::{Encode, Decode};
+use crate::{configuration::{self, HostConfiguration}, paras};
+use super::{Trait, Module, Store, Error};
+
+/// The identifier of a unidirectional HRMP channel.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Encode, Decode, RuntimeDebug)]
+pub struct HrmpChannelId {
+	/// The para which sends messages over the channel.
+	pub sender: ParaId,
+	/// The para which receives messages over the channel.
+	pub recipient: ParaId,
+}
+
+/// A request to open an HRMP channel.
+#[derive(Clone, PartialEq, Encode, Decode, RuntimeDebug)]
+pub struct HrmpOpenChannelRequest {
+	/// Whether the recipient has accepted the request.
+	pub confirmed: bool,
+	/// The number of session changes the request has been waiting for.
+	pub age: u32,
+	/// The deposit reserved from the sender.
+	pub sender_deposit: Balance,
+	/// The deposit reserved from the recipient, once it has accepted the request.
+	pub recipient_deposit: Balance,
+	/// The maximum number of messages allowed in the channel at once.
+	pub max_capacity: u32,
+	/// The maximum size of a message sent over the channel, in bytes.
+	pub max_message_size: u32,
+}
+
+/// An open HRMP channel.
+#[derive(Clone, PartialEq, Encode, Decode, RuntimeDebug)]
+pub struct HrmpChannel {
+	/// The deposit reserved from the sender.
+	pub sender_deposit: Balance,
+	/// The deposit reserved from the recipient.
+	pub recipient_deposit: Balance,
+	/// The maximum number of messages allowed in the channel at once.
+	pub max_capacity: u32,
+	/// The maximum size of a message sent over the channel, in bytes.
+	pub max_message_size: u32,
+	/// The number of messages currently in the channel.
+	pub msg_count: u32,
+}
+
+impl<T: Trait> Module<T> {
+	/// Request to open a channel from `origin` to `recipient`, reserving the sender deposit.
+	pub(crate) fn init_open_channel(
+		origin: ParaId,
+		recipient: ParaId,
+		proposed_max_capacity: u32,
+		proposed_max_message_size: u32,
+	) -> DispatchResult {
+		ensure!(origin != recipient, Error::<T>::OpenHrmpChannelToSelf);
+		ensure!(
+			<paras::Module<T>>::is_valid_para(recipient),
+			Error::<T>::OpenHrmpChannelInvalidRecipient,
+		);
+
+		let config = <configuration::Module<T>>::config();
+		ensure!(proposed_max_capacity > 0, Error::<T>::OpenHrmpChannelZeroCapacity);
+		ensure!(
+			proposed_max_capacity <= config.hrmp_channel_max_capacity,
+			Error::<T>::OpenHrmpChannelCapacityExceedsLimit,
+		);
+		ensure!(proposed_max_message_size > 0, Error::<T>::OpenHrmpChannelZeroMessageSize);
+		ensure!(
+			proposed_max_message_size <= config.hrmp_channel_max_message_size,
+			Error::<T>::OpenHrmpChannelMessageSizeExceedsLimit,
+		);
+
+		let channel_id = HrmpChannelId { sender: origin, recipient };
+		ensure!(
+			!<Self as Store>::HrmpChannels::contains_key(&channel_id),
+			Error::<T>::OpenHrmpChannelAlreadyExists,
+		);
+		ensure!(
+			!<Self as Store>::HrmpOpenChannelRequests::contains_key(&channel_id),
+			Error::<T>::OpenHrmpChannelAlreadyRequested,
+		);
+
+		let egress_count = <Self as Store>::HrmpEgressChannelsIndex::decode_len(&origin).unwrap_or(0);
+		let open_request_count = <Self as Store>::HrmpOpenChannelRequestCount::get(&origin);
+		ensure!(
+			egress_count + (open_request_count as usize)
+				< config.hrmp_max_parachain_outbound_channels as usize,
+			Error::<T>::OpenHrmpChannelLimitExceeded,
+		);
+
+		T::Currency::reserve(&origin.into_account(), config.hrmp_sender_deposit)?;
+
+		<Self as Store>::HrmpOpenChannelRequestCount::insert(&origin, open_request_count + 1);
+		<Self as Store>::HrmpOpenChannelRequests::insert(&channel_id, HrmpOpenChannelRequest {
+			confirmed: false,
+			age: 0,
+			sender_deposit: config.hrmp_sender_deposit,
+			recipient_deposit: 0,
+			max_capacity: proposed_max_capacity,
+			max_message_size: proposed_max_message_size,
+		});
+		<Self as Store>::HrmpOpenChannelRequestsList::append(channel_id);
+
+		Ok(())
+	}
+
+	/// Accept the request to open a channel from `sender` to `origin`, reserving the recipient
+	/// deposit.
+	pub(crate) fn accept_open_channel(origin: ParaId, sender: ParaId) -> DispatchResult {
+		let channel_id = HrmpChannelId { sender, recipient: origin };
+		let mut request = <Self as Store>::HrmpOpenChannelRequests::get(&channel_id)
+			.ok_or(Error::<T>::AcceptHrmpChannelDoesntExist)?;
+		ensure!(!request.confirmed, Error::<T>::AcceptHrmpChannelAlreadyConfirmed);
+
+		let config = <configuration::Module<T>>::config();
+		let ingress_count = <Self as Store>::HrmpIngressChannelsIndex::decode_len(&origin).unwrap_or(0);
+		let accepted_count = <Self as Store>::HrmpAcceptedChannelRequestCount::get(&origin);
+		ensure!(
+			ingress_count + (accepted_count as usize)
+				< config.hrmp_max_parachain_inbound_channels as usize,
+			Error::<T>::AcceptHrmpChannelLimitExceeded,
+		);
+
+		T::Currency::reserve(&origin.into_account(), config.hrmp_recipient_deposit)?;
+
+		request.confirmed = true;
+		request.recipient_deposit = config.hrmp_recipient_deposit;
+
+		<Self as Store>::HrmpAcceptedChannelRequestCount::insert(&origin, accepted_count + 1);
+		<Self as Store>::HrmpOpenChannelRequests::insert(&channel_id, request);
+
+		Ok(())
+	}
+
+	/// Request to close a channel which `origin` is the sender or recipient of.
+	pub(crate) fn close_channel(origin: ParaId, channel_id: HrmpChannelId) -> DispatchResult {
+		ensure!(
+			origin == channel_id.sender || origin == channel_id.recipient,
+			Error::<T>::CloseHrmpChannelUnauthorized,
+		);
+		ensure!(
+			<Self as Store>::HrmpChannels::contains_key(&channel_id),
+			Error::<T>::CloseHrmpChannelDoesntExist,
+		);
+		ensure!(
+			!<Self as Store>::HrmpCloseChannelRequests::contains_key(&channel_id),
+			Error::<T>::CloseHrmpChannelAlreadyUnderway,
+		);
+
+		<Self as Store>::HrmpCloseChannelRequests::insert(&channel_id, ());
+		<Self as Store>::HrmpCloseChannelRequestsList::append(channel_id);
+
+		Ok(())
+	}
+
+	/// Close all channels of the outgoing paras and remove their watermarks.
+	///
+	/// Requests involving outgoing paras are dropped in `process_hrmp_open_channel_requests`.
+	pub(super) fn clean_up_outgoing_hrmp(outgoing_paras: &[ParaId]) {
+		for &para in outgoing_paras {
+			let ingress = <Self as Store>::HrmpIngressChannelsIndex::get(&para)
+				.into_iter()
+				.map(|sender| HrmpChannelId { sender, recipient: para });
+			let egress = <Self as Store>::HrmpEgressChannelsIndex::get(&para)
+				.into_iter()
+				.map(|recipient| HrmpChannelId { sender: para, recipient });
+
+			for channel_id in ingress.chain(egress).collect::<Vec<_>>() {
+				Self::close_hrmp_channel(&channel_id);
+			}
+
+			<Self as Store>::HrmpIngressChannelsIndex::remove(&para);
+			<Self as Store>::HrmpEgressChannelsIndex::remove(&para);
+			<Self as Store>::HrmpWatermarks::remove(&para);
+		}
+	}
+
+	/// Open the channels of all accepted requests, and drop the requests which have expired or
+	/// involve paras which are no longer valid, releasing their deposits.
+	pub(super) fn process_hrmp_open_channel_requests(config: &HostConfiguration<T::BlockNumber>) {
+		let mut requests = <Self as Store>::HrmpOpenChannelRequestsList::get();
+
+		requests.retain(|channel_id| {
+			let mut request = match <Self as Store>::HrmpOpenChannelRequests::get(channel_id) {
+				Some(request) => request,
+				None => return false,
+			};
+
+			let paras_valid = <paras::Module<T>>::is_valid_para(channel_id.sender)
+				&& <paras::Module<T>>::is_valid_para(channel_id.recipient);
+
+			if request.confirmed && paras_valid {
+				<Self as Store>::HrmpChannels::insert(channel_id, HrmpChannel {
+					sender_deposit: request.sender_deposit,
+					recipient_deposit: request.recipient_deposit,
+					max_capacity: request.max_capacity,
+					max_message_size: request.max_message_size,
+					msg_count: 0,
+				});
+
+				<Self as Store>::HrmpIngressChannelsIndex::mutate(&channel_id.recipient, |senders| {
+					if let Err(i) = senders.binary_search(&channel_id.sender) {
+						senders.insert(i, channel_id.sender);
+					}
+				});
+				<Self as Store>::HrmpEgressChannelsIndex::mutate(&channel_id.sender, |recipients| {
+					if let Err(i) = recipients.binary_search(&channel_id.recipient) {
+						recipients.insert(i, channel_id.recipient);
+					}
+				});
+			} else if !paras_valid || (!request.confirmed && request.age >= config.hrmp_open_request_ttl) {
+				T::Currency::unreserve(&channel_id.sender.into_account(), request.sender_deposit);
+				if request.confirmed {
+					T::Currency::unreserve(&channel_id.recipient.into_account(), request.recipient_deposit);
+				}
+			} else {
+				request.age += 1;
+				<Self as Store>::HrmpOpenChannelRequests::insert(channel_id, request);
+				return true;
+			}
+
+			Self::decrement_open_request_count(channel_id.sender);
+			if request.confirmed {
+				Self::decrement_accepted_request_count(channel_id.recipient);
+			}
+			<Self as Store>::HrmpOpenChannelRequests::remove(channel_id);
+
+			false
+		});
+
+		<Self as Store>::HrmpOpenChannelRequestsList::put(requests);
+	}
+
+	/// Close the channels of all close requests.
+	pub(super) fn process_hrmp_close_channel_requests() {
+		for channel_id in <Self as Store>::HrmpCloseChannelRequestsList::take() {
+			<Self as Store>::HrmpCloseChannelRequests::remove(&channel_id);
+			Self::close_hrmp_channel(&channel_id);
+		}
+	}
+
+	/// Remove a channel along with its contents, releasing the deposits.
+	fn close_hrmp_channel(channel_id: &HrmpChannelId) {
+		let channel = match <Self as Store>::HrmpChannels::take(channel_id) {
+			Some(channel) => channel,
+			None => return,
+		};
+
+		T::Currency::unreserve(&channel_id.sender.into_account(), channel.sender_deposit);
+		T::Currency::unreserve(&channel_id.recipient.into_account(), channel.recipient_deposit);
+
+		<Self as Store>::HrmpChannelContents::remove(channel_id);
+		<Self as Store>::HrmpIngressChannelsIndex::mutate(&channel_id.recipient, |senders| {
+			if let Ok(i) = senders.binary_search(&channel_id.sender) {
+				senders.remove(i);
+			}
+		});
+		<Self as Store>::HrmpEgressChannelsIndex::mutate(&channel_id.sender, |recipients| {
+			if let Ok(i) = recipients.binary_search(&channel_id.recipient) {
+				recipients.remove(i);
+			}
+		});
+	}
+
+	fn decrement_open_request_count(sender: ParaId) {
+		let count = <Self as Store>::HrmpOpenChannelRequestCount::get(&sender).saturating_sub(1);
+		if count == 0 {
+			<Self as Store>::HrmpOpenChannelRequestCount::remove(&sender);
+		} else {
+			<Self as Store>::HrmpOpenChannelRequestCount::insert(&sender, count);
+		}
+	}
+
+	fn decrement_accepted_request_count(recipient: ParaId) {
+		let count = <Self as Store>::HrmpAcceptedChannelRequestCount::get(&recipient).saturating_sub(1);
+		if count == 0 {
+			<Self as Store>::HrmpAcceptedChannelRequestCount::remove(&recipient);
+		} else {
+			<Self as Store>::HrmpAcceptedChannelRequestCount::insert(&recipient, count);
+		}
+	}
+
+	/// Ensure the HRMP watermark of a candidate is neither ahead of its relay-parent nor behind
+	/// the current watermark of the para.
+	pub(crate) fn check_hrmp_watermark(
+		recipient: ParaId,
+		relay_parent_number: T::BlockNumber,
+		new_hrmp_watermark: BlockNumber,
+	) -> DispatchResult {
+		ensure!(
+			T::BlockNumber::from(new_hrmp_watermark) <= relay_parent_number,
+			Error::<T>::HrmpWatermarkAheadOfRelayParent,
+		);
+
+		if let Some(last_watermark) = <Self as Store>::HrmpWatermarks::get(&recipient) {
+			ensure!(new_hrmp_watermark >= last_watermark, Error::<T>::HrmpWatermarkRegressed);
+		}
+
+		Ok(())
+	}
+
+	/// Ensure the HRMP messages of a candidate can be sent by the given para.
+	///
+	/// The messages must be sorted by recipient, be sent over open channels and fit into the
+	/// channels' limits.
+	pub(crate) fn check_outbound_hrmp(
+		config: &HostConfiguration<T::BlockNumber>,
+		sender: ParaId,
+		horizontal_messages: &[OutboundHrmpMessage],
+	) -> DispatchResult {
+		ensure!(
+			horizontal_messages.len() <= config.hrmp_max_message_num_per_candidate as usize,
+			Error::<T>::TooManyHorizontalMessages,
+		);
+		ensure!(
+			horizontal_messages.windows(2).all(|w| w[0].recipient <= w[1].recipient),
+			Error::<T>::HorizontalMessagesNotSorted,
+		);
+
+		let mut remaining = horizontal_messages;
+		while let Some(first) = remaining.first() {
+			let recipient = first.recipient;
+			let count = remaining.iter().take_while(|m| m.recipient == recipient).count();
+			let (to_recipient, rest) = remaining.split_at(count);
+
+			let channel = <Self as Store>::HrmpChannels::get(&HrmpChannelId { sender, recipient })
+				.ok_or(Error::<T>::NoSuchHrmpChannel)?;
+
+			ensure!(
+				to_recipient.iter().all(|m| m.data.len() <= channel.max_message_size as usize),
+				Error::<T>::HorizontalMessageTooLarge,
+			);
+			ensure!(
+				channel.msg_count as usize + count <= channel.max_capacity as usize,
+				Error::<T>::HrmpChannelFull,
+			);
+
+			remaining = rest;
+		}
+
+		Ok(())
+	}
+
+	/// Place the HRMP messages of an enacted candidate into the channels to their recipients.
+	pub(crate) fn queue_outbound_hrmp(
+		sender: ParaId,
+		horizontal_messages: Vec<OutboundHrmpMessage>,
+	) -> Weight {
+		let now: BlockNumber = <system::Module<T>>::block_number().saturated_into();
+		let mut weight = 0;
+
+		for OutboundHrmpMessage { recipient, data } in horizontal_messages {
+			let channel_id = HrmpChannelId { sender, recipient };
+
+			let queued = <Self as Store>::HrmpChannels::mutate(&channel_id, |channel| match channel {
+				Some(channel) => {
+					channel.msg_count += 1;
+					true
+				}
+				None => false,
+			});
+
+			if queued {
+				<Self as Store>::HrmpChannelContents::mutate(&channel_id, |contents| {
+					contents.push(InboundHrmpMessage { sender, sent_at: now, data })
+				});
+			}
+
+			weight += T::DbWeight::get().reads_writes(2, 2);
+		}
+
+		weight
+	}
+
+	/// Remove the inbound HRMP messages of the given para which were sent at or before the new
+	/// watermark, and advance the para's watermark.
+	pub(crate) fn prune_hrmp(recipient: ParaId, new_hrmp_watermark: BlockNumber) -> Weight {
+		let senders = <Self as Store>::HrmpIngressChannelsIndex::get(&recipient);
+		let mut weight = T::DbWeight::get().reads_writes(1, 1);
+
+		for sender in senders {
+			let channel_id = HrmpChannelId { sender, recipient };
+			let mut contents = <Self as Store>::HrmpChannelContents::get(&channel_id);
+
+			// messages are kept in the order they were sent.
+			let pruned = contents.iter().take_while(|m| m.sent_at <= new_hrmp_watermark).count();
+			weight += T::DbWeight::get().reads_writes(1, 0);
+
+			if pruned == 0 {
+				continue;
+			}
+
+			contents.drain(..pruned);
+			if contents.is_empty() {
+				<Self as Store>::HrmpChannelContents::remove(&channel_id);
+			} else {
+				<Self as Store>::HrmpChannelContents::insert(&channel_id, contents);
+			}
+
+			<Self as Store>::HrmpChannels::mutate(&channel_id, |channel| {
+				if let Some(channel) = channel {
+					channel.msg_count = channel.msg_count.saturating_sub(pruned as u32);
+				}
+			});
+
+			weight += T::DbWeight::get().reads_writes(1, 2);
+		}
+
+		<Self as Store>::HrmpWatermarks::insert(&recipient, new_hrmp_watermark);
+
+		weight
+	}
+
+	/// The messages waiting in the inbound channels of the given para, ordered by sender and then
+	/// by the block they were sent at.
+	pub fn inbound_hrmp_messages(recipient: ParaId) -> Vec<InboundHrmpMessage> {
+		<Self as Store>::HrmpIngressChannelsIndex::get(&recipient)
+			.into_iter()
+			.flat_map(|sender| {
+				<Self as Store>::HrmpChannelContents::get(&HrmpChannelId { sender, recipient })
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use primitives::v1::ValidationCode;
+	use crate::mock::{
+		new_test_ext, Balances, GenesisConfig as MockGenesisConfig, Origin, Router, System, Test,
+	};
+	use crate::paras::ParaGenesisArgs;
+
+	const SENDER_DEPOSIT: Balance = 10;
+	const RECIPIENT_DEPOSIT: Balance = 5;
+
+	fn para_origin(para: ParaId) -> Origin {
+		crate::router::Origin::Parachain(para).into()
+	}
+
+	fn para_balance(para: ParaId) -> (Balance, Balance) {
+		let account = para.into_account();
+		(Balances::free_balance(account), Balances::reserved_balance(account))
+	}
+
+	fn new_session() {
+		let notification = crate::initializer::SessionChangeNotification {
+			new_config: <configuration::Module<Test>>::config(),
+			..Default::default()
+		};
+
+		Router::initializer_on_new_session(&notification, &[]);
+	}
+
+	fn genesis_config(paras: Vec<ParaId>) -> MockGenesisConfig {
+		MockGenesisConfig {
+			paras: crate::paras::GenesisConfig {
+				paras: paras.iter().map(|&id| (id, ParaGenesisArgs {
+					genesis_head: Vec::new().into(),
+					validation_code: ValidationCode(Vec::new()),
+					parachain: true,
+				})).collect(),
+				..Default::default()
+			},
+			configuration: crate::configuration::GenesisConfig {
+				config: HostConfiguration {
+					hrmp_open_request_ttl: 1,
+					hrmp_sender_deposit: SENDER_DEPOSIT,
+					hrmp_recipient_deposit: RECIPIENT_DEPOSIT,
+					hrmp_channel_max_capacity: 2,
+					hrmp_channel_max_message_size: 4,
+					hrmp_max_parachain_inbound_channels: 1,
+					hrmp_max_parachain_outbound_channels: 1,
+					hrmp_max_message_num_per_candidate: 3,
+					..Default::default()
+				},
+				..Default::default()
+			},
+			balances: balances::GenesisConfig {
+				balances: paras.iter().map(|&id| (id.into_account(), 100)).collect(),
+			},
+			..Default::default()
+		}
+	}
+
+	fn open_channel(sender: ParaId, recipient: ParaId) {
+		Router::hrmp_init_open_channel(para_origin(sender), recipient, 2, 4).unwrap();
+		Router::hrmp_accept_open_channel(para_origin(recipient), sender).unwrap();
+		new_session();
+	}
+
+	#[test]
+	fn open_and_close_channel_with_deposits() {
+		let para_a = ParaId::from(1);
+		let para_b = ParaId::from(2);
+		let channel_id = HrmpChannelId { sender: para_a, recipient: para_b };
+
+		new_test_ext(genesis_config(vec![para_a, para_b])).execute_with(|| {
+			Router::hrmp_init_open_channel(para_origin(para_a), para_b, 2, 4).unwrap();
+			assert_eq!(para_balance(para_a), (90, SENDER_DEPOSIT));
+
+			// not accepted yet, so no channel is opened at the session change.
+			new_session();
+			assert!(<Router as Store>::HrmpChannels::get(&channel_id).is_none());
+
+			Router::hrmp_accept_open_channel(para_origin(para_b), para_a).unwrap();
+			assert_eq!(para_balance(para_b), (95, RECIPIENT_DEPOSIT));
+			assert!(<Router as Store>::HrmpChannels::get(&channel_id).is_none());
+
+			new_session();
+			assert_eq!(<Router as Store>::HrmpChannels::get(&channel_id).unwrap().max_capacity, 2);
+			assert_eq!(<Router as Store>::HrmpEgressChannelsIndex::get(&para_a), vec![para_b]);
+			assert_eq!(<Router as Store>::HrmpIngressChannelsIndex::get(&para_b), vec![para_a]);
+			assert!(<Router as Store>::HrmpOpenChannelRequests::get(&channel_id).is_none());
+			assert!(<Router as Store>::HrmpOpenChannelRequestsList::get().is_empty());
+
+			// the recipient may close the channel as well.
+			Router::hrmp_close_channel(para_origin(para_b), channel_id.clone()).unwrap();
+			assert!(<Router as Store>::HrmpChannels::get(&channel_id).is_some());
+
+			new_session();
+			assert!(<Router as Store>::HrmpChannels::get(&channel_id).is_none());
+			assert!(<Router as Store>::HrmpEgressChannelsIndex::get(&para_a).is_empty());
+			assert!(<Router as Store>::HrmpIngressChannelsIndex::get(&para_b).is_empty());
+			assert_eq!(para_balance(para_a), (100, 0));
+			assert_eq!(para_balance(para_b), (100, 0));
+		});
+	}
+
+	#[test]
+	fn open_channel_requests_are_checked() {
+		let para_a = ParaId::from(1);
+		let para_b = ParaId::from(2);
+		let para_c = ParaId::from(3);
+
+		new_test_ext(genesis_config(vec![para_a, para_b, para_c])).execute_with(|| {
+			let init = |sender, recipient, capacity, size| Router::hrmp_init_open_channel(
+				para_origin(sender),
+				recipient,
+				capacity,
+				size,
+			);
+
+			assert!(Router::hrmp_init_open_channel(Origin::signed(1), para_b, 2, 4).is_err());
+			assert_eq!(init(para_a, para_a, 2, 4), Err(Error::<Test>::OpenHrmpChannelToSelf.into()));
+			assert_eq!(
+				init(para_a, ParaId::from(4), 2, 4),
+				Err(Error::<Test>::OpenHrmpChannelInvalidRecipient.into()),
+			);
+			assert_eq!(init(para_a, para_b, 0, 4), Err(Error::<Test>::OpenHrmpChannelZeroCapacity.into()));
+			assert_eq!(
+				init(para_a, para_b, 3, 4),
+				Err(Error::<Test>::OpenHrmpChannelCapacityExceedsLimit.into()),
+			);
+			assert_eq!(init(para_a, para_b, 2, 0), Err(Error::<Test>::OpenHrmpChannelZeroMessageSize.into()));
+			assert_eq!(
+				init(para_a, para_b, 2, 5),
+				Err(Error::<Test>::OpenHrmpChannelMessageSizeExceedsLimit.into()),
+			);
+
+			assert!(init(para_a, para_b, 2, 4).is_ok());
+			assert_eq!(init(para_a, para_b, 2, 4), Err(Error::<Test>::OpenHrmpChannelAlreadyRequested.into()));
+
+			// only one outbound channel is allowed.
+			assert_eq!(init(para_a, para_c, 2, 4), Err(Error::<Test>::OpenHrmpChannelLimitExceeded.into()));
+
+			// only one inbound channel is allowed.
+			assert!(init(para_c, para_b, 2, 4).is_ok());
+			assert!(Router::hrmp_accept_open_channel(para_origin(para_b), para_a).is_ok());
+			assert_eq!(
+				Router::hrmp_accept_open_channel(para_origin(para_b), para_a),
+				Err(Error::<Test>::AcceptHrmpChannelAlreadyConfirmed.into()),
+			);
+			assert_eq!(
+				Router::hrmp_accept_open_channel(para_origin(para_b), para_c),
+				Err(Error::<Test>::AcceptHrmpChannelLimitExceeded.into()),
+			);
+			assert_eq!(
+				Router::hrmp_accept_open_channel(para_origin(para_c), para_b),
+				Err(Error::<Test>::AcceptHrmpChannelDoesntExist.into()),
+			);
+
+			new_session();
+			assert_eq!(init(para_a, para_b, 2, 4), Err(Error::<Test>::OpenHrmpChannelAlreadyExists.into()));
+
+			let channel_id = HrmpChannelId { sender: para_a, recipient: para_b };
+			assert_eq!(
+				Router::hrmp_close_channel(para_origin(para_c), channel_id.clone()),
+				Err(Error::<Test>::CloseHrmpChannelUnauthorized.into()),
+			);
+			assert_eq!(
+				Router::hrmp_close_channel(
+					para_origin(para_b),
+					HrmpChannelId { sender: para_b, recipient: para_a },
+				),
+				Err(Error::<Test>::CloseHrmpChannelDoesntExist.into()),
+			);
+			assert!(Router::hrmp_close_channel(para_origin(para_a), channel_id.clone()).is_ok());
+			assert_eq!(
+				Router::hrmp_close_channel(para_origin(para_b), channel_id),
+				Err(Error::<Test>::CloseHrmpChannelAlreadyUnderway.into()),
+			);
+		});
+	}
+
+	#[test]
+	fn unaccepted_open_channel_requests_expire() {
+		let para_a = ParaId::from(1);
+		let para_b = ParaId::from(2);
+		let channel_id = HrmpChannelId { sender: para_a, recipient: para_b };
+
+		new_test_ext(genesis_config(vec![para_a, para_b])).execute_with(|| {
+			Router::hrmp_init_open_channel(para_origin(para_a), para_b, 2, 4).unwrap();
+
+			// the request stays valid for a single session.
+			new_session();
+			assert!(<Router as Store>::HrmpOpenChannelRequests::get(&channel_id).is_some());
+
+			new_session();
+			assert!(<Router as Store>::HrmpOpenChannelRequests::get(&channel_id).is_none());
+			assert!(<Router as Store>::HrmpOpenChannelRequestsList::get().is_empty());
+			assert_eq!(<Router as Store>::HrmpOpenChannelRequestCount::get(&para_a), 0);
+			assert_eq!(para_balance(para_a), (100, 0));
+		});
+	}
+
+	#[test]
+	fn channels_of_outgoing_paras_are_closed() {
+		let para_a = ParaId::from(1);
+		let para_b = ParaId::from(2);
+		let channel_id = HrmpChannelId { sender: para_a, recipient: para_b };
+
+		new_test_ext(genesis_config(vec![para_a, para_b])).execute_with(|| {
+			open_channel(para_a, para_b);
+			Router::prune_hrmp(para_b, 0);
+
+			Router::initializer_on_new_session(&Default::default(), &[para_b]);
+
+			assert!(<Router as Store>::HrmpChannels::get(&channel_id).is_none());
+			assert!(<Router as Store>::HrmpEgressChannelsIndex::get(&para_a).is_empty());
+			assert!(<Router as Store>::HrmpWatermarks::get(&para_b).is_none());
+			assert_eq!(para_balance(para_a), (100, 0));
+			assert_eq!(para_balance(para_b), (100, 0));
+		});
+	}
+
+	#[test]
+	fn horizontal_messages_are_checked_queued_and_pruned() {
+		let para_a = ParaId::from(1);
+		let para_b = ParaId::from(2);
+		let para_c = ParaId::from(3);
+
+		let mut genesis = genesis_config(vec![para_a, para_b, para_c]);
+		genesis.configuration.config.hrmp_max_parachain_inbound_channels = 2;
+
+		new_test_ext(genesis).execute_with(|| {
+			open_channel(para_a, para_b);
+			open_channel(para_c, para_b);
+
+			let config = <configuration::Module<Test>>::config();
+			let message = |recipient, data: Vec<u8>| OutboundHrmpMessage { recipient, data };
+
+			assert!(Router::check_outbound_hrmp(&config, para_a, &[message(para_b, vec![1])]).is_ok());
+			assert_eq!(
+				Router::check_outbound_hrmp(
+					&config,
+					para_a,
+					&[message(para_b, vec![1]), message(para_b, vec![2]), message(para_b, vec![3])],
+				),
+				Err(Error::<Test>::HrmpChannelFull.into()),
+			);
+			assert_eq!(
+				Router::check_outbound_hrmp(&config, para_a, &vec![message(para_b, vec![1]); 4]),
+				Err(Error::<Test>::TooManyHorizontalMessages.into()),
+			);
+			assert_eq!(
+				Router::check_outbound_hrmp(&config, para_a, &[message(para_b, vec![1; 5])]),
+				Err(Error::<Test>::HorizontalMessageTooLarge.into()),
+			);
+			assert_eq!(
+				Router::check_outbound_hrmp(&config, para_a, &[message(para_c, vec![1])]),
+				Err(Error::<Test>::NoSuchHrmpChannel.into()),
+			);
+			assert_eq!(
+				Router::check_outbound_hrmp(
+					&config,
+					para_a,
+					&[message(para_c, vec![1]), message(para_b, vec![1])],
+				),
+				Err(Error::<Test>::HorizontalMessagesNotSorted.into()),
+			);
+
+			System::set_block_number(2);
+			Router::queue_outbound_hrmp(para_c, vec![message(para_b, vec![3])]);
+			Router::queue_outbound_hrmp(para_a, vec![message(para_b, vec![1])]);
+
+			System::set_block_number(3);
+			Router::queue_outbound_hrmp(para_a, vec![message(para_b, vec![2])]);
+
+			let inbound = |sender, sent_at, data| InboundHrmpMessage { sender, sent_at, data };
+			assert_eq!(
+				Router::inbound_hrmp_messages(para_b),
+				vec![inbound(para_a, 2, vec![1]), inbound(para_a, 3, vec![2]), inbound(para_c, 2, vec![3])],
+			);
+
+			// the channel from A to B is full now.
+			assert_eq!(
+				Router::check_outbound_hrmp(&config, para_a, &[message(para_b, vec![1])]),
+				Err(Error::<Test>::HrmpChannelFull.into()),
+			);
+
+			assert!(Router::check_hrmp_watermark(para_b, 3, 2).is_ok());
+			assert_eq!(
+				Router::check_hrmp_watermark(para_b, 3, 4),
+				Err(Error::<Test>::HrmpWatermarkAheadOfRelayParent.into()),
+			);
+
+			Router::prune_hrmp(para_b, 2);
+			assert_eq!(Router::inbound_hrmp_messages(para_b), vec![inbound(para_a, 3, vec![2])]);
+			assert!(Router::check_outbound_hrmp(&config, para_a, &[message(para_b, vec![1])]).is_ok());
+
+			assert_eq!(
+				Router::check_hrmp_watermark(para_b, 3, 1),
+				Err(Error::<Test>::HrmpWatermarkRegressed.into()),
+			);
+		});
+	}
+}
@@ -0,0 +1,224 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! A module exporting runtime API implementation functions for all runtime APIs using v1
+//! primitives.
+//!
+//! Runtimes implementing the v1 runtime API are recommended to forward directly to these
+//! functions.
+
+use sp_std::prelude::*;
+use primitives::v1::{
+	ValidatorId, SchedulerRoster, GlobalValidationSchedule, LocalValidationData, Id as ParaId,
+	ValidationCode, HeadData, BlockNumber, CommittedCandidateReceipt, DownwardMessage, CoreState,
+	CoreIndex, CoreAssignment, ScheduledCore, OccupiedCore, OmittedValidationData, Hash, SessionInfo,
+};
+use sp_runtime::traits::{BlakeTwo256, Hash as HashT, One, Saturating, SaturatedConversion};
+use sp_staking::SessionIndex;
+use crate::{
+	initializer, inclusion, scheduler::{self, FreedReason}, session_info, configuration, paras, router,
+};
+
+/// Implementation for the `validators` function of the runtime API.
+pub fn validators<T: initializer::Trait>() -> Vec<ValidatorId> {
+	<inclusion::Module<T>>::validators()
+}
+
+/// Implementation for the `validator_groups` function of the runtime API.
+pub fn validator_groups<T: initializer::Trait>() -> SchedulerRoster {
+	SchedulerRoster {
+		validator_groups: <scheduler::Module<T>>::validator_groups(),
+		scheduled: <scheduler::Module<T>>::scheduled(),
+		upcoming: <scheduler::Module<T>>::upcoming_paras(),
+		availability_cores: <scheduler::Module<T>>::availability_cores(),
+	}
+}
+
+/// Implementation for the `session_index_for_child` function of the runtime API.
+pub fn session_index_for_child<T: initializer::Trait>() -> SessionIndex {
+	<initializer::Module<T>>::session_index_for_child()
+}
+
+/// Implementation for the `global_validation_schedule` function of the runtime API.
+pub fn global_validation_schedule<T: initializer::Trait>() -> GlobalValidationSchedule {
+	let config = <configuration::Module<T>>::config();
+
+	GlobalValidationSchedule {
+		max_code_size: config.max_code_size,
+		max_head_data_size: config.max_head_data_size,
+		block_number: <system::Module<T>>::block_number().saturated_into(),
+	}
+}
+
+/// Implementation for the `local_validation_data` function of the runtime API.
+pub fn local_validation_data<T: initializer::Trait>(para_id: ParaId) -> Option<LocalValidationData> {
+	let config = <configuration::Module<T>>::config();
+
+	// candidates executed in the context of this block will be backed in a child of this block,
+	// so this block's number is their relay-parent number.
+	let relay_parent_number = <system::Module<T>>::block_number();
+
+	let parent_head = <paras::Module<T>>::para_head(&para_id)?;
+	let validation_code_hash = <paras::Module<T>>::current_code_hash(&para_id)?;
+
+	let code_upgrade_allowed = <paras::Module<T>>::last_code_upgrade(para_id, true)
+		.map_or(
+			true,
+			|last| last <= relay_parent_number &&
+				relay_parent_number.saturating_sub(last) >= config.validation_upgrade_frequency,
+		);
+
+	Some(LocalValidationData {
+		parent_head,
+		balance: 0,
+		validation_code_hash,
+		downward_messages_hash: BlakeTwo256::hash_of(&<router::Module<T>>::downward_messages(&para_id)),
+		inbound_hrmp_messages: <router::Module<T>>::inbound_hrmp_messages(para_id),
+		code_upgrade_allowed: if code_upgrade_allowed {
+			Some((relay_parent_number + config.validation_upgrade_delay).saturated_into())
+		} else {
+			None
+		},
+	})
+}
+
+/// Implementation for the `validation_code` function of the runtime API.
+pub fn validation_code<T: initializer::Trait>(
+	para_id: ParaId,
+	at: BlockNumber,
+	assume_intermediate: Option<BlockNumber>,
+) -> Option<ValidationCode> {
+	<paras::Module<T>>::validation_code_at(
+		para_id,
+		at.into(),
+		assume_intermediate.map(Into::into),
+	)
+}
+
+/// Implementation for the `validation_code_hash` function of the runtime API.
+pub fn validation_code_hash<T: initializer::Trait>(
+	para_id: ParaId,
+	at: BlockNumber,
+	assume_intermediate: Option<BlockNumber>,
+) -> Option<Hash> {
+	<paras::Module<T>>::validation_code_hash_at(
+		para_id,
+		at.into(),
+		assume_intermediate.map(Into::into),
+	)
+}
+
+/// Implementation for the `validation_code_by_hash` function of the runtime API.
+pub fn validation_code_by_hash<T: initializer::Trait>(hash: Hash) -> Option<ValidationCode> {
+	<paras::Module<T>>::code_by_hash(&hash)
+}
+
+/// Implementation for the `head_data` function of the runtime API.
+pub fn head_data<T: initializer::Trait>(para_id: ParaId) -> Option<HeadData> {
+	<paras::Module<T>>::para_head(&para_id)
+}
+
+/// Implementation for the `candidate_pending_availability` function of the runtime API.
+pub fn candidate_pending_availability<T: initializer::Trait>(para_id: ParaId)
+	-> Option<CommittedCandidateReceipt<T::Hash>>
+{
+	<inclusion::Module<T>>::candidate_pending_availability(para_id)
+}
+
+/// Implementation for the `downward_messages` function of the runtime API.
+pub fn downward_messages<T: initializer::Trait>(para_id: ParaId)
+	-> Vec<DownwardMessage<T::AccountId>>
+{
+	<router::Module<T>>::downward_messages(&para_id)
+}
+
+/// Implementation for the `availability_cores` function of the runtime API.
+pub fn availability_cores<T: initializer::Trait>() -> Vec<CoreState<T::Hash, T::BlockNumber>> {
+	let cores = <scheduler::Module<T>>::availability_cores();
+	let next = <system::Module<T>>::block_number() + One::one();
+
+	// the candidates pending availability on each core, and whether they time out in the next
+	// block. For the purpose of predicting the assignments of the next block, all occupied cores
+	// are considered freed.
+	let mut occupied = Vec::with_capacity(cores.len());
+	let mut freed = Vec::new();
+	for (i, core) in cores.iter().enumerate() {
+		let core_index = CoreIndex(i as u32);
+		let pending = core.as_ref()
+			.and_then(|_| <scheduler::Module<T>>::core_para(core_index))
+			.and_then(<inclusion::Module<T>>::pending_availability);
+
+		let pending = match pending {
+			None => {
+				// the candidate of an occupied core may have been cleared by root, in which case
+				// the core is freed by the next inclusion inherent.
+				if core.is_some() {
+					freed.push((core_index, FreedReason::TimedOut));
+				}
+				occupied.push(None);
+				continue
+			}
+			Some(pending) => pending,
+		};
+
+		let time_out_at = <scheduler::Module<T>>::availability_timeout_at(
+			core_index,
+			pending.backed_in_number(),
+		);
+		let freeing = time_out_at.as_ref().map_or(false, |time_out_at| time_out_at <= &next);
+
+		freed.push((core_index, if freeing { FreedReason::TimedOut } else { FreedReason::Concluded }));
+		occupied.push(Some((OccupiedCore {
+			candidate_hash: pending.receipt().hash(),
+			candidate_descriptor: pending.receipt().descriptor().clone(),
+			availability: pending.availability_votes().clone(),
+			occupied_since: pending.backed_in_number(),
+			time_out_at,
+			next_up_on_available: None,
+		}, freeing)));
+	}
+
+	let scheduled = <scheduler::Module<T>>::predict_next_scheduled(&freed);
+	let global_validation = global_validation_schedule::<T>();
+	let scheduled_core = |assignment: &CoreAssignment| {
+		local_validation_data::<T>(assignment.para_id).map(|local_validation| ScheduledCore {
+			assignment: assignment.clone(),
+			omitted_validation: OmittedValidationData {
+				global_validation: global_validation.clone(),
+				local_validation,
+			},
+		})
+	};
+
+	occupied.into_iter().enumerate().map(|(i, occupied)| {
+		let core_index = CoreIndex(i as u32);
+		let assignment = scheduled.iter().find(|assignment| assignment.core == core_index);
+
+		match occupied {
+			None => CoreState::Free(assignment.and_then(&scheduled_core)),
+			Some((occupied, true)) => CoreState::Freeing(occupied, assignment.and_then(&scheduled_core)),
+			Some((mut occupied, false)) => {
+				occupied.next_up_on_available = assignment.cloned();
+				CoreState::Occupied(occupied)
+			}
+		}
+	}).collect()
+}
+
+/// Implementation for the `session_info` function of the runtime API.
+pub fn session_info<T: session_info::Trait>(index: SessionIndex) -> Option<SessionInfo> {
+	<session_info::Module<T>>::session_info(index)
+}
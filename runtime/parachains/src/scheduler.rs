@@ -34,24 +34,32 @@
 //! used for multiplexing parathreads. Validators will be partitioned into groups, with the same
 //! number of groups as availability cores. Validator groups will be assigned to different availability cores
 //! over time.
+//!
+//! Parathreads are scheduled by claims, which are bought with the `claim_parathread` call. The fee
+//! for a claim grows with the number of claims already queued. It is reserved until the claim is
+//! either concluded or expires, in which case it is burned, or pruned because the parathread is no
+//! longer live, in which case it is refunded.
 
 use sp_std::prelude::*;
 use sp_std::convert::TryInto;
 use primitives::v1::{
 	Id as ParaId, ValidatorIndex, CoreAssignment, CoreOccupied, CoreIndex, AssignmentKind,
-	GroupIndex, ParathreadClaim, ParathreadEntry,
+	GroupIndex, ParathreadClaim, ParathreadEntry, CollatorId, Balance,
 };
 use frame_support::{
-	decl_storage, decl_module, decl_error,
-	weights::Weight,
+	decl_storage, decl_module, decl_event, decl_error, ensure,
+	dispatch::DispatchResult,
+	traits::{Get, ReservableCurrency},
+	weights::{Weight, constants::RocksDbWeight},
 };
+use system::ensure_signed;
 use codec::{Encode, Decode};
-use sp_runtime::traits::{Saturating, Zero};
+use sp_runtime::traits::{One, Saturating, Zero};
 
 use rand::{SeedableRng, seq::SliceRandom};
 use rand_chacha::ChaCha20Rng;
 
-use crate::{configuration, paras, initializer::SessionChangeNotification};
+use crate::{configuration::{self, HostConfiguration}, paras, initializer::SessionChangeNotification};
 
 /// A queued parathread entry, pre-assigned to a core.
 #[derive(Encode, Decode, Default)]
@@ -99,7 +107,45 @@ pub enum FreedReason {
 	TimedOut,
 }
 
-pub trait Trait: system::Trait + configuration::Trait + paras::Trait { }
+pub trait Trait: system::Trait + configuration::Trait + paras::Trait {
+	/// The overarching event type.
+	type Event: From<Event<Self>> + Into<<Self as system::Trait>::Event>;
+
+	/// The currency in which parathread claims are paid.
+	type Currency: ReservableCurrency<Self::AccountId, Balance = Balance>;
+
+	/// The weights of the routines of this module, as generated by its benchmarks.
+	type WeightInfo: WeightInfo;
+}
+
+/// The hard limit on the number of claims in the parathread queue, regardless of the number of
+/// parathread cores and the scheduling lookahead. This bounds the weight of `claim_parathread`.
+pub const MAX_PARATHREAD_QUEUE_SIZE: u32 = 1_000;
+
+/// The weights of the routines of the scheduler module.
+pub trait WeightInfo {
+	/// The weight of `schedule` with `c` availability cores and `q` queued parathread claims.
+	fn schedule(c: u32, q: u32) -> Weight;
+	/// The weight of `claim_parathread` with `q` queued parathread claims.
+	fn claim_parathread(q: u32) -> Weight;
+}
+
+/// Conservative weights for runtimes which haven't run the benchmarks of this module.
+impl WeightInfo for () {
+	fn schedule(c: u32, q: u32) -> Weight {
+		(20_000_000 as Weight)
+			.saturating_add((2_000_000 as Weight).saturating_mul(c as Weight))
+			.saturating_add((500_000 as Weight).saturating_mul(q as Weight))
+			.saturating_add(RocksDbWeight::get().reads(6 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(3 as Weight))
+	}
+	fn claim_parathread(q: u32) -> Weight {
+		(50_000_000 as Weight)
+			.saturating_add((200_000 as Weight).saturating_mul(q as Weight))
+			.saturating_add(RocksDbWeight::get().reads(5 as Weight))
+			.saturating_add(RocksDbWeight::get().writes(5 as Weight))
+	}
+}
 
 decl_storage! {
 	trait Store for Module<T: Trait> as ParaScheduler {
@@ -107,7 +153,7 @@ decl_storage! {
 		///
 		/// Bound: The number of cores is the sum of the numbers of parachains and parathread multiplexers.
 		/// Reasonably, 100-1000. The dominant factor is the number of validators: safe upper bound at 10k.
-		ValidatorGroups: Vec<Vec<ValidatorIndex>>;
+		ValidatorGroups get(fn validator_groups): Vec<Vec<ValidatorIndex>>;
 
 		/// A queue of upcoming claims and which core they should be mapped onto.
 		///
@@ -120,12 +166,17 @@ decl_storage! {
 		/// parathread-multiplexers.
 		///
 		/// Bounded by the number of cores: one for each parachain and parathread multiplexer.
-		AvailabilityCores: Vec<Option<CoreOccupied>>;
+		AvailabilityCores get(fn availability_cores): Vec<Option<CoreOccupied>>;
 		/// An index used to ensure that only one claim on a parathread exists in the queue or is
 		/// currently being handled by an occupied core.
 		///
 		/// Bounded by the number of parathread cores and scheduling lookahead. Reasonably, 10 * 50 = 500.
 		ParathreadClaimIndex: Vec<ParaId>;
+		/// The account which paid for the current claim on a parathread, along with the fee reserved
+		/// from it. Claims which were added without a fee have no entry.
+		///
+		/// Bounded by the number of entries in the `ParathreadClaimIndex`.
+		ParathreadClaimFees: map hasher(twox_64_concat) ParaId => Option<(T::AccountId, Balance)>;
 		/// The block number where the session start occurred. Used to track how many group rotations have occurred.
 		SessionStartBlock: T::BlockNumber;
 		/// Currently scheduled cores - free but up to be occupied. Ephemeral storage item that's wiped on finalization.
@@ -135,23 +186,74 @@ decl_storage! {
 	}
 }
 
+decl_event! {
+	pub enum Event<T> where AccountId = <T as system::Trait>::AccountId {
+		/// A claim on a parathread was bought by the given account for the given fee.
+		ParathreadClaimed(ParaId, AccountId, Balance),
+		/// The fee for a claim on a parathread was refunded, as the parathread is no longer live.
+		ParathreadClaimFeeRefunded(ParaId, AccountId, Balance),
+		/// The fee for a claim on a parathread was burned, as the claim was concluded or expired.
+		ParathreadClaimFeeBurned(ParaId, AccountId, Balance),
+	}
+}
+
 decl_error! {
-	pub enum Error for Module<T: Trait> { }
+	pub enum Error for Module<T: Trait> {
+		/// The claimed para is not a live parathread.
+		NotParathread,
+		/// The parathread queue is full.
+		ParathreadQueueFull,
+		/// There is already a claim on the parathread in the queue or on a core.
+		ParathreadClaimExists,
+		/// The fee for a claim is higher than the maximum the sender is willing to pay.
+		ParathreadClaimFeeTooHigh,
+	}
 }
 
 decl_module! {
 	/// The scheduler module.
 	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
 		type Error = Error<T>;
+
+		fn deposit_event() = default;
+
+		/// Buy a claim on the given parathread for the given collator, to be scheduled onto a
+		/// parathread core in an upcoming block.
+		///
+		/// The current fee, given by `parathread_claim_fee`, is reserved from the sender and must
+		/// not exceed `max_fee`.
+		#[weight = <T as Trait>::WeightInfo::claim_parathread(MAX_PARATHREAD_QUEUE_SIZE)]
+		pub fn claim_parathread(
+			origin,
+			para_id: ParaId,
+			collator: CollatorId,
+			max_fee: Balance,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+
+			let config = <configuration::Module<T>>::config();
+			let mut queue = ParathreadQueue::get();
+
+			let fee = Self::claim_fee(&config, &queue);
+			ensure!(fee <= max_fee, Error::<T>::ParathreadClaimFeeTooHigh);
+			Self::check_parathread_claim(para_id, &config, &queue)?;
+
+			T::Currency::reserve(&who, fee)?;
+			<ParathreadClaimFees<T>>::insert(&para_id, (who.clone(), fee));
+			Self::enqueue_parathread_claim(ParathreadClaim(para_id, collator), &config, &mut queue);
+			ParathreadQueue::set(queue);
+
+			Self::deposit_event(RawEvent::ParathreadClaimed(para_id, who, fee));
+
+			Ok(())
+		}
 	}
 }
 
 impl<T: Trait> Module<T> {
 	/// Called by the initializer to initialize the scheduler module.
 	pub(crate) fn initializer_initialize(_now: T::BlockNumber) -> Weight {
-		Self::schedule(Vec::new());
-
-		0
+		Self::schedule(Vec::new())
 	}
 
 	/// Called by the initializer to finalize the scheduler module.
@@ -168,6 +270,8 @@ impl<T: Trait> Module<T> {
 
 					if entry.retries <= config.parathread_retries {
 						queue.enqueue_entry(entry, config.parathread_cores);
+					} else {
+						Self::remove_parathread_claim(core_assignment.para_id, false);
 					}
 				}
 			}
@@ -243,18 +347,23 @@ impl<T: Trait> Module<T> {
 					queue: Vec::new(),
 					next_core_offset: 0,
 				};
-				claim_index.clear();
+				for para_id in claim_index.drain(..) {
+					Self::release_parathread_claim_fee(para_id, true);
+				}
 				return;
 			}
 
 			// prune out all entries beyond retry or that no longer correspond to live parathread.
 			thread_queue.queue.retain(|queued| {
-				let will_keep = queued.claim.retries <= config.parathread_retries
-					&& <paras::Module<T>>::is_parathread(queued.claim.claim.0);
+				let is_live = <paras::Module<T>>::is_parathread(queued.claim.claim.0);
+				let will_keep = queued.claim.retries <= config.parathread_retries && is_live;
 
 				if !will_keep {
 					let claim_para = queued.claim.claim.0;
 
+					// claims are only refunded if they were pruned through no fault of their own.
+					Self::release_parathread_claim_fee(claim_para, !is_live);
+
 					// clean up the pruned entry from the index.
 					if let Ok(i) = claim_index.binary_search(&claim_para) {
 						claim_index.remove(i);
@@ -277,43 +386,108 @@ impl<T: Trait> Module<T> {
 		ParathreadQueue::set(thread_queue);
 	}
 
+	/// The fee for a new parathread claim, which grows linearly with the number of claims in the
+	/// queue.
+	pub fn parathread_claim_fee() -> Balance {
+		Self::claim_fee(&<configuration::Module<T>>::config(), &ParathreadQueue::get())
+	}
+
+	// the fee for a new parathread claim under the given configuration and queue.
+	fn claim_fee(config: &HostConfiguration<T::BlockNumber>, queue: &ParathreadClaimQueue) -> Balance {
+		let queued = queue.queue.len() as Balance;
+
+		config.parathread_claim_fee.saturating_mul(queued.saturating_add(1))
+	}
+
+	/// Ensure that a claim on the given parathread can be added to the given queue: the para must
+	/// be a live parathread without a competing claim in the queue or assigned to a core, and the
+	/// queue must not be full.
+	fn check_parathread_claim(
+		para_id: ParaId,
+		config: &HostConfiguration<T::BlockNumber>,
+		queue: &ParathreadClaimQueue,
+	) -> DispatchResult {
+		ensure!(<paras::Module<T>>::is_parathread(para_id), Error::<T>::NotParathread);
+
+		let queue_max_size = sp_std::cmp::min(
+			config.parathread_cores.saturating_mul(config.scheduling_lookahead),
+			MAX_PARATHREAD_QUEUE_SIZE,
+		);
+		ensure!(
+			queue.queue.len() < queue_max_size as usize,
+			Error::<T>::ParathreadQueueFull,
+		);
+		ensure!(
+			ParathreadClaimIndex::get().binary_search(&para_id).is_err(),
+			Error::<T>::ParathreadClaimExists,
+		);
+
+		Ok(())
+	}
+
 	/// Add a parathread claim to the queue. If there is a competing claim in the queue or currently
 	/// assigned to a core, this call will fail. This call will also fail if the queue is full.
 	///
 	/// Fails if the claim does not correspond to any live parathread.
-	#[allow(unused)]
 	pub fn add_parathread_claim(claim: ParathreadClaim) {
-		if !<paras::Module<T>>::is_parathread(claim.0) { return }
-
 		let config = <configuration::Module<T>>::config();
-		let queue_max_size = config.parathread_cores * config.scheduling_lookahead;
 
 		ParathreadQueue::mutate(|queue| {
-			if queue.queue.len() >= queue_max_size as usize { return }
+			if Self::check_parathread_claim(claim.0, &config, queue).is_err() { return }
 
-			let para_id = claim.0;
+			Self::enqueue_parathread_claim(claim, &config, queue);
+		})
+	}
 
-			let competes_with_another = ParathreadClaimIndex::mutate(|index| {
-				match index.binary_search(&para_id) {
-					Ok(_) => true,
-					Err(i) => {
-						index.insert(i, para_id);
-						false
-					}
-				}
-			});
+	// add a claim which passed `check_parathread_claim` to the given queue and the claim index.
+	fn enqueue_parathread_claim(
+		claim: ParathreadClaim,
+		config: &HostConfiguration<T::BlockNumber>,
+		queue: &mut ParathreadClaimQueue,
+	) {
+		let para_id = claim.0;
 
-			if competes_with_another { return }
+		ParathreadClaimIndex::mutate(|index| {
+			if let Err(i) = index.binary_search(&para_id) {
+				index.insert(i, para_id);
+			}
+		});
 
-			let entry = ParathreadEntry { claim, retries: 0 };
-			queue.enqueue_entry(entry, config.parathread_cores);
-		})
+		let entry = ParathreadEntry { claim, retries: 0 };
+		queue.enqueue_entry(entry, config.parathread_cores);
+	}
+
+	/// Remove the claim on a parathread from the index, opening the parathread up for further
+	/// claims, and release the fee paid for the claim.
+	fn remove_parathread_claim(para_id: ParaId, refund: bool) {
+		ParathreadClaimIndex::mutate(|index| {
+			if let Ok(i) = index.binary_search(&para_id) {
+				index.remove(i);
+			}
+		});
+
+		Self::release_parathread_claim_fee(para_id, refund);
+	}
+
+	/// Release the fee paid for the claim on a parathread, if any, either refunding or burning it.
+	fn release_parathread_claim_fee(para_id: ParaId, refund: bool) {
+		if let Some((who, fee)) = <ParathreadClaimFees<T>>::take(&para_id) {
+			if refund {
+				T::Currency::unreserve(&who, fee);
+				Self::deposit_event(RawEvent::ParathreadClaimFeeRefunded(para_id, who, fee));
+			} else {
+				let _ = T::Currency::slash_reserved(&who, fee);
+				Self::deposit_event(RawEvent::ParathreadClaimFeeBurned(para_id, who, fee));
+			}
+		}
 	}
 
 	/// Schedule all unassigned cores, where possible. Provide a list of cores that should be considered
 	/// newly-freed along with the reason for them being freed. The list is assumed to be sorted in
 	/// ascending order by core index.
-	pub(crate) fn schedule(just_freed_cores: Vec<(CoreIndex, FreedReason)>) {
+	///
+	/// Returns the weight of scheduling.
+	pub(crate) fn schedule(just_freed_cores: Vec<(CoreIndex, FreedReason)>) -> Weight {
 		let mut cores = AvailabilityCores::get();
 		let config = <configuration::Module<T>>::config();
 
@@ -326,12 +500,8 @@ impl<T: Trait> Module<T> {
 						match freed_reason {
 							FreedReason::Concluded => {
 								// After a parathread candidate has successfully been included,
-								// open it up for further claims!
-								ParathreadClaimIndex::mutate(|index| {
-									if let Ok(i) = index.binary_search(&entry.claim.0) {
-										index.remove(i);
-									}
-								})
+								// open it up for further claims! The fee pays for the inclusion.
+								Self::remove_parathread_claim(entry.claim.0, false);
 							}
 							FreedReason::TimedOut => {
 								// If a parathread candidate times out, it's not the collator's fault,
@@ -346,101 +516,152 @@ impl<T: Trait> Module<T> {
 			}
 		}
 
-		let parachains = <paras::Module<T>>::parachains();
+		AvailabilityCores::put(&cores);
+
 		let mut scheduled = Scheduled::get();
 		let mut parathread_queue = ParathreadQueue::get();
 		let now = <system::Module<T>>::block_number();
 
-		if ValidatorGroups::get().is_empty() { return }
+		let weight = <T as Trait>::WeightInfo::schedule(
+			cores.len() as u32,
+			parathread_queue.queue.len() as u32,
+		);
 
-		{
-			let mut prev_scheduled_in_order = scheduled.iter().enumerate().peekable();
-
-			// Updates to the previous list of scheduled updates and the position of where to insert
-			// them, without accounting for prior updates.
-			let mut scheduled_updates: Vec<(usize, CoreAssignment)> = Vec::new();
-
-			// single-sweep O(n) in the number of cores.
-			for (core_index, _core) in cores.iter().enumerate().filter(|(_, ref c)| c.is_none()) {
-				let schedule_and_insert_at = {
-					// advance the iterator until just before the core index we are looking at now.
-					while prev_scheduled_in_order.peek().map_or(
-						false,
-						|(_, assign)| (assign.core.0 as usize) < core_index,
-					) {
-						let _ = prev_scheduled_in_order.next();
-					}
+		if ValidatorGroups::get().is_empty() { return weight }
 
-					// check the first entry already scheduled with core index >= than the one we
-					// are looking at. 3 cases:
-					//  1. No such entry, clearly this core is not scheduled, so we need to schedule and put at the end.
-					//  2. Entry exists and has same index as the core we are inspecting. do not schedule again.
-					//  3. Entry exists and has higher index than the core we are inspecting. schedule and note
-					//     insertion position.
-					prev_scheduled_in_order.peek().map_or(
-						Some(scheduled.len()),
-						|(idx_in_scheduled, assign)| if (assign.core.0 as usize) == core_index {
-							None
-						} else {
-							Some(*idx_in_scheduled)
-						},
-					)
-				};
+		Self::assign_free_cores(&cores, &mut scheduled, &mut parathread_queue, now);
 
-				let schedule_and_insert_at = match schedule_and_insert_at {
-					None => continue,
-					Some(at) => at,
-				};
+		Scheduled::set(scheduled);
+		ParathreadQueue::set(parathread_queue);
 
-				let core = CoreIndex(core_index as u32);
-
-				let core_assignment = if core_index < parachains.len() {
-					// parachain core.
-					Some(CoreAssignment {
-						kind: AssignmentKind::Parachain,
-						para_id: parachains[core_index],
-						core: core.clone(),
-						group_idx: Self::group_assigned_to_core(core, now)
-							.expect("core is not out of bounds and we are guaranteed \
-									to be after the most recent session start; qed"),
-					})
-				} else {
-					// parathread core offset, rel. to beginning.
-					let core_offset = (core_index - parachains.len()) as u32;
-
-					parathread_queue.take_next_on_core(core_offset).map(|entry| CoreAssignment {
-						kind: AssignmentKind::Parathread(entry.claim.1, entry.retries),
-						para_id: entry.claim.0,
-						core: core.clone(),
-						group_idx: Self::group_assigned_to_core(core, now)
-							.expect("core is not out of bounds and we are guaranteed \
-									to be after the most recent session start; qed"),
-					})
-				};
+		weight
+	}
 
-				if let Some(assignment) = core_assignment {
-					scheduled_updates.push((schedule_and_insert_at, assignment))
-				}
+	/// Predict the assignments of cores in the child of the current block, if the given occupied
+	/// cores were freed in it for the given reasons. No storage is altered.
+	///
+	/// This is meant to be called on the state of a finalized block, where nothing is scheduled
+	/// yet. Session changes in the child are not accounted for.
+	pub(crate) fn predict_next_scheduled(freed: &[(CoreIndex, FreedReason)]) -> Vec<CoreAssignment> {
+		let config = <configuration::Module<T>>::config();
+		let mut cores = AvailabilityCores::get();
+		let mut parathread_queue = ParathreadQueue::get();
+		let next = <system::Module<T>>::block_number() + One::one();
+
+		if ValidatorGroups::get().is_empty() { return Vec::new() }
+
+		// the child first schedules the cores which are already free on initialization, and then
+		// the cores freed by the inclusion inherent.
+		let mut scheduled = Vec::new();
+		Self::assign_free_cores(&cores, &mut scheduled, &mut parathread_queue, next);
+
+		for (freed_index, freed_reason) in freed {
+			let freed = cores.get_mut(freed_index.0 as usize).and_then(Option::take);
+			if let (Some(CoreOccupied::Parathread(entry)), FreedReason::TimedOut) = (freed, freed_reason) {
+				parathread_queue.enqueue_entry(entry, config.parathread_cores);
 			}
+		}
+
+		Self::assign_free_cores(&cores, &mut scheduled, &mut parathread_queue, next);
+
+		scheduled
+	}
+
+	/// Assign all free cores that are not yet scheduled to paras, as of the given block number.
+	/// Parachain cores are assigned to their parachain and parathread cores take the next claim
+	/// for them from the queue. `scheduled` is assumed to be sorted ascending by core index, and
+	/// remains so.
+	fn assign_free_cores(
+		cores: &[Option<CoreOccupied>],
+		scheduled: &mut Vec<CoreAssignment>,
+		parathread_queue: &mut ParathreadClaimQueue,
+		now: T::BlockNumber,
+	) {
+		let parachains = <paras::Module<T>>::parachains();
+
+		let mut prev_scheduled_in_order = scheduled.iter().enumerate().peekable();
+
+		// Updates to the previous list of scheduled updates and the position of where to insert
+		// them, without accounting for prior updates.
+		let mut scheduled_updates: Vec<(usize, CoreAssignment)> = Vec::new();
+
+		// single-sweep O(n) in the number of cores.
+		for (core_index, _core) in cores.iter().enumerate().filter(|(_, ref c)| c.is_none()) {
+			let schedule_and_insert_at = {
+				// advance the iterator until just before the core index we are looking at now.
+				while prev_scheduled_in_order.peek().map_or(
+					false,
+					|(_, assign)| (assign.core.0 as usize) < core_index,
+				) {
+					let _ = prev_scheduled_in_order.next();
+				}
+
+				// check the first entry already scheduled with core index >= than the one we
+				// are looking at. 3 cases:
+				//  1. No such entry, clearly this core is not scheduled, so we need to schedule and put at the end.
+				//  2. Entry exists and has same index as the core we are inspecting. do not schedule again.
+				//  3. Entry exists and has higher index than the core we are inspecting. schedule and note
+				//     insertion position.
+				prev_scheduled_in_order.peek().map_or(
+					Some(scheduled.len()),
+					|(idx_in_scheduled, assign)| if (assign.core.0 as usize) == core_index {
+						None
+					} else {
+						Some(*idx_in_scheduled)
+					},
+				)
+			};
 
-			// at this point, because `Scheduled` is guaranteed to be sorted and we navigated unassigned
-			// core indices in ascending order, we can enact the updates prepared by the previous actions.
-			//
-			// while inserting, we have to account for the amount of insertions already done.
-			//
-			// This is O(n) as well, capped at n operations, where n is the number of cores.
-			for (num_insertions_before, (insert_at, to_insert)) in scheduled_updates.into_iter().enumerate() {
-				let insert_at = num_insertions_before + insert_at;
-				scheduled.insert(insert_at, to_insert);
+			let schedule_and_insert_at = match schedule_and_insert_at {
+				None => continue,
+				Some(at) => at,
+			};
+
+			let core = CoreIndex(core_index as u32);
+
+			let core_assignment = if core_index < parachains.len() {
+				// parachain core.
+				Some(CoreAssignment {
+					kind: AssignmentKind::Parachain,
+					para_id: parachains[core_index],
+					core: core.clone(),
+					group_idx: Self::group_assigned_to_core(core, now)
+						.expect("core is not out of bounds and we are guaranteed \
+								to be after the most recent session start; qed"),
+				})
+			} else {
+				// parathread core offset, rel. to beginning.
+				let core_offset = (core_index - parachains.len()) as u32;
+
+				parathread_queue.take_next_on_core(core_offset).map(|entry| CoreAssignment {
+					kind: AssignmentKind::Parathread(entry.claim.1, entry.retries),
+					para_id: entry.claim.0,
+					core: core.clone(),
+					group_idx: Self::group_assigned_to_core(core, now)
+						.expect("core is not out of bounds and we are guaranteed \
+								to be after the most recent session start; qed"),
+				})
+			};
+
+			if let Some(assignment) = core_assignment {
+				scheduled_updates.push((schedule_and_insert_at, assignment))
 			}
+		}
 
-			// scheduled is guaranteed to be sorted after this point because it was sorted before, and we
-			// applied sorted updates at their correct positions, accounting for the offsets of previous
-			// insertions.
+		// at this point, because `Scheduled` is guaranteed to be sorted and we navigated unassigned
+		// core indices in ascending order, we can enact the updates prepared by the previous actions.
+		//
+		// while inserting, we have to account for the amount of insertions already done.
+		//
+		// This is O(n) as well, capped at n operations, where n is the number of cores.
+		for (num_insertions_before, (insert_at, to_insert)) in scheduled_updates.into_iter().enumerate() {
+			let insert_at = num_insertions_before + insert_at;
+			scheduled.insert(insert_at, to_insert);
 		}
 
-		Scheduled::set(scheduled);
-		ParathreadQueue::set(parathread_queue);
+		// scheduled is guaranteed to be sorted after this point because it was sorted before, and we
+		// applied sorted updates at their correct positions, accounting for the offsets of previous
+		// insertions.
 	}
 
 	/// Note that the given cores have become occupied. Behavior undefined if any of the given cores were not scheduled
@@ -496,6 +717,15 @@ impl<T: Trait> Module<T> {
 		ValidatorGroups::get().get(group_index.0 as usize).map(|g| g.clone())
 	}
 
+	/// Get all paras which are expected to be assigned to a core in the future: all parachains,
+	/// followed by all parathreads with a claim in the queue.
+	pub(crate) fn upcoming_paras() -> Vec<ParaId> {
+		let mut upcoming = <paras::Module<T>>::parachains();
+		upcoming.extend(ParathreadQueue::get().queue.into_iter().map(|queued| queued.claim.claim.0));
+
+		upcoming
+	}
+
 	/// Get the group assigned to a specific core by index at the current block number. Result undefined if the core index is unknown
 	/// or the block number is less than the session start index.
 	pub(crate) fn group_assigned_to_core(core: CoreIndex, at: T::BlockNumber) -> Option<GroupIndex> {
@@ -578,6 +808,37 @@ impl<T: Trait> Module<T> {
 			}))
 		}
 	}
+
+	/// Get the block number at which the candidate occupying the given core since `occupied_since`
+	/// would be timed out by the `availability_timeout_predicate`, if it does not become available
+	/// before. Returns `None` for unoccupied cores, or if the candidate can never time out under the
+	/// current configuration.
+	pub(crate) fn availability_timeout_at(core: CoreIndex, occupied_since: T::BlockNumber)
+		-> Option<T::BlockNumber>
+	{
+		let config = <configuration::Module<T>>::config();
+		let availability_period = match AvailabilityCores::get().get(core.0 as usize) {
+			None | Some(None) => return None,
+			Some(Some(CoreOccupied::Parachain)) => config.chain_availability_period,
+			Some(Some(CoreOccupied::Parathread(_))) => config.thread_availability_period,
+		};
+
+		let rotation_frequency = config.parachain_rotation_frequency;
+		if availability_period.is_zero() || rotation_frequency.is_zero() { return None }
+
+		// the earliest block at which the period has elapsed. Timing out is only done within
+		// `availability_period` blocks of a rotation, so if that is not the case, the candidate
+		// times out at the next rotation.
+		let session_start = <SessionStartBlock<T>>::get();
+		let earliest = occupied_since + availability_period;
+		let blocks_since_last_rotation = earliest.saturating_sub(session_start) % rotation_frequency;
+
+		if blocks_since_last_rotation < availability_period {
+			Some(earliest)
+		} else {
+			Some(earliest + (rotation_frequency - blocks_since_last_rotation))
+		}
+	}
 }
 
 #[cfg(test)]
@@ -588,7 +849,10 @@ mod tests {
 	use frame_support::traits::{OnFinalize, OnInitialize};
 	use keyring::Sr25519Keyring;
 
-	use crate::mock::{new_test_ext, Configuration, Paras, System, Scheduler, GenesisConfig as MockGenesisConfig};
+	use crate::mock::{
+		new_test_ext, events, Balances, Configuration, Origin, Paras, System, Scheduler, Test,
+		TestEvent, GenesisConfig as MockGenesisConfig,
+	};
 	use crate::initializer::SessionChangeNotification;
 	use crate::configuration::HostConfiguration;
 	use crate::paras::ParaGenesisArgs;
@@ -618,6 +882,13 @@ mod tests {
 		}
 	}
 
+	fn scheduler_events() -> Vec<RawEvent<u64>> {
+		events().into_iter().filter_map(|event| match event {
+			TestEvent::scheduler(event) => Some(event),
+			_ => None,
+		}).collect()
+	}
+
 	fn default_config() -> HostConfiguration<BlockNumber> {
 		HostConfiguration {
 			parathread_cores: 3,
@@ -1182,6 +1453,13 @@ mod tests {
 					},
 					core_offset: 2, // reassigned to next core. thread_e claim was on offset 1.
 				});
+
+				// the freed cores are no longer occupied.
+				let cores = AvailabilityCores::get();
+				assert!(cores[0].is_none());
+				assert!(cores[1].is_some());
+				assert!(cores[2].is_none());
+				assert!(cores[3].is_none());
 			}
 		});
 	}
@@ -1440,4 +1718,460 @@ mod tests {
 			assert!(Scheduler::availability_timeout_predicate().is_none());
 		});
 	}
+
+	#[test]
+	fn availability_timeout_at_agrees_with_predicate() {
+		let genesis_config = MockGenesisConfig {
+			configuration: crate::configuration::GenesisConfig {
+				config: default_config(),
+				..Default::default()
+			},
+			..Default::default()
+		};
+
+		let HostConfiguration {
+			parachain_rotation_frequency,
+			chain_availability_period,
+			..
+		} = default_config();
+		let collator = CollatorId::from(Sr25519Keyring::Alice.public());
+
+		let chain_a = ParaId::from(1);
+		let thread_a = ParaId::from(2);
+
+		let schedule_blank_para = |id, is_chain| Paras::schedule_para_initialize(id, ParaGenesisArgs {
+			genesis_head: Vec::new().into(),
+			validation_code: Vec::new().into(),
+			parachain: is_chain,
+		});
+
+		new_test_ext(genesis_config).execute_with(|| {
+			schedule_blank_para(chain_a, true);
+			schedule_blank_para(thread_a, false);
+
+			run_to_block(1, |number| match number {
+				1 => Some(SessionChangeNotification {
+					new_config: default_config(),
+					validators: vec![
+						ValidatorId::from(Sr25519Keyring::Alice.public()),
+						ValidatorId::from(Sr25519Keyring::Bob.public()),
+						ValidatorId::from(Sr25519Keyring::Charlie.public()),
+						ValidatorId::from(Sr25519Keyring::Dave.public()),
+						ValidatorId::from(Sr25519Keyring::Eve.public()),
+					],
+					..Default::default()
+				}),
+				_ => None,
+			});
+
+			AvailabilityCores::mutate(|cores| {
+				cores[0] = Some(CoreOccupied::Parachain);
+				cores[1] = Some(CoreOccupied::Parathread(ParathreadEntry {
+					claim: ParathreadClaim(thread_a, collator),
+					retries: 0,
+				}))
+			});
+
+			assert!(Scheduler::availability_timeout_at(CoreIndex(2), 1).is_none());
+
+			let occupied_since = 2;
+			let timeout_at: Vec<_> = (0..2)
+				.map(|core| Scheduler::availability_timeout_at(CoreIndex(core), occupied_since).unwrap())
+				.collect();
+
+			// occupied too late after the session start to time out before the first rotation.
+			assert_eq!(timeout_at, vec![
+				1 + parachain_rotation_frequency,
+				1 + parachain_rotation_frequency,
+			]);
+
+			// check against the predicate at every block until both have timed out.
+			for now in occupied_since..=timeout_at[1] {
+				run_to_block(now, |_| None);
+
+				for core in 0..2 {
+					let timed_out = Scheduler::availability_timeout_predicate()
+						.map_or(false, |pred| pred(CoreIndex(core), occupied_since));
+
+					assert_eq!(timed_out, now >= timeout_at[core as usize]);
+				}
+			}
+
+			// occupied just before a rotation, the timeout is as soon as the period has elapsed.
+			let occupied_since = parachain_rotation_frequency;
+			assert_eq!(
+				Scheduler::availability_timeout_at(CoreIndex(0), occupied_since),
+				Some(occupied_since + chain_availability_period),
+			);
+		});
+	}
+
+	#[test]
+	fn predict_next_scheduled_matches_schedule() {
+		let genesis_config = MockGenesisConfig {
+			configuration: crate::configuration::GenesisConfig {
+				config: default_config(),
+				..Default::default()
+			},
+			..Default::default()
+		};
+
+		let chain_a = ParaId::from(1);
+		let chain_b = ParaId::from(2);
+
+		let thread_a = ParaId::from(3);
+		let thread_b = ParaId::from(4);
+		let thread_c = ParaId::from(5);
+
+		let collator = CollatorId::from(Sr25519Keyring::Alice.public());
+
+		let schedule_blank_para = |id, is_chain| Paras::schedule_para_initialize(id, ParaGenesisArgs {
+			genesis_head: Vec::new().into(),
+			validation_code: Vec::new().into(),
+			parachain: is_chain,
+		});
+
+		new_test_ext(genesis_config).execute_with(|| {
+			schedule_blank_para(chain_a, true);
+			schedule_blank_para(chain_b, true);
+			schedule_blank_para(thread_a, false);
+			schedule_blank_para(thread_b, false);
+			schedule_blank_para(thread_c, false);
+
+			run_to_block(1, |number| match number {
+				1 => Some(SessionChangeNotification {
+					new_config: default_config(),
+					validators: vec![
+						ValidatorId::from(Sr25519Keyring::Alice.public()),
+						ValidatorId::from(Sr25519Keyring::Bob.public()),
+						ValidatorId::from(Sr25519Keyring::Charlie.public()),
+						ValidatorId::from(Sr25519Keyring::Dave.public()),
+						ValidatorId::from(Sr25519Keyring::Eve.public()),
+					],
+					..Default::default()
+				}),
+				_ => None,
+			});
+
+			Scheduler::add_parathread_claim(ParathreadClaim(thread_a, collator.clone()));
+			Scheduler::add_parathread_claim(ParathreadClaim(thread_b, collator.clone()));
+
+			run_to_block(2, |_| None);
+
+			assert_eq!(Scheduler::scheduled().len(), 4);
+			Scheduler::occupied(&[CoreIndex(0), CoreIndex(1), CoreIndex(2), CoreIndex(3)]);
+
+			Scheduler::add_parathread_claim(ParathreadClaim(thread_c, collator.clone()));
+
+			// finalize the block, as the runtime API would see it.
+			Scheduler::initializer_finalize();
+
+			let freed = || vec![
+				(CoreIndex(0), FreedReason::Concluded),
+				(CoreIndex(3), FreedReason::TimedOut),
+			];
+
+			let cores_before = AvailabilityCores::get();
+			let queue_before = ParathreadQueue::get();
+
+			let predicted = Scheduler::predict_next_scheduled(&freed());
+
+			assert_eq!(AvailabilityCores::get(), cores_before);
+			assert_eq!(ParathreadQueue::get(), queue_before);
+			assert!(Scheduler::scheduled().is_empty());
+
+			run_to_block(3, |_| None);
+			Scheduler::schedule(freed());
+
+			assert!(predicted.iter().any(|a| a.core == CoreIndex(0) && a.para_id == chain_a));
+			assert_eq!(predicted, Scheduler::scheduled());
+		});
+	}
+
+	#[test]
+	fn claim_parathread_charges_fee_by_queue_occupancy() {
+		let config = HostConfiguration {
+			parathread_cores: 1,
+			scheduling_lookahead: 2,
+			parathread_claim_fee: 10,
+			..default_config()
+		};
+		let genesis_config = MockGenesisConfig {
+			configuration: crate::configuration::GenesisConfig {
+				config,
+				..Default::default()
+			},
+			balances: balances::GenesisConfig {
+				balances: vec![(1, 1_000)],
+			},
+			..Default::default()
+		};
+
+		let thread_a = ParaId::from(1);
+		let thread_b = ParaId::from(2);
+		let thread_c = ParaId::from(3);
+		let collator = CollatorId::from(Sr25519Keyring::Alice.public());
+
+		new_test_ext(genesis_config).execute_with(|| {
+			for &id in &[thread_a, thread_b, thread_c] {
+				Paras::schedule_para_initialize(id, ParaGenesisArgs {
+					genesis_head: Vec::new().into(),
+					validation_code: Vec::new().into(),
+					parachain: false,
+				});
+			}
+
+			run_to_block(10, |n| if n == 10 { Some(Default::default()) } else { None });
+
+			let claim = |id, max_fee| Scheduler::claim_parathread(
+				Origin::signed(1),
+				id,
+				collator.clone(),
+				max_fee,
+			);
+
+			assert_eq!(Scheduler::parathread_claim_fee(), 10);
+			assert_eq!(claim(thread_a, 9), Err(Error::<Test>::ParathreadClaimFeeTooHigh.into()));
+			assert_eq!(claim(ParaId::from(4), 10), Err(Error::<Test>::NotParathread.into()));
+
+			assert!(claim(thread_a, 10).is_ok());
+			assert_eq!(Balances::reserved_balance(1), 10);
+			assert_eq!(Scheduler::parathread_claim_fee(), 20);
+			assert_eq!(claim(thread_a, 20), Err(Error::<Test>::ParathreadClaimExists.into()));
+
+			assert!(claim(thread_b, 20).is_ok());
+			assert_eq!(Balances::reserved_balance(1), 30);
+			assert_eq!(claim(thread_c, 30), Err(Error::<Test>::ParathreadQueueFull.into()));
+			assert_eq!(Balances::reserved_balance(1), 30);
+
+			assert_eq!(ParathreadQueue::get().queue.len(), 2);
+			assert_eq!(ParathreadClaimIndex::get(), vec![thread_a, thread_b]);
+
+			assert_eq!(scheduler_events(), vec![
+				RawEvent::ParathreadClaimed(thread_a, 1, 10),
+				RawEvent::ParathreadClaimed(thread_b, 1, 20),
+			]);
+		});
+	}
+
+	#[test]
+	fn parathread_claim_fees_are_burned_or_refunded() {
+		let max_retries = default_config().parathread_retries;
+		let config = HostConfiguration {
+			parathread_claim_fee: 10,
+			..default_config()
+		};
+		let genesis_config = MockGenesisConfig {
+			configuration: crate::configuration::GenesisConfig {
+				config: config.clone(),
+				..Default::default()
+			},
+			balances: balances::GenesisConfig {
+				balances: vec![(1, 1_000)],
+			},
+			..Default::default()
+		};
+
+		let thread_a = ParaId::from(1);
+		let thread_b = ParaId::from(2);
+		let thread_c = ParaId::from(3);
+		let collator = CollatorId::from(Sr25519Keyring::Alice.public());
+
+		new_test_ext(genesis_config).execute_with(|| {
+			for &id in &[thread_a, thread_b, thread_c] {
+				Paras::schedule_para_initialize(id, ParaGenesisArgs {
+					genesis_head: Vec::new().into(),
+					validation_code: Vec::new().into(),
+					parachain: false,
+				});
+			}
+
+			let new_session = |config: &HostConfiguration<BlockNumber>| SessionChangeNotification {
+				new_config: config.clone(),
+				validators: vec![
+					ValidatorId::from(Sr25519Keyring::Alice.public()),
+					ValidatorId::from(Sr25519Keyring::Eve.public()),
+				],
+				..Default::default()
+			};
+
+			run_to_block(1, |n| if n == 1 { Some(new_session(&config)) } else { None });
+
+			let claim = |id| Scheduler::claim_parathread(Origin::signed(1), id, collator.clone(), 100);
+
+			// the claim on A is concluded, the claim on B expires.
+			assert!(claim(thread_a).is_ok());
+			assert!(claim(thread_b).is_ok());
+			assert_eq!(Balances::reserved_balance(1), 30);
+
+			run_to_block(2, |_| None);
+
+			let core_a = Scheduler::scheduled().iter()
+				.find(|assignment| assignment.para_id == thread_a)
+				.map(|assignment| assignment.core)
+				.unwrap();
+
+			Scheduler::occupied(&[core_a]);
+			Scheduler::schedule(vec![(core_a, FreedReason::Concluded)]);
+			assert_eq!(Balances::reserved_balance(1), 20);
+
+			run_to_block(2 + max_retries + 1, |_| None);
+			assert_eq!(Balances::reserved_balance(1), 0);
+			assert_eq!(Balances::free_balance(1), 970);
+			assert!(ParathreadClaimIndex::get().is_empty());
+
+			// the claim on C is refunded when C is offboarded.
+			assert!(claim(thread_c).is_ok());
+			assert_eq!(Balances::reserved_balance(1), 10);
+
+			Paras::schedule_para_cleanup(thread_c);
+			let n = System::block_number() + 1;
+			run_to_block(n, |b| if b == n { Some(new_session(&config)) } else { None });
+
+			assert_eq!(Balances::reserved_balance(1), 0);
+			assert_eq!(Balances::free_balance(1), 970);
+			assert!(ParathreadClaimIndex::get().is_empty());
+
+			assert_eq!(scheduler_events(), vec![
+				RawEvent::ParathreadClaimed(thread_a, 1, 10),
+				RawEvent::ParathreadClaimed(thread_b, 1, 20),
+				RawEvent::ParathreadClaimFeeBurned(thread_a, 1, 10),
+				RawEvent::ParathreadClaimFeeBurned(thread_b, 1, 20),
+				RawEvent::ParathreadClaimed(thread_c, 1, 10),
+				RawEvent::ParathreadClaimFeeRefunded(thread_c, 1, 10),
+			]);
+		});
+	}
+}
+
+#[cfg(feature = "runtime-benchmarks")]
+mod benchmarking {
+	use super::*;
+	use system as frame_system; // NOTE: required for the benchmarks! macro
+	use system::RawOrigin;
+	use frame_benchmarking::{benchmarks, account};
+	use frame_support::traits::Currency;
+	use primitives::v1::ValidatorId;
+	use sp_core::sr25519;
+	use crate::paras::ParaGenesisArgs;
+
+	// The most parachains, each of which has a core of its own.
+	const MAX_PARACHAINS: u32 = 100;
+	// The number of parathread cores.
+	const PARATHREAD_CORES: u32 = 10;
+	// The most queued parathread claims.
+	const MAX_QUEUED: u32 = 100;
+
+	fn public(i: u32) -> sr25519::Public {
+		let mut raw = [0u8; 32];
+		raw[..4].copy_from_slice(&i.to_le_bytes());
+		sr25519::Public::from_raw(raw)
+	}
+
+	benchmarks! {
+		_ { }
+
+		// Benchmark `schedule` with `c` parachains and `q` queued parathread claims.
+		schedule {
+			let c in 0 .. MAX_PARACHAINS;
+			let q in 0 .. MAX_QUEUED;
+
+			configuration::Module::<T>::set_parathread_cores(RawOrigin::Root.into(), PARATHREAD_CORES)?;
+			configuration::Module::<T>::set_scheduling_lookahead(
+				RawOrigin::Root.into(),
+				MAX_QUEUED / PARATHREAD_CORES,
+			)?;
+			configuration::Module::<T>::set_parachain_rotation_frequency(RawOrigin::Root.into(), 10u32.into())?;
+			configuration::Module::<T>::initializer_on_new_session(&[], &[]);
+
+			for i in 0 .. c + q {
+				paras::Module::<T>::schedule_para_initialize(ParaId::from(i), ParaGenesisArgs {
+					genesis_head: Default::default(),
+					validation_code: Default::default(),
+					parachain: i < c,
+				});
+			}
+
+			// one validator for each core.
+			let notification = SessionChangeNotification {
+				validators: (0 .. c + PARATHREAD_CORES).map(|i| ValidatorId::from(public(i))).collect(),
+				new_config: <configuration::Module<T>>::config(),
+				session_index: 1,
+				..Default::default()
+			};
+			paras::Module::<T>::initializer_on_new_session(&notification);
+			Module::<T>::initializer_on_new_session(&notification);
+
+			for i in c .. c + q {
+				Module::<T>::add_parathread_claim(
+					ParathreadClaim(ParaId::from(i), CollatorId::from(public(i))),
+				);
+			}
+		}: {
+			Module::<T>::schedule(Vec::new());
+		}
+		verify {
+			assert_eq!(
+				Scheduled::get().len() as u32,
+				c + sp_std::cmp::min(q, PARATHREAD_CORES),
+			);
+		}
+
+		// Benchmark `claim_parathread` with `q` parathread claims already queued.
+		claim_parathread {
+			let q in 0 .. MAX_QUEUED;
+
+			configuration::Module::<T>::set_parathread_cores(RawOrigin::Root.into(), PARATHREAD_CORES)?;
+			configuration::Module::<T>::set_scheduling_lookahead(
+				RawOrigin::Root.into(),
+				MAX_QUEUED / PARATHREAD_CORES + 1,
+			)?;
+			configuration::Module::<T>::set_parathread_claim_fee(RawOrigin::Root.into(), 1_000)?;
+			configuration::Module::<T>::initializer_on_new_session(&[], &[]);
+
+			for i in 0 ..= q {
+				paras::Module::<T>::schedule_para_initialize(ParaId::from(i), ParaGenesisArgs {
+					genesis_head: Default::default(),
+					validation_code: Default::default(),
+					parachain: false,
+				});
+			}
+
+			let notification = SessionChangeNotification {
+				validators: (0 .. PARATHREAD_CORES).map(|i| ValidatorId::from(public(i))).collect(),
+				new_config: <configuration::Module<T>>::config(),
+				session_index: 1,
+				..Default::default()
+			};
+			paras::Module::<T>::initializer_on_new_session(&notification);
+			Module::<T>::initializer_on_new_session(&notification);
+
+			for i in 0 .. q {
+				Module::<T>::add_parathread_claim(
+					ParathreadClaim(ParaId::from(i), CollatorId::from(public(i))),
+				);
+			}
+
+			let caller: T::AccountId = account("caller", 0, 0);
+			T::Currency::make_free_balance_be(&caller, Balance::max_value() / 2);
+		}: _(RawOrigin::Signed(caller), ParaId::from(q), CollatorId::from(public(q)), Balance::max_value())
+		verify {
+			assert!(ParathreadClaimIndex::get().binary_search(&ParaId::from(q)).is_ok());
+		}
+	}
+
+	#[cfg(test)]
+	mod tests {
+		use super::*;
+		use crate::mock::{new_test_ext, Test};
+		use frame_support::assert_ok;
+
+		#[test]
+		fn test_benchmarks() {
+			new_test_ext(Default::default()).execute_with(|| {
+				assert_ok!(test_benchmark_schedule::<Test>());
+				assert_ok!(test_benchmark_claim_parathread::<Test>());
+			});
+		}
+	}
 }
@@ -0,0 +1,201 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The session info module keeps information about recent sessions.
+//!
+//! The validator set and its assignment to groups change at every session boundary, but
+//! candidates of a session may need to be checked after the session has ended. This module keeps
+//! the information needed for that for the last `config.dispute_period` sessions, in addition to
+//! the current one.
+
+use sp_std::prelude::*;
+use primitives::v1::{AuthorityDiscoveryId, SessionIndex, SessionInfo};
+use frame_support::{
+	decl_storage, decl_module, decl_error,
+	traits::Get,
+	weights::Weight,
+};
+
+use crate::{configuration, scheduler};
+
+pub trait Trait: system::Trait + configuration::Trait + scheduler::Trait {
+	/// The authority discovery keys of the current validator set, in the same order as the
+	/// validators.
+	type AuthorityDiscovery: Get<Vec<AuthorityDiscoveryId>>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as ParaSessionInfo {
+		/// The earliest session for which information is stored.
+		EarliestStoredSession get(fn earliest_stored_session): SessionIndex;
+
+		/// The current session index.
+		CurrentSessionIndex get(fn session_index): SessionIndex;
+
+		/// Information about recent sessions, by session index.
+		///
+		/// Sessions are stored from `EarliestStoredSession` up to and including the current session.
+		Sessions get(fn session_info): map hasher(identity) SessionIndex => Option<SessionInfo>;
+	}
+}
+
+decl_error! {
+	pub enum Error for Module<T: Trait> { }
+}
+
+decl_module! {
+	/// The session info module.
+	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
+		type Error = Error<T>;
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// Called by the initializer to initialize the session info module.
+	pub(crate) fn initializer_initialize(_now: T::BlockNumber) -> Weight {
+		0
+	}
+
+	/// Called by the initializer to finalize the session info module.
+	pub(crate) fn initializer_finalize() { }
+
+	/// Called by the initializer to note that a new session has started.
+	///
+	/// This must be called after the scheduler has handled the session change, as the validator
+	/// groups and the number of cores of the new session are taken from it.
+	pub(crate) fn initializer_on_new_session(
+		notification: &crate::initializer::SessionChangeNotification<T::BlockNumber>
+	) {
+		let config = &notification.new_config;
+		let new_session_index = notification.session_index;
+		let new_earliest = new_session_index.saturating_sub(config.dispute_period);
+
+		if EarliestStoredSession::exists() {
+			for session in EarliestStoredSession::get()..new_earliest {
+				Sessions::remove(session);
+			}
+
+			if new_earliest > EarliestStoredSession::get() {
+				EarliestStoredSession::set(new_earliest);
+			}
+		} else {
+			// nothing is stored before the first session this module sees.
+			EarliestStoredSession::set(new_session_index);
+		}
+
+		let info = SessionInfo {
+			validators: notification.validators.clone(),
+			discovery_keys: T::AuthorityDiscovery::get(),
+			validator_groups: <scheduler::Module<T>>::validator_groups(),
+			n_cores: <scheduler::Module<T>>::availability_cores().len() as u32,
+			needed_approvals: config.needed_approvals,
+		};
+
+		Sessions::insert(new_session_index, info);
+		CurrentSessionIndex::set(new_session_index);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::v1::ValidatorId;
+	use keyring::Sr25519Keyring;
+
+	use crate::mock::{new_test_ext, SessionInfo as ParaSessionInfo, Scheduler};
+	use crate::initializer::SessionChangeNotification;
+	use crate::configuration::HostConfiguration;
+
+	fn config(dispute_period: SessionIndex) -> HostConfiguration<primitives::v1::BlockNumber> {
+		HostConfiguration {
+			parathread_cores: 2,
+			parachain_rotation_frequency: 10,
+			chain_availability_period: 3,
+			thread_availability_period: 3,
+			needed_approvals: 3,
+			dispute_period,
+			..Default::default()
+		}
+	}
+
+	fn new_session(session_index: SessionIndex, dispute_period: SessionIndex) {
+		let notification = SessionChangeNotification {
+			validators: vec![
+				ValidatorId::from(Sr25519Keyring::Alice.public()),
+				ValidatorId::from(Sr25519Keyring::Bob.public()),
+				ValidatorId::from(Sr25519Keyring::Charlie.public()),
+			],
+			new_config: config(dispute_period),
+			session_index,
+			..Default::default()
+		};
+
+		Scheduler::initializer_on_new_session(&notification);
+		ParaSessionInfo::initializer_on_new_session(&notification);
+	}
+
+	#[test]
+	fn session_info_is_recorded() {
+		new_test_ext(Default::default()).execute_with(|| {
+			new_session(1, 2);
+
+			let info = ParaSessionInfo::session_info(1).unwrap();
+			assert_eq!(info.validators.len(), 3);
+			assert!(info.discovery_keys.is_empty());
+			assert_eq!(info.validator_groups, Scheduler::validator_groups());
+			assert_eq!(info.validator_groups.len(), 2);
+			assert_eq!(info.n_cores, 2);
+			assert_eq!(info.needed_approvals, 3);
+
+			assert_eq!(ParaSessionInfo::earliest_stored_session(), 1);
+			assert_eq!(ParaSessionInfo::session_index(), 1);
+			assert!(ParaSessionInfo::session_info(0).is_none());
+			assert!(ParaSessionInfo::session_info(2).is_none());
+		})
+	}
+
+	#[test]
+	fn sessions_outside_dispute_period_are_pruned() {
+		new_test_ext(Default::default()).execute_with(|| {
+			for session in 1..=3 {
+				new_session(session, 2);
+			}
+
+			assert_eq!(ParaSessionInfo::earliest_stored_session(), 1);
+			for session in 1..=3 {
+				assert!(ParaSessionInfo::session_info(session).is_some());
+			}
+
+			new_session(4, 2);
+
+			assert_eq!(ParaSessionInfo::earliest_stored_session(), 2);
+			assert!(ParaSessionInfo::session_info(1).is_none());
+			for session in 2..=4 {
+				assert!(ParaSessionInfo::session_info(session).is_some());
+			}
+
+			// shrinking the dispute period prunes everything now outside of it.
+			new_session(5, 0);
+
+			assert_eq!(ParaSessionInfo::earliest_stored_session(), 5);
+			for session in 1..5 {
+				assert!(ParaSessionInfo::session_info(session).is_none());
+			}
+			assert!(ParaSessionInfo::session_info(5).is_some());
+		})
+	}
+}
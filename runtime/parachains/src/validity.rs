@@ -13,3 +13,821 @@
 
 // You should have received a copy of the GNU General Public License
 // along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! The validity module is responsible for the acceptance period of included candidates.
+//!
+//! Every candidate enacted by the inclusion module is noted here along with the validators that
+//! backed it. Until `config.acceptance_period` blocks have passed, validators of the session the
+//! candidate was included in may submit signed statements disputing or confirming its validity,
+//! as long as the session info module still keeps the validator set of that session.
+//! Once a supermajority of the validator set agrees that the candidate is invalid, the para is
+//! frozen and all validators which vouched for the candidate are reported for an offence. Root may
+//! discard an open dispute or unfreeze a frozen para.
+
+use sp_std::prelude::*;
+use primitives::v1::{
+	ValidatorId, ValidatorIndex, Id as ParaId, Hash, SigningContext, SignedStatement,
+	CompactStatement, PARACHAIN_KEY_TYPE_ID,
+};
+use frame_support::{
+	decl_storage, decl_module, decl_error, ensure, Parameter,
+	dispatch::DispatchResult,
+	traits::{Get, KeyOwnerProofSystem},
+	weights::{DispatchClass, Weight},
+};
+use codec::{Encode, Decode};
+use bitvec::{order::Lsb0 as BitOrderLsb0, vec::BitVec};
+use sp_runtime::{KeyTypeId, Perbill, RuntimeDebug, traits::Saturating};
+use sp_staking::{
+	SessionIndex,
+	offence::{Offence, ReportOffence, Kind},
+};
+use system::{ensure_root, ensure_signed};
+
+use crate::{configuration, paras, session_info};
+
+/// A candidate which was included and is within its acceptance period.
+#[derive(Encode, Decode, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct IncludedCandidate<H, N> {
+	/// The para the candidate belongs to.
+	para_id: ParaId,
+	/// The relay-parent of the candidate, which is part of the signing context of statements.
+	relay_parent: H,
+	/// The session the candidate was backed and included in.
+	session: SessionIndex,
+	/// The block number of the relay-chain block this was included in.
+	included_in_number: N,
+	/// The validators which backed the candidate, by their index in the session's validator set.
+	backers: Vec<ValidatorIndex>,
+}
+
+/// The votes on an included candidate whose validity has been disputed.
+#[derive(Encode, Decode, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+pub struct DisputeState {
+	/// Validators which have attested to the validity of the candidate. One bit per validator.
+	valid_votes: BitVec<BitOrderLsb0, u8>,
+	/// Validators which have attested to the invalidity of the candidate. One bit per validator.
+	invalid_votes: BitVec<BitOrderLsb0, u8>,
+}
+
+/// An offence that is filed when a candidate that validators have vouched for, either by backing
+/// it or by voting for its validity in a dispute, is confirmed to be invalid.
+#[derive(RuntimeDebug)]
+#[cfg_attr(feature = "std", derive(Clone, PartialEq, Eq))]
+pub struct InvalidCandidateOffence<Offender> {
+	/// The session index in which the invalid candidate was included.
+	session_index: SessionIndex,
+	/// The size of the validator set in that session.
+	validator_set_count: u32,
+	/// The validators that vouched for the invalid candidate.
+	offenders: Vec<Offender>,
+}
+
+impl<Offender: Clone> Offence<Offender> for InvalidCandidateOffence<Offender> {
+	const ID: Kind = *b"para:bad-backing";
+	type TimeSlot = SessionIndex;
+
+	fn offenders(&self) -> Vec<Offender> {
+		self.offenders.clone()
+	}
+
+	fn session_index(&self) -> SessionIndex {
+		self.session_index
+	}
+
+	fn validator_set_count(&self) -> u32 {
+		self.validator_set_count
+	}
+
+	fn time_slot(&self) -> Self::TimeSlot {
+		self.session_index
+	}
+
+	fn slash_fraction(_offenders_count: u32, _validator_set_count: u32) -> Perbill {
+		// Slash 100%.
+		Perbill::from_percent(100)
+	}
+}
+
+/// The weight of verifying the signature of a single dispute statement.
+const STATEMENT_VERIFICATION_WEIGHT: Weight = 60_000_000;
+
+pub trait Trait: system::Trait + configuration::Trait + paras::Trait + session_info::Trait {
+	/// Used to obtain the full identification of validators in order to report offences.
+	///
+	/// Key ownership is proven against the current session, so validators that have rotated their
+	/// parachain keys since the offending session are not reported.
+	type KeyOwnerProofSystem: KeyOwnerProofSystem<
+		(KeyTypeId, ValidatorId),
+		IdentificationTuple = Self::IdentificationTuple,
+	>;
+
+	/// An identification tuple type bound to `Parameter`.
+	type IdentificationTuple: Parameter;
+
+	/// Report an offence.
+	type ReportOffence: ReportOffence<
+		Self::AccountId,
+		Self::IdentificationTuple,
+		InvalidCandidateOffence<Self::IdentificationTuple>,
+	>;
+}
+
+decl_storage! {
+	trait Store for Module<T: Trait> as ParaValidity {
+		/// Candidates within their acceptance period, by candidate hash.
+		IncludedCandidates: map hasher(identity) Hash
+			=> Option<IncludedCandidate<T::Hash, T::BlockNumber>>;
+
+		/// The hashes of candidates within their acceptance period along with the block number and
+		/// session they were included in, ordered ascending by block number.
+		AcceptancePeriodQueue: Vec<(T::BlockNumber, SessionIndex, Hash)>;
+
+		/// Open disputes, by candidate hash.
+		Disputes: map hasher(identity) Hash => Option<DisputeState>;
+
+		/// Paras which had a candidate confirmed invalid, along with the hash of that candidate.
+		///
+		/// Candidates of frozen paras are neither backed nor enacted.
+		FrozenParas: map hasher(twox_64_concat) ParaId => Option<Hash>;
+	}
+}
+
+decl_error! {
+	pub enum Error for Module<T: Trait> {
+		/// The candidate is unknown or not within its acceptance period.
+		CandidateNotInAcceptancePeriod,
+		/// The validator set of the candidate's session is not known.
+		UnknownSession,
+		/// A statement refers to a different candidate.
+		StatementForWrongCandidate,
+		/// Validator index out of bounds.
+		ValidatorIndexOutOfBounds,
+		/// Invalid statement signature.
+		InvalidStatementSignature,
+		/// A validator has attested to both the validity and invalidity of the candidate.
+		ConflictingStatements,
+		/// A validator has already attested to the validity or invalidity of the candidate.
+		DuplicateStatement,
+		/// There are more statements than validators in the candidate's session.
+		TooManyStatements,
+		/// Statements submitted without a dispute being open or opened by them, or no dispute is
+		/// open on the candidate.
+		NoDispute,
+		/// The para is not frozen.
+		ParaNotFrozen,
+	}
+}
+
+decl_module! {
+	/// The parachain validity module.
+	pub struct Module<T: Trait> for enum Call where origin: <T as system::Trait>::Origin, system = system {
+		type Error = Error<T>;
+
+		/// Submit statements on the validity of a candidate within its acceptance period.
+		///
+		/// A dispute is opened by a statement attesting to the invalidity of the candidate, at
+		/// which point the backers of the candidate are counted as having attested to its validity.
+		/// The dispute concludes once a supermajority of the validators of the candidate's session
+		/// agree on either side. If the candidate is confirmed invalid, its para is frozen and all
+		/// validators that vouched for it are reported, with the sender as the reporter.
+		///
+		/// At most one statement per validator of the candidate's session is accepted, and
+		/// validators may not vote again on the same candidate.
+		#[weight = (100_000_000 as Weight)
+			.saturating_add(STATEMENT_VERIFICATION_WEIGHT.saturating_mul(statements.len() as Weight))
+			.saturating_add(T::DbWeight::get().reads_writes(4, 4))
+		]
+		pub fn submit_dispute_statements(
+			origin,
+			candidate_hash: Hash,
+			statements: Vec<SignedStatement>,
+		) -> DispatchResult {
+			let who = ensure_signed(origin)?;
+			Self::process_dispute_statements(who, candidate_hash, statements)
+		}
+
+		/// Discard the open dispute on a candidate, e.g. one opened because of a fault in the
+		/// validation logic rather than in the candidate. The candidate remains within its
+		/// acceptance period and may be disputed again.
+		#[weight = (10_000_000, DispatchClass::Operational)]
+		pub fn discard_dispute(origin, candidate_hash: Hash) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(Disputes::contains_key(&candidate_hash), Error::<T>::NoDispute);
+
+			Disputes::remove(&candidate_hash);
+			Ok(())
+		}
+
+		/// Unfreeze a para which had a candidate confirmed invalid.
+		#[weight = (1_000, DispatchClass::Operational)]
+		pub fn unfreeze_para(origin, id: ParaId) -> DispatchResult {
+			ensure_root(origin)?;
+			ensure!(FrozenParas::contains_key(&id), Error::<T>::ParaNotFrozen);
+
+			FrozenParas::remove(&id);
+			Ok(())
+		}
+	}
+}
+
+impl<T: Trait> Module<T> {
+	/// Block initialization logic, called by initializer.
+	pub(crate) fn initializer_initialize(now: T::BlockNumber) -> Weight {
+		let config = <configuration::Module<T>>::config();
+
+		let mut queue = <Self as Store>::AcceptancePeriodQueue::get();
+		let n_expired = queue.iter()
+			.take_while(|(included_in, _, _)| {
+				now.saturating_sub(*included_in) >= config.acceptance_period
+			})
+			.count();
+
+		// config and queue reads.
+		let mut weight = T::DbWeight::get().reads_writes(2, 0);
+		if n_expired == 0 {
+			return weight;
+		}
+
+		// candidates that leave the acceptance period are accepted, along with any
+		// unconcluded dispute on them.
+		for (_, _, candidate_hash) in queue.drain(..n_expired) {
+			<Self as Store>::IncludedCandidates::remove(&candidate_hash);
+			Disputes::remove(&candidate_hash);
+		}
+		weight += T::DbWeight::get().reads_writes(0, 2 * n_expired as Weight + 1);
+
+		<Self as Store>::AcceptancePeriodQueue::set(queue);
+
+		weight
+	}
+
+	/// Block finalization logic, called by initializer.
+	pub(crate) fn initializer_finalize() { }
+
+	/// Note that a candidate has been included, starting its acceptance period.
+	pub(crate) fn note_included(
+		candidate_hash: Hash,
+		para_id: ParaId,
+		relay_parent: T::Hash,
+		backers: Vec<ValidatorIndex>,
+	) -> Weight {
+		let now = <system::Module<T>>::block_number();
+		let session = <session_info::Module<T>>::session_index();
+
+		<Self as Store>::IncludedCandidates::insert(&candidate_hash, IncludedCandidate {
+			para_id,
+			relay_parent,
+			session,
+			included_in_number: now,
+			backers,
+		});
+		<Self as Store>::AcceptancePeriodQueue::append((now, session, candidate_hash));
+
+		T::DbWeight::get().reads_writes(1, 2)
+	}
+
+	/// Whether the para had a candidate confirmed invalid and is frozen.
+	pub(crate) fn is_frozen(id: ParaId) -> bool {
+		FrozenParas::contains_key(&id)
+	}
+
+	/// Freeze a para because of the given candidate being confirmed invalid.
+	pub(crate) fn freeze_para(id: ParaId, candidate_hash: Hash) -> Weight {
+		FrozenParas::insert(&id, &candidate_hash);
+
+		T::DbWeight::get().reads_writes(0, 1)
+	}
+
+	fn process_dispute_statements(
+		reporter: T::AccountId,
+		candidate_hash: Hash,
+		statements: Vec<SignedStatement>,
+	) -> DispatchResult {
+		let candidate = <Self as Store>::IncludedCandidates::get(&candidate_hash)
+			.ok_or(Error::<T>::CandidateNotInAcceptancePeriod)?;
+		let validators = <session_info::Module<T>>::session_info(candidate.session)
+			.map(|info| info.validators)
+			.ok_or(Error::<T>::UnknownSession)?;
+		ensure!(statements.len() <= validators.len(), Error::<T>::TooManyStatements);
+
+		let mut dispute = Disputes::get(&candidate_hash).unwrap_or_else(|| {
+			let mut valid_votes = bitvec::bitvec![BitOrderLsb0, u8; 0; validators.len()];
+			for backer in &candidate.backers {
+				if let Some(mut bit) = valid_votes.get_mut(*backer as usize) {
+					*bit = true;
+				}
+			}
+
+			DisputeState {
+				valid_votes,
+				invalid_votes: bitvec::bitvec![BitOrderLsb0, u8; 0; validators.len()],
+			}
+		});
+
+		let signing_context = SigningContext {
+			parent_hash: candidate.relay_parent.clone(),
+			session_index: candidate.session,
+		};
+
+		// do all checks before writing storage.
+		for statement in &statements {
+			ensure!(
+				statement.payload().candidate_hash() == &candidate_hash,
+				Error::<T>::StatementForWrongCandidate,
+			);
+
+			let validator_index = statement.validator_index() as usize;
+			let validator_public = validators.get(validator_index)
+				.ok_or(Error::<T>::ValidatorIndexOutOfBounds)?;
+
+			// seconding a candidate is an implicit attestation to its validity.
+			let (votes, opposing_votes) = match statement.payload() {
+				CompactStatement::Candidate(_) | CompactStatement::Valid(_)
+					=> (&mut dispute.valid_votes, &dispute.invalid_votes),
+				CompactStatement::Invalid(_)
+					=> (&mut dispute.invalid_votes, &dispute.valid_votes),
+			};
+
+			// reject votes of validators which have already voted before the costly signature check.
+			ensure!(!opposing_votes[validator_index], Error::<T>::ConflictingStatements);
+			ensure!(!votes[validator_index], Error::<T>::DuplicateStatement);
+
+			statement.check_signature(&signing_context, validator_public)
+				.map_err(|_| Error::<T>::InvalidStatementSignature)?;
+
+			votes.set(validator_index, true);
+		}
+
+		ensure!(dispute.invalid_votes.any(), Error::<T>::NoDispute);
+
+		let threshold = supermajority_threshold(validators.len());
+
+		if dispute.invalid_votes.count_ones() >= threshold {
+			Self::conclude_invalid(reporter, candidate_hash, candidate, &validators, dispute);
+		} else if dispute.valid_votes.count_ones() >= threshold {
+			// the candidate is confirmed valid and can no longer be disputed.
+			<Self as Store>::IncludedCandidates::remove(&candidate_hash);
+			Disputes::remove(&candidate_hash);
+		} else {
+			Disputes::insert(&candidate_hash, &dispute);
+		}
+
+		Ok(())
+	}
+
+	fn conclude_invalid(
+		reporter: T::AccountId,
+		candidate_hash: Hash,
+		candidate: IncludedCandidate<T::Hash, T::BlockNumber>,
+		validators: &[ValidatorId],
+		dispute: DisputeState,
+	) {
+		<Self as Store>::IncludedCandidates::remove(&candidate_hash);
+		Disputes::remove(&candidate_hash);
+		Self::freeze_para(candidate.para_id, candidate_hash);
+
+		let offenders = dispute.valid_votes.iter()
+			.enumerate()
+			.filter(|(_, voted)| **voted)
+			.filter_map(|(i, _)| validators.get(i))
+			.filter_map(|validator| {
+				let key = (PARACHAIN_KEY_TYPE_ID, validator.clone());
+				T::KeyOwnerProofSystem::prove(key.clone())
+					.and_then(|proof| T::KeyOwnerProofSystem::check_proof(key, proof))
+			})
+			.collect();
+
+		let offence = InvalidCandidateOffence {
+			session_index: candidate.session,
+			validator_set_count: validators.len() as u32,
+			offenders,
+		};
+
+		// the para is frozen regardless of whether the offence is a duplicate of one reported on
+		// another fork.
+		let _ = T::ReportOffence::report_offence(vec![reporter], offence);
+	}
+}
+
+/// The number of validators required to conclude a dispute: more than two thirds.
+const fn supermajority_threshold(n_validators: usize) -> usize {
+	n_validators * 2 / 3 + 1
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use primitives::v1::BlockNumber;
+	use frame_support::{assert_noop, assert_ok};
+	use sp_runtime::DispatchError;
+	use keyring::Sr25519Keyring;
+
+	use crate::mock::{
+		new_test_ext, System, Validity, Origin, Test, GenesisConfig as MockGenesisConfig,
+		SessionInfo as ParaSessionInfo, reported_offences,
+	};
+	use crate::initializer::SessionChangeNotification;
+	use crate::configuration::HostConfiguration;
+
+	const VALIDATORS: [Sr25519Keyring; 7] = [
+		Sr25519Keyring::Alice,
+		Sr25519Keyring::Bob,
+		Sr25519Keyring::Charlie,
+		Sr25519Keyring::Dave,
+		Sr25519Keyring::Eve,
+		Sr25519Keyring::Ferdie,
+		Sr25519Keyring::One,
+	];
+
+	fn validator_pubkeys() -> Vec<ValidatorId> {
+		VALIDATORS.iter().map(|v| v.public().into()).collect()
+	}
+
+	fn genesis_config() -> MockGenesisConfig {
+		MockGenesisConfig {
+			configuration: crate::configuration::GenesisConfig {
+				config: HostConfiguration {
+					acceptance_period: 10,
+					..Default::default()
+				},
+				..Default::default()
+			},
+			..Default::default()
+		}
+	}
+
+	fn new_session(session_index: SessionIndex) {
+		ParaSessionInfo::initializer_on_new_session(&SessionChangeNotification {
+			validators: validator_pubkeys(),
+			new_config: HostConfiguration {
+				dispute_period: 1,
+				..Default::default()
+			},
+			session_index,
+			..Default::default()
+		});
+	}
+
+	fn run_to_block(to: BlockNumber) {
+		while System::block_number() < to {
+			let b = System::block_number() + 1;
+			System::set_block_number(b);
+			Validity::initializer_initialize(b);
+		}
+	}
+
+	fn sign_statement(
+		statement: CompactStatement,
+		validator_index: ValidatorIndex,
+		signing_context: &SigningContext,
+	) -> SignedStatement {
+		SignedStatement::sign(
+			statement,
+			signing_context,
+			validator_index,
+			&VALIDATORS[validator_index as usize].pair().into(),
+		)
+	}
+
+	#[test]
+	fn confirmed_invalid_candidate_freezes_para_and_reports_backers() {
+		new_test_ext(genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(1);
+			let candidate_hash = Hash::from([1; 32]);
+			let relay_parent = Hash::from([2; 32]);
+			let signing_context = SigningContext { parent_hash: relay_parent, session_index: 5 };
+			let invalid = |i| sign_statement(
+				CompactStatement::Invalid(candidate_hash),
+				i,
+				&signing_context,
+			);
+
+			new_session(5);
+			run_to_block(5);
+			Validity::note_included(candidate_hash, para_id, relay_parent, vec![0, 1]);
+
+			// 5 of 7 validators are required to conclude a dispute.
+			assert_eq!(supermajority_threshold(VALIDATORS.len()), 5);
+
+			assert_ok!(Validity::submit_dispute_statements(
+				Origin::signed(1),
+				candidate_hash,
+				vec![invalid(2), invalid(3)],
+			));
+			assert!(Disputes::get(&candidate_hash).is_some());
+			assert!(!Validity::is_frozen(para_id));
+
+			// backers are counted as attesting to the validity of the candidate.
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					vec![invalid(0)],
+				),
+				Error::<Test>::ConflictingStatements,
+			);
+
+			assert_ok!(Validity::submit_dispute_statements(
+				Origin::signed(2),
+				candidate_hash,
+				vec![invalid(4), invalid(5), invalid(6)],
+			));
+
+			assert_eq!(FrozenParas::get(&para_id), Some(candidate_hash));
+			assert!(Disputes::get(&candidate_hash).is_none());
+			assert!(IncludedCandidates::<Test>::get(&candidate_hash).is_none());
+
+			let offences = reported_offences();
+			assert_eq!(offences.len(), 1);
+			assert_eq!(offences[0].0, vec![2]);
+			assert_eq!(offences[0].1, InvalidCandidateOffence {
+				session_index: 5,
+				validator_set_count: 7,
+				offenders: validator_pubkeys()[..2].to_vec(),
+			});
+
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					vec![invalid(3)],
+				),
+				Error::<Test>::CandidateNotInAcceptancePeriod,
+			);
+		});
+	}
+
+	#[test]
+	fn dispute_statements_are_checked() {
+		new_test_ext(genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(1);
+			let candidate_hash = Hash::from([1; 32]);
+			let relay_parent = Hash::from([2; 32]);
+			let signing_context = SigningContext { parent_hash: relay_parent, session_index: 5 };
+			let invalid = |i| sign_statement(
+				CompactStatement::Invalid(candidate_hash),
+				i,
+				&signing_context,
+			);
+			let valid = |i| sign_statement(
+				CompactStatement::Valid(candidate_hash),
+				i,
+				&signing_context,
+			);
+
+			new_session(5);
+			run_to_block(5);
+			Validity::note_included(candidate_hash, para_id, relay_parent, vec![0, 1]);
+
+			// validity statements alone don't open a dispute.
+			assert_noop!(
+				Validity::submit_dispute_statements(Origin::signed(1), candidate_hash, vec![valid(2)]),
+				Error::<Test>::NoDispute,
+			);
+
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					vec![sign_statement(
+						CompactStatement::Invalid(Hash::from([3; 32])),
+						2,
+						&signing_context,
+					)],
+				),
+				Error::<Test>::StatementForWrongCandidate,
+			);
+
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					vec![SignedStatement::sign(
+						CompactStatement::Invalid(candidate_hash),
+						&signing_context,
+						7,
+						&Sr25519Keyring::Two.pair().into(),
+					)],
+				),
+				Error::<Test>::ValidatorIndexOutOfBounds,
+			);
+
+			// signed in the context of a different session.
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					vec![sign_statement(
+						CompactStatement::Invalid(candidate_hash),
+						2,
+						&SigningContext { parent_hash: relay_parent, session_index: 6 },
+					)],
+				),
+				Error::<Test>::InvalidStatementSignature,
+			);
+
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					vec![invalid(2), valid(2)],
+				),
+				Error::<Test>::ConflictingStatements,
+			);
+
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					vec![invalid(2), invalid(2)],
+				),
+				Error::<Test>::DuplicateStatement,
+			);
+
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					candidate_hash,
+					(0..8).map(|_| invalid(2)).collect(),
+				),
+				Error::<Test>::TooManyStatements,
+			);
+
+			// the validator set of a session no longer kept by the session info module is unknown.
+			new_session(6);
+			new_session(7);
+			assert_noop!(
+				Validity::submit_dispute_statements(Origin::signed(1), candidate_hash, vec![invalid(2)]),
+				Error::<Test>::UnknownSession,
+			);
+
+			assert_noop!(
+				Validity::submit_dispute_statements(
+					Origin::signed(1),
+					Hash::from([3; 32]),
+					vec![],
+				),
+				Error::<Test>::CandidateNotInAcceptancePeriod,
+			);
+		});
+	}
+
+	#[test]
+	fn confirmed_valid_candidate_concludes_dispute() {
+		new_test_ext(genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(1);
+			let candidate_hash = Hash::from([1; 32]);
+			let relay_parent = Hash::from([2; 32]);
+			let signing_context = SigningContext { parent_hash: relay_parent, session_index: 5 };
+			let invalid = |i| sign_statement(
+				CompactStatement::Invalid(candidate_hash),
+				i,
+				&signing_context,
+			);
+			let valid = |i| sign_statement(
+				CompactStatement::Valid(candidate_hash),
+				i,
+				&signing_context,
+			);
+
+			new_session(5);
+			run_to_block(5);
+			Validity::note_included(candidate_hash, para_id, relay_parent, vec![0, 1]);
+
+			assert_ok!(Validity::submit_dispute_statements(
+				Origin::signed(1),
+				candidate_hash,
+				vec![invalid(2), valid(3), valid(4)],
+			));
+			assert!(Disputes::get(&candidate_hash).is_some());
+
+			assert_ok!(Validity::submit_dispute_statements(
+				Origin::signed(1),
+				candidate_hash,
+				vec![valid(5)],
+			));
+
+			assert!(Disputes::get(&candidate_hash).is_none());
+			assert!(IncludedCandidates::<Test>::get(&candidate_hash).is_none());
+			assert!(!Validity::is_frozen(para_id));
+			assert!(reported_offences().is_empty());
+		});
+	}
+
+	#[test]
+	fn candidates_leave_acceptance_period() {
+		new_test_ext(genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(1);
+			let candidate_a = Hash::from([1; 32]);
+			let candidate_b = Hash::from([2; 32]);
+			let relay_parent = Hash::from([3; 32]);
+
+			new_session(5);
+			run_to_block(5);
+			Validity::note_included(candidate_a, para_id, relay_parent, vec![0, 1]);
+
+			assert_ok!(Validity::submit_dispute_statements(
+				Origin::signed(1),
+				candidate_a,
+				vec![sign_statement(
+					CompactStatement::Invalid(candidate_a),
+					2,
+					&SigningContext { parent_hash: relay_parent, session_index: 5 },
+				)],
+			));
+
+			new_session(6);
+			run_to_block(8);
+			Validity::note_included(candidate_b, para_id, relay_parent, vec![0, 1]);
+
+			run_to_block(14);
+			assert!(IncludedCandidates::<Test>::get(&candidate_a).is_some());
+			assert_eq!(
+				IncludedCandidates::<Test>::get(&candidate_b).map(|c| c.session),
+				Some(6),
+			);
+
+			// the unconcluded dispute is dropped along with the candidate.
+			run_to_block(15);
+			assert!(IncludedCandidates::<Test>::get(&candidate_a).is_none());
+			assert!(Disputes::get(&candidate_a).is_none());
+			assert!(IncludedCandidates::<Test>::get(&candidate_b).is_some());
+
+			run_to_block(18);
+			assert!(IncludedCandidates::<Test>::get(&candidate_b).is_none());
+			assert!(AcceptancePeriodQueue::<Test>::get().is_empty());
+			assert!(!Validity::is_frozen(para_id));
+		});
+	}
+
+	#[test]
+	fn frozen_paras_are_unfrozen_by_root() {
+		new_test_ext(genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(1);
+
+			assert_noop!(
+				Validity::unfreeze_para(Origin::root(), para_id),
+				Error::<Test>::ParaNotFrozen,
+			);
+
+			Validity::freeze_para(para_id, Hash::from([1; 32]));
+			assert!(Validity::is_frozen(para_id));
+
+			assert_noop!(
+				Validity::unfreeze_para(Origin::signed(1), para_id),
+				DispatchError::BadOrigin,
+			);
+			assert_ok!(Validity::unfreeze_para(Origin::root(), para_id));
+			assert!(!Validity::is_frozen(para_id));
+		});
+	}
+
+	#[test]
+	fn open_disputes_are_discarded_by_root() {
+		new_test_ext(genesis_config()).execute_with(|| {
+			let para_id = ParaId::from(1);
+			let candidate_hash = Hash::from([1; 32]);
+			let relay_parent = Hash::from([2; 32]);
+			let signing_context = SigningContext { parent_hash: relay_parent, session_index: 5 };
+			let invalid = |i| sign_statement(
+				CompactStatement::Invalid(candidate_hash),
+				i,
+				&signing_context,
+			);
+
+			new_session(5);
+			run_to_block(5);
+			Validity::note_included(candidate_hash, para_id, relay_parent, vec![0, 1]);
+
+			assert_noop!(
+				Validity::discard_dispute(Origin::root(), candidate_hash),
+				Error::<Test>::NoDispute,
+			);
+
+			assert_ok!(Validity::submit_dispute_statements(
+				Origin::signed(1),
+				candidate_hash,
+				vec![invalid(2)],
+			));
+
+			assert_noop!(
+				Validity::discard_dispute(Origin::signed(1), candidate_hash),
+				DispatchError::BadOrigin,
+			);
+			assert_ok!(Validity::discard_dispute(Origin::root(), candidate_hash));
+			assert!(Disputes::get(&candidate_hash).is_none());
+			assert!(IncludedCandidates::<Test>::get(&candidate_hash).is_some());
+
+			// the votes of the discarded dispute don't count towards a new one.
+			assert_ok!(Validity::submit_dispute_statements(
+				Origin::signed(1),
+				candidate_hash,
+				vec![invalid(2)],
+			));
+		});
+	}
+}
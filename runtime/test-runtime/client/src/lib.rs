@@ -326,10 +326,10 @@ pub fn new_native_executor() -> sc_executor::NativeExecutor<LocalExecutor> {
 /// The index of the block must be provided to calculate a valid timestamp for the block. The value starts at 0 and
 /// should be incremented by one for every block produced.
 pub fn needed_extrinsics(
-	heads: Vec<polkadot_primitives::v0::AttestedCandidate>,
+	backed_candidates: Vec<polkadot_primitives::v1::BackedCandidate>,
 	i: u64,
 ) -> Vec<polkadot_test_runtime::UncheckedExtrinsic> {
-	use polkadot_runtime_common::parachains;
+	use polkadot_runtime_parachains::inclusion_inherent;
 
 	let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
 		.expect("now always later than unix epoch; qed")
@@ -337,7 +337,9 @@ pub fn needed_extrinsics(
 
 	vec![
 		polkadot_test_runtime::UncheckedExtrinsic {
-			function: polkadot_test_runtime::Call::Parachains(parachains::Call::set_heads(heads)),
+			function: polkadot_test_runtime::Call::InclusionInherent(
+				inclusion_inherent::Call::inclusion(Vec::new(), backed_candidates),
+			),
 			signature: None,
 		},
 		polkadot_test_runtime::UncheckedExtrinsic {
@@ -21,28 +21,42 @@
 #![recursion_limit="256"]
 
 use rstd::prelude::*;
-use codec::{Encode, Decode};
-use primitives::v0::{
-	self as parachain,
+use codec::Encode;
+use primitives::v1::{
 	AccountId, AccountIndex, Balance, BlockNumber, Hash as HashT, Nonce, Signature, Moment,
-	ActiveParas, AbridgedCandidateReceipt, SigningContext, ValidityError,
+	ValidatorId, SchedulerRoster, GlobalValidationSchedule, LocalValidationData, Id as ParaId,
+	ValidationCode, HeadData, CommittedCandidateReceipt, DownwardMessage, CoreState,
+	SessionInfo,
 };
 use runtime_common::{
-	attestations, claims, parachains, registrar, slots, SlowAdjustingFeeUpdate,
+	claims, SlowAdjustingFeeUpdate,
 	impls::CurrencyToVoteHandler,
 	BlockHashCount, MaximumBlockWeight, AvailableBlockRatio,
 	MaximumBlockLength, BlockExecutionWeight, ExtrinsicBaseWeight,
 };
+use runtime_parachains::{
+	configuration as parachains_configuration,
+	inclusion as parachains_inclusion,
+	inclusion_inherent as parachains_inclusion_inherent,
+	initializer as parachains_initializer,
+	paras as parachains_paras,
+	registrar as parachains_registrar,
+	router as parachains_router,
+	scheduler as parachains_scheduler,
+	session_info as parachains_session_info,
+	validity as parachains_validity,
+	runtime_api_impl::v1 as runtime_impl,
+};
 use sp_runtime::{
 	create_runtime_str, generic, impl_opaque_keys,
-	ApplyExtrinsicResult, Perbill, RuntimeDebug, KeyTypeId,
+	ApplyExtrinsicResult, Perbill, KeyTypeId,
 	transaction_validity::{
-		TransactionValidity, InvalidTransaction, TransactionValidityError, TransactionSource, TransactionPriority,
+		TransactionValidity, TransactionSource, TransactionPriority,
 	},
 	curve::PiecewiseLinear,
 	traits::{
-		BlakeTwo256, Block as BlockT, StaticLookup, SignedExtension, OpaqueKeys, ConvertInto,
-		DispatchInfoOf, Extrinsic as ExtrinsicT, SaturatedConversion, Verify,
+		BlakeTwo256, Block as BlockT, StaticLookup, OpaqueKeys, ConvertInto,
+		Extrinsic as ExtrinsicT, SaturatedConversion, Verify,
 	},
 };
 use version::RuntimeVersion;
@@ -53,7 +67,7 @@ use sp_core::OpaqueMetadata;
 use sp_staking::SessionIndex;
 use frame_support::{
 	parameter_types, construct_runtime, debug,
-	traits::{KeyOwnerProofSystem, Randomness},
+	traits::{Get, KeyOwnerProofSystem, Randomness},
 	weights::Weight,
 };
 use authority_discovery_primitives::AuthorityId as AuthorityDiscoveryId;
@@ -66,13 +80,14 @@ pub use staking::StakerStatus;
 pub use sp_runtime::BuildStorage;
 pub use timestamp::Call as TimestampCall;
 pub use balances::Call as BalancesCall;
-pub use attestations::{Call as AttestationsCall, MORE_ATTESTATIONS_IDENTIFIER};
-pub use parachains::Call as ParachainsCall;
+pub use parachains_inclusion_inherent::Call as InclusionInherentCall;
 
 /// Constant values used within the runtime.
 pub mod constants;
 use constants::{time::*, currency::*, fee::*};
 
+pub mod weights;
+
 // Make the WASM binary available.
 #[cfg(feature = "std")]
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
@@ -82,10 +97,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	spec_name: create_runtime_str!("polkadot-test-runtime"),
 	impl_name: create_runtime_str!("parity-polkadot-test-runtime"),
 	authoring_version: 2,
-	spec_version: 1053,
+	spec_version: 1054,
 	impl_version: 0,
 	apis: RUNTIME_API_VERSIONS,
-	transaction_version: 1,
+	transaction_version: 2,
 };
 
 /// Native version.
@@ -97,35 +112,6 @@ pub fn native_version() -> NativeVersion {
 	}
 }
 
-/// Avoid processing transactions from slots and parachain registrar.
-#[derive(Default, Encode, Decode, Clone, Eq, PartialEq, RuntimeDebug)]
-pub struct RestrictFunctionality;
-impl SignedExtension for RestrictFunctionality {
-	const IDENTIFIER: &'static str = "RestrictFunctionality";
-	type AccountId = AccountId;
-	type Call = Call;
-	type AdditionalSigned = ();
-	type Pre = ();
-
-	fn additional_signed(&self) -> rstd::result::Result<(), TransactionValidityError> { Ok(()) }
-
-	fn validate(
-		&self,
-		_: &Self::AccountId,
-		call: &Self::Call,
-		_: &DispatchInfoOf<Self::Call>,
-		_: usize
-	)
-		-> TransactionValidity
-	{
-		match call {
-			Call::Slots(_) | Call::Registrar(_)
-				=> Err(InvalidTransaction::Custom(ValidityError::NoPermission.into()).into()),
-			_ => Ok(Default::default()),
-		}
-	}
-}
-
 parameter_types! {
 	pub const Version: RuntimeVersion = VERSION;
 }
@@ -261,7 +247,7 @@ impl_opaque_keys! {
 	pub struct SessionKeys {
 		pub grandpa: Grandpa,
 		pub babe: Babe,
-		pub parachain_validator: Parachains,
+		pub parachain_validator: Initializer,
 	}
 }
 
@@ -356,48 +342,68 @@ impl grandpa::Trait for Runtime {
 	type HandleEquivocation = ();
 }
 
-parameter_types! {
-	pub const AttestationPeriod: BlockNumber = 50;
+impl parachains_configuration::Trait for Runtime {
+	type Event = Event;
 }
 
-impl attestations::Trait for Runtime {
-	type AttestationPeriod = AttestationPeriod;
-	type ValidatorIdentities = parachains::ValidatorIdentities<Runtime>;
-	type RewardAttestation = Staking;
+impl parachains_paras::Trait for Runtime {
+	type Event = Event;
+	type WeightInfo = weights::parachains_paras::WeightInfo;
 }
 
-parameter_types! {
-	pub storage MaxCodeSize: u32 = 10 * 1024 * 1024; // 10 MB
-	pub storage MaxHeadDataSize: u32 = 20 * 1024; // 20 KB
+impl parachains_scheduler::Trait for Runtime {
+	type Event = Event;
+	type Currency = Balances;
+	type WeightInfo = weights::parachains_scheduler::WeightInfo;
+}
+
+/// The authority discovery keys of the current validator set.
+pub struct CurrentAuthorityDiscoveryKeys;
+
+impl Get<Vec<AuthorityDiscoveryId>> for CurrentAuthorityDiscoveryKeys {
+	fn get() -> Vec<AuthorityDiscoveryId> {
+		AuthorityDiscovery::authorities()
+	}
+}
 
-	pub storage ValidationUpgradeFrequency: BlockNumber = 2;
-	pub storage ValidationUpgradeDelay: BlockNumber = 1;
-	pub storage SlashPeriod: BlockNumber = 1 * MINUTES;
+impl parachains_session_info::Trait for Runtime {
+	type AuthorityDiscovery = CurrentAuthorityDiscoveryKeys;
 }
 
-impl parachains::Trait for Runtime {
-	type AuthorityId = primitives::v0::fisherman::FishermanAppCrypto;
+impl parachains_inclusion::Trait for Runtime {
+	type Event = Event;
+}
+
+impl parachains_inclusion_inherent::Trait for Runtime {
+	type WeightInfo = weights::parachains_inclusion_inherent::WeightInfo;
+}
+
+impl parachains_validity::Trait for Runtime {
+	type KeyOwnerProofSystem = Historical;
+	type IdentificationTuple =
+		<Self::KeyOwnerProofSystem as KeyOwnerProofSystem<(KeyTypeId, ValidatorId)>>::IdentificationTuple;
+	type ReportOffence = Offences;
+}
+
+impl parachains_router::Trait for Runtime {
 	type Origin = Origin;
 	type Call = Call;
-	type ParachainCurrency = Balances;
-	type BlockNumberConversion = sp_runtime::traits::Identity;
+	type Currency = Balances;
+	type WeightInfo = weights::parachains_router::WeightInfo;
+}
+
+parameter_types! {
+	pub storage ParathreadDeposit: Balance = 5 * DOLLARS;
+}
+
+impl parachains_registrar::Trait for Runtime {
+	type Currency = Balances;
+	type ParathreadDeposit = ParathreadDeposit;
+}
+
+impl parachains_initializer::Trait for Runtime {
 	type Randomness = RandomnessCollectiveFlip;
-	type ActiveParachains = Registrar;
-	type Registrar = Registrar;
-	type MaxCodeSize = MaxCodeSize;
-	type MaxHeadDataSize = MaxHeadDataSize;
-
-	type ValidationUpgradeFrequency = ValidationUpgradeFrequency;
-	type ValidationUpgradeDelay = ValidationUpgradeDelay;
-	type SlashPeriod = SlashPeriod;
-
-	type Proof = sp_session::MembershipProof;
-	type KeyOwnerProofSystem = session::historical::Module<Self>;
-	type IdentificationTuple = <
-			Self::KeyOwnerProofSystem as KeyOwnerProofSystem<(KeyTypeId, Vec<u8>)>
-		>::IdentificationTuple;
-	type ReportOffence = Offences;
-	type BlockHashConversion = sp_runtime::traits::Identity;
+	type WeightInfo = weights::parachains_initializer::WeightInfo;
 }
 
 impl<LocalCall> system::offchain::CreateSignedTransaction<LocalCall> for Runtime where
@@ -419,7 +425,6 @@ impl<LocalCall> system::offchain::CreateSignedTransaction<LocalCall> for Runtime
 			.saturating_sub(1);
 		let tip = 0;
 		let extra: SignedExtra = (
-			RestrictFunctionality,
 			system::CheckSpecVersion::<Runtime>::new(),
 			system::CheckTxVersion::<Runtime>::new(),
 			system::CheckGenesis::<Runtime>::new(),
@@ -427,8 +432,6 @@ impl<LocalCall> system::offchain::CreateSignedTransaction<LocalCall> for Runtime
 			system::CheckNonce::<Runtime>::from(nonce),
 			system::CheckWeight::<Runtime>::new(),
 			transaction_payment::ChargeTransactionPayment::<Runtime>::from(tip),
-			registrar::LimitParathreadCommits::<Runtime>::new(),
-			parachains::ValidateDoubleVoteReports::<Runtime>::new(),
 		);
 		let raw_payload = SignedPayload::new(call, extra).map_err(|e| {
 			debug::warn!("Unable to create signed payload: {:?}", e);
@@ -461,36 +464,6 @@ impl offences::Trait for Runtime {
 
 impl authority_discovery::Trait for Runtime {}
 
-parameter_types! {
-	pub storage ParathreadDeposit: Balance = 5 * DOLLARS;
-	pub const QueueSize: usize = 2;
-	pub storage MaxRetries: u32 = 3;
-}
-
-impl registrar::Trait for Runtime {
-	type Event = Event;
-	type Origin = Origin;
-	type Currency = Balances;
-	type ParathreadDeposit = ParathreadDeposit;
-	type SwapAux = Slots;
-	type QueueSize = QueueSize;
-	type MaxRetries = MaxRetries;
-}
-
-parameter_types! {
-	pub storage LeasePeriod: BlockNumber = 100_000;
-	pub storage EndingPeriod: BlockNumber = 1000;
-}
-
-impl slots::Trait for Runtime {
-	type Event = Event;
-	type Currency = Balances;
-	type Parachains = Registrar;
-	type LeasePeriod = LeasePeriod;
-	type EndingPeriod = EndingPeriod;
-	type Randomness = RandomnessCollectiveFlip;
-}
-
 parameter_types! {
 	pub Prefix: &'static [u8] = b"Pay KSMs to the Kusama account:";
 }
@@ -522,7 +495,7 @@ impl sudo::Trait for Runtime {
 construct_runtime! {
 	pub enum Runtime where
 		Block = Block,
-		NodeBlock = primitives::v0::Block,
+		NodeBlock = primitives::v1::Block,
 		UncheckedExtrinsic = UncheckedExtrinsic
 	{
 		// Basic stuff; balances is uncallable initially.
@@ -549,12 +522,18 @@ construct_runtime! {
 		// Claims. Usable initially.
 		Claims: claims::{Module, Call, Storage, Event<T>, Config<T>, ValidateUnsigned},
 
-		// Parachains stuff; slots are disabled (no auctions initially). The rest are safe as they
-		// have no public dispatchables.
-		Parachains: parachains::{Module, Call, Storage, Config, Inherent, Origin},
-		Attestations: attestations::{Module, Call, Storage},
-		Slots: slots::{Module, Call, Storage, Event<T>},
-		Registrar: registrar::{Module, Call, Storage, Event, Config<T>},
+		// Parachains runtime modules. The initializer drives the rest of them on session changes
+		// and block boundaries.
+		Initializer: parachains_initializer::{Module, Call, Storage},
+		Configuration: parachains_configuration::{Module, Call, Storage, Config<T>, Event},
+		Paras: parachains_paras::{Module, Call, Storage, Config<T>, Event<T>},
+		Scheduler: parachains_scheduler::{Module, Call, Storage, Event<T>},
+		ParaSessionInfo: parachains_session_info::{Module, Call, Storage},
+		Inclusion: parachains_inclusion::{Module, Call, Storage, Event<T>},
+		InclusionInherent: parachains_inclusion_inherent::{Module, Call, Storage, Inherent},
+		Validity: parachains_validity::{Module, Call, Storage},
+		Router: parachains_router::{Module, Call, Storage, Origin},
+		Registrar: parachains_registrar::{Module, Call, Storage},
 
 		// Vesting. Usable initially, but removed once all vesting is finished.
 		Vesting: vesting::{Module, Call, Storage, Event<T>, Config<T>},
@@ -576,7 +555,6 @@ pub type SignedBlock = generic::SignedBlock<Block>;
 pub type BlockId = generic::BlockId<Block>;
 /// The SignedExtension to the basic transaction logic.
 pub type SignedExtra = (
-	RestrictFunctionality,
 	system::CheckSpecVersion<Runtime>,
 	system::CheckTxVersion<Runtime>,
 	system::CheckGenesis<Runtime>,
@@ -584,8 +562,6 @@ pub type SignedExtra = (
 	system::CheckNonce<Runtime>,
 	system::CheckWeight<Runtime>,
 	transaction_payment::ChargeTransactionPayment::<Runtime>,
-	registrar::LimitParathreadCommits<Runtime>,
-	parachains::ValidateDoubleVoteReports<Runtime>,
 );
 /// Unchecked extrinsic type as expected by this runtime.
 pub type UncheckedExtrinsic = generic::UncheckedExtrinsic<Address, Call, Signature, SignedExtra>;
@@ -666,45 +642,65 @@ sp_api::impl_runtime_apis! {
 		}
 	}
 
-	impl parachain::ParachainHost<Block> for Runtime {
-		fn validators() -> Vec<parachain::ValidatorId> {
-			Parachains::authorities()
+	impl primitives::v1::ParachainHost<Block> for Runtime {
+		fn validators() -> Vec<ValidatorId> {
+			runtime_impl::validators::<Runtime>()
 		}
-		fn duty_roster() -> parachain::DutyRoster {
-			Parachains::calculate_duty_roster().0
+
+		fn validator_groups() -> SchedulerRoster {
+			runtime_impl::validator_groups::<Runtime>()
 		}
-		fn active_parachains() -> Vec<(parachain::Id, Option<(parachain::CollatorId, parachain::Retriable)>)> {
-			Registrar::active_paras()
+
+		fn session_index_for_child() -> SessionIndex {
+			runtime_impl::session_index_for_child::<Runtime>()
 		}
-		fn global_validation_schedule() -> parachain::GlobalValidationSchedule {
-			Parachains::global_validation_schedule()
+
+		fn global_validation_schedule() -> GlobalValidationSchedule {
+			runtime_impl::global_validation_schedule::<Runtime>()
+		}
+
+		fn local_validation_data(para_id: ParaId) -> Option<LocalValidationData> {
+			runtime_impl::local_validation_data::<Runtime>(para_id)
 		}
-		fn local_validation_data(id: parachain::Id) -> Option<parachain::LocalValidationData> {
-			Parachains::current_local_validation_data(&id)
+
+		fn validation_code(
+			para_id: ParaId,
+			at: BlockNumber,
+			assume_intermediate: Option<BlockNumber>,
+		) -> Option<ValidationCode> {
+			runtime_impl::validation_code::<Runtime>(para_id, at, assume_intermediate)
 		}
-		fn parachain_code(id: parachain::Id) -> Option<parachain::ValidationCode> {
-			Parachains::parachain_code(&id)
+
+		fn validation_code_hash(
+			para_id: ParaId,
+			at: BlockNumber,
+			assume_intermediate: Option<BlockNumber>,
+		) -> Option<Hash> {
+			runtime_impl::validation_code_hash::<Runtime>(para_id, at, assume_intermediate)
 		}
-		fn get_heads(extrinsics: Vec<<Block as BlockT>::Extrinsic>)
-			-> Option<Vec<AbridgedCandidateReceipt>>
-		{
-			extrinsics
-				.into_iter()
-				.find_map(|ex| match UncheckedExtrinsic::decode(&mut ex.encode().as_slice()) {
-					Ok(ex) => match ex.function {
-						Call::Parachains(ParachainsCall::set_heads(heads)) => {
-							Some(heads.into_iter().map(|c| c.candidate).collect())
-						}
-						_ => None,
-					}
-					Err(_) => None,
-				})
+
+		fn validation_code_by_hash(hash: Hash) -> Option<ValidationCode> {
+			runtime_impl::validation_code_by_hash::<Runtime>(hash)
 		}
-		fn signing_context() -> SigningContext {
-			Parachains::signing_context()
+
+		fn head_data(para_id: ParaId) -> Option<HeadData> {
+			runtime_impl::head_data::<Runtime>(para_id)
 		}
-		fn downward_messages(id: parachain::Id) -> Vec<primitives::v0::DownwardMessage> {
-			Parachains::downward_messages(id)
+
+		fn candidate_pending_availability(para_id: ParaId) -> Option<CommittedCandidateReceipt> {
+			runtime_impl::candidate_pending_availability::<Runtime>(para_id)
+		}
+
+		fn downward_messages(para_id: ParaId) -> Vec<DownwardMessage> {
+			runtime_impl::downward_messages::<Runtime>(para_id)
+		}
+
+		fn availability_cores() -> Vec<CoreState> {
+			runtime_impl::availability_cores::<Runtime>()
+		}
+
+		fn session_info(index: SessionIndex) -> Option<SessionInfo> {
+			runtime_impl::session_info::<Runtime>(index)
 		}
 	}
 
@@ -794,4 +790,42 @@ sp_api::impl_runtime_apis! {
 			TransactionPayment::query_info(uxt, len)
 		}
 	}
+
+	#[cfg(feature = "runtime-benchmarks")]
+	impl frame_benchmarking::Benchmark<Block> for Runtime {
+		fn dispatch_benchmark(
+			pallet: Vec<u8>,
+			benchmark: Vec<u8>,
+			lowest_range_values: Vec<u32>,
+			highest_range_values: Vec<u32>,
+			steps: Vec<u32>,
+			repeat: u32,
+		) -> Result<Vec<frame_benchmarking::BenchmarkBatch>, sp_runtime::RuntimeString> {
+			use frame_benchmarking::{Benchmarking, BenchmarkBatch, add_benchmark};
+
+			let whitelist: Vec<Vec<u8>> = vec![
+				// Block Number
+				hex_literal::hex!("26aa394eea5630e07c48ae0c9558cef702a5c1b19ab7a04f536c519aca4983ac").to_vec(),
+				// Total Issuance
+				hex_literal::hex!("c2261276cc9d1f8598ea4b6a74b15c2f57c875e4cff74148e4628f264b974c80").to_vec(),
+				// Execution Phase
+				hex_literal::hex!("26aa394eea5630e07c48ae0c9558cef7ff553b5a9862a516939d82b3d3d8661a").to_vec(),
+				// Event Count
+				hex_literal::hex!("26aa394eea5630e07c48ae0c9558cef70a98fdbe9ce6c55837576c60c7af3850").to_vec(),
+				// System Events
+				hex_literal::hex!("26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7").to_vec(),
+			];
+
+			let mut batches = Vec::<BenchmarkBatch>::new();
+			let params = (&pallet, &benchmark, &lowest_range_values, &highest_range_values, &steps, repeat, &whitelist);
+			add_benchmark!(params, batches, parachains_inclusion_inherent, InclusionInherent);
+			add_benchmark!(params, batches, parachains_initializer, Initializer);
+			add_benchmark!(params, batches, parachains_paras, Paras);
+			add_benchmark!(params, batches, parachains_router, Router);
+			add_benchmark!(params, batches, parachains_scheduler, Scheduler);
+
+			if batches.is_empty() { return Err("Benchmark not found for this pallet.".into()) }
+			Ok(batches)
+		}
+	}
 }
@@ -0,0 +1,35 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Weights of the parachains modules in this runtime.
+//!
+//! The benchmarks of these modules have not been run on the reference hardware yet, so until
+//! they are, every weight here is the conservative weight of its module. To regenerate a file,
+//! build the node with `--features runtime-benchmarks`, export a chain spec of this runtime and
+//! run, e.g. for the router module:
+//!
+//! ```sh
+//! polkadot benchmark --chain=<test runtime chain spec> --execution=wasm --wasm-execution=compiled \
+//! 	--pallet=parachains_router --extrinsic="*" --steps=50 --repeat=20
+//! ```
+//!
+//! and write the resulting weight functions into the file of the module.
+
+pub mod parachains_inclusion_inherent;
+pub mod parachains_initializer;
+pub mod parachains_paras;
+pub mod parachains_router;
+pub mod parachains_scheduler;
@@ -0,0 +1,40 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Weights for `parachains_paras`.
+//!
+//! See the module docs of `weights` for how to regenerate this file.
+
+use frame_support::weights::Weight;
+use runtime_parachains::paras;
+
+/// The weights of `parachains_paras` in this runtime.
+pub struct WeightInfo;
+
+impl paras::WeightInfo for WeightInfo {
+	fn prune_old_code(p: u32) -> Weight {
+		<() as paras::WeightInfo>::prune_old_code(p)
+	}
+	fn force_set_current_code(c: u32) -> Weight {
+		<() as paras::WeightInfo>::force_set_current_code(c)
+	}
+	fn force_schedule_code_upgrade(c: u32) -> Weight {
+		<() as paras::WeightInfo>::force_schedule_code_upgrade(c)
+	}
+	fn force_queue_action(a: u32) -> Weight {
+		<() as paras::WeightInfo>::force_queue_action(a)
+	}
+}
@@ -0,0 +1,40 @@
+// Copyright 2020 Parity Technologies (UK) Ltd.
+// This file is part of Polkadot.
+
+// Polkadot is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Polkadot is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Polkadot.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Weights for `parachains_router`.
+//!
+//! See the module docs of `weights` for how to regenerate this file.
+
+use frame_support::weights::Weight;
+use runtime_parachains::router;
+
+/// The weights of `parachains_router` in this runtime.
+pub struct WeightInfo;
+
+impl router::WeightInfo for WeightInfo {
+	fn transfer_to_para() -> Weight {
+		<() as router::WeightInfo>::transfer_to_para()
+	}
+	fn hrmp_init_open_channel() -> Weight {
+		<() as router::WeightInfo>::hrmp_init_open_channel()
+	}
+	fn hrmp_accept_open_channel() -> Weight {
+		<() as router::WeightInfo>::hrmp_accept_open_channel()
+	}
+	fn hrmp_close_channel() -> Weight {
+		<() as router::WeightInfo>::hrmp_close_channel()
+	}
+}
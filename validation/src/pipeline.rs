@@ -206,6 +206,8 @@ pub fn validate<'a>(
 		max_head_data_size: global_validation.max_head_data_size,
 		relay_chain_height: global_validation.block_number,
 		code_upgrade_allowed: local_validation.code_upgrade_allowed,
+		downward_messages: Vec::new(),
+		inbound_hrmp_messages: Vec::new(),
 	};
 
 	// TODO: remove when ext does not do this.